@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+/// An address prefix such as `192.0.2.0/24` or `2001:db8::/32`.
+///
+/// A plain address without a prefix length matches only that address.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Returns the prefix of length `prefix_len` that `addr` lies within, capped at the
+    /// length of the address. IPv4-mapped IPv6 addresses give IPv4 prefixes.
+    pub fn containing(addr: IpAddr, prefix_len: u8) -> Self {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            v4 => v4,
+        };
+        match addr {
+            IpAddr::V4(v4) => {
+                let prefix_len = prefix_len.min(32);
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0);
+                Cidr { network: IpAddr::V4((u32::from(v4) & mask).into()), prefix_len }
+            }
+            IpAddr::V6(v6) => {
+                let prefix_len = prefix_len.min(128);
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix_len)).unwrap_or(0);
+                Cidr { network: IpAddr::V6((u128::from(v6) & mask).into()), prefix_len }
+            }
+        }
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Returns whether `addr` lies within this prefix. IPv4-mapped IPv6 addresses match
+    /// IPv4 prefixes.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            v4 => v4,
+        };
+        match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let network = IpAddr::from_str(addr).map_err(|e| format!("Invalid address in {}: {}", s, e))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.parse::<u8>().map_err(|e| format!("Invalid prefix length in {}: {}", s, e))?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(format!("Prefix length {} too long for {}", prefix_len, addr));
+        }
+        Ok(Cidr { network, prefix_len })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Cidr::from_str(&s)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// A named, reusable list of client prefixes, given as an `[[acl]]` table. A client is in
+/// the list when it is within one of the `allow` prefixes and none of the `deny` ones.
+#[derive(Deserialize, Clone, Default)]
+pub struct AclConfig {
+    pub name: String,
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+}
+
+impl AclConfig {
+    pub fn holds(&self, addr: IpAddr) -> bool {
+        self.allow.iter().any(|cidr| cidr.contains(addr)) && !self.deny.iter().any(|cidr| cidr.contains(addr))
+    }
+}
+
+/// What a request asks the server to do, as far as access control is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// A query answered from the local zones.
+    Query,
+    /// A query for the resolver. It must pass the `query` ACLs too.
+    Recursion,
+    Transfer,
+    Update,
+    Notify,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Query => write!(f, "query"),
+            Operation::Recursion => write!(f, "recursion"),
+            Operation::Transfer => write!(f, "transfer"),
+            Operation::Update => write!(f, "update"),
+            Operation::Notify => write!(f, "NOTIFY"),
+        }
+    }
+}
+
+/// The ACLs each operation is restricted to at one level of the configuration, by name.
+/// A client passes when one of the ACLs holds it. Operations without ACLs are not
+/// restricted at that level.
+#[derive(Deserialize, Clone, Default)]
+pub struct Access {
+    pub query: Option<Vec<String>>,
+    pub recursion: Option<Vec<String>>,
+    pub transfer: Option<Vec<String>>,
+    pub update: Option<Vec<String>>,
+    pub notify: Option<Vec<String>>,
+}
+
+impl Access {
+    /// Returns the ACLs for an operation, if it is restricted.
+    pub fn acls(&self, operation: Operation) -> Option<&[String]> {
+        match operation {
+            Operation::Query => self.query.as_deref(),
+            Operation::Recursion => self.recursion.as_deref(),
+            Operation::Transfer => self.transfer.as_deref(),
+            Operation::Update => self.update.as_deref(),
+            Operation::Notify => self.notify.as_deref(),
+        }
+    }
+
+    /// Returns the names of every ACL referred to.
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        [&self.query, &self.recursion, &self.transfer, &self.update, &self.notify].into_iter().flatten().flatten()
+    }
+}
+
+/// The configured ACLs by name, besides `any` and `none`, which hold every client and no
+/// client.
+#[derive(Default)]
+pub struct Acls {
+    acls: HashMap<String, AclConfig>,
+}
+
+impl Acls {
+    pub fn new(configs: &[AclConfig]) -> Self {
+        Acls { acls: configs.iter().map(|acl| (acl.name.clone(), acl.clone())).collect() }
+    }
+
+    /// Returns whether an ACL with this name exists.
+    pub fn is_defined(&self, name: &str) -> bool {
+        name == "any" || name == "none" || self.acls.contains_key(name)
+    }
+
+    /// Returns whether one of the named ACLs holds `addr`. Undefined names hold no one.
+    pub fn holds(&self, names: &[String], addr: IpAddr) -> bool {
+        names.iter().any(|name| name == "any" || self.acls.get(name).is_some_and(|acl| acl.holds(addr)))
+    }
+
+    /// Checks an operation from `addr` against each level of the configuration in turn.
+    /// Returns the first level whose ACLs do not hold the client.
+    pub fn check<'a>(&self, levels: &[(&'a str, &Access)], operation: Operation, addr: IpAddr) -> Result<(), &'a str> {
+        let operations: &[Operation] = match operation {
+            Operation::Recursion => &[Operation::Query, Operation::Recursion],
+            _ => &[operation],
+        };
+        for (level, access) in levels {
+            for operation in operations {
+                if access.acls(*operation).is_some_and(|names| !self.holds(names, addr)) {
+                    return Err(level);
+                }
+            }
+        }
+        Ok(())
+    }
+}
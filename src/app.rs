@@ -1,43 +1,164 @@
-use std::net::SocketAddr;
+pub mod access;
+pub mod notify;
+pub mod query;
+pub mod recursion;
+pub mod secondary;
+pub mod signing;
+pub mod transfer;
+pub mod update;
+pub mod views;
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 use trust_dns_proto::op::{Message, MessageType, OpCode, ResponseCode};
 use trust_dns_proto::rr::{Name, RData, Record};
 use trust_dns_proto::serialize::binary::{BinDecodable, BinDecoder, BinEncodable};
 
-use crate::db::Database;
-use crate::server::Server;
+use crate::acl::Acls;
+use crate::config::Config;
+use crate::db::{Database, RecordData, ResourceRecord, Zone};
+use crate::dnssec::KeyStore;
+use crate::resolver::Resolver;
+use crate::rrl::{Decision, RateLimiter, ResponseClass};
+use crate::server::{Server, TcpConnection};
+use crate::tsig::{self, TsigError, TsigSigner};
 
 type D = dyn Database + Send + 'static;
-type Payload = (Message, SocketAddr);
+/// A parsed request with the bytes it was parsed from, which TSIG signatures cover.
+type Payload = (Message, Vec<u8>, SocketAddr, Transport);
+
+/// How often signed zones are checked for signatures that are about to expire.
+const SIGNATURE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the resolver's cache statistics are logged.
+const CACHE_STATS_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How a message reached the server, and therefore how replies are sent back.
+pub enum Transport {
+    Udp,
+    /// A TCP connection; replies are queued for it to write with a length prefix.
+    Tcp(TcpConnection),
+}
+
+/// A change to the set of loaded zones, applied by the app between messages.
+pub enum ZoneUpdate {
+    /// Install a new version of a zone, or add it if it is not loaded.
+    Replace(Zone),
+    /// Stop serving the zone with this origin.
+    Remove(String),
+}
 
 pub struct App {
     database: Box<D>,
     server: Server,
+    config: Config,
     tx: Sender<Payload>,
     rx: Receiver<Payload>,
+    zone_tx: Sender<ZoneUpdate>,
+    zone_rx: Receiver<ZoneUpdate>,
+    refresh_tx: Option<Sender<String>>,
+    zone_files: HashMap<String, PathBuf>,
+    /// DNSSEC keys by lowercase zone origin.
+    signing_keys: HashMap<String, signing::ZoneKeys>,
+    /// The keys of zones whose keys the server manages, by lowercase zone origin.
+    key_stores: HashMap<String, KeyStore>,
+    last_signature_check: Instant,
+    /// Resolves queries for names outside the local zones, if configured.
+    resolver: Option<Arc<Resolver>>,
+    /// How many queries are being resolved.
+    resolutions: Arc<AtomicUsize>,
+    last_cache_stats: Instant,
+    /// Split-horizon views, in the order they are matched.
+    views: Vec<views::View>,
+    acls: Acls,
+    /// Limits the rate of UDP responses, if configured. Shared with the UDP listener,
+    /// which answers unparsable packets itself.
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
 }
 
 impl App {
     pub fn new(database: Box<D>, socket: SocketAddr) -> Self {
+        App::with_config(database, socket, Config::default())
+    }
+
+    /// Creates an `App` that applies the per-zone settings in `config`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the DNSSEC keys or key store of a signed zone cannot be loaded.
+    pub fn with_config(database: Box<D>, socket: SocketAddr, config: Config) -> Self {
         let (tx, rx) = mpsc::channel();
+        let (zone_tx, zone_rx) = mpsc::channel();
         let server = Server::new(socket);
+        let signing_keys = App::load_signing_keys(&config).unwrap_or_else(|e| panic!("{}", e));
+        let key_stores = App::open_key_stores(&config).unwrap_or_else(|e| panic!("{}", e));
+        let resolver = config.resolver.clone().map(|resolver| Arc::new(Resolver::new(resolver)));
+        let views = config.views.iter().cloned().map(views::View::new).collect();
+        let acls = Acls::new(&config.acls);
+        let rate_limiter = config.rate_limit.clone().map(|config| Arc::new(Mutex::new(RateLimiter::new(config))));
         let mut a: App = App {
             database,
             server,
+            config,
             tx,
             rx,
+            zone_tx,
+            zone_rx,
+            refresh_tx: None,
+            zone_files: HashMap::new(),
+            signing_keys,
+            key_stores,
+            last_signature_check: Instant::now(),
+            resolver,
+            resolutions: Arc::new(AtomicUsize::new(0)),
+            last_cache_stats: Instant::now(),
+            views,
+            acls,
+            rate_limiter,
         };
+        a.warn_undefined_acls();
 
         let tx_cloned = a.tx.clone();
+        let limiter = a.rate_limiter.clone();
         let callback = move |data: &[u8], src: SocketAddr, socket: &std::net::UdpSocket| {
-            App::accept_udp_packet(&tx_cloned, data, src, socket);
+            App::accept_udp_packet(&tx_cloned, data, src, socket, limiter.as_deref());
         };
         a.server.register_callback(Box::new(callback));
+
+        let tx_cloned = a.tx.clone();
+        let tcp_callback = move |data: &[u8], src: SocketAddr, connection: &TcpConnection| {
+            App::accept_tcp_message(&tx_cloned, data, src, connection);
+        };
+        a.server.register_tcp_callback(Box::new(tcp_callback));
         a
     }
 
+    /// Returns a sender for changes to the loaded zones, e.g. after a zone file was edited.
+    ///
+    /// Updates sent here are applied through `Database::replace_zone` and
+    /// `Database::remove_zone` before the next message is handled.
+    pub fn zone_sender(&self) -> Sender<ZoneUpdate> {
+        self.zone_tx.clone()
+    }
+
+    /// Records the file a zone was loaded from, so that dynamic updates to the zone are
+    /// written back to it.
+    pub fn set_zone_file(&mut self, origin: &str, path: PathBuf) {
+        self.zone_files.insert(origin.to_string(), path);
+    }
+
     pub fn run(&mut self) {
         self.server.run();
+        self.roll_keys();
+        for view in self.all_views() {
+            self.in_view(view, App::sign_loaded_zones);
+        }
+        self.refresh_tx = Some(secondary::spawn(&self.config, self.zone_tx.clone()));
         loop {
             self.process_message();
         }
@@ -49,25 +170,65 @@ impl App {
         Ok(msg)
     }
 
+    /// Passes a UDP packet on to the app, or answers it if it cannot be parsed. The
+    /// answer counts against the client's rate limit, if any.
     pub fn accept_udp_packet(
         tx: &Sender<Payload>,
         data: &[u8],
         src: SocketAddr,
-        _socket: &std::net::UdpSocket,
+        socket: &std::net::UdpSocket,
+        limiter: Option<&Mutex<RateLimiter>>,
     ) {
         match App::parse_dns_packet(data) {
             Err(e) => {
                 eprintln!("Failed to parse packet {:?}", e);
+                let decision = limiter.map_or(Decision::Send, |limiter| {
+                    App::lock(limiter).check_class(src.ip(), ResponseClass::Error, String::new(), Instant::now())
+                });
+                // The reply holds no records, so a truncated one would be no different.
+                if let (Some(reply), Decision::Send | Decision::Slip) = (App::unparsable_reply(data), decision) {
+                    let _ = socket.send_to(&reply, src);
+                }
+            }
+            Ok(msg) => {
+                let _ = tx.send((msg, data.to_vec(), src, Transport::Udp));
+            }
+        };
+    }
+
+    pub fn accept_tcp_message(tx: &Sender<Payload>, data: &[u8], src: SocketAddr, connection: &TcpConnection) {
+        match App::parse_dns_packet(data) {
+            Err(e) => {
+                eprintln!("Failed to parse TCP message {:?}", e);
+                if let Some(reply) = App::unparsable_reply(data) {
+                    let _ = connection.send(vec![reply]);
+                }
             }
             Ok(msg) => {
-                let _ = tx.send((msg, src));
+                let _ = tx.send((msg, data.to_vec(), src, Transport::Tcp(connection.clone())));
             }
         };
     }
 
-    fn process_message(&self) {
+    fn process_message(&mut self) {
+        for view in self.all_views() {
+            self.in_view(view, |app| app.apply_zone_updates(view.is_some()));
+        }
+        if self.last_signature_check.elapsed() >= SIGNATURE_CHECK_INTERVAL {
+            self.last_signature_check = Instant::now();
+            self.roll_keys();
+            for view in self.all_views() {
+                self.in_view(view, App::refresh_signatures);
+            }
+        }
+        if let Some(resolver) = &self.resolver
+            && self.last_cache_stats.elapsed() >= CACHE_STATS_INTERVAL
+        {
+            self.last_cache_stats = Instant::now();
+            println!("Resolver cache: {}", resolver.stats());
+        }
         match self.rx.try_recv() {
-            Ok((msg, src)) => self.handle_message(msg, src, self.server.socket()),
+            Ok((msg, bytes, src, transport)) => self.handle_message(msg, &bytes, src, transport),
             Err(std::sync::mpsc::TryRecvError::Empty) => {
                 // no message yet — do something else or sleep briefly
                 std::thread::sleep(std::time::Duration::from_millis(10));
@@ -76,45 +237,225 @@ impl App {
         }
     }
 
-    fn handle_message(&self, msg: Message, src: SocketAddr, socket: &std::net::UdpSocket) {
-        if msg.queries().is_empty() {
-            return;
+    /// Applies the changes sent through `zone_sender`. Views refuse the zones they cannot
+    /// serve, see `Config::check_view_zone`.
+    fn apply_zone_updates(&mut self, in_view: bool) {
+        while let Ok(update) = self.zone_rx.try_recv() {
+            match update {
+                ZoneUpdate::Replace(zone) => {
+                    let origin = zone.origin.clone();
+                    if in_view && let Err(e) = self.config.check_view_zone(&origin) {
+                        eprintln!("Failed to reload zone {}: {}", origin, e);
+                        continue;
+                    }
+                    let old_serial = self.database.get_zone(&origin).and_then(Zone::serial);
+                    match self.install_zone(zone) {
+                        Ok(()) => {
+                            println!("Reloaded zone {}", origin);
+                            if let Some(zone) = self.database.get_zone(&origin)
+                                && zone.serial() != old_serial
+                            {
+                                self.notify_secondaries(zone);
+                            }
+                        }
+                        Err(e) => eprintln!("Failed to reload zone {}: {}", origin, e),
+                    }
+                }
+                ZoneUpdate::Remove(origin) => {
+                    if self.database.remove_zone(&origin).is_some() {
+                        println!("Removed zone {}", origin);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Answers a message, checking its TSIG signature first. Responses to signed requests
+    /// are signed with the same key. The message is answered from the zones of the view
+    /// matching its source and key, if any, once the ACLs allow it. UDP responses are
+    /// subject to rate limiting.
+    fn handle_message(&mut self, msg: Message, bytes: &[u8], src: SocketAddr, transport: Transport) {
+        let now = tsig::now();
+        let verified = match tsig::verify_request(bytes, &self.config.keys, now) {
+            Ok(verified) => verified,
+            Err(failure) => {
+                eprintln!("Rejected request from {}: {}", src, failure.error);
+                let code = match failure.error {
+                    TsigError::Malformed => ResponseCode::FormErr,
+                    _ => ResponseCode::NotAuth,
+                };
+                let response = App::error_response(&msg, code);
+                if App::rate_limit(self.rate_limiter.as_deref(), &response, src, &transport) != Decision::Drop
+                    && let Ok(response) = response.to_bytes()
+                {
+                    App::send_bytes(self.server.socket(), &[failure.sign_response(&response, now)], src, &transport);
+                }
+                return;
+            }
+        };
+
+        let view = self.select_view(src.ip(), verified.as_ref().map(|verified| verified.key.name.as_str()));
+        self.in_view(view, |app| {
+            if let Err(e) = app.check_access(&msg, src, &transport) {
+                eprintln!("Refused request from {}: {}", src, e);
+                let signer = verified.map(|verified| TsigSigner::new(verified.key, Some(verified.mac)));
+                let response = App::error_response(&msg, ResponseCode::Refused);
+                App::send_responses(app.server.socket(), &msg, &[response], src, &transport, signer, app.rate_limiter.as_deref());
+                return;
+            }
+            if app.is_recursive(&msg) {
+                let signer = verified.map(|verified| TsigSigner::new(verified.key, Some(verified.mac)));
+                app.resolve_recursively(msg, src, transport, signer);
+                return;
+            }
+            let key = verified.as_ref().map(|verified| verified.key.name.as_str());
+            let responses = app.respond(&msg, src, &transport, key);
+            let signer = verified.map(|verified| TsigSigner::new(verified.key, Some(verified.mac)));
+            App::send_responses(app.server.socket(), &msg, &responses, src, &transport, signer, app.rate_limiter.as_deref());
+        });
+    }
+
+    /// Encodes the responses to `msg` and sends them. UDP responses go through the rate
+    /// limiter and are truncated when too large for the client, and responses are signed
+    /// when the request was.
+    fn send_responses(
+        socket: &UdpSocket,
+        msg: &Message,
+        responses: &[Message],
+        src: SocketAddr,
+        transport: &Transport,
+        mut signer: Option<TsigSigner>,
+        limiter: Option<&Mutex<RateLimiter>>,
+    ) {
+        let slipped;
+        let responses = match responses {
+            [response] => match App::rate_limit(limiter, response, src, transport) {
+                Decision::Send => responses,
+                Decision::Slip => {
+                    slipped = [App::truncated(response)];
+                    &slipped[..]
+                }
+                Decision::Drop => return,
+            },
+            _ => responses,
+        };
+        let now = tsig::now();
+        let max_udp_size = App::max_udp_size(msg);
+        let encoded: Vec<Vec<u8>> = responses
+            .iter()
+            .filter_map(|response| match (response.to_bytes(), transport) {
+                (Ok(bytes), Transport::Udp) if bytes.len() > max_udp_size => App::truncated(response).to_bytes().ok(),
+                (Ok(bytes), _) => Some(bytes),
+                (Err(e), _) => {
+                    eprintln!("Failed to encode response to {}: {}", src, e);
+                    None
+                }
+            })
+            .map(|response| match &mut signer {
+                Some(signer) => signer.sign(&response, now),
+                None => response,
+            })
+            .collect();
+        App::send_bytes(socket, &encoded, src, transport);
+    }
+
+    /// Dispatches a message on its type and opcode.
+    ///
+    /// Responses are dropped so that spoofed sources cannot bounce messages between
+    /// servers. Requests must carry exactly one question.
+    ///
+    /// `key` names the TSIG key the request was signed with, if any.
+    fn respond(&mut self, msg: &Message, src: SocketAddr, transport: &Transport, key: Option<&str>) -> Vec<Message> {
+        if msg.message_type() == MessageType::Response {
+            eprintln!("Dropping unsolicited response from {}", src);
+            return Vec::new();
+        }
+        if msg.queries().len() != 1 {
+            return vec![App::error_response(msg, ResponseCode::FormErr)];
+        }
+        match msg.op_code() {
+            OpCode::Query => self.handle_query(msg, src, transport, key),
+            OpCode::Notify => self.handle_notify(msg, src, key),
+            OpCode::Update => self.handle_update(msg, src, key),
+            OpCode::Status => vec![App::error_response(msg, ResponseCode::NotImp)],
+        }
+    }
+
+    /// Checks a UDP response against the rate limiter. TCP responses are always sent.
+    fn rate_limit(limiter: Option<&Mutex<RateLimiter>>, response: &Message, src: SocketAddr, transport: &Transport) -> Decision {
+        match (limiter, transport) {
+            (Some(limiter), Transport::Udp) => App::lock(limiter).check(src.ip(), response, Instant::now()),
+            _ => Decision::Send,
+        }
+    }
+
+    fn lock(limiter: &Mutex<RateLimiter>) -> MutexGuard<'_, RateLimiter> {
+        limiter.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Sends responses. TCP responses are handed to the connection's writer, so that a
+    /// client that stops reading does not hold up the app.
+    fn send_bytes(socket: &UdpSocket, responses: &[Vec<u8>], src: SocketAddr, transport: &Transport) {
+        match transport {
+            Transport::Udp => {
+                for response in responses {
+                    let _ = socket.send_to(response, src);
+                }
+            }
+            Transport::Tcp(connection) => {
+                if let Err(e) = connection.send(responses.to_vec()) {
+                    eprintln!("Failed to send TCP response to {}: {}", src, e);
+                }
+            }
         }
-        let question = &msg.queries()[0];
-        let fqdn = question.name().to_string();
-        let record_type = App::convert_record_type(question.query_type());
-        let result = self.database.lookup_resource_record(&fqdn, record_type);
+    }
 
+    /// Creates an empty authoritative response echoing the id, opcode and question of `msg`.
+    fn response_to(msg: &Message) -> Message {
         let mut response = Message::new();
         response
             .set_id(msg.id())
             .set_message_type(MessageType::Response)
-            .set_op_code(OpCode::Query)
+            .set_op_code(msg.op_code())
             .set_authoritative(true)
-            .add_query(question.clone());
-
-        match result {
-            Ok(Some(record_data)) => {
-                let mut record = Record::new();
-                record.set_name(Name::from_utf8(fqdn).unwrap());
-                record.set_ttl(3600);
-                record.set_rr_type(question.query_type());
-                let rdata = App::convert_record_data(record_data);
-                record.set_data(Some(rdata));
-                response.add_answer(record);
-            }
-            Ok(None) => {
-                response.set_response_code(ResponseCode::NXDomain);
-            }
-            Err(_) => {
-                response.set_response_code(ResponseCode::ServFail);
-            }
+            .add_queries(msg.queries().iter().cloned());
+        response
+    }
+
+    fn error_response(msg: &Message, code: ResponseCode) -> Message {
+        let mut response = App::response_to(msg);
+        response.set_response_code(code);
+        response
+    }
+
+    /// Builds the reply to a request that could not be parsed: NOTIMP for opcodes the
+    /// crate does not know, FORMERR otherwise. Returns `None` if `data` is too short to
+    /// hold a header or is itself a response.
+    fn unparsable_reply(data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < 12 || data[2] & 0x80 != 0 {
+            return None;
         }
+        let op_code = (data[2] >> 3) & 0x0f;
+        let response_code = match OpCode::from_u8(op_code) {
+            Ok(_) => ResponseCode::FormErr,
+            Err(_) => ResponseCode::NotImp,
+        };
+        let mut reply = vec![0; 12];
+        reply[..2].copy_from_slice(&data[..2]);
+        reply[2] = 0x80 | (op_code << 3) | (data[2] & 0x01);
+        reply[3] = response_code.low();
+        Some(reply)
+    }
 
-        let mut response_buffer = Vec::new();
-        let mut encoder = trust_dns_proto::serialize::binary::BinEncoder::new(&mut response_buffer);
-        response.emit(&mut encoder).unwrap();
-        let _ = socket.send_to(&response_buffer, src);
+    /// Builds a wire record of class IN.
+    fn to_record(name: &str, ttl: u32, record_data: &crate::db::RecordData) -> Record {
+        let rdata = App::convert_record_data(record_data);
+        let mut record = Record::new();
+        record.set_name(Name::from_utf8(name).unwrap());
+        record.set_ttl(ttl);
+        record.set_rr_type(rdata.to_record_type());
+        record.set_data(Some(rdata));
+        record
     }
 
     fn convert_record_type(
@@ -131,8 +472,64 @@ impl App {
             trust_dns_proto::rr::RecordType::SOA => crate::db::RecordType::SOA,
             trust_dns_proto::rr::RecordType::SRV => crate::db::RecordType::SRV,
             trust_dns_proto::rr::RecordType::TXT => crate::db::RecordType::TXT,
-            _ => unimplemented!(),
+            other => crate::db::RecordType::Unknown(u16::from(other)),
+        }
+    }
+
+    /// Converts a record received from another server, e.g. in a zone transfer.
+    ///
+    /// Types the crate does not model are kept as opaque RDATA. Returns `None` for records
+    /// without data or outside class IN.
+    pub fn from_wire_record(record: &Record) -> Option<ResourceRecord> {
+        if record.dns_class() != trust_dns_proto::rr::DNSClass::IN {
+            return None;
         }
+        Some(ResourceRecord {
+            name: record.name().to_string(),
+            ttl: record.ttl(),
+            class: crate::db::record::RecordClass::IN,
+            data: App::from_wire_data(record)?,
+        })
+    }
+
+    /// Converts the RDATA of a wire record, whatever its class.
+    fn from_wire_data(record: &Record) -> Option<RecordData> {
+        let data = match record.data()? {
+            RData::A(addr) => RecordData::A(*addr),
+            RData::AAAA(addr) => RecordData::AAAA(*addr),
+            RData::CNAME(name) => RecordData::CNAME(name.to_string()),
+            RData::MX(mx) => RecordData::MX {
+                preference: mx.preference(),
+                exchange: mx.exchange().to_string(),
+            },
+            RData::NS(name) => RecordData::NS(name.to_string()),
+            RData::PTR(name) => RecordData::PTR(name.to_string()),
+            RData::SOA(soa) => RecordData::SOA {
+                mname: soa.mname().to_string(),
+                rname: soa.rname().to_string(),
+                serial: soa.serial(),
+                refresh: soa.refresh() as u32,
+                retry: soa.retry() as u32,
+                expire: soa.expire() as u32,
+                minimum: soa.minimum(),
+            },
+            RData::SRV(srv) => RecordData::SRV {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                port: srv.port(),
+                target: srv.target().to_string(),
+            },
+            RData::TXT(txt) => RecordData::TXT(
+                txt.txt_data().iter().map(|part| String::from_utf8_lossy(part).into_owned()).collect(),
+            ),
+            other => {
+                let mut data = Vec::new();
+                let mut encoder = trust_dns_proto::serialize::binary::BinEncoder::new(&mut data);
+                other.emit(&mut encoder).ok()?;
+                RecordData::Unknown { rtype: u16::from(record.record_type()), data }
+            }
+        };
+        Some(data)
     }
 
     fn convert_record_data(record_data: &crate::db::RecordData) -> RData {
@@ -177,8 +574,12 @@ impl App {
                 Name::from_utf8(target).unwrap(),
             )),
             crate::db::RecordData::TXT(txt) => {
-                RData::TXT(trust_dns_proto::rr::rdata::TXT::new(vec![txt.clone()]))
+                RData::TXT(trust_dns_proto::rr::rdata::TXT::new(txt.clone()))
             }
+            crate::db::RecordData::Unknown { rtype, data } => RData::Unknown {
+                code: *rtype,
+                rdata: trust_dns_proto::rr::rdata::NULL::with(data.clone()),
+            },
         }
     }
 }
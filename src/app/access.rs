@@ -0,0 +1,61 @@
+use std::net::SocketAddr;
+
+use trust_dns_proto::op::{Message, MessageType, OpCode};
+use trust_dns_proto::rr::RecordType;
+
+use super::{App, Transport};
+use crate::acl::{Access, Operation};
+
+impl App {
+    /// Warns about ACLs the configuration refers to but does not define. They hold no
+    /// client, so the operations they apply to are refused.
+    pub(super) fn warn_undefined_acls(&self) {
+        let levels = std::iter::once(&self.config.access)
+            .chain([&self.config.server.udp, &self.config.server.tcp])
+            .chain(self.config.zones.iter().map(|zone| &zone.access));
+        for name in levels.flat_map(Access::names) {
+            if !self.acls.is_defined(name) {
+                eprintln!("ACL {} is not defined, no client is in it", name);
+            }
+        }
+    }
+
+    /// Returns what a request asks the server to do, or `None` for messages the ACLs do
+    /// not apply to.
+    fn operation(&self, msg: &Message) -> Option<Operation> {
+        let [question] = msg.queries() else {
+            return None;
+        };
+        if msg.message_type() == MessageType::Response {
+            return None;
+        }
+        match msg.op_code() {
+            OpCode::Query if matches!(question.query_type(), RecordType::AXFR | RecordType::IXFR) => Some(Operation::Transfer),
+            OpCode::Query if self.is_recursive(msg) => Some(Operation::Recursion),
+            OpCode::Query => Some(Operation::Query),
+            OpCode::Update => Some(Operation::Update),
+            OpCode::Notify => Some(Operation::Notify),
+            OpCode::Status => None,
+        }
+    }
+
+    /// Checks a request against the global ACLs, those of the listener it came in on,
+    /// and those of the zone it is for. Recursive queries are for no zone.
+    pub(super) fn check_access(&self, msg: &Message, src: SocketAddr, transport: &Transport) -> Result<(), String> {
+        let Some(operation) = self.operation(msg) else {
+            return Ok(());
+        };
+        let name = msg.queries()[0].name().to_string();
+        let mut levels = vec![("global", &self.config.access)];
+        levels.push(match transport {
+            Transport::Udp => ("UDP listener", &self.config.server.udp),
+            Transport::Tcp(_) => ("TCP listener", &self.config.server.tcp),
+        });
+        if let Some(zone) = self.config.zone_holding(&name).filter(|_| operation != Operation::Recursion) {
+            levels.push(("zone", &zone.access));
+        }
+        self.acls
+            .check(&levels, operation, src.ip())
+            .map_err(|level| format!("{} for {} is not allowed by the {} ACLs", operation, name, level))
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, Record, RecordType};
+use trust_dns_proto::serialize::binary::BinEncodable;
+
+use super::App;
+use crate::db::validation::normalize;
+use crate::db::{RecordData, Zone};
+use crate::tsig::{self, TsigKey, TsigSigner, TsigVerifier};
+
+/// Port NOTIFY messages are sent to on name servers of a zone.
+const DNS_PORT: u16 = 53;
+
+/// How long to wait for a secondary to acknowledge a NOTIFY before sending it again.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often a NOTIFY is sent before giving up on a secondary.
+const NOTIFY_ATTEMPTS: u32 = 5;
+
+impl App {
+    /// Answers a NOTIFY (RFC 1996) by refreshing the secondary zone it names right away.
+    ///
+    /// Only NOTIFY messages from one of the zone's configured primaries are accepted, and
+    /// only if signed with the zone's `primary_key` when it has one.
+    pub(super) fn handle_notify(&self, msg: &Message, src: SocketAddr, key: Option<&str>) -> Vec<Message> {
+        let mut response = App::response_to(msg);
+
+        let origin = msg.queries()[0].name().to_string();
+        match self.config.zone(&origin).filter(|zone| zone.is_secondary()) {
+            None => {
+                response.set_response_code(ResponseCode::NotAuth);
+            }
+            Some(zone) if !zone.primaries.iter().any(|primary| primary.ip() == src.ip()) => {
+                eprintln!("Ignoring NOTIFY for {} from {}: not a primary", zone.origin, src);
+                response.set_response_code(ResponseCode::Refused);
+            }
+            Some(zone)
+                if zone
+                    .primary_key
+                    .as_ref()
+                    .is_some_and(|primary_key| !key.is_some_and(|key| key.eq_ignore_ascii_case(primary_key))) =>
+            {
+                eprintln!("Ignoring NOTIFY for {} from {}: not signed with the primary key", zone.origin, src);
+                response.set_response_code(ResponseCode::Refused);
+            }
+            Some(zone) => {
+                println!("Received NOTIFY for {} from {}", zone.origin, src);
+                if let Some(refresh_tx) = &self.refresh_tx {
+                    let _ = refresh_tx.send(zone.origin.clone());
+                }
+            }
+        }
+        vec![response]
+    }
+
+    /// Sends NOTIFY for `zone` to its name servers and the zone's `also_notify` addresses,
+    /// each from its own thread.
+    pub(super) fn notify_secondaries(&self, zone: &Zone) {
+        let Some(soa) = zone.soa() else {
+            return;
+        };
+        let config = self.config.zone(&zone.origin);
+        let also_notify = config.map_or(&[][..], |config| &config.also_notify[..]);
+        let key = config.and_then(|config| config.notify_key.as_deref()).and_then(|name| self.config.key(name));
+        for target in notify_targets(zone, also_notify) {
+            let origin = zone.origin.clone();
+            let soa = App::to_wire_record(soa);
+            let key = key.cloned();
+            thread::spawn(move || {
+                if let Err(e) = send_notify(&origin, soa, target, key) {
+                    eprintln!("Failed to notify {} of changes to {}: {}", target, origin, e);
+                }
+            });
+        }
+    }
+}
+
+/// Returns the addresses to notify of changes to `zone`: the in-zone addresses of its
+/// name servers other than the primary named in the SOA record (RFC 1996, section 3.6),
+/// followed by `also_notify`.
+pub fn notify_targets(zone: &Zone, also_notify: &[SocketAddr]) -> Vec<SocketAddr> {
+    let origin = normalize(&zone.origin);
+    let primary = match zone.soa().map(|soa| &soa.data) {
+        Some(RecordData::SOA { mname, .. }) => normalize(mname),
+        _ => String::new(),
+    };
+    let name_servers = zone.records.iter().filter_map(|record| match &record.data {
+        RecordData::NS(host) if normalize(&record.name) == origin && normalize(host) != primary => Some(normalize(host)),
+        _ => None,
+    });
+
+    let mut targets: Vec<SocketAddr> = Vec::new();
+    for host in name_servers {
+        for record in zone.records.iter().filter(|record| normalize(&record.name) == host) {
+            let addr: IpAddr = match record.data {
+                RecordData::A(addr) => addr.into(),
+                RecordData::AAAA(addr) => addr.into(),
+                _ => continue,
+            };
+            targets.push(SocketAddr::new(addr, DNS_PORT));
+        }
+    }
+    targets.extend_from_slice(also_notify);
+    let mut seen = HashSet::new();
+    targets.retain(|target| seen.insert(*target));
+    targets
+}
+
+/// Sends a NOTIFY carrying the zone's SOA record to `target` over UDP until it is
+/// acknowledged, up to `NOTIFY_ATTEMPTS` times. With a `key`, the NOTIFY is signed and the
+/// acknowledgement must be too.
+fn send_notify(origin: &str, soa: Record, target: SocketAddr, key: Option<TsigKey>) -> Result<(), String> {
+    let mut msg = Message::new();
+    msg.set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Notify)
+        .set_authoritative(true)
+        .add_query(Query::query(Name::from_utf8(origin).map_err(|e| e.to_string())?, RecordType::SOA))
+        .add_answer(soa);
+    let mut bytes = msg.to_bytes().map_err(|e| e.to_string())?;
+    let mut verifier = None;
+    if let Some(key) = key {
+        let mut signer = TsigSigner::new(key.clone(), None);
+        bytes = signer.sign(&bytes, tsig::now());
+        verifier = signer.mac().map(|mac| TsigVerifier::new(key, mac.to_vec()));
+    }
+
+    let local: SocketAddr = match target {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(NOTIFY_TIMEOUT)).map_err(|e| e.to_string())?;
+
+    let mut buf = [0; 4096];
+    for _ in 0..NOTIFY_ATTEMPTS {
+        socket.send_to(&bytes, target).map_err(|e| e.to_string())?;
+        while let Ok((len, src)) = socket.recv_from(&mut buf) {
+            let Ok(response) = Message::from_vec(&buf[..len]) else {
+                continue;
+            };
+            if src != target || response.id() != msg.id() || response.op_code() != OpCode::Notify {
+                continue;
+            }
+            if let Some(verifier) = &mut verifier {
+                verifier.verify(&buf[..len], tsig::now()).map_err(|e| e.to_string())?;
+            }
+            return match response.response_code() {
+                ResponseCode::NoError => Ok(()),
+                code => Err(format!("NOTIFY answered with {}", code)),
+            };
+        }
+    }
+    Err(format!("no answer after {} attempts", NOTIFY_ATTEMPTS))
+}
@@ -0,0 +1,245 @@
+use std::net::SocketAddr;
+
+use trust_dns_proto::op::{Edns, Message, ResponseCode};
+use trust_dns_proto::rr::RecordType as WireType;
+
+use super::{App, Transport};
+use crate::db::validation::{is_in_zone, normalize};
+use crate::db::{RecordData, RecordType, ResourceRecord, Zone, ZoneIndex};
+use crate::dnssec::denial::Denial;
+use crate::dnssec;
+
+/// How many CNAME records are followed within a zone before giving up.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// The UDP payload size advertised in responses to EDNS queries (RFC 9715).
+pub(super) const EDNS_PAYLOAD: u16 = 1232;
+
+impl App {
+    /// Answers a standard query from the zone the name belongs to.
+    ///
+    /// When the query has the DNSSEC OK bit set, each RRset in the response comes with
+    /// the RRSIG records covering it, and negative and wildcard answers with the NSEC or
+    /// NSEC3 records proving them.
+    pub(super) fn handle_query(&self, msg: &Message, src: SocketAddr, transport: &Transport, key: Option<&str>) -> Vec<Message> {
+        let question = &msg.queries()[0];
+        match question.query_type() {
+            WireType::AXFR => return self.handle_axfr(msg, src, transport, key),
+            WireType::IXFR => return self.handle_ixfr(msg, src, transport, key),
+            _ => {}
+        }
+
+        let mut response = App::response_to(msg);
+        let dnssec_ok = msg.extensions().as_ref().is_some_and(Edns::dnssec_ok);
+        if msg.extensions().is_some() {
+            let mut edns = Edns::new();
+            edns.set_max_payload(EDNS_PAYLOAD).set_dnssec_ok(dnssec_ok);
+            response.set_edns(edns);
+        }
+
+        let qname = question.name().to_string();
+        match self.database.find_zone(&qname) {
+            Some(zone) => match self.database.zone_index(&zone.origin) {
+                Some(index) => {
+                    let qtype = App::convert_record_type(question.query_type());
+                    let answer = Answer::lookup(zone, index, &qname, &qtype);
+                    answer.write(zone, index, dnssec_ok, &mut response);
+                }
+                None => {
+                    eprintln!("Zone {} has no index", zone.origin);
+                    response.set_response_code(ResponseCode::ServFail);
+                }
+            },
+            None => {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+        }
+        vec![response]
+    }
+
+    /// Returns the largest UDP response the client that sent `msg` accepts.
+    pub(super) fn max_udp_size(msg: &Message) -> usize {
+        match msg.extensions() {
+            Some(edns) => edns.max_payload().clamp(512, EDNS_PAYLOAD) as usize,
+            None => 512,
+        }
+    }
+
+    /// Empties a response that is too large for UDP and sets its TC bit, so that the
+    /// client retries over TCP.
+    pub(super) fn truncated(response: &Message) -> Message {
+        let mut truncated = response.clone();
+        truncated.set_truncated(true);
+        truncated.take_answers();
+        truncated.take_name_servers();
+        truncated.take_additionals();
+        truncated
+    }
+}
+
+/// What a zone says about a name and type.
+enum Answer<'z> {
+    /// The RRsets answering the question, possibly after following CNAME records.
+    Records(Vec<Found<'z>>),
+    /// The name is delegated to a child zone with these NS records.
+    Referral(Vec<&'z ResourceRecord>),
+    /// The name exists, or a wildcard matches it, but has no data of the type.
+    NoData { name: String, wildcard: Option<String> },
+    /// The name does not exist.
+    NxDomain(String),
+}
+
+/// An RRset answering for a name, found at the name itself or at a wildcard matching it.
+struct Found<'z> {
+    name: String,
+    wildcard: Option<String>,
+    records: Vec<&'z ResourceRecord>,
+}
+
+impl<'z> Answer<'z> {
+    fn lookup(zone: &'z Zone, index: &ZoneIndex, qname: &str, qtype: &RecordType) -> Answer<'z> {
+        let mut name = normalize(qname);
+        let mut found: Vec<Found> = Vec::new();
+        for _ in 0..MAX_CNAME_CHAIN {
+            // The highest delegation point at or above the name decides, except that DS
+            // records at a delegation point belong to the parent.
+            if let Some(cut) = index.delegation(&name, *qtype != RecordType::Unknown(dnssec::DS)) {
+                if !found.is_empty() {
+                    break;
+                }
+                return Answer::Referral(index.rrset(zone, cut, &RecordType::NS));
+            }
+
+            // A name that does not exist is answered from the wildcard at its closest
+            // encloser, if there is one (RFC 4592).
+            let wildcard = if index.name_exists(&name) {
+                None
+            } else {
+                let wildcard = format!("*.{}", index.closest_encloser(&name));
+                match index.name_exists(&wildcard) {
+                    true => Some(wildcard),
+                    false if found.is_empty() => return Answer::NxDomain(name),
+                    false => break,
+                }
+            };
+            let owner = wildcard.as_deref().unwrap_or(&name);
+
+            let matching = index.rrset(zone, owner, qtype);
+            if !matching.is_empty() {
+                found.push(Found { name, wildcard, records: matching });
+                break;
+            }
+            let cname = index.rrset(zone, owner, &RecordType::CNAME);
+            match cname.first().map(|record| &record.data) {
+                Some(RecordData::CNAME(target)) => {
+                    let target = normalize(target);
+                    found.push(Found { name, wildcard, records: cname });
+                    if !is_in_zone(&target, &normalize(&zone.origin)) {
+                        break;
+                    }
+                    name = target;
+                }
+                _ if !found.is_empty() => break,
+                _ => return Answer::NoData { name, wildcard },
+            }
+        }
+        Answer::Records(found)
+    }
+
+    /// Fills in the response. When `dnssec_ok` is set, signatures are added, along with
+    /// the NSEC or NSEC3 records proving negative and wildcard answers.
+    fn write(&self, zone: &'z Zone, index: &ZoneIndex, dnssec_ok: bool, response: &mut Message) {
+        let with_signatures = |rrset: &[&'z ResourceRecord]| {
+            let mut records: Vec<&ResourceRecord> = rrset.to_vec();
+            if dnssec_ok {
+                records.extend(signatures(zone, index, rrset));
+            }
+            records.into_iter().map(App::to_wire_record)
+        };
+        let proof = |denial: Denial| {
+            let records = match dnssec_ok {
+                true => index.denial_records(zone, &denial),
+                false => Vec::new(),
+            };
+            records.into_iter().flat_map(|record| with_signatures(&[record]).collect::<Vec<_>>())
+        };
+        match self {
+            Answer::Records(found) => {
+                for rrset in found {
+                    // Records from a wildcard, and their signatures, take the name asked for.
+                    let mut records: Vec<ResourceRecord> = rrset.records.iter().map(|record| (*record).clone()).collect();
+                    if dnssec_ok {
+                        records.extend(signatures(zone, index, &rrset.records).into_iter().cloned());
+                    }
+                    if rrset.wildcard.is_some() {
+                        records.iter_mut().for_each(|record| record.name = rrset.name.clone());
+                    }
+                    response.add_answers(records.iter().map(App::to_wire_record));
+                    if let Some(wildcard) = &rrset.wildcard {
+                        response.add_name_servers(proof(Denial::Wildcard { name: &rrset.name, wildcard }));
+                    }
+                }
+            }
+            Answer::Referral(ns) => {
+                response.set_authoritative(false);
+                response.add_name_servers(ns.iter().map(|record| App::to_wire_record(record)));
+                if dnssec_ok && let Some(cut) = ns.first() {
+                    let cut = normalize(&cut.name);
+                    let ds = index.rrset(zone, &cut, &RecordType::Unknown(dnssec::DS));
+                    match ds.is_empty() {
+                        // An unsigned delegation comes with the proof that it has no DS.
+                        true => response.add_name_servers(proof(Denial::NoData(&cut))),
+                        false => response.add_name_servers(with_signatures(&ds)),
+                    };
+                }
+                response.add_additionals(glue(zone, index, ns).into_iter().map(App::to_wire_record));
+            }
+            Answer::NoData { .. } | Answer::NxDomain(_) => {
+                if matches!(self, Answer::NxDomain(_)) {
+                    response.set_response_code(ResponseCode::NXDomain);
+                }
+                if let Some(soa) = zone.soa() {
+                    // Negative answers may be cached for the SOA minimum (RFC 2308).
+                    let ttl = match soa.data {
+                        RecordData::SOA { minimum, .. } => soa.ttl.min(minimum),
+                        _ => soa.ttl,
+                    };
+                    let mut soa = soa.clone();
+                    soa.ttl = ttl;
+                    response.add_name_server(App::to_wire_record(&soa));
+                    if dnssec_ok {
+                        let origin = normalize(&zone.origin);
+                        response.add_name_servers(index.signatures(zone, &origin, RecordType::SOA.code()).into_iter().map(App::to_wire_record));
+                    }
+                }
+                let denial = match self {
+                    Answer::NoData { name, wildcard: Some(wildcard) } => Denial::WildcardNoData { name, wildcard },
+                    Answer::NoData { name, wildcard: None } => Denial::NoData(name),
+                    Answer::NxDomain(name) => Denial::NxDomain(name),
+                    _ => unreachable!(),
+                };
+                response.add_name_servers(proof(denial));
+            }
+        }
+    }
+}
+
+/// Returns the RRSIG records covering an RRset.
+fn signatures<'z>(zone: &'z Zone, index: &ZoneIndex, rrset: &[&ResourceRecord]) -> Vec<&'z ResourceRecord> {
+    match rrset.first() {
+        Some(record) => index.signatures(zone, &normalize(&record.name), record.data.get_type().code()),
+        None => Vec::new(),
+    }
+}
+
+/// Returns the address records in the zone for the targets of NS records.
+fn glue<'z>(zone: &'z Zone, index: &ZoneIndex, ns: &[&ResourceRecord]) -> Vec<&'z ResourceRecord> {
+    let targets = ns.iter().filter_map(|record| match &record.data {
+        RecordData::NS(target) => Some(normalize(target)),
+        _ => None,
+    });
+    targets
+        .flat_map(|target| [index.rrset(zone, &target, &RecordType::A), index.rrset(zone, &target, &RecordType::AAAA)])
+        .flatten()
+        .collect()
+}
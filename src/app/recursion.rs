@@ -0,0 +1,182 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, ResponseCode};
+use trust_dns_proto::rr::{RData, Record, RecordType};
+
+use super::query::EDNS_PAYLOAD;
+use super::{App, Transport};
+use crate::db::ResourceRecord;
+use crate::resolver::policy::{Action, Hit, Policies};
+use crate::resolver::Resolver;
+use crate::tsig::TsigSigner;
+
+/// How many queries may be resolved at once. Further ones are answered with SERVFAIL.
+const MAX_RESOLUTIONS: usize = 100;
+
+impl App {
+    /// Returns whether a query is for the resolver: it asks for recursion and no local
+    /// zone holds the name.
+    pub(super) fn is_recursive(&self, msg: &Message) -> bool {
+        let [question] = msg.queries() else {
+            return false;
+        };
+        self.resolver.is_some()
+            && msg.message_type() == MessageType::Query
+            && msg.op_code() == OpCode::Query
+            && msg.recursion_desired()
+            && !matches!(question.query_type(), RecordType::AXFR | RecordType::IXFR)
+            && self.database.find_zone(&question.name().to_string()).is_none()
+    }
+
+    /// Resolves a recursive query on a thread of its own, which sends the response and
+    /// then refreshes popular cache entries, so that slow servers do not hold up other
+    /// clients. Clients outside `allow_recursion` are refused.
+    pub(super) fn resolve_recursively(&self, msg: Message, src: SocketAddr, transport: Transport, signer: Option<TsigSigner>) {
+        let Some(resolver) = &self.resolver else {
+            return;
+        };
+        let refusal = if !resolver.config().allows(src.ip()) {
+            Some(ResponseCode::Refused)
+        } else if self.resolutions.fetch_add(1, Ordering::SeqCst) >= MAX_RESOLUTIONS {
+            self.resolutions.fetch_sub(1, Ordering::SeqCst);
+            Some(ResponseCode::ServFail)
+        } else {
+            None
+        };
+        if let Some(code) = refusal {
+            let mut response = App::recursive_response_to(&msg);
+            response.set_response_code(code);
+            App::send_responses(self.server.socket(), &msg, &[response], src, &transport, signer, self.rate_limiter.as_deref());
+            return;
+        }
+
+        let socket = match self.server.socket().try_clone() {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("Failed to clone UDP socket: {}", e);
+                self.resolutions.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+        };
+        let resolver = Arc::clone(resolver);
+        let resolutions = Arc::clone(&self.resolutions);
+        thread::spawn(move || {
+            // Resolved answers go to clients `allow_recursion` knows, so they are not
+            // rate limited.
+            if let Some(response) = App::resolve(&resolver, &msg, src.ip()) {
+                App::send_responses(&socket, &msg, &[response], src, &transport, signer, None);
+            }
+            resolver.prefetch();
+            resolutions.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    /// Resolves a query, applying the response policies: first to the name asked for,
+    /// then to the names in the CNAME chain and the addresses in the answer. Returns
+    /// `None` when a policy drops the query.
+    fn resolve(resolver: &Resolver, msg: &Message, client: IpAddr) -> Option<Message> {
+        let question = &msg.queries()[0];
+        let name = question.name().to_string();
+        let (rtype, dnssec_ok) = (question.query_type(), App::dnssec_ok(msg));
+        let policies = resolver.policies();
+        let hit = policies.check_name(&name, client);
+        if let Some(hit) = &hit {
+            App::log_policy(hit, &name, client);
+            if hit.action != Action::Passthru {
+                return App::apply_policy(resolver, msg, &hit.action, &name, Vec::new());
+            }
+        }
+
+        let resolution = resolver.resolve(&name, rtype, dnssec_ok);
+        if hit.is_none()
+            && let Some((hit, target, kept)) = App::check_resolution(policies, &resolution.answers, &name, client)
+        {
+            App::log_policy(&hit, &target, client);
+            if hit.action != Action::Passthru {
+                return App::apply_policy(resolver, msg, &hit.action, &target, kept);
+            }
+        }
+        let mut response = App::recursive_response_to(msg);
+        response
+            .set_response_code(resolution.code)
+            .add_answers(resolution.answers)
+            .add_name_servers(resolution.authority);
+        Some(response)
+    }
+
+    /// Returns the policy that applies to a resolved answer, the name it applies to and
+    /// the CNAME records leading to that name, which are kept in the response.
+    fn check_resolution(policies: &Policies, answers: &[Record], name: &str, client: IpAddr) -> Option<(Hit, String, Vec<Record>)> {
+        for (i, record) in answers.iter().enumerate() {
+            if let Some(RData::CNAME(target)) = record.data()
+                && let Some(hit) = policies.check_name(&target.to_string(), client)
+            {
+                return Some((hit, target.to_string(), answers[..=i].to_vec()));
+            }
+        }
+        let hit = policies.check_answers(answers, client)?;
+        Some((hit, name.to_string(), Vec::new()))
+    }
+
+    /// Answers a query as a policy says, after the `kept` answers. Local data replaces the
+    /// records of `name`, and a CNAME record in it is followed.
+    fn apply_policy(resolver: &Resolver, msg: &Message, action: &Action, name: &str, kept: Vec<Record>) -> Option<Message> {
+        let question = &msg.queries()[0];
+        let rtype = question.query_type();
+        let mut response = App::recursive_response_to(msg);
+        response.add_answers(kept);
+        match action {
+            Action::Drop => return None,
+            Action::Nxdomain => {
+                response.set_response_code(ResponseCode::NXDomain);
+            }
+            Action::Nodata | Action::Passthru => {}
+            Action::LocalData(records) => {
+                let local: Vec<Record> = records
+                    .iter()
+                    .filter(|record| {
+                        let code = record.data.get_type().code();
+                        rtype == RecordType::ANY || code == u16::from(rtype) || code == u16::from(RecordType::CNAME)
+                    })
+                    .map(|record| App::to_wire_record(&ResourceRecord { name: name.to_string(), ..record.clone() }))
+                    .collect();
+                let target = local.iter().find_map(|record| match record.data() {
+                    Some(RData::CNAME(target)) if rtype != RecordType::CNAME => Some(target.to_string()),
+                    _ => None,
+                });
+                response.add_answers(local);
+                if let Some(target) = target {
+                    let resolution = resolver.resolve(&target, rtype, App::dnssec_ok(msg));
+                    response
+                        .set_response_code(resolution.code)
+                        .add_answers(resolution.answers)
+                        .add_name_servers(resolution.authority);
+                }
+            }
+        }
+        Some(response)
+    }
+
+    fn log_policy(hit: &Hit, name: &str, client: IpAddr) {
+        println!("Policy {} ({}): {} for {} from {}", hit.policy, hit.trigger, hit.action, name, client);
+    }
+
+    /// Creates an empty non-authoritative response to a recursive query.
+    fn recursive_response_to(msg: &Message) -> Message {
+        let mut response = App::response_to(msg);
+        response.set_authoritative(false).set_recursion_desired(true).set_recursion_available(true);
+        if msg.extensions().is_some() {
+            let mut edns = Edns::new();
+            edns.set_max_payload(EDNS_PAYLOAD).set_dnssec_ok(App::dnssec_ok(msg));
+            response.set_edns(edns);
+        }
+        response
+    }
+
+    fn dnssec_ok(msg: &Message) -> bool {
+        msg.extensions().as_ref().is_some_and(Edns::dnssec_ok)
+    }
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::serialize::binary::BinEncodable;
+
+use super::{App, ZoneUpdate};
+use crate::config::{Config, ZoneConfig};
+use crate::db::journal::serial_lt;
+use crate::db::{RecordData, ResourceRecord, Zone};
+use crate::server::Server;
+use crate::tsig::{self, TsigKey, TsigSigner, TsigVerifier};
+
+/// How long to wait for a primary to answer a SOA query.
+const SOA_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait for each message of a zone transfer.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Retry interval for a secondary zone that has never been transferred, and so has no SOA
+/// retry timer yet.
+const INITIAL_RETRY: Duration = Duration::from_secs(10);
+
+/// State of a single secondary zone.
+struct SecondaryZone {
+    config: ZoneConfig,
+    /// The key requests to the primaries are signed with.
+    key: Option<TsigKey>,
+    zone: Option<Zone>,
+    next_refresh: Instant,
+    expires_at: Option<Instant>,
+}
+
+/// The result of asking a primary for a newer version of a zone.
+enum Refresh {
+    UpToDate,
+    Transferred(Zone),
+}
+
+/// Starts keeping the secondary zones in `config` in sync with their primaries.
+///
+/// Each zone is transferred immediately and then refreshed according to the timers in its
+/// SOA record (RFC 1035, section 4.3.5). New versions are handed to the app through
+/// `updates`; a zone that could not be refreshed before its expire timer ran out is
+/// removed. Sending a zone origin to the returned channel refreshes that zone right away.
+pub fn spawn(config: &Config, updates: Sender<ZoneUpdate>) -> Sender<String> {
+    let (refresh_tx, refresh_rx) = mpsc::channel::<String>();
+    let now = Instant::now();
+    let mut secondaries: HashMap<String, SecondaryZone> = config
+        .zones
+        .iter()
+        .filter(|zone| zone.is_secondary())
+        .map(|zone| {
+            let state = SecondaryZone {
+                config: zone.clone(),
+                key: zone.primary_key.as_deref().and_then(|name| config.key(name)).cloned(),
+                zone: None,
+                next_refresh: now,
+                expires_at: None,
+            };
+            (zone.origin.clone(), state)
+        })
+        .collect();
+    if secondaries.is_empty() {
+        return refresh_tx;
+    }
+
+    thread::spawn(move || loop {
+        let now = Instant::now();
+        for state in secondaries.values_mut() {
+            if state.next_refresh <= now
+                && let Some(zone) = state.refresh()
+                && updates.send(ZoneUpdate::Replace(zone)).is_err()
+            {
+                return;
+            }
+            if state.expires_at.is_some_and(|expires_at| expires_at <= Instant::now()) {
+                eprintln!("Secondary zone {} expired", state.config.origin);
+                state.zone = None;
+                state.expires_at = None;
+                if updates.send(ZoneUpdate::Remove(state.config.origin.clone())).is_err() {
+                    return;
+                }
+            }
+        }
+
+        let next = secondaries
+            .values()
+            .flat_map(|state| [Some(state.next_refresh), state.expires_at])
+            .flatten()
+            .min()
+            .unwrap_or(now + INITIAL_RETRY);
+        let wait = next.saturating_duration_since(Instant::now());
+        match refresh_rx.recv_timeout(wait) {
+            Ok(origin) => {
+                if let Some(state) = secondaries.get_mut(&origin) {
+                    state.next_refresh = Instant::now();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => thread::sleep(wait),
+        }
+    });
+    refresh_tx
+}
+
+impl SecondaryZone {
+    /// Asks the primaries in turn for a newer version of the zone and restarts the refresh
+    /// timer. Returns the new version if one was transferred.
+    fn refresh(&mut self) -> Option<Zone> {
+        let origin = &self.config.origin;
+        for primary in &self.config.primaries {
+            match fetch(origin, self.zone.as_ref(), *primary, self.key.as_ref()) {
+                Ok(refresh) => {
+                    let transferred = match refresh {
+                        Refresh::UpToDate => None,
+                        Refresh::Transferred(zone) => {
+                            println!(
+                                "Transferred zone {} serial {} from {}",
+                                origin,
+                                zone.serial().unwrap_or_default(),
+                                primary
+                            );
+                            self.zone = Some(zone.clone());
+                            Some(zone)
+                        }
+                    };
+                    let (refresh, _, expire) = self.timers();
+                    self.next_refresh = Instant::now() + refresh;
+                    self.expires_at = Some(Instant::now() + expire);
+                    return transferred;
+                }
+                Err(e) => eprintln!("Failed to refresh zone {} from {}: {}", origin, primary, e),
+            }
+        }
+        let (_, retry, _) = self.timers();
+        self.next_refresh = Instant::now() + retry;
+        None
+    }
+
+    /// Returns the refresh, retry and expire timers from the zone's SOA record.
+    fn timers(&self) -> (Duration, Duration, Duration) {
+        match self.zone.as_ref().and_then(Zone::soa).map(|soa| &soa.data) {
+            Some(RecordData::SOA {
+                refresh, retry, expire, ..
+            }) => (
+                Duration::from_secs(u64::from(*refresh)),
+                Duration::from_secs(u64::from(*retry)),
+                Duration::from_secs(u64::from(*expire)),
+            ),
+            _ => (INITIAL_RETRY, INITIAL_RETRY, INITIAL_RETRY),
+        }
+    }
+}
+
+/// Fetches a newer version of a zone from `primary`, if it has one.
+///
+/// A zone that is already loaded is only transferred when the primary's serial is newer,
+/// and then incrementally where the primary supports it.
+fn fetch(origin: &str, current: Option<&Zone>, primary: SocketAddr, key: Option<&TsigKey>) -> Result<Refresh, String> {
+    if let Some(serial) = current.and_then(Zone::serial) {
+        let primary_serial = query_serial(origin, primary, key)?;
+        if !serial_lt(serial, primary_serial) {
+            return Ok(Refresh::UpToDate);
+        }
+    }
+    transfer(origin, current, primary, key)
+}
+
+fn request(origin: &str, record_type: RecordType) -> Result<Message, String> {
+    let name = Name::from_utf8(origin).map_err(|e| e.to_string())?;
+    let mut msg = Message::new();
+    msg.set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .add_query(Query::query(name, record_type));
+    Ok(msg)
+}
+
+/// Encodes a request, signing it if there is a key. Returns the bytes to send and the
+/// verifier for the responses.
+fn encode_request(msg: &Message, key: Option<&TsigKey>) -> Result<(Vec<u8>, Option<TsigVerifier>), String> {
+    let bytes = msg.to_bytes().map_err(|e| e.to_string())?;
+    let Some(key) = key else {
+        return Ok((bytes, None));
+    };
+    let mut signer = TsigSigner::new(key.clone(), None);
+    let bytes = signer.sign(&bytes, tsig::now());
+    let verifier = signer.mac().map(|mac| TsigVerifier::new(key.clone(), mac.to_vec()));
+    Ok((bytes, verifier))
+}
+
+/// Asks `primary` for the serial of its version of the zone over UDP.
+fn query_serial(origin: &str, primary: SocketAddr, key: Option<&TsigKey>) -> Result<u32, String> {
+    let msg = request(origin, RecordType::SOA)?;
+    let local: SocketAddr = match primary {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+    socket.set_read_timeout(Some(SOA_QUERY_TIMEOUT)).map_err(|e| e.to_string())?;
+    let (bytes, mut verifier) = encode_request(&msg, key)?;
+    socket.send_to(&bytes, primary).map_err(|e| e.to_string())?;
+
+    let mut buf = [0; 4096];
+    loop {
+        let (len, src) = socket.recv_from(&mut buf).map_err(|e| e.to_string())?;
+        let Ok(response) = Message::from_vec(&buf[..len]) else {
+            continue;
+        };
+        if src != primary || response.id() != msg.id() {
+            continue;
+        }
+        if let Some(verifier) = &mut verifier {
+            verifier.verify(&buf[..len], tsig::now()).map_err(|e| e.to_string())?;
+        }
+        if response.response_code() != ResponseCode::NoError {
+            return Err(format!("SOA query answered with {}", response.response_code()));
+        }
+        return response
+            .answers()
+            .iter()
+            .find_map(soa_serial)
+            .ok_or_else(|| "SOA query answered without SOA record".to_string());
+    }
+}
+
+/// Transfers the zone from `primary` over TCP: incrementally (IXFR) when a version is
+/// already loaded, in full (AXFR) otherwise.
+fn transfer(origin: &str, current: Option<&Zone>, primary: SocketAddr, key: Option<&TsigKey>) -> Result<Refresh, String> {
+    let current_soa = current.and_then(Zone::soa);
+    let mut msg = request(origin, if current_soa.is_some() { RecordType::IXFR } else { RecordType::AXFR })?;
+    if let Some(soa) = current_soa {
+        msg.add_name_server(App::to_wire_record(soa));
+    }
+
+    let stream = TcpStream::connect_timeout(&primary, TRANSFER_TIMEOUT).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(TRANSFER_TIMEOUT)).map_err(|e| e.to_string())?;
+    let (bytes, mut verifier) = encode_request(&msg, key)?;
+    Server::write_tcp_message(&stream, &bytes).map_err(|e| e.to_string())?;
+
+    let mut records: Vec<Record> = Vec::new();
+    loop {
+        let bytes = Server::read_tcp_message(&stream).map_err(|e| e.to_string())?;
+        let response = Message::from_vec(&bytes).map_err(|e| e.to_string())?;
+        if response.id() != msg.id() {
+            return Err("transfer response with mismatched id".to_string());
+        }
+        if let Some(verifier) = &mut verifier {
+            verifier.verify(&bytes, tsig::now()).map_err(|e| e.to_string())?;
+        }
+        if response.response_code() != ResponseCode::NoError {
+            return Err(format!("transfer answered with {}", response.response_code()));
+        }
+        records.extend(response.answers().iter().cloned());
+
+        let Some(first) = records.first() else {
+            return Err("empty transfer response".to_string());
+        };
+        let serial = soa_serial(first).ok_or("transfer does not start with SOA")?;
+        if current.is_some() && records.len() == 1 {
+            // A single SOA record: the primary has no newer version, or wants a full transfer.
+            return match current.and_then(Zone::serial) {
+                Some(current_serial) if !serial_lt(current_serial, serial) => Ok(Refresh::UpToDate),
+                _ => transfer(origin, None, primary, key),
+            };
+        }
+
+        let incremental = records.get(1).and_then(soa_serial).is_some_and(|second| second != serial);
+        let soa_count = records.iter().filter(|record| soa_serial(record) == Some(serial)).count();
+        if soa_count < if incremental { 3 } else { 2 } {
+            continue;
+        }
+
+        let records: Vec<ResourceRecord> = records.iter().filter_map(App::from_wire_record).collect();
+        let body = &records[1..records.len() - 1];
+        let zone = match current {
+            Some(current) if incremental => apply_incremental(current, body)?,
+            _ => Zone {
+                origin: origin.to_string(),
+                ttl: current.map_or(records[0].ttl, |zone| zone.ttl),
+                records: records[..records.len() - 1].to_vec(),
+            },
+        };
+        if zone.serial() != Some(serial) {
+            return Err(format!("transfer did not end at serial {}", serial));
+        }
+        return Ok(Refresh::Transferred(zone));
+    }
+}
+
+/// Applies the difference sequences of an incremental transfer (RFC 1995, section 4) to
+/// `zone`. Each sequence starts with the old SOA record, followed by the deleted records,
+/// the new SOA record and the added records.
+fn apply_incremental(zone: &Zone, body: &[ResourceRecord]) -> Result<Zone, String> {
+    let mut records = zone.records.clone();
+    let mut deleting = false;
+    for record in body {
+        if matches!(record.data, RecordData::SOA { .. }) {
+            deleting = !deleting;
+        }
+        if deleting {
+            let position = records
+                .iter()
+                .position(|existing| existing.name.eq_ignore_ascii_case(&record.name) && existing.data == record.data)
+                .ok_or_else(|| format!("transfer deletes missing record {}", record.name))?;
+            records.remove(position);
+        } else {
+            records.push(record.clone());
+        }
+    }
+    Ok(Zone {
+        origin: zone.origin.clone(),
+        ttl: zone.ttl,
+        records,
+    })
+}
+
+fn soa_serial(record: &Record) -> Option<u32> {
+    match record.data() {
+        Some(RData::SOA(soa)) => Some(soa.serial()),
+        _ => None,
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use super::App;
+use crate::config::Config;
+use crate::db::Zone;
+use crate::dnssec::keystore::ZoneTtls;
+use crate::dnssec::{KeyStore, Signer, SigningKey};
+use crate::tsig;
+
+/// The DNSSEC keys a zone is signed with.
+#[derive(PartialEq, Eq)]
+pub(super) struct ZoneKeys {
+    signing: Vec<SigningKey>,
+    /// Keys whose DNSKEY records are in the zone but that do not sign.
+    published: Vec<SigningKey>,
+}
+
+impl App {
+    /// Loads the DNSSEC keys of the zones that have some. Fails if any key cannot be
+    /// loaded, as serving the zone unsigned would make it bogus once its parent has a DS
+    /// record for it.
+    pub(super) fn load_signing_keys(config: &Config) -> Result<HashMap<String, ZoneKeys>, String> {
+        let mut keys = HashMap::new();
+        let configured = config.zones.iter().filter(|zone| !zone.signing_keys.is_empty() && zone.key_policy.is_none());
+        for zone in configured.filter(|zone| !zone.is_secondary()) {
+            let loaded: Result<Vec<SigningKey>, String> = zone
+                .signing_keys
+                .iter()
+                .map(|key| SigningKey::load(&key.file, key.role.flags()))
+                .collect();
+            let loaded = loaded.map_err(|e| format!("Cannot sign zone {}: {}", zone.origin, e))?;
+            keys.insert(zone.origin.to_ascii_lowercase(), ZoneKeys { signing: loaded, published: Vec::new() });
+        }
+        Ok(keys)
+    }
+
+    /// Opens the key stores of the zones whose keys the server manages. Fails if a store
+    /// cannot be read, for the same reason as `load_signing_keys`.
+    pub(super) fn open_key_stores(config: &Config) -> Result<HashMap<String, KeyStore>, String> {
+        let mut stores = HashMap::new();
+        for zone in config.zones.iter().filter(|zone| zone.key_policy.is_some() && !zone.is_secondary()) {
+            let store = KeyStore::open(&config.server.zones_directory, &zone.origin)
+                .map_err(|e| format!("Cannot sign zone {}: {}", zone.origin, e))?;
+            stores.insert(zone.origin.to_ascii_lowercase(), store);
+        }
+        Ok(stores)
+    }
+
+    /// Returns a signer for a zone that has DNSSEC keys.
+    fn signer(&self, origin: &str) -> Option<Signer<'_>> {
+        let keys = self.signing_keys.get(&origin.to_ascii_lowercase())?;
+        let config = self.config.zone(origin);
+        let signer = Signer::new(&keys.signing, tsig::now() as u32, config.map_or(0, |zone| zone.signature_validity()))
+            .with_published_keys(&keys.published);
+        match config.and_then(|zone| zone.nsec3.clone()) {
+            Some(params) => Some(signer.with_nsec3(params)),
+            None => Some(signer),
+        }
+    }
+
+    /// Signs the zones loaded before the app started. Nothing has been served from them
+    /// yet, so they are swapped for their signed versions without journaling the change.
+    pub(super) fn sign_loaded_zones(&mut self) {
+        let origins: Vec<String> = self.signing_keys.keys().cloned().collect();
+        for origin in origins {
+            let Some(zone) = self.database.get_zone(&origin) else {
+                continue;
+            };
+            let Some(signed) = self.signer(&origin).map(|signer| signer.sign_zone(zone, None)) else {
+                continue;
+            };
+            self.database.remove_zone(&origin);
+            match self.database.add_zone(signed) {
+                Ok(()) => println!("Signed zone {}", origin),
+                Err(e) => eprintln!("Failed to sign zone {}: {}", origin, e),
+            }
+        }
+    }
+
+    /// Installs a new version of a zone, signing it first if it has DNSSEC keys.
+    pub(super) fn install_zone(&mut self, zone: Zone) -> Result<(), String> {
+        let zone = match self.signer(&zone.origin) {
+            Some(signer) => signer.sign_zone(&zone, self.database.get_zone(&zone.origin)),
+            None => zone,
+        };
+        self.database.replace_zone(zone)
+    }
+
+    /// Returns a zone without the records DNSSEC signing added, as it is written to its
+    /// zone file.
+    pub(super) fn unsigned_zone(&self, zone: &Zone) -> Zone {
+        match self.signer(&zone.origin) {
+            Some(signer) => signer.unsigned_zone(zone),
+            None => zone.clone(),
+        }
+    }
+
+    /// Renews the signatures of signed zones that are about to expire. The new version
+    /// gets the next serial so that secondaries pick it up.
+    pub(super) fn refresh_signatures(&mut self) {
+        let origins: Vec<String> = self.signing_keys.keys().cloned().collect();
+        for origin in origins {
+            let Some(zone) = self.database.get_zone(&origin) else {
+                continue;
+            };
+            if !self.signer(&origin).is_some_and(|signer| signer.needs_refresh(zone)) {
+                continue;
+            }
+            let mut zone = zone.clone();
+            zone.increment_serial();
+            match self.install_zone(zone) {
+                Ok(()) => {
+                    if let Some(zone) = self.database.get_zone(&origin) {
+                        println!("Re-signed zone {} with serial {}", origin, zone.serial().unwrap_or_default());
+                        self.notify_secondaries(zone);
+                    }
+                }
+                Err(e) => eprintln!("Failed to re-sign zone {}: {}", origin, e),
+            }
+        }
+    }
+
+    /// Moves the key rollovers of the zones whose keys the server manages forward, and
+    /// re-signs those whose DNSKEY records changed with the next serial. Zones that were
+    /// not signed yet are left to `sign_loaded_zones` and `refresh_signatures`.
+    pub(super) fn roll_keys(&mut self) {
+        let now = tsig::now() as u32;
+        let mut changed = Vec::new();
+        for (origin, store) in &mut self.key_stores {
+            let policy = self.config.zone(origin).and_then(|zone| zone.key_policy.as_ref());
+            let (Some(zone), Some(policy)) = (self.database.get_zone(origin), policy) else {
+                continue;
+            };
+            match store.advance(policy, ZoneTtls::of(zone), now) {
+                Ok(true) => {
+                    println!("Key timeline of zone {} changed, the parent should publish:", origin);
+                    for ds in store.ds_records(now) {
+                        println!("  {}", ds);
+                    }
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("Failed to roll the keys of zone {}: {}", origin, e);
+                    continue;
+                }
+            }
+            let (signing, published) = store.zone_keys(now);
+            let keys = ZoneKeys { signing, published };
+            if self.signing_keys.get(origin) != Some(&keys) {
+                changed.push((origin.clone(), keys));
+            }
+        }
+
+        for (origin, keys) in changed {
+            // The records generated with the old keys are removed before switching, in
+            // every view that serves the zone.
+            let unsigned: Vec<(Option<usize>, Zone)> = self
+                .all_views()
+                .into_iter()
+                .filter_map(|view| self.in_view(view, |app| app.database.get_zone(&origin).map(|zone| (view, app.unsigned_zone(zone)))))
+                .collect();
+            if self.signing_keys.insert(origin.clone(), keys).is_none() {
+                continue;
+            }
+            for (view, mut zone) in unsigned {
+                zone.increment_serial();
+                self.in_view(view, |app| match app.install_zone(zone) {
+                    Ok(()) => {
+                        if let Some(zone) = app.database.get_zone(&origin) {
+                            println!("Re-signed zone {} with new keys, serial {}", origin, zone.serial().unwrap_or_default());
+                            app.notify_secondaries(zone);
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to re-sign zone {}: {}", origin, e),
+                });
+            }
+        }
+    }
+}
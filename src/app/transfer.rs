@@ -0,0 +1,170 @@
+use std::net::SocketAddr;
+
+use trust_dns_proto::op::{Message, ResponseCode};
+use trust_dns_proto::rr::{RData, Record};
+use trust_dns_proto::serialize::binary::BinEncodable;
+
+use super::{App, Transport};
+use crate::db::journal::serial_lt;
+use crate::db::{RecordData, ResourceRecord, Zone, ZoneDiff};
+
+/// Upper bound for the encoded size of a single AXFR message, well below the 64 KiB TCP limit.
+const MAX_TRANSFER_MESSAGE_SIZE: usize = 16384;
+
+/// Largest response sent over UDP without EDNS (RFC 1035, section 4.2.1).
+const MAX_UDP_MESSAGE_SIZE: usize = 512;
+
+impl App {
+    /// Answers an AXFR request (RFC 5936).
+    ///
+    /// Transfers are only served over TCP and only to clients listed in the zone's
+    /// `allow_transfer` setting or signed with one of its `transfer_keys`.
+    pub(super) fn handle_axfr(&self, msg: &Message, src: SocketAddr, transport: &Transport, key: Option<&str>) -> Vec<Message> {
+        if !matches!(transport, Transport::Tcp(_)) {
+            return vec![App::error_response(msg, ResponseCode::Refused)];
+        }
+        let zone = match self.transfer_zone(msg, src, key) {
+            Ok(zone) => zone,
+            Err(code) => return vec![App::error_response(msg, code)],
+        };
+
+        match App::transfer_messages(msg, zone) {
+            Some(messages) => messages,
+            None => vec![App::error_response(msg, ResponseCode::ServFail)],
+        }
+    }
+
+    /// Answers an IXFR request (RFC 1995) with the condensed changes since the client's
+    /// serial, taken from the zone's journal.
+    ///
+    /// Falls back to a full transfer when the journal does not reach back to the client's
+    /// serial. Over UDP, a response that does not fit into one datagram is replaced by the
+    /// current SOA record, telling the client to retry over TCP.
+    pub(super) fn handle_ixfr(&self, msg: &Message, src: SocketAddr, transport: &Transport, key: Option<&str>) -> Vec<Message> {
+        let zone = match self.transfer_zone(msg, src, key) {
+            Ok(zone) => zone,
+            Err(code) => return vec![App::error_response(msg, code)],
+        };
+        let Some(current_soa) = zone.soa() else {
+            return vec![App::error_response(msg, ResponseCode::ServFail)];
+        };
+        let current_soa = App::to_wire_record(current_soa);
+
+        let client_serial = msg.name_servers().iter().find_map(|record| match record.data() {
+            Some(RData::SOA(soa)) => Some(soa.serial()),
+            _ => None,
+        });
+        let Some(client_serial) = client_serial else {
+            return vec![App::error_response(msg, ResponseCode::FormErr)];
+        };
+
+        let only_soa = || {
+            let mut response = App::response_to(msg);
+            response.add_answer(current_soa.clone());
+            vec![response]
+        };
+        if !serial_lt(client_serial, zone.serial().unwrap_or(client_serial)) {
+            return only_soa();
+        }
+
+        let messages = match self.database.zone_changes_since(&zone.origin, client_serial) {
+            Some(diffs) => App::pack_records(msg, App::incremental_records(&ZoneDiff::condense(&diffs))),
+            None if matches!(transport, Transport::Tcp(_)) => match App::transfer_messages(msg, zone) {
+                Some(messages) => messages,
+                None => return vec![App::error_response(msg, ResponseCode::ServFail)],
+            },
+            None => return only_soa(),
+        };
+
+        if matches!(transport, Transport::Udp) {
+            let fits = messages.len() == 1
+                && messages[0].to_bytes().is_ok_and(|bytes| bytes.len() <= MAX_UDP_MESSAGE_SIZE);
+            if !fits {
+                return only_soa();
+            }
+        }
+        messages
+    }
+
+    /// Finds the zone named in a transfer request and checks that `src`, or the TSIG `key`
+    /// the request was signed with, may transfer it, returning the response code to send
+    /// otherwise.
+    fn transfer_zone(&self, msg: &Message, src: SocketAddr, key: Option<&str>) -> Result<&Zone, ResponseCode> {
+        let origin = msg.queries()[0].name().to_string();
+        let Some(zone) = self.database.get_zone(&origin) else {
+            return Err(ResponseCode::NotAuth);
+        };
+        if !self.transfer_allowed(&zone.origin, src, key) {
+            eprintln!("Refused transfer of {} to {}", zone.origin, src);
+            return Err(ResponseCode::Refused);
+        }
+        Ok(zone)
+    }
+
+    /// Zones with `transfer_keys` are only transferred with one of them, and then only
+    /// to `allow_transfer` if it is set. Other zones are transferred to `allow_transfer`.
+    fn transfer_allowed(&self, origin: &str, src: SocketAddr, key: Option<&str>) -> bool {
+        self.config.zone(origin).is_some_and(|zone| {
+            let by_address = zone.allow_transfer.iter().any(|cidr| cidr.contains(src.ip()));
+            if zone.transfer_keys.is_empty() {
+                return by_address;
+            }
+            let by_key = key.is_some_and(|key| zone.transfer_keys.iter().any(|allowed| allowed.eq_ignore_ascii_case(key)));
+            by_key && (zone.allow_transfer.is_empty() || by_address)
+        })
+    }
+
+    /// Splits the whole zone into AXFR response messages, starting and ending with the SOA
+    /// record. Returns `None` if the zone has no SOA record at its apex.
+    fn transfer_messages(msg: &Message, zone: &Zone) -> Option<Vec<Message>> {
+        let soa = App::to_wire_record(zone.soa()?);
+
+        let mut records: Vec<Record> = vec![soa.clone()];
+        records.extend(
+            zone.records
+                .iter()
+                .filter(|rec| !matches!(rec.data, RecordData::SOA { .. }))
+                .map(App::to_wire_record),
+        );
+        records.push(soa);
+
+        Some(App::pack_records(msg, records))
+    }
+
+    /// Lays out a diff in IXFR order: new SOA, old SOA, deleted records, new SOA, added
+    /// records, new SOA.
+    fn incremental_records(diff: &ZoneDiff) -> Vec<Record> {
+        let new_soa = App::to_wire_record(&diff.new_soa);
+        let mut records = vec![new_soa.clone(), App::to_wire_record(&diff.old_soa)];
+        records.extend(diff.deleted.iter().map(App::to_wire_record));
+        records.push(new_soa.clone());
+        records.extend(diff.added.iter().map(App::to_wire_record));
+        records.push(new_soa);
+        records
+    }
+
+    pub(super) fn to_wire_record(record: &ResourceRecord) -> Record {
+        App::to_record(&record.name, record.ttl, &record.data)
+    }
+
+    /// Packs records into as few responses to `msg` as `MAX_TRANSFER_MESSAGE_SIZE` allows.
+    /// Only the first response repeats the question.
+    fn pack_records(msg: &Message, records: Vec<Record>) -> Vec<Message> {
+        let mut messages = Vec::new();
+        let mut current = App::response_to(msg);
+        let mut size = current.to_bytes().map_or(0, |bytes| bytes.len());
+        for record in records {
+            let record_size = record.to_bytes().map_or(0, |bytes| bytes.len());
+            if size + record_size > MAX_TRANSFER_MESSAGE_SIZE && !current.answers().is_empty() {
+                messages.push(current);
+                current = App::response_to(msg);
+                current.take_queries();
+                size = current.to_bytes().map_or(0, |bytes| bytes.len());
+            }
+            size += record_size;
+            current.add_answer(record);
+        }
+        messages.push(current);
+        messages
+    }
+}
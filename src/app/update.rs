@@ -0,0 +1,175 @@
+use std::net::SocketAddr;
+
+use trust_dns_proto::op::{Message, ResponseCode};
+use trust_dns_proto::rr::{DNSClass, Record, RecordType};
+
+use super::App;
+use crate::db::{Prerequisite, ResourceRecord, UpdateError, UpdateOperation};
+use crate::zone_writer::ZoneWriter;
+
+impl App {
+    /// Answers a dynamic update (RFC 2136).
+    ///
+    /// Updates are only accepted for primary zones. Zones with an `update_policy` take
+    /// them only signed with a key whose grants cover every changed name, and then only
+    /// from `allow_update` if it is set. Other zones take them from `allow_update`.
+    ///
+    /// A successful update is announced to the zone's secondaries and written back to
+    /// the zone file, when the zone was loaded from one. The written file is flattened:
+    /// `$INCLUDE` and `$GENERATE` directives and comments give way to their records.
+    pub(super) fn handle_update(&mut self, msg: &Message, src: SocketAddr, key: Option<&str>) -> Vec<Message> {
+        let question = &msg.queries()[0];
+        if question.query_type() != RecordType::SOA {
+            return vec![App::error_response(msg, ResponseCode::FormErr)];
+        }
+        // Only zones of class IN are served.
+        if question.query_class() != DNSClass::IN {
+            return vec![App::error_response(msg, ResponseCode::NotAuth)];
+        }
+        let origin = match self.database.get_zone(&question.name().to_string()) {
+            Some(zone) => zone.origin.clone(),
+            None => return vec![App::error_response(msg, ResponseCode::NotAuth)],
+        };
+
+        let prerequisites = match App::prerequisites(msg.answers()) {
+            Some(prerequisites) => prerequisites,
+            None => return vec![App::error_response(msg, ResponseCode::FormErr)],
+        };
+        let operations = match App::update_operations(msg.name_servers()) {
+            Some(operations) => operations,
+            None => return vec![App::error_response(msg, ResponseCode::FormErr)],
+        };
+
+        let allowed = self.config.zone(&origin).is_some_and(|zone| {
+            let by_address = zone.allow_update.iter().any(|cidr| cidr.contains(src.ip()));
+            let by_key = key.is_some_and(|key| {
+                operations.iter().all(|operation| {
+                    zone.update_policy
+                        .iter()
+                        .any(|grant| grant.key.eq_ignore_ascii_case(key) && grant.covers(operation.name()))
+                })
+            });
+            let by_policy = if zone.update_policy.is_empty() {
+                by_address
+            } else {
+                by_key && (zone.allow_update.is_empty() || by_address)
+            };
+            !zone.is_secondary() && by_policy
+        });
+        if !allowed {
+            eprintln!("Refused update of {} from {}", origin, src);
+            return vec![App::error_response(msg, ResponseCode::Refused)];
+        }
+
+        // Signed zones are re-signed before the new version is installed, so the update
+        // is applied here rather than with `Database::update_zone`.
+        let updated = match self.database.get_zone(&origin) {
+            Some(zone) => zone.update(&prerequisites, &operations),
+            None => Err(UpdateError::NotAuth(origin.clone())),
+        };
+        let installed = match updated {
+            Ok(Some(zone)) => self.install_zone(zone).map(|()| true).map_err(UpdateError::Failed),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        };
+        let code = match installed {
+            Ok(changed) => {
+                if changed && let Some(zone) = self.database.get_zone(&origin) {
+                    println!("Updated zone {} to serial {} for {}", origin, zone.serial().unwrap_or_default(), src);
+                    if let Some(path) = self.zone_files.get(&origin)
+                        && let Err(e) = ZoneWriter::write_zone_file(&self.unsigned_zone(zone), path)
+                    {
+                        eprintln!("Failed to write zone {} to {:?}: {}", origin, path, e);
+                    }
+                    self.notify_secondaries(zone);
+                }
+                ResponseCode::NoError
+            }
+            Err(e) => {
+                eprintln!("Rejected update of {} from {}: {}", origin, src, e);
+                match e {
+                    UpdateError::NameNotInUse(_) => ResponseCode::NXDomain,
+                    UpdateError::NameInUse(_) => ResponseCode::YXDomain,
+                    UpdateError::RRsetMissing(_) => ResponseCode::NXRRSet,
+                    UpdateError::RRsetExists(_) => ResponseCode::YXRRSet,
+                    UpdateError::NotZone(_) => ResponseCode::NotZone,
+                    UpdateError::NotAuth(_) => ResponseCode::NotAuth,
+                    UpdateError::Invalid(_) => ResponseCode::Refused,
+                    UpdateError::Failed(_) => ResponseCode::ServFail,
+                }
+            }
+        };
+        vec![App::error_response(msg, code)]
+    }
+
+    /// Reads the prerequisite section (RFC 2136, section 2.4). Returns `None` if a record
+    /// is malformed.
+    fn prerequisites(records: &[Record]) -> Option<Vec<Prerequisite>> {
+        let mut prerequisites: Vec<Prerequisite> = Vec::new();
+        for record in records {
+            if record.ttl() != 0 {
+                return None;
+            }
+            let name = record.name().to_string();
+            let rtype = App::convert_record_type(record.record_type());
+            let any_type = record.record_type() == RecordType::ANY;
+            let prerequisite = match (record.dns_class(), record.data()) {
+                (DNSClass::ANY, None) if any_type => Prerequisite::NameInUse(name),
+                (DNSClass::ANY, None) => Prerequisite::RRsetExists { name, rtype },
+                (DNSClass::NONE, None) if any_type => Prerequisite::NameNotInUse(name),
+                (DNSClass::NONE, None) => Prerequisite::RRsetDoesNotExist { name, rtype },
+                (DNSClass::IN, Some(_)) if !any_type => {
+                    let data = App::from_wire_data(record)?;
+                    // Records of the same RRset together describe its exact contents.
+                    let existing = prerequisites.iter_mut().find_map(|prerequisite| match prerequisite {
+                        Prerequisite::RRsetEquals { name: n, rtype: t, data } if n.eq_ignore_ascii_case(&name) && *t == rtype => {
+                            Some(data)
+                        }
+                        _ => None,
+                    });
+                    match existing {
+                        Some(existing) => {
+                            existing.push(data);
+                            continue;
+                        }
+                        None => Prerequisite::RRsetEquals { name, rtype, data: vec![data] },
+                    }
+                }
+                _ => return None,
+            };
+            prerequisites.push(prerequisite);
+        }
+        Some(prerequisites)
+    }
+
+    /// Reads the update section (RFC 2136, section 2.5). Returns `None` if a record is
+    /// malformed.
+    fn update_operations(records: &[Record]) -> Option<Vec<UpdateOperation>> {
+        records
+            .iter()
+            .map(|record| {
+                let name = record.name().to_string();
+                let rtype = App::convert_record_type(record.record_type());
+                let any_type = record.record_type() == RecordType::ANY;
+                if matches!(record.record_type(), RecordType::AXFR | RecordType::IXFR | RecordType::OPT) {
+                    return None;
+                }
+                match (record.dns_class(), record.data()) {
+                    (DNSClass::IN, Some(_)) if !any_type => Some(UpdateOperation::Add(ResourceRecord {
+                        name,
+                        ttl: record.ttl(),
+                        class: crate::db::record::RecordClass::IN,
+                        data: App::from_wire_data(record)?,
+                    })),
+                    (DNSClass::ANY, None) if record.ttl() == 0 && any_type => Some(UpdateOperation::DeleteName(name)),
+                    (DNSClass::ANY, None) if record.ttl() == 0 => Some(UpdateOperation::DeleteRRset { name, rtype }),
+                    (DNSClass::NONE, Some(_)) if record.ttl() == 0 && !any_type => Some(UpdateOperation::DeleteRecord {
+                        name,
+                        data: App::from_wire_data(record)?,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use super::{App, ZoneUpdate, D};
+use crate::config::ViewConfig;
+use crate::db::InMemoryDatabase;
+
+/// The zones served to the clients a view matches, in place of the default ones.
+pub struct View {
+    config: ViewConfig,
+    database: Box<D>,
+    zone_files: HashMap<String, PathBuf>,
+    zone_tx: Sender<ZoneUpdate>,
+    zone_rx: Receiver<ZoneUpdate>,
+}
+
+impl View {
+    /// Creates a view without zones.
+    pub fn new(config: ViewConfig) -> Self {
+        let (zone_tx, zone_rx) = mpsc::channel();
+        View { config, database: Box::new(InMemoryDatabase::new()), zone_files: HashMap::new(), zone_tx, zone_rx }
+    }
+}
+
+impl App {
+    /// Sets the zones of a view. Must be called before `run`.
+    pub fn set_view_database(&mut self, name: &str, database: Box<D>) -> Result<(), String> {
+        let view = self.view_mut(name)?;
+        view.database = database;
+        Ok(())
+    }
+
+    /// Records the file a zone of a view was loaded from, see `set_zone_file`.
+    pub fn set_view_zone_file(&mut self, name: &str, origin: &str, path: PathBuf) -> Result<(), String> {
+        let view = self.view_mut(name)?;
+        view.zone_files.insert(origin.to_string(), path);
+        Ok(())
+    }
+
+    /// Returns a sender for changes to the zones of a view, see `zone_sender`.
+    pub fn view_zone_sender(&self, name: &str) -> Option<Sender<ZoneUpdate>> {
+        self.views.iter().find(|view| view.config.name == name).map(|view| view.zone_tx.clone())
+    }
+
+    fn view_mut(&mut self, name: &str) -> Result<&mut View, String> {
+        self.views.iter_mut().find(|view| view.config.name == name).ok_or_else(|| format!("No view named {}", name))
+    }
+
+    /// Returns the index of the view for a request from `client`, signed with `key` if
+    /// any. `None` stands for the default zones.
+    pub(super) fn select_view(&self, client: IpAddr, key: Option<&str>) -> Option<usize> {
+        self.views.iter().position(|view| view.config.matches(client, key))
+    }
+
+    /// Returns the default zones followed by every view.
+    pub(super) fn all_views(&self) -> Vec<Option<usize>> {
+        std::iter::once(None).chain((0..self.views.len()).map(Some)).collect()
+    }
+
+    /// Runs `f` with the zones of a view in place of the default ones, so that the
+    /// handlers serve, transfer and update the view's zones. `None` runs it on the
+    /// default zones.
+    pub(super) fn in_view<T>(&mut self, view: Option<usize>, f: impl FnOnce(&mut App) -> T) -> T {
+        let Some(index) = view else {
+            return f(self);
+        };
+        self.swap_view(index);
+        let result = f(self);
+        self.swap_view(index);
+        result
+    }
+
+    fn swap_view(&mut self, index: usize) {
+        let view = &mut self.views[index];
+        std::mem::swap(&mut self.database, &mut view.database);
+        std::mem::swap(&mut self.zone_files, &mut view.zone_files);
+        std::mem::swap(&mut self.zone_rx, &mut view.zone_rx);
+    }
+}
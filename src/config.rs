@@ -0,0 +1,205 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::acl::{Access, AclConfig, Cidr};
+use crate::db::validation::{is_in_zone, normalize};
+use crate::dnssec::key::{SECURE_ENTRY_POINT, ZONE_KEY};
+use crate::dnssec::{KeyPolicy, Nsec3Params};
+use crate::resolver::ResolverConfig;
+use crate::rrl::RateLimitConfig;
+use crate::server::ServerConfig;
+use crate::tsig::TsigKey;
+
+/// The contents of `config.toml`.
+#[derive(Deserialize, Default)]
+pub struct Config {
+    pub server: ServerConfig,
+    /// Per-zone settings, given as `[[zone]]` tables.
+    #[serde(default, rename = "zone")]
+    pub zones: Vec<ZoneConfig>,
+    /// TSIG keys, given as `[[key]]` tables.
+    #[serde(default, rename = "key")]
+    pub keys: Vec<TsigKey>,
+    /// Resolve names outside the local zones recursively.
+    pub resolver: Option<ResolverConfig>,
+    /// Split-horizon views, given as `[[view]]` tables.
+    #[serde(default, rename = "view")]
+    pub views: Vec<ViewConfig>,
+    /// Named client lists, given as `[[acl]]` tables.
+    #[serde(default, rename = "acl")]
+    pub acls: Vec<AclConfig>,
+    /// The ACLs that apply to every request.
+    #[serde(default)]
+    pub access: Access,
+    /// Limit the rate of UDP responses to each client prefix.
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl Config {
+    /// Returns the settings for the zone with the given origin, if any.
+    pub fn zone(&self, origin: &str) -> Option<&ZoneConfig> {
+        self.zones.iter().find(|zone| zone.origin.eq_ignore_ascii_case(origin))
+    }
+
+    /// Returns the settings for the closest zone holding `name`, if any.
+    pub fn zone_holding(&self, name: &str) -> Option<&ZoneConfig> {
+        let name = normalize(name);
+        self.zones.iter().filter(|zone| is_in_zone(&name, &normalize(&zone.origin))).max_by_key(|zone| zone.origin.len())
+    }
+
+    /// Checks that a zone can be served from a view. Secondary and signed zones keep
+    /// their transfer state and keys with the default zones only, so views cannot hold them.
+    pub fn check_view_zone(&self, origin: &str) -> Result<(), String> {
+        match self.zone(origin) {
+            Some(zone) if zone.is_secondary() => Err(format!("Secondary zone {} cannot be served from a view", origin)),
+            Some(zone) if !zone.signing_keys.is_empty() || zone.key_policy.is_some() => {
+                Err(format!("Signed zone {} cannot be served from a view", origin))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the TSIG key with the given name, if any.
+    pub fn key(&self, name: &str) -> Option<&TsigKey> {
+        self.keys.iter().find(|key| key.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Settings for a single zone.
+#[derive(Deserialize, Clone, Default)]
+pub struct ZoneConfig {
+    /// The zone's origin, e.g. `example.com.`.
+    pub origin: String,
+    /// Client prefixes allowed to transfer the zone with AXFR. Transfers are refused when
+    /// the list is empty.
+    #[serde(default)]
+    pub allow_transfer: Vec<Cidr>,
+    /// TSIG keys allowed to transfer the zone. When set, transfers must be signed with
+    /// one of them, and `allow_transfer` only narrows down where they may come from.
+    #[serde(default)]
+    pub transfer_keys: Vec<String>,
+    /// Client prefixes allowed to change the zone with dynamic updates. Updates are
+    /// refused when the list is empty.
+    #[serde(default)]
+    pub allow_update: Vec<Cidr>,
+    /// TSIG keys allowed to update parts of the zone. When set, updates must be signed
+    /// with one of them, and `allow_update` only narrows down where they may come from.
+    #[serde(default)]
+    pub update_policy: Vec<UpdateGrant>,
+    /// Primary servers to transfer the zone from. A zone with primaries is a secondary
+    /// zone: it is not read from a file but kept in sync with the primaries.
+    #[serde(default)]
+    pub primaries: Vec<SocketAddr>,
+    /// TSIG key to sign requests to the primaries with. NOTIFY for the zone must then be
+    /// signed with it too.
+    pub primary_key: Option<String>,
+    /// Secondaries to send NOTIFY to when the zone changes, in addition to the zone's
+    /// name servers.
+    #[serde(default)]
+    pub also_notify: Vec<SocketAddr>,
+    /// TSIG key to sign NOTIFY messages with.
+    pub notify_key: Option<String>,
+    /// DNSSEC keys to sign the zone with. Zones without keys are served unsigned.
+    #[serde(default)]
+    pub signing_keys: Vec<SigningKeyConfig>,
+    /// Generate the zone's DNSSEC keys and roll them over automatically, keeping them in
+    /// the zones directory. Takes precedence over `signing_keys`.
+    pub key_policy: Option<KeyPolicy>,
+    /// How long DNSSEC signatures are valid, in seconds. Defaults to 14 days.
+    pub signature_validity: Option<u32>,
+    /// Chain the signed zone with NSEC3 instead of NSEC records.
+    pub nsec3: Option<Nsec3Params>,
+    /// The ACLs that apply to requests for the zone.
+    #[serde(default)]
+    pub access: Access,
+}
+
+impl ZoneConfig {
+    pub fn is_secondary(&self) -> bool {
+        !self.primaries.is_empty()
+    }
+
+    pub fn signature_validity(&self) -> u32 {
+        self.signature_validity.unwrap_or(14 * 24 * 3600)
+    }
+}
+
+/// A set of zones served to some clients instead of the zones in the server's zones
+/// directory. A request gets the first view that matches it.
+#[derive(Deserialize, Clone, Default)]
+pub struct ViewConfig {
+    pub name: String,
+    /// Client prefixes the view is served to.
+    #[serde(default)]
+    pub match_clients: Vec<Cidr>,
+    /// TSIG keys whose requests get the view, wherever they come from. A view with
+    /// neither clients nor keys matches every request.
+    #[serde(default)]
+    pub match_keys: Vec<String>,
+    /// The directory the view's zone files are read from.
+    pub zones_directory: PathBuf,
+}
+
+impl ViewConfig {
+    /// Returns whether a request from `client`, signed with `key` if any, gets the view.
+    pub fn matches(&self, client: IpAddr, key: Option<&str>) -> bool {
+        (self.match_clients.is_empty() && self.match_keys.is_empty())
+            || self.match_clients.iter().any(|cidr| cidr.contains(client))
+            || key.is_some_and(|key| self.match_keys.iter().any(|name| name.eq_ignore_ascii_case(key)))
+    }
+}
+
+/// A DNSSEC private key file and the role of the key.
+#[derive(Deserialize, Clone)]
+pub struct SigningKeyConfig {
+    /// A private key file in BIND's format.
+    pub file: PathBuf,
+    #[serde(default)]
+    pub role: KeyRole,
+}
+
+/// Which RRsets a DNSSEC key signs.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyRole {
+    /// A combined signing key: signs the whole zone and is referred to by the parent.
+    #[default]
+    Csk,
+    /// A key signing key: signs the DNSKEY RRset and is referred to by the parent.
+    Ksk,
+    /// A zone signing key: signs everything else.
+    Zsk,
+}
+
+impl KeyRole {
+    /// Returns the DNSKEY flags of a key with this role.
+    pub fn flags(self) -> u16 {
+        match self {
+            KeyRole::Csk | KeyRole::Ksk => ZONE_KEY | SECURE_ENTRY_POINT,
+            KeyRole::Zsk => ZONE_KEY,
+        }
+    }
+}
+
+/// Permission for a TSIG key to update some names of a zone.
+#[derive(Deserialize, Clone, Default)]
+pub struct UpdateGrant {
+    pub key: String,
+    /// Names the key may change. `*.example.com.` stands for `example.com.` and every
+    /// name below it, so granting a whole zone takes `*.` followed by its origin.
+    pub names: Vec<String>,
+}
+
+impl UpdateGrant {
+    /// Returns whether the grant covers `name`. Names are compared as FQDNs, with or
+    /// without the trailing dot.
+    pub fn covers(&self, name: &str) -> bool {
+        let name = normalize(name);
+        self.names.iter().any(|pattern| match pattern.strip_prefix("*.") {
+            Some(suffix) => is_in_zone(&name, &normalize(suffix)),
+            None => name == normalize(pattern),
+        })
+    }
+}
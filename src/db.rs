@@ -1,8 +1,16 @@
 pub mod in_memory;
+pub mod index;
+pub mod journal;
 pub mod record;
+pub mod update;
+pub mod validation;
 
 pub use self::record::{RecordType, RecordData, ResourceRecord, Zone};
 pub use self::in_memory::InMemoryDatabase;
+pub use self::index::ZoneIndex;
+pub use self::journal::{Journal, ZoneDiff};
+pub use self::update::{Prerequisite, UpdateError, UpdateOperation};
+pub use self::validation::{Severity, ZoneIssue, ZoneIssueKind};
 
 /// A trait for database operations.
 pub trait Database {
@@ -19,6 +27,46 @@ pub trait Database {
     /// An `Option` containing the `RecordData` if found, otherwise `None`.
     fn lookup_resource_record(&self, fqdn: &str, record_type: RecordType) -> Result<Option<&RecordData>, String>;
     fn add_zone(&mut self, zone: Zone) -> Result<(), String>;
+    /// Returns the zone with the given origin, if it is loaded. Origins are compared
+    /// case-insensitively, with or without the trailing dot.
+    fn get_zone(&self, origin: &str) -> Option<&Zone>;
+    /// Returns the index of a loaded zone, kept up to date as the zone changes.
+    fn zone_index(&self, origin: &str) -> Option<&ZoneIndex>;
+    /// Returns the loaded zone that `fqdn` belongs to: the one with the longest origin
+    /// that is `fqdn` or one of its ancestors.
+    fn find_zone(&self, fqdn: &str) -> Option<&Zone> {
+        let fqdn = validation::normalize(fqdn);
+        let mut name = fqdn.as_str();
+        loop {
+            if let Some(zone) = self.get_zone(name) {
+                return Some(zone);
+            }
+            name = match name.split_once('.') {
+                Some((_, "")) | None => return self.get_zone("."),
+                Some((_, parent)) => parent,
+            };
+        }
+    }
+
+    /// Replaces a loaded zone with a new version, or adds it if it is not loaded yet.
+    ///
+    /// When the content changes, the SOA serial must increase; the differences are then
+    /// recorded in the zone's journal for incremental transfers.
+    fn replace_zone(&mut self, zone: Zone) -> Result<(), String>;
+    /// Applies a dynamic update to a zone atomically: either all prerequisites hold and
+    /// every operation is applied, or the zone is left unchanged. Changes are journaled
+    /// like those made by `replace_zone`. Returns whether the zone changed.
+    fn update_zone(
+        &mut self,
+        origin: &str,
+        prerequisites: &[Prerequisite],
+        operations: &[UpdateOperation],
+    ) -> Result<bool, UpdateError>;
+    /// Removes a zone and its journal, returning the zone if it was loaded.
+    fn remove_zone(&mut self, origin: &str) -> Option<Zone>;
+    /// Returns the journaled changes from `serial` to the current version of a zone, or
+    /// `None` if the history does not reach back that far.
+    fn zone_changes_since(&self, origin: &str, serial: u32) -> Option<Vec<&ZoneDiff>>;
 }
 
 
@@ -1,32 +1,42 @@
 use std::collections::HashMap;
 
-use super::{Database, RecordData, RecordType, Zone};
+use super::journal::serial_lt;
+use super::validation::normalize;
+use super::{Database, Journal, ZoneIndex, Prerequisite, RecordData, RecordType, UpdateError, UpdateOperation, Zone, ZoneDiff};
 
+/// Zones, their journals and indexes, by normalized origin.
+#[derive(Default)]
 pub struct InMemoryDatabase {
     zones: HashMap<String, Zone>,
+    journals: HashMap<String, Journal>,
+    indexes: HashMap<String, ZoneIndex>,
 }
 
 impl InMemoryDatabase {
     pub fn new() -> Self {
         InMemoryDatabase {
             zones: HashMap::new(),
+            journals: HashMap::new(),
+            indexes: HashMap::new(),
         }
     }
 
     pub fn add_zone(&mut self, zone: Zone) -> Result<(), String> {
-        if self.zones.contains_key(&zone.origin) {
+        let origin = normalize(&zone.origin);
+        if self.zones.contains_key(&origin) {
             return Err(format!("Zone {} already exists", zone.origin));
         }
-        self.zones.insert(zone.origin.clone(), zone);
+        self.indexes.insert(origin.clone(), ZoneIndex::new(&zone));
+        self.zones.insert(origin, zone);
         Ok(())
     }
 }
 
 impl Database for InMemoryDatabase {
     fn lookup_meta_records(&self, fqdn: &str, record_type: RecordType) -> Result<Option<Vec<&RecordData>>, String> {
-        if let Some(zone) = self.zones.get(fqdn) {
+        if let Some(zone) = self.find_zone(fqdn) {
             let records: Vec<&RecordData> = zone.records.iter()
-                .filter(|rec| rec.data.get_type() == record_type)
+                .filter(|rec| rec.name.eq_ignore_ascii_case(fqdn) && rec.data.get_type() == record_type)
                 .map(|rec| &rec.data)
                 .collect();
             if records.is_empty() {
@@ -40,9 +50,9 @@ impl Database for InMemoryDatabase {
     }
 
     fn lookup_resource_record(&self, fqdn: &str, record_type: RecordType) -> Result<Option<&RecordData>, String> {
-        if let Some(zone) = self.zones.get(fqdn) {
+        if let Some(zone) = self.find_zone(fqdn) {
             let record = zone.records.iter()
-                .find(|rec| rec.data.get_type() == record_type)
+                .find(|rec| rec.name.eq_ignore_ascii_case(fqdn) && rec.data.get_type() == record_type)
                 .map(|rec| &rec.data);
             Ok(record)
         } else {
@@ -53,4 +63,71 @@ impl Database for InMemoryDatabase {
     fn add_zone(&mut self, zone: Zone) -> Result<(), String> {
         InMemoryDatabase::add_zone(self, zone)
     }
+
+    fn get_zone(&self, origin: &str) -> Option<&Zone> {
+        self.zones.get(&normalize(origin))
+    }
+
+    fn zone_index(&self, origin: &str) -> Option<&ZoneIndex> {
+        self.indexes.get(&normalize(origin))
+    }
+
+    fn replace_zone(&mut self, zone: Zone) -> Result<(), String> {
+        let origin = normalize(&zone.origin);
+        let Some(old) = self.zones.get(&origin) else {
+            return InMemoryDatabase::add_zone(self, zone);
+        };
+        if *old == zone {
+            return Ok(());
+        }
+        match (old.serial(), zone.serial()) {
+            (Some(old_serial), Some(new_serial)) if serial_lt(old_serial, new_serial) => {
+                if let Some(diff) = ZoneDiff::between(old, &zone) {
+                    self.journals.entry(origin.clone()).or_default().record(diff);
+                }
+            }
+            (Some(old_serial), Some(new_serial)) => {
+                return Err(format!(
+                    "Zone {} changed but its serial did not increase ({} -> {})",
+                    zone.origin, old_serial, new_serial
+                ));
+            }
+            _ => {
+                // Without SOA records the change cannot be journaled.
+                self.journals.remove(&origin);
+            }
+        }
+        self.indexes.insert(origin.clone(), ZoneIndex::new(&zone));
+        self.zones.insert(origin, zone);
+        Ok(())
+    }
+
+    fn update_zone(
+        &mut self,
+        origin: &str,
+        prerequisites: &[Prerequisite],
+        operations: &[UpdateOperation],
+    ) -> Result<bool, UpdateError> {
+        let Some(zone) = self.get_zone(origin) else {
+            return Err(UpdateError::NotAuth(origin.to_string()));
+        };
+        match zone.update(prerequisites, operations)? {
+            Some(updated) => {
+                self.replace_zone(updated).map_err(UpdateError::Failed)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove_zone(&mut self, origin: &str) -> Option<Zone> {
+        let origin = normalize(origin);
+        self.journals.remove(&origin);
+        self.indexes.remove(&origin);
+        self.zones.remove(&origin)
+    }
+
+    fn zone_changes_since(&self, origin: &str, serial: u32) -> Option<Vec<&ZoneDiff>> {
+        self.journals.get(&normalize(origin))?.changes_since(serial)
+    }
 }
\ No newline at end of file
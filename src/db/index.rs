@@ -0,0 +1,108 @@
+//! Lookups into a zone for answering queries, built once per version of the zone instead
+//! of scanning its records for every query.
+
+use std::collections::{HashMap, HashSet};
+
+use super::record::{RecordData, RecordType, ResourceRecord, Zone};
+use super::validation::normalize;
+use crate::dnssec::denial::{Denial, DenialChain};
+use crate::dnssec::{delegation_points, RRSIG};
+
+/// The records at a name, by the positions in `Zone::records`.
+#[derive(Default)]
+struct Node {
+    rrsets: HashMap<RecordType, Vec<usize>>,
+    /// RRSIG records by the type they cover.
+    signatures: HashMap<u16, Vec<usize>>,
+}
+
+/// The records of a zone by normalized owner name and type, its delegation points and its
+/// NSEC or NSEC3 chain. Records are referred to by their position in `Zone::records`, so
+/// an index only fits the version of the zone it was built from.
+pub struct ZoneIndex {
+    origin: String,
+    /// Every owner name and each of its ancestors, so that empty non-terminals exist.
+    names: HashMap<String, Node>,
+    cuts: HashSet<String>,
+    denial: DenialChain,
+}
+
+impl ZoneIndex {
+    pub fn new(zone: &Zone) -> Self {
+        let mut names: HashMap<String, Node> = HashMap::new();
+        for (position, record) in zone.records.iter().enumerate() {
+            let name = normalize(&record.name);
+            let mut ancestor = name.as_str();
+            while let Some((_, parent)) = ancestor.split_once('.').filter(|_| !names.contains_key(ancestor)) {
+                names.insert(ancestor.to_string(), Node::default());
+                ancestor = if parent.is_empty() { "." } else { parent };
+            }
+            let node = names.entry(name).or_default();
+            node.rrsets.entry(record.data.get_type()).or_default().push(position);
+            if let RecordData::Unknown { rtype: RRSIG, data } = &record.data
+                && let [high, low, ..] = data[..]
+            {
+                node.signatures.entry(u16::from_be_bytes([high, low])).or_default().push(position);
+            }
+        }
+        ZoneIndex {
+            origin: normalize(&zone.origin),
+            names,
+            cuts: delegation_points(zone).into_iter().collect(),
+            denial: DenialChain::new(zone),
+        }
+    }
+
+    /// Returns the records of a type at a normalized name.
+    pub fn rrset<'z>(&self, zone: &'z Zone, name: &str, rtype: &RecordType) -> Vec<&'z ResourceRecord> {
+        let positions = self.names.get(name).and_then(|node| node.rrsets.get(rtype));
+        positions.into_iter().flatten().map(|&position| &zone.records[position]).collect()
+    }
+
+    /// Returns the RRSIG records at a normalized name covering a type.
+    pub fn signatures<'z>(&self, zone: &'z Zone, name: &str, covered: u16) -> Vec<&'z ResourceRecord> {
+        let positions = self.names.get(name).and_then(|node| node.signatures.get(&covered));
+        positions.into_iter().flatten().map(|&position| &zone.records[position]).collect()
+    }
+
+    /// Returns whether a normalized name exists in the zone: it has records, or names
+    /// below it do (an empty non-terminal).
+    pub fn name_exists(&self, name: &str) -> bool {
+        self.names.contains_key(name)
+    }
+
+    /// Returns the closest encloser of a normalized name in the zone: the name itself or
+    /// its nearest ancestor that exists (RFC 5155, section 1.3).
+    pub fn closest_encloser(&self, name: &str) -> String {
+        let mut candidate = name;
+        while candidate != self.origin && !self.name_exists(candidate) {
+            match candidate.split_once('.') {
+                Some((_, "")) | None => return ".".to_string(),
+                Some((_, parent)) => candidate = parent,
+            }
+        }
+        candidate.to_string()
+    }
+
+    /// Returns the highest delegation point at or above a normalized name. The name
+    /// itself is left out unless `at_name` is set.
+    pub fn delegation<'n>(&self, name: &'n str, at_name: bool) -> Option<&'n str> {
+        let mut highest = None;
+        let mut candidate = name;
+        loop {
+            if (at_name || candidate != name) && self.cuts.contains(candidate) {
+                highest = Some(candidate);
+            }
+            match candidate.split_once('.') {
+                Some((_, "")) | None => return highest,
+                Some((_, parent)) => candidate = parent,
+            }
+        }
+    }
+
+    /// Returns the NSEC or NSEC3 records of a signed zone that prove `denial`, without
+    /// duplicates. Unsigned zones have none.
+    pub fn denial_records<'z>(&self, zone: &'z Zone, denial: &Denial) -> Vec<&'z ResourceRecord> {
+        self.denial.prove(zone, self, denial)
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::record::{RecordData, ResourceRecord, Zone};
+
+/// How many changes are kept per zone before the oldest are forgotten.
+pub const MAX_JOURNAL_ENTRIES: usize = 100;
+
+/// The changes that took a zone from one SOA serial to another (RFC 1995).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ZoneDiff {
+    pub old_soa: ResourceRecord,
+    pub new_soa: ResourceRecord,
+    /// Records removed from the zone, excluding the SOA.
+    pub deleted: Vec<ResourceRecord>,
+    /// Records added to the zone, excluding the SOA.
+    pub added: Vec<ResourceRecord>,
+}
+
+impl ZoneDiff {
+    /// Computes the changes between two versions of a zone. Returns `None` if either
+    /// version has no SOA record at its apex.
+    pub fn between(old: &Zone, new: &Zone) -> Option<ZoneDiff> {
+        let old_soa = old.soa()?.clone();
+        let new_soa = new.soa()?.clone();
+
+        let mut counts: HashMap<&ResourceRecord, i64> = HashMap::new();
+        for record in old.records.iter().filter(|rec| !is_soa(rec)) {
+            *counts.entry(record).or_default() -= 1;
+        }
+        for record in new.records.iter().filter(|rec| !is_soa(rec)) {
+            *counts.entry(record).or_default() += 1;
+        }
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+        // Keep the order of the zones so that diffs are deterministic.
+        for record in old.records.iter().chain(new.records.iter()) {
+            if let Some(count) = counts.get_mut(record) {
+                while *count < 0 {
+                    deleted.push(record.clone());
+                    *count += 1;
+                }
+                while *count > 0 {
+                    added.push(record.clone());
+                    *count -= 1;
+                }
+            }
+        }
+
+        Some(ZoneDiff { old_soa, new_soa, deleted, added })
+    }
+
+    pub fn old_serial(&self) -> u32 {
+        soa_serial(&self.old_soa)
+    }
+
+    pub fn new_serial(&self) -> u32 {
+        soa_serial(&self.new_soa)
+    }
+
+    /// Merges consecutive diffs into one, dropping records that were added and deleted
+    /// again in between (the condensed form of RFC 1995, section 5).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `diffs` is empty.
+    pub fn condense(diffs: &[&ZoneDiff]) -> ZoneDiff {
+        let mut deleted: Vec<ResourceRecord> = Vec::new();
+        let mut added: Vec<ResourceRecord> = Vec::new();
+        for diff in diffs {
+            for record in &diff.deleted {
+                match added.iter().position(|rec| rec == record) {
+                    Some(index) => {
+                        added.remove(index);
+                    }
+                    None => deleted.push(record.clone()),
+                }
+            }
+            for record in &diff.added {
+                match deleted.iter().position(|rec| rec == record) {
+                    Some(index) => {
+                        deleted.remove(index);
+                    }
+                    None => added.push(record.clone()),
+                }
+            }
+        }
+        ZoneDiff {
+            old_soa: diffs.first().unwrap().old_soa.clone(),
+            new_soa: diffs.last().unwrap().new_soa.clone(),
+            deleted,
+            added,
+        }
+    }
+}
+
+/// The recent history of changes to one zone.
+#[derive(Debug, Default, Clone)]
+pub struct Journal {
+    diffs: VecDeque<ZoneDiff>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Journal::default()
+    }
+
+    /// Appends a change, forgetting the oldest one if the journal is full.
+    pub fn record(&mut self, diff: ZoneDiff) {
+        if self.diffs.len() == MAX_JOURNAL_ENTRIES {
+            self.diffs.pop_front();
+        }
+        self.diffs.push_back(diff);
+    }
+
+    /// Returns the chain of changes from `serial` to the latest version, or `None` if the
+    /// journal does not reach back to `serial`.
+    pub fn changes_since(&self, serial: u32) -> Option<Vec<&ZoneDiff>> {
+        let start = self.diffs.iter().position(|diff| diff.old_serial() == serial)?;
+        let chain: Vec<&ZoneDiff> = self.diffs.iter().skip(start).collect();
+        let connected = chain.windows(2).all(|pair| pair[0].new_serial() == pair[1].old_serial());
+        if connected { Some(chain) } else { None }
+    }
+}
+
+/// Returns whether serial `a` is older than serial `b` in RFC 1982 serial number arithmetic.
+pub fn serial_lt(a: u32, b: u32) -> bool {
+    a != b && b.wrapping_sub(a) < 0x8000_0000
+}
+
+fn is_soa(record: &ResourceRecord) -> bool {
+    matches!(record.data, RecordData::SOA { .. })
+}
+
+fn soa_serial(record: &ResourceRecord) -> u32 {
+    match record.data {
+        RecordData::SOA { serial, .. } => serial,
+        _ => 0,
+    }
+}
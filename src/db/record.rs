@@ -1,5 +1,11 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::dnssec::presentation;
+
 /// Represents the type of a DNS record for querying.
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum RecordType {
@@ -12,41 +18,86 @@ pub enum RecordType {
     SOA,
     SRV,
     TXT,
+    /// A type the crate does not model, identified by its numeric code (RFC 3597).
+    Unknown(u16),
 }
 
-impl ToString for RecordType {
-    fn to_string(&self) -> String {
+impl RecordType {
+    /// Returns the numeric type code used on the wire.
+    pub fn code(&self) -> u16 {
         match self {
-            RecordType::A => "A".to_string(),
-            RecordType::AAAA => "AAAA".to_string(),
-            RecordType::CNAME => "CNAME".to_string(),
-            RecordType::MX => "MX".to_string(),
-            RecordType::NS => "NS".to_string(),
-            RecordType::PTR => "PTR".to_string(),
-            RecordType::SOA => "SOA".to_string(),
-            RecordType::SRV => "SRV".to_string(),
-            RecordType::TXT => "TXT".to_string(),
+            RecordType::A => 1,
+            RecordType::NS => 2,
+            RecordType::CNAME => 5,
+            RecordType::SOA => 6,
+            RecordType::PTR => 12,
+            RecordType::MX => 15,
+            RecordType::TXT => 16,
+            RecordType::AAAA => 28,
+            RecordType::SRV => 33,
+            RecordType::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<u16> for RecordType {
+    fn from(code: u16) -> Self {
+        match code {
+            1 => RecordType::A,
+            2 => RecordType::NS,
+            5 => RecordType::CNAME,
+            6 => RecordType::SOA,
+            12 => RecordType::PTR,
+            15 => RecordType::MX,
+            16 => RecordType::TXT,
+            28 => RecordType::AAAA,
+            33 => RecordType::SRV,
+            _ => RecordType::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordType::A => write!(f, "A"),
+            RecordType::AAAA => write!(f, "AAAA"),
+            RecordType::CNAME => write!(f, "CNAME"),
+            RecordType::MX => write!(f, "MX"),
+            RecordType::NS => write!(f, "NS"),
+            RecordType::PTR => write!(f, "PTR"),
+            RecordType::SOA => write!(f, "SOA"),
+            RecordType::SRV => write!(f, "SRV"),
+            RecordType::TXT => write!(f, "TXT"),
+            RecordType::Unknown(code) => match presentation::type_mnemonic(*code) {
+                Some(mnemonic) => write!(f, "{}", mnemonic),
+                None => write!(f, "TYPE{}", code),
+            },
         }
     }
 }
 
 /// Represents the class of a DNS record.
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default, Serialize, Deserialize)]
 pub enum RecordClass {
+    #[default]
     IN, // Internet
     // Add other classes if needed
 }
 
-impl ToString for RecordClass {
-    fn to_string(&self) -> String {
+impl fmt::Display for RecordClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            RecordClass::IN => "IN".to_string(),
+            RecordClass::IN => write!(f, "IN"),
         }
     }
 }
 
 /// Represents the data associated with a DNS record.
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+///
+/// In JSON and YAML the variant is the `type` field and its contents the `data` field.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
 pub enum RecordData {
     A(Ipv4Addr),
     AAAA(Ipv6Addr),
@@ -64,7 +115,29 @@ pub enum RecordData {
         minimum: u32,
     },
     SRV { priority: u16, weight: u16, port: u16, target: String },
-    TXT(String),
+    /// One entry per character-string, each at most 255 bytes long.
+    TXT(#[serde(deserialize_with = "character_strings")] Vec<String>),
+    /// Opaque RDATA of a type the crate does not model (RFC 3597).
+    Unknown { rtype: u16, data: Vec<u8> },
+}
+
+/// Reads TXT data from a structured zone, either a list of character-strings or a single
+/// one.
+fn character_strings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Strings {
+        One(String),
+        Many(Vec<String>),
+    }
+    let strings = match Strings::deserialize(deserializer)? {
+        Strings::One(string) => vec![string],
+        Strings::Many(strings) => strings,
+    };
+    if let Some(long) = strings.iter().find(|string| string.len() > 255) {
+        return Err(serde::de::Error::custom(format!("TXT string of {} bytes is longer than 255", long.len())));
+    }
+    Ok(strings)
 }
 
 impl RecordData {
@@ -79,23 +152,79 @@ impl RecordData {
             RecordData::SOA { .. } => RecordType::SOA,
             RecordData::SRV { .. } => RecordType::SRV,
             RecordData::TXT(_) => RecordType::TXT,
+            RecordData::Unknown { rtype, .. } => RecordType::Unknown(*rtype),
         }
     }
 }
 
 /// Represents a complete DNS resource record.
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct ResourceRecord {
     pub name: String,
     pub ttl: u32,
+    #[serde(default)]
     pub class: RecordClass,
+    #[serde(flatten)]
     pub data: RecordData,
 }
 
 /// Represents a DNS zone, containing its origin, default TTL, and resource records.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Zone {
     pub origin: String,
     pub ttl: u32, // Default TTL for records in this zone
     pub records: Vec<ResourceRecord>,
 }
+
+impl Zone {
+    /// Returns the SOA record at the zone apex, if there is one. Names are compared
+    /// case-insensitively, with or without the trailing dot.
+    pub fn soa(&self) -> Option<&ResourceRecord> {
+        let origin = self.origin.trim_end_matches('.');
+        self.records
+            .iter()
+            .find(|rec| matches!(rec.data, RecordData::SOA { .. }) && rec.name.trim_end_matches('.').eq_ignore_ascii_case(origin))
+    }
+
+    /// Returns the serial of the SOA record at the zone apex, if there is one.
+    pub fn serial(&self) -> Option<u32> {
+        match self.soa()?.data {
+            RecordData::SOA { serial, .. } => Some(serial),
+            _ => None,
+        }
+    }
+
+    /// Increments the serial of the SOA record at the zone apex, wrapping around as RFC
+    /// 1982 serial arithmetic allows.
+    pub fn increment_serial(&mut self) {
+        let origin = self.origin.trim_end_matches('.').to_string();
+        if let Some(RecordData::SOA { serial, .. }) = self
+            .records
+            .iter_mut()
+            .find(|rec| matches!(rec.data, RecordData::SOA { .. }) && rec.name.trim_end_matches('.').eq_ignore_ascii_case(&origin))
+            .map(|rec| &mut rec.data)
+        {
+            *serial = serial.wrapping_add(1);
+        }
+    }
+}
+
+/// Zones are equal when they have the same origin, default TTL and records; the order of
+/// the records is not significant.
+impl PartialEq for Zone {
+    fn eq(&self, other: &Self) -> bool {
+        if self.origin != other.origin || self.ttl != other.ttl || self.records.len() != other.records.len() {
+            return false;
+        }
+        let mut counts: HashMap<&ResourceRecord, i64> = HashMap::new();
+        for record in &self.records {
+            *counts.entry(record).or_default() += 1;
+        }
+        for record in &other.records {
+            *counts.entry(record).or_default() -= 1;
+        }
+        counts.values().all(|&count| count == 0)
+    }
+}
+
+impl Eq for Zone {}
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use super::record::{RecordData, RecordType, ResourceRecord, Zone};
+use super::validation::{is_in_zone, normalize, Severity, ZoneIssue};
+
+/// A condition that must hold for a dynamic update to be applied (RFC 2136, section 2.4).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prerequisite {
+    /// At least one record exists at the name.
+    NameInUse(String),
+    /// No record exists at the name.
+    NameNotInUse(String),
+    /// Records of the type exist at the name, whatever their data.
+    RRsetExists { name: String, rtype: RecordType },
+    /// No records of the type exist at the name.
+    RRsetDoesNotExist { name: String, rtype: RecordType },
+    /// The records of the type at the name have exactly this data.
+    RRsetEquals { name: String, rtype: RecordType, data: Vec<RecordData> },
+}
+
+/// A change made by a dynamic update (RFC 2136, section 2.5).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateOperation {
+    /// Adds a record, or updates the TTL of an existing one.
+    Add(ResourceRecord),
+    /// Deletes all records of a type at a name.
+    DeleteRRset { name: String, rtype: RecordType },
+    /// Deletes all records at a name.
+    DeleteName(String),
+    /// Deletes the record with this data.
+    DeleteRecord { name: String, data: RecordData },
+}
+
+impl UpdateOperation {
+    /// Returns the owner name the operation changes.
+    pub fn name(&self) -> &str {
+        match self {
+            UpdateOperation::Add(record) => &record.name,
+            UpdateOperation::DeleteRRset { name, .. }
+            | UpdateOperation::DeleteName(name)
+            | UpdateOperation::DeleteRecord { name, .. } => name,
+        }
+    }
+}
+
+/// Why a dynamic update was rejected. Each variant corresponds to a response code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpdateError {
+    /// NXDOMAIN: a name that should be in use is not.
+    NameNotInUse(String),
+    /// YXDOMAIN: a name that should not be in use is.
+    NameInUse(String),
+    /// NXRRSET: an RRset that should exist does not, or has different data.
+    RRsetMissing(String),
+    /// YXRRSET: an RRset that should not exist does.
+    RRsetExists(String),
+    /// NOTZONE: a name is outside the zone.
+    NotZone(String),
+    /// NOTAUTH: the zone is not loaded.
+    NotAuth(String),
+    /// REFUSED: the updated zone would fail validation, e.g. lose the glue of its name
+    /// servers.
+    Invalid(String),
+    /// SERVFAIL: the updated zone could not be stored.
+    Failed(String),
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::NameNotInUse(name) => write!(f, "{} is not in use", name),
+            UpdateError::NameInUse(name) => write!(f, "{} is in use", name),
+            UpdateError::RRsetMissing(name) => write!(f, "required RRset at {} does not match", name),
+            UpdateError::RRsetExists(name) => write!(f, "RRset at {} exists", name),
+            UpdateError::NotZone(name) => write!(f, "{} is outside the zone", name),
+            UpdateError::NotAuth(origin) => write!(f, "zone {} is not loaded", origin),
+            UpdateError::Invalid(message) => write!(f, "updated zone is invalid: {}", message),
+            UpdateError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Zone {
+    /// Checks the prerequisites and applies the operations to a copy of the zone.
+    ///
+    /// The operations follow RFC 2136, section 3.4.2: the SOA record and the apex NS
+    /// RRset cannot be deleted, a CNAME cannot be added next to other data or the other
+    /// way round, and an added SOA only replaces the current one if its serial is newer.
+    /// If the records changed but the serial did not, the serial is incremented. Returns
+    /// `None` if the update leaves the zone as it is, and an error if the updated zone
+    /// has `Severity::Error` issues the zone did not have before.
+    pub fn update(&self, prerequisites: &[Prerequisite], operations: &[UpdateOperation]) -> Result<Option<Zone>, UpdateError> {
+        let origin = normalize(&self.origin);
+        for name in prerequisites.iter().map(prerequisite_name).chain(operations.iter().map(UpdateOperation::name)) {
+            if !is_in_zone(&normalize(name), &origin) {
+                return Err(UpdateError::NotZone(name.to_string()));
+            }
+        }
+        for prerequisite in prerequisites {
+            self.check(prerequisite)?;
+        }
+
+        let mut zone = self.clone();
+        for operation in operations {
+            zone.apply(operation, &origin);
+        }
+        if zone == *self {
+            return Ok(None);
+        }
+        if zone.serial() == self.serial() {
+            zone.increment_serial();
+        }
+
+        let errors = |zone: &Zone| -> Vec<ZoneIssue> {
+            zone.validate().into_iter().filter(|issue| issue.severity() == Severity::Error).collect()
+        };
+        let before = errors(self);
+        let introduced = errors(&zone)
+            .into_iter()
+            .find(|issue| !before.iter().any(|old| old.kind == issue.kind && same_name(&old.name, &issue.name)));
+        if let Some(issue) = introduced {
+            return Err(UpdateError::Invalid(format!("{}: {}", issue.name, issue.message)));
+        }
+        Ok(Some(zone))
+    }
+
+    fn check(&self, prerequisite: &Prerequisite) -> Result<(), UpdateError> {
+        match prerequisite {
+            Prerequisite::NameInUse(name) => {
+                if !self.records.iter().any(|record| same_name(&record.name, name)) {
+                    return Err(UpdateError::NameNotInUse(name.clone()));
+                }
+            }
+            Prerequisite::NameNotInUse(name) => {
+                if self.records.iter().any(|record| same_name(&record.name, name)) {
+                    return Err(UpdateError::NameInUse(name.clone()));
+                }
+            }
+            Prerequisite::RRsetExists { name, rtype } => {
+                if self.rrset(name, rtype).is_empty() {
+                    return Err(UpdateError::RRsetMissing(name.clone()));
+                }
+            }
+            Prerequisite::RRsetDoesNotExist { name, rtype } => {
+                if !self.rrset(name, rtype).is_empty() {
+                    return Err(UpdateError::RRsetExists(name.clone()));
+                }
+            }
+            Prerequisite::RRsetEquals { name, rtype, data } => {
+                // An RRset is a set: compare without duplicates or order.
+                let expected: HashSet<&RecordData> = data.iter().collect();
+                let actual: HashSet<&RecordData> = self.rrset(name, rtype).into_iter().collect();
+                if actual != expected {
+                    return Err(UpdateError::RRsetMissing(name.clone()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the data of the records of a type at a name.
+    fn rrset(&self, name: &str, rtype: &RecordType) -> Vec<&RecordData> {
+        self.records
+            .iter()
+            .filter(|record| same_name(&record.name, name) && record.data.get_type() == *rtype)
+            .map(|record| &record.data)
+            .collect()
+    }
+
+    fn apply(&mut self, operation: &UpdateOperation, origin: &str) {
+        match operation {
+            UpdateOperation::Add(new) => {
+                let at_name = |record: &&ResourceRecord| same_name(&record.name, &new.name);
+                let is_cname = |record: &&ResourceRecord| matches!(record.data, RecordData::CNAME(_));
+                match &new.data {
+                    RecordData::SOA { serial, .. } => {
+                        if !same_name(&new.name, origin) {
+                            return;
+                        }
+                        if self.serial().is_some_and(|current| !super::journal::serial_lt(current, *serial)) {
+                            return;
+                        }
+                        self.records.retain(|record| !is_apex_soa(record, origin));
+                    }
+                    RecordData::CNAME(_) => {
+                        if self.records.iter().filter(at_name).any(|record| !is_cname(&record)) {
+                            return;
+                        }
+                        self.records.retain(|record| !(same_name(&record.name, &new.name) && is_cname(&record)));
+                    }
+                    _ => {
+                        if self.records.iter().filter(at_name).any(|record| is_cname(&record)) {
+                            return;
+                        }
+                    }
+                }
+                match self.records.iter_mut().find(|record| same_name(&record.name, &new.name) && record.data == new.data) {
+                    Some(existing) => existing.ttl = new.ttl,
+                    None => self.records.push(new.clone()),
+                }
+            }
+            UpdateOperation::DeleteRRset { name, rtype } => {
+                let at_apex = same_name(name, origin);
+                if at_apex && matches!(rtype, RecordType::SOA | RecordType::NS) {
+                    return;
+                }
+                self.records.retain(|record| !(same_name(&record.name, name) && record.data.get_type() == *rtype));
+            }
+            UpdateOperation::DeleteName(name) => {
+                let at_apex = same_name(name, origin);
+                self.records.retain(|record| {
+                    !same_name(&record.name, name)
+                        || (at_apex && matches!(record.data, RecordData::SOA { .. } | RecordData::NS(_)))
+                });
+            }
+            UpdateOperation::DeleteRecord { name, data } => {
+                if matches!(data, RecordData::SOA { .. }) {
+                    return;
+                }
+                if same_name(name, origin) && matches!(data, RecordData::NS(_)) && self.rrset(name, &RecordType::NS).len() <= 1 {
+                    return;
+                }
+                self.records.retain(|record| !(same_name(&record.name, name) && record.data == *data));
+            }
+        }
+    }
+}
+
+fn prerequisite_name(prerequisite: &Prerequisite) -> &str {
+    match prerequisite {
+        Prerequisite::NameInUse(name)
+        | Prerequisite::NameNotInUse(name)
+        | Prerequisite::RRsetExists { name, .. }
+        | Prerequisite::RRsetDoesNotExist { name, .. }
+        | Prerequisite::RRsetEquals { name, .. } => name,
+    }
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    normalize(a) == normalize(b)
+}
+
+fn is_apex_soa(record: &ResourceRecord, origin: &str) -> bool {
+    matches!(record.data, RecordData::SOA { .. }) && same_name(&record.name, origin)
+}
@@ -0,0 +1,186 @@
+use std::collections::{HashMap, HashSet};
+
+use super::record::{RecordData, RecordType, ResourceRecord, Zone};
+use crate::dnssec::{NSEC, RRSIG};
+
+/// How serious a problem found by `Zone::validate` is.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    /// The zone must not be served.
+    Error,
+    /// The zone can be served but is probably not what was intended.
+    Warning,
+}
+
+/// The kind of problem found by `Zone::validate`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ZoneIssueKind {
+    MissingSoa,
+    MultipleSoa,
+    SoaNotAtApex,
+    MissingApexNs,
+    CnameWithOtherData,
+    MissingGlue,
+    OutOfZone,
+    DuplicateRecord,
+    TtlMismatch,
+}
+
+impl ZoneIssueKind {
+    pub fn severity(&self) -> Severity {
+        match self {
+            ZoneIssueKind::DuplicateRecord | ZoneIssueKind::TtlMismatch => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+/// A problem found by `Zone::validate`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ZoneIssue {
+    pub kind: ZoneIssueKind,
+    /// The owner name the problem was found at.
+    pub name: String,
+    pub message: String,
+}
+
+impl ZoneIssue {
+    fn new(kind: ZoneIssueKind, name: &str, message: String) -> Self {
+        ZoneIssue {
+            kind,
+            name: name.to_string(),
+            message,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+impl Zone {
+    /// Runs semantic checks on a parsed zone, similar to `named-checkzone`.
+    ///
+    /// Returns every problem found; the zone should only be served if none of them has
+    /// `Severity::Error`.
+    pub fn validate(&self) -> Vec<ZoneIssue> {
+        let mut issues = Vec::new();
+        let origin = normalize(&self.origin);
+
+        let mut by_name: HashMap<String, Vec<&ResourceRecord>> = HashMap::new();
+        for record in &self.records {
+            by_name.entry(normalize(&record.name)).or_default().push(record);
+        }
+
+        let soas: Vec<&ResourceRecord> = self.records.iter()
+            .filter(|rec| rec.data.get_type() == RecordType::SOA)
+            .collect();
+        match soas.len() {
+            0 => issues.push(ZoneIssue::new(ZoneIssueKind::MissingSoa, &self.origin, "Zone has no SOA record".to_string())),
+            1 => {}
+            n => issues.push(ZoneIssue::new(ZoneIssueKind::MultipleSoa, &self.origin, format!("Zone has {} SOA records", n))),
+        }
+        for soa in &soas {
+            if normalize(&soa.name) != origin {
+                issues.push(ZoneIssue::new(
+                    ZoneIssueKind::SoaNotAtApex,
+                    &soa.name,
+                    format!("SOA record is not at the zone apex {}", self.origin),
+                ));
+            }
+        }
+
+        let apex_has_ns = by_name.get(&origin)
+            .is_some_and(|recs| recs.iter().any(|rec| rec.data.get_type() == RecordType::NS));
+        if !apex_has_ns {
+            issues.push(ZoneIssue::new(ZoneIssueKind::MissingApexNs, &self.origin, "Zone apex has no NS records".to_string()));
+        }
+
+        let mut names: Vec<&String> = by_name.keys().collect();
+        names.sort();
+        for name in names {
+            let records = &by_name[name];
+            let owner = &records[0].name;
+
+            if !is_in_zone(name, &origin) {
+                issues.push(ZoneIssue::new(
+                    ZoneIssueKind::OutOfZone,
+                    owner,
+                    format!("Record is outside the zone {}", self.origin),
+                ));
+            }
+
+            // RRSIG and NSEC records may accompany a CNAME (RFC 4035, section 2.5).
+            let cnames = records.iter().filter(|rec| rec.data.get_type() == RecordType::CNAME).count();
+            let others = records.iter().filter(|rec| !matches!(rec.data.get_type().code(), RRSIG | NSEC)).count();
+            if cnames > 0 && others > 1 {
+                issues.push(ZoneIssue::new(
+                    ZoneIssueKind::CnameWithOtherData,
+                    owner,
+                    "CNAME record exists alongside other data".to_string(),
+                ));
+            }
+
+            let mut seen = HashSet::new();
+            // RRSIG records form one RRset per covered type as far as TTLs are concerned.
+            let mut rrset_ttls: HashMap<(RecordType, u16), u32> = HashMap::new();
+            for record in records {
+                if !seen.insert((&record.class, &record.data)) {
+                    issues.push(ZoneIssue::new(
+                        ZoneIssueKind::DuplicateRecord,
+                        owner,
+                        format!("Duplicate {} record", record.data.get_type()),
+                    ));
+                }
+                let rtype = record.data.get_type();
+                let covered = match &record.data {
+                    RecordData::Unknown { rtype: RRSIG, data } if data.len() >= 2 => u16::from_be_bytes([data[0], data[1]]),
+                    _ => 0,
+                };
+                match rrset_ttls.get(&(rtype.clone(), covered)) {
+                    Some(&ttl) if ttl != record.ttl => issues.push(ZoneIssue::new(
+                        ZoneIssueKind::TtlMismatch,
+                        owner,
+                        format!("{} RRset has TTLs {} and {}", rtype, ttl, record.ttl),
+                    )),
+                    Some(_) => {}
+                    None => {
+                        rrset_ttls.insert((rtype, covered), record.ttl);
+                    }
+                }
+            }
+
+            for record in records {
+                if let RecordData::NS(target) = &record.data {
+                    let target = normalize(target);
+                    if !is_in_zone(&target, &origin) {
+                        continue;
+                    }
+                    let has_address = by_name.get(&target).is_some_and(|recs| {
+                        recs.iter().any(|rec| matches!(rec.data, RecordData::A(_) | RecordData::AAAA(_)))
+                    });
+                    if !has_address {
+                        issues.push(ZoneIssue::new(
+                            ZoneIssueKind::MissingGlue,
+                            owner,
+                            format!("NS target {} is inside the zone but has no A or AAAA records", target),
+                        ));
+                    }
+                }
+            }
+        }
+
+        issues
+    }
+}
+
+/// Lowercases a name and makes sure it ends with a dot, for comparisons.
+pub(crate) fn normalize(name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    if name.ends_with('.') { name } else { format!("{}.", name) }
+}
+
+/// Returns whether normalized `name` is `origin` or below it.
+pub(crate) fn is_in_zone(name: &str, origin: &str) -> bool {
+    origin == "." || name == origin || name.ends_with(&format!(".{}", origin))
+}
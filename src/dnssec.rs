@@ -0,0 +1,66 @@
+//! DNSSEC signing of zones (RFC 4033, 4034 and 4035).
+//!
+//! The crate does not model the DNSSEC record types, so DNSKEY, RRSIG, NSEC and NSEC3
+//! records are kept as RFC 3597 opaque RDATA (`RecordData::Unknown`) in wire format.
+
+pub mod canonical;
+pub mod denial;
+pub mod key;
+pub mod keystore;
+pub mod nsec;
+pub mod nsec3;
+pub mod presentation;
+pub mod sign;
+
+pub use self::key::{Algorithm, SigningKey};
+pub use self::keystore::{KeyPolicy, KeyStore};
+pub use self::nsec3::Nsec3Params;
+pub use self::sign::Signer;
+
+use crate::db::validation::{is_in_zone, normalize};
+use crate::db::{RecordData, ResourceRecord, Zone};
+
+/// Type code of DS records.
+pub const DS: u16 = 43;
+/// Type code of RRSIG records.
+pub const RRSIG: u16 = 46;
+/// Type code of NSEC records.
+pub const NSEC: u16 = 47;
+/// Type code of DNSKEY records.
+pub const DNSKEY: u16 = 48;
+/// Type code of NSEC3 records.
+pub const NSEC3: u16 = 50;
+/// Type code of NSEC3PARAM records.
+pub const NSEC3PARAM: u16 = 51;
+
+/// Returns the normalized names below the apex that have NS records: the points where
+/// the zone delegates to child zones.
+pub(crate) fn delegation_points(zone: &Zone) -> Vec<String> {
+    let origin = normalize(&zone.origin);
+    let mut cuts: Vec<String> = zone
+        .records
+        .iter()
+        .filter(|record| matches!(record.data, RecordData::NS(_)))
+        .map(|record| normalize(&record.name))
+        .filter(|name| *name != origin)
+        .collect();
+    cuts.sort();
+    cuts.dedup();
+    cuts
+}
+
+/// Returns whether a normalized name lies strictly below one of the delegation points, so
+/// that its records are glue rather than data the zone is authoritative for.
+pub(crate) fn is_below_cut(name: &str, cuts: &[String]) -> bool {
+    cuts.iter().any(|cut| name != cut && is_in_zone(name, cut))
+}
+
+/// Returns whether the zone is authoritative for a record and therefore signs it: not
+/// glue, and at a delegation point only DS and NSEC records.
+pub(crate) fn is_authoritative(record: &ResourceRecord, cuts: &[String]) -> bool {
+    let name = normalize(&record.name);
+    if is_below_cut(&name, cuts) {
+        return false;
+    }
+    !cuts.contains(&name) || matches!(record.data.get_type().code(), DS | NSEC)
+}
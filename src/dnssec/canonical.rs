@@ -0,0 +1,92 @@
+//! The canonical form and order of names and records (RFC 4034, section 6).
+
+use std::cmp::Ordering;
+
+use crate::db::{RecordData, ResourceRecord};
+
+/// Encodes a name in lowercase uncompressed wire form.
+pub fn name_to_wire(name: &str) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in labels(name) {
+        wire.push(label.len() as u8);
+        wire.extend(label.bytes().map(|byte| byte.to_ascii_lowercase()));
+    }
+    wire.push(0);
+    wire
+}
+
+/// Returns the labels of a name from left to right, without the root label.
+fn labels(name: &str) -> impl DoubleEndedIterator<Item = &str> {
+    name.split('.').filter(|label| !label.is_empty())
+}
+
+/// Returns the number of labels in a name as counted by the RRSIG labels field: the root
+/// label and a leading wildcard label are not counted.
+pub fn label_count(name: &str) -> u8 {
+    labels(name).filter(|label| *label != "*").count() as u8
+}
+
+/// Compares names in canonical DNS order: label by label from the right, each label as
+/// lowercase bytes.
+pub fn compare_names(a: &str, b: &str) -> Ordering {
+    let a = labels(a).rev().map(|label| label.to_ascii_lowercase());
+    let b = labels(b).rev().map(|label| label.to_ascii_lowercase());
+    a.cmp(b)
+}
+
+/// Encodes RDATA in canonical wire form, with the names of the types listed in RFC 4034,
+/// section 6.2 in lowercase.
+pub fn rdata_to_wire(data: &RecordData) -> Vec<u8> {
+    let mut wire = Vec::new();
+    match data {
+        RecordData::A(addr) => wire.extend_from_slice(&addr.octets()),
+        RecordData::AAAA(addr) => wire.extend_from_slice(&addr.octets()),
+        RecordData::CNAME(name) | RecordData::NS(name) | RecordData::PTR(name) => wire.extend(name_to_wire(name)),
+        RecordData::MX { preference, exchange } => {
+            wire.extend_from_slice(&preference.to_be_bytes());
+            wire.extend(name_to_wire(exchange));
+        }
+        RecordData::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+            wire.extend(name_to_wire(mname));
+            wire.extend(name_to_wire(rname));
+            for value in [serial, refresh, retry, expire, minimum] {
+                wire.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        RecordData::SRV { priority, weight, port, target } => {
+            for value in [priority, weight, port] {
+                wire.extend_from_slice(&value.to_be_bytes());
+            }
+            wire.extend(name_to_wire(target));
+        }
+        RecordData::TXT(strings) => {
+            for text in strings {
+                wire.push(text.len() as u8);
+                wire.extend_from_slice(text.as_bytes());
+            }
+        }
+        RecordData::Unknown { data, .. } => wire.extend_from_slice(data),
+    }
+    wire
+}
+
+/// Encodes an RRset in the canonical form that RRSIG signatures cover (RFC 4034,
+/// section 3.1.8.1): every record with a lowercase owner name and `ttl`, ordered by
+/// RDATA, without duplicates.
+pub fn rrset_to_wire(records: &[&ResourceRecord], ttl: u32) -> Vec<u8> {
+    let mut rdatas: Vec<Vec<u8>> = records.iter().map(|record| rdata_to_wire(&record.data)).collect();
+    rdatas.sort();
+    rdatas.dedup();
+
+    let mut wire = Vec::new();
+    for rdata in rdatas {
+        let record = records[0];
+        wire.extend(name_to_wire(&record.name));
+        wire.extend_from_slice(&record.data.get_type().code().to_be_bytes());
+        wire.extend_from_slice(&1u16.to_be_bytes());
+        wire.extend_from_slice(&ttl.to_be_bytes());
+        wire.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        wire.extend(rdata);
+    }
+    wire
+}
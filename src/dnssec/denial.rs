@@ -0,0 +1,201 @@
+//! Proofs of non-existence: the NSEC or NSEC3 records that show a name or type does not
+//! exist, for negative answers and answers synthesized from wildcards (RFC 4035, section
+//! 3.1.3, and RFC 5155, section 7.2).
+
+use super::canonical::compare_names;
+use super::nsec3::Nsec3Params;
+use super::{NSEC, NSEC3, NSEC3PARAM};
+use crate::db::validation::normalize;
+use crate::db::{RecordData, ResourceRecord, Zone, ZoneIndex};
+
+/// What a response has to prove. Names are normalized.
+pub enum Denial<'a> {
+    /// The name does not exist and no wildcard matches it.
+    NxDomain(&'a str),
+    /// The name exists but has no records of the queried type.
+    NoData(&'a str),
+    /// The answer was synthesized from the wildcard, so the name itself must not exist.
+    Wildcard { name: &'a str, wildcard: &'a str },
+    /// The wildcard matches the name but has no records of the queried type.
+    WildcardNoData { name: &'a str, wildcard: &'a str },
+}
+
+/// Returns the NSEC or NSEC3 records of a signed zone that prove `denial`, without
+/// duplicates. Unsigned zones have none. This indexes the whole zone; answers to queries
+/// use the index kept with the zone instead.
+pub fn denial_records<'z>(zone: &'z Zone, denial: &Denial) -> Vec<&'z ResourceRecord> {
+    ZoneIndex::new(zone).denial_records(zone, denial)
+}
+
+/// The NSEC or NSEC3 chain of a zone, sorted once so that proofs are binary searches.
+/// Records are referred to by their position in `Zone::records`.
+pub struct DenialChain(Chain);
+
+enum Chain {
+    Nsec(NsecChain),
+    Nsec3(Nsec3Chain),
+}
+
+impl DenialChain {
+    /// Collects the NSEC3 chain if the apex has an NSEC3PARAM record, and the NSEC
+    /// chain otherwise.
+    pub fn new(zone: &Zone) -> Self {
+        let origin = normalize(&zone.origin);
+        let nsec3param = zone.records.iter().find_map(|record| match &record.data {
+            RecordData::Unknown { rtype: NSEC3PARAM, data } if normalize(&record.name) == origin => Nsec3Params::parse(data),
+            _ => None,
+        });
+        match nsec3param {
+            Some(params) => DenialChain(Chain::Nsec3(Nsec3Chain::new(zone, params))),
+            None => DenialChain(Chain::Nsec(NsecChain::new(zone))),
+        }
+    }
+
+    /// Returns the records of the chain that prove `denial`, without duplicates. The
+    /// chain and `index` must have been built from `zone`.
+    pub fn prove<'z>(&self, zone: &'z Zone, index: &ZoneIndex, denial: &Denial) -> Vec<&'z ResourceRecord> {
+        let positions = match &self.0 {
+            Chain::Nsec(chain) => chain.prove(index, denial),
+            Chain::Nsec3(chain) => chain.prove(denial),
+        };
+        let mut unique: Vec<usize> = Vec::with_capacity(positions.len());
+        for position in positions {
+            if !unique.contains(&position) {
+                unique.push(position);
+            }
+        }
+        unique.into_iter().map(|position| &zone.records[position]).collect()
+    }
+}
+
+/// Returns the ancestor of `name` one label below `encloser`: the next closer name.
+fn next_closer(name: &str, encloser: &str) -> String {
+    let mut candidate = name;
+    while let Some((_, parent)) = candidate.split_once('.') {
+        let parent = if parent.is_empty() { "." } else { parent };
+        if parent == encloser {
+            break;
+        }
+        candidate = parent;
+    }
+    candidate.to_string()
+}
+
+/// Returns the wildcard name directly below `encloser`.
+fn wildcard_at(encloser: &str) -> String {
+    if encloser == "." { "*.".to_string() } else { format!("*.{}", encloser) }
+}
+
+/// The NSEC records of a zone in canonical order.
+struct NsecChain {
+    records: Vec<usize>,
+    /// The owner names of the records, in the same order.
+    names: Vec<String>,
+}
+
+impl NsecChain {
+    fn new(zone: &Zone) -> Self {
+        let mut records: Vec<usize> = (0..zone.records.len())
+            .filter(|&position| matches!(zone.records[position].data, RecordData::Unknown { rtype: NSEC, .. }))
+            .collect();
+        records.sort_by(|&a, &b| compare_names(&zone.records[a].name, &zone.records[b].name));
+        let names = records.iter().map(|&position| zone.records[position].name.clone()).collect();
+        NsecChain { records, names }
+    }
+
+    /// Returns the NSEC record at `name`, or the one covering it if there is none.
+    fn covering(&self, name: &str) -> Option<usize> {
+        let after = self.names.partition_point(|owner| compare_names(owner, name).is_le());
+        self.records.get(after.wrapping_sub(1)).or(self.records.last()).copied()
+    }
+
+    fn prove(&self, index: &ZoneIndex, denial: &Denial) -> Vec<usize> {
+        let names = match denial {
+            Denial::NxDomain(name) => vec![name.to_string(), wildcard_at(&index.closest_encloser(name))],
+            // At an empty non-terminal, the record covering the name proves that it has
+            // no types.
+            Denial::NoData(name) | Denial::Wildcard { name, .. } => vec![name.to_string()],
+            Denial::WildcardNoData { name, wildcard } => vec![name.to_string(), wildcard.to_string()],
+        };
+        names.iter().filter_map(|name| self.covering(name)).collect()
+    }
+}
+
+/// The NSEC3 records of a zone, sorted by hashed owner label.
+struct Nsec3Chain {
+    params: Nsec3Params,
+    records: Vec<(String, usize)>,
+}
+
+impl Nsec3Chain {
+    fn new(zone: &Zone, params: Nsec3Params) -> Self {
+        let mut records: Vec<(String, usize)> = zone
+            .records
+            .iter()
+            .enumerate()
+            .filter(|(_, record)| matches!(record.data, RecordData::Unknown { rtype: NSEC3, .. }))
+            .map(|(position, record)| (record.name.split('.').next().unwrap_or_default().to_ascii_lowercase(), position))
+            .collect();
+        records.sort();
+        Nsec3Chain { params, records }
+    }
+
+    /// Returns the NSEC3 record at the hash of `name`, if there is one.
+    fn matching(&self, name: &str) -> Option<usize> {
+        let label = self.params.hashed_label(name);
+        let found = self.records.binary_search_by(|(hash, _)| hash.as_str().cmp(&label));
+        found.ok().map(|at| self.records[at].1)
+    }
+
+    /// Returns the NSEC3 record whose interval covers the hash of `name`.
+    fn covering(&self, name: &str) -> Option<usize> {
+        let label = self.params.hashed_label(name);
+        let after = self.records.partition_point(|(hash, _)| *hash <= label);
+        self.records.get(after.wrapping_sub(1)).or(self.records.last()).map(|(_, position)| *position)
+    }
+
+    /// Proves the closest encloser of a name that does not exist: the NSEC3 record
+    /// matching the nearest ancestor that has one, and the one covering the next closer
+    /// name. Returns the encloser and the records.
+    fn closest_provable_encloser(&self, name: &str) -> (String, Vec<usize>) {
+        let mut encloser = name;
+        loop {
+            if let Some(record) = self.matching(encloser) {
+                let mut records = vec![record];
+                if encloser != name {
+                    records.extend(self.covering(&next_closer(name, encloser)));
+                }
+                return (encloser.to_string(), records);
+            }
+            match encloser.split_once('.') {
+                Some((_, parent)) if !parent.is_empty() => encloser = parent,
+                _ => return (encloser.to_string(), Vec::new()),
+            }
+        }
+    }
+
+    fn prove(&self, denial: &Denial) -> Vec<usize> {
+        match denial {
+            Denial::NxDomain(name) => {
+                let (encloser, mut records) = self.closest_provable_encloser(name);
+                records.extend(self.covering(&wildcard_at(&encloser)));
+                records
+            }
+            // Without a matching record, the name is an unsigned delegation left out by
+            // opt-out: the covering opt-out record shows that it may exist unsigned.
+            Denial::NoData(name) => match self.matching(name) {
+                Some(record) => vec![record],
+                None => self.closest_provable_encloser(name).1,
+            },
+            Denial::Wildcard { name, wildcard } => {
+                let encloser = wildcard.strip_prefix("*.").unwrap_or(".");
+                self.covering(&next_closer(name, encloser)).into_iter().collect()
+            }
+            Denial::WildcardNoData { name, wildcard } => {
+                let (_, mut records) = self.closest_provable_encloser(name);
+                records.extend(self.matching(wildcard));
+                records
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! DNSSEC signing keys.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use data_encoding::BASE64;
+use ed25519_dalek::Verifier;
+use p256::ecdsa::signature::Signer;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Deserialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use super::canonical::name_to_wire;
+use super::presentation::format_rdata;
+use super::DS;
+use crate::db::validation::normalize;
+
+/// DNSKEY flags of a zone key (RFC 4034, section 2.1.1).
+pub const ZONE_KEY: u16 = 256;
+/// DNSKEY flag marking a key signing key, the key referred to by the parent's DS record.
+pub const SECURE_ENTRY_POINT: u16 = 1;
+
+/// DS digest type of SHA-1 (RFC 4034, appendix A.2).
+pub const DIGEST_SHA1: u8 = 1;
+/// DS digest type of SHA-256 (RFC 4509), the one the server hands to parents.
+pub const DIGEST_SHA256: u8 = 2;
+
+/// The signing algorithms the server implements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    /// ECDSA with curve P-256 and SHA-256 (RFC 6605), algorithm 13.
+    EcdsaP256Sha256,
+    /// Ed25519 (RFC 8080), algorithm 15.
+    Ed25519,
+}
+
+impl Algorithm {
+    /// Returns the algorithm number used in DNSKEY, RRSIG and DS records.
+    pub fn number(self) -> u8 {
+        match self {
+            Algorithm::EcdsaP256Sha256 => 13,
+            Algorithm::Ed25519 => 15,
+        }
+    }
+
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            13 => Some(Algorithm::EcdsaP256Sha256),
+            15 => Some(Algorithm::Ed25519),
+            _ => None,
+        }
+    }
+
+    /// Returns the mnemonic used in BIND key files.
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Algorithm::EcdsaP256Sha256 => "ECDSAP256SHA256",
+            Algorithm::Ed25519 => "ED25519",
+        }
+    }
+}
+
+/// A private key the server signs zones with, together with its DNSKEY flags.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SigningKey {
+    pub algorithm: Algorithm,
+    pub flags: u16,
+    private_key: [u8; 32],
+    public_key: Vec<u8>,
+    keypair: KeyPair,
+}
+
+/// The private key in the form the signature crates use.
+#[derive(Clone, PartialEq, Eq)]
+enum KeyPair {
+    Ecdsa(p256::ecdsa::SigningKey),
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl SigningKey {
+    /// Creates a key from its 32 private key bytes: the scalar for ECDSA, the seed for
+    /// Ed25519.
+    pub fn new(algorithm: Algorithm, private_key: [u8; 32], flags: u16) -> Result<Self, String> {
+        let (keypair, public_key) = match algorithm {
+            Algorithm::EcdsaP256Sha256 => {
+                let key = p256::ecdsa::SigningKey::from_slice(&private_key).map_err(|_| "ECDSA private key out of range")?;
+                // DNSKEY records hold the point without the SEC1 uncompressed prefix
+                // (RFC 6605, section 4).
+                let public_key = key.verifying_key().to_encoded_point(false).as_bytes()[1..].to_vec();
+                (KeyPair::Ecdsa(key), public_key)
+            }
+            Algorithm::Ed25519 => {
+                let key = ed25519_dalek::SigningKey::from_bytes(&private_key);
+                let public_key = key.verifying_key().to_bytes().to_vec();
+                (KeyPair::Ed25519(key), public_key)
+            }
+        };
+        Ok(SigningKey { algorithm, flags, private_key, public_key, keypair })
+    }
+
+    /// Generates a new random key from the operating system's random number generator.
+    pub fn generate(algorithm: Algorithm, flags: u16) -> Self {
+        loop {
+            let mut private_key = [0; 32];
+            OsRng.fill_bytes(&mut private_key);
+            // Only ECDSA rejects keys, when the scalar is zero or not below the group order.
+            if let Ok(key) = SigningKey::new(algorithm, private_key, flags) {
+                return key;
+            }
+        }
+    }
+
+    /// Reads a private key in the format BIND's `dnssec-keygen` writes
+    /// (`K<zone>+<alg>+<tag>.private`).
+    pub fn load(path: &Path, flags: u16) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        SigningKey::parse(&content, flags).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Parses the contents of a BIND private key file.
+    pub fn parse(content: &str, flags: u16) -> Result<Self, String> {
+        let field = |name: &str| {
+            content
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+                .ok_or(format!("missing field {}", name))
+        };
+        let number = field("Algorithm")?.split_whitespace().next().unwrap_or_default();
+        let algorithm = number
+            .parse()
+            .ok()
+            .and_then(Algorithm::from_number)
+            .ok_or(format!("unsupported algorithm {}", number))?;
+        let private_key = BASE64.decode(field("PrivateKey")?.as_bytes()).map_err(|e| format!("invalid PrivateKey: {}", e))?;
+        let private_key: [u8; 32] = private_key.try_into().map_err(|_| "PrivateKey must be 32 bytes".to_string())?;
+        SigningKey::new(algorithm, private_key, flags)
+    }
+
+    /// Writes the key to a new private key file that only the owner can read. Existing
+    /// files are not overwritten.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        file.write_all(self.to_private_file().as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Returns the name BIND gives the key's private key file for zone `origin`.
+    pub fn file_name(&self, origin: &str) -> String {
+        format!("K{}+{:03}+{:05}.private", normalize(origin), self.algorithm.number(), self.key_tag())
+    }
+
+    /// Renders the key in the format read by `SigningKey::parse`.
+    pub fn to_private_file(&self) -> String {
+        format!(
+            "Private-key-format: v1.3\nAlgorithm: {} ({})\nPrivateKey: {}\n",
+            self.algorithm.number(),
+            self.algorithm.mnemonic(),
+            BASE64.encode(&self.private_key)
+        )
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Returns whether the key is a key signing key, which only signs the DNSKEY RRset.
+    pub fn is_ksk(&self) -> bool {
+        self.flags & SECURE_ENTRY_POINT != 0
+    }
+
+    /// Returns the RDATA of the key's DNSKEY record.
+    pub fn dnskey_rdata(&self) -> Vec<u8> {
+        let mut rdata = Vec::with_capacity(4 + self.public_key.len());
+        rdata.extend_from_slice(&self.flags.to_be_bytes());
+        rdata.push(3);
+        rdata.push(self.algorithm.number());
+        rdata.extend_from_slice(&self.public_key);
+        rdata
+    }
+
+    pub fn key_tag(&self) -> u16 {
+        key_tag(&self.dnskey_rdata())
+    }
+
+    /// Returns the RDATA of the SHA-256 DS record that the parent of zone `origin`
+    /// publishes for the key.
+    pub fn ds_rdata(&self, origin: &str) -> Vec<u8> {
+        ds_rdata(origin, &self.dnskey_rdata(), DIGEST_SHA256).expect("SHA-256 is supported")
+    }
+
+    /// Returns the DS record for the key in presentation format, to hand to the parent.
+    pub fn ds_record(&self, origin: &str) -> String {
+        let rdata = format_rdata(DS, &self.ds_rdata(origin)).expect("DS RDATA is well-formed");
+        format!("{} IN DS {}", normalize(origin), rdata)
+    }
+
+    /// Signs `data`. ECDSA signatures use deterministic nonces (RFC 6979).
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        match &self.keypair {
+            KeyPair::Ecdsa(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(data);
+                signature.to_bytes().to_vec()
+            }
+            KeyPair::Ed25519(key) => key.sign(data).to_bytes().to_vec(),
+        }
+    }
+}
+
+/// Computes the key tag of a DNSKEY record from its RDATA (RFC 4034, appendix B).
+pub fn key_tag(dnskey_rdata: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for (i, byte) in dnskey_rdata.iter().enumerate() {
+        sum += if i % 2 == 0 { (*byte as u32) << 8 } else { *byte as u32 };
+    }
+    sum += sum >> 16;
+    sum as u16
+}
+
+/// Computes the RDATA of a DS record for the DNSKEY record of zone `owner` (RFC 4034,
+/// section 5.1.4). Returns `None` for unsupported digest types.
+pub fn ds_rdata(owner: &str, dnskey_rdata: &[u8], digest_type: u8) -> Option<Vec<u8>> {
+    let signed = [name_to_wire(owner), dnskey_rdata.to_vec()].concat();
+    let digest = match digest_type {
+        DIGEST_SHA1 => Sha1::digest(&signed).to_vec(),
+        DIGEST_SHA256 => Sha256::digest(&signed).to_vec(),
+        _ => return None,
+    };
+    let mut rdata = key_tag(dnskey_rdata).to_be_bytes().to_vec();
+    rdata.push(*dnskey_rdata.get(3)?);
+    rdata.push(digest_type);
+    rdata.extend(digest);
+    Some(rdata)
+}
+
+/// Checks a signature made with the algorithm `number` against the public key field of
+/// a DNSKEY record. Unsupported algorithms never verify.
+pub fn verify(number: u8, public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    match Algorithm::from_number(number) {
+        Some(Algorithm::EcdsaP256Sha256) => {
+            if public_key.len() != 64 {
+                return false;
+            }
+            let point = [&[0x04], public_key].concat();
+            match (p256::ecdsa::VerifyingKey::from_sec1_bytes(&point), p256::ecdsa::Signature::from_slice(signature)) {
+                (Ok(key), Ok(signature)) => key.verify(data, &signature).is_ok(),
+                _ => false,
+            }
+        }
+        Some(Algorithm::Ed25519) => {
+            let (Ok(public_key), Ok(signature)) = (public_key.try_into(), signature.try_into()) else {
+                return false;
+            };
+            match ed25519_dalek::VerifyingKey::from_bytes(public_key) {
+                Ok(key) => key.verify(data, &ed25519_dalek::Signature::from_bytes(signature)).is_ok(),
+                Err(_) => false,
+            }
+        }
+        None => false,
+    }
+}
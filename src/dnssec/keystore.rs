@@ -0,0 +1,331 @@
+//! Keys the server generates and rolls over by itself (RFC 6781, section 4.1, and RFC
+//! 7583).
+//!
+//! Every key has a timeline: when its DNSKEY record is added to and removed from the
+//! zone, when it starts and stops signing and, for key signing keys, when the parent
+//! should start and stop publishing its DS record. A rollover is planned by filling in
+//! these times from the zone's TTLs. The timelines are kept in `<zone>.keys.toml` next to
+//! the private key files in the zones directory, so a restarted server carries on with
+//! the rollovers in progress.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::key::SigningKey;
+use super::Algorithm;
+use crate::config::KeyRole;
+use crate::db::validation::normalize;
+use crate::db::Zone;
+
+/// How the keys of a zone are generated and rolled over, given as the `key_policy` table
+/// of a zone in `config.toml`. Durations are in seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct KeyPolicy {
+    /// The algorithm of new keys.
+    pub algorithm: Algorithm,
+    /// How long a zone signing key signs before it is replaced. 0 keeps it forever.
+    pub zsk_lifetime: u32,
+    /// How long a key signing key is used before it is replaced. 0 keeps it forever.
+    pub ksk_lifetime: u32,
+    pub zsk_rollover: ZskRollover,
+    /// How long a new version of the zone takes to reach every secondary.
+    pub propagation_delay: u32,
+    /// The TTL of the DS records in the parent zone.
+    pub parent_ds_ttl: u32,
+    /// How long the parent takes to publish a DS change once it has been handed over.
+    pub parent_propagation_delay: u32,
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        KeyPolicy {
+            algorithm: Algorithm::EcdsaP256Sha256,
+            zsk_lifetime: 30 * 24 * 3600,
+            ksk_lifetime: 365 * 24 * 3600,
+            zsk_rollover: ZskRollover::PrePublish,
+            propagation_delay: 3600,
+            parent_ds_ttl: 24 * 3600,
+            parent_propagation_delay: 3600,
+        }
+    }
+}
+
+/// The method of zone signing key rollovers (RFC 6781, section 4.1.1).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZskRollover {
+    /// The new key is published first and takes over signing once every cache has seen
+    /// it. The zone is only signed once at any time.
+    #[default]
+    PrePublish,
+    /// The new key is published and signs alongside the old one until caches only hold
+    /// data signed by both.
+    DoubleSignature,
+}
+
+/// The TTLs of a zone that rollover timing depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneTtls {
+    /// The TTL of the DNSKEY RRset.
+    pub dnskey: u32,
+    /// The highest TTL in the zone, which bounds how long signatures stay in caches.
+    pub max: u32,
+}
+
+impl ZoneTtls {
+    pub fn of(zone: &Zone) -> Self {
+        let max = zone.records.iter().map(|record| record.ttl).max().unwrap_or_default();
+        ZoneTtls { dnskey: zone.ttl, max: max.max(zone.ttl) }
+    }
+}
+
+/// The timeline of a managed key, as Unix times.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyState {
+    /// The private key file, in the zones directory.
+    pub file: String,
+    pub role: KeyRole,
+    pub created: u32,
+    /// When the DNSKEY record is added to the zone.
+    pub publish: u32,
+    /// When the key starts signing.
+    pub activate: u32,
+    /// When the key stops signing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inactive: Option<u32>,
+    /// When the DNSKEY record is removed from the zone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete: Option<u32>,
+    /// When the parent should start publishing the DS record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_publish: Option<u32>,
+    /// When the parent may stop publishing the DS record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_delete: Option<u32>,
+}
+
+impl KeyState {
+    pub fn is_published(&self, now: u32) -> bool {
+        self.publish <= now && self.delete.is_none_or(|delete| now < delete)
+    }
+
+    pub fn is_active(&self, now: u32) -> bool {
+        self.activate <= now && self.inactive.is_none_or(|inactive| now < inactive)
+    }
+
+    /// Returns whether the parent should publish the key's DS record.
+    pub fn needs_ds(&self, now: u32) -> bool {
+        self.sync_publish.is_some_and(|publish| publish <= now) && self.sync_delete.is_none_or(|delete| now < delete)
+    }
+
+    /// Returns whether the key is in use or will be: it has not been removed from the
+    /// zone yet.
+    fn is_live(&self, now: u32) -> bool {
+        self.delete.is_none_or(|delete| now < delete)
+    }
+
+    /// Returns whether the key is gone from both the zone and the parent.
+    fn is_retired(&self, now: u32) -> bool {
+        !self.is_live(now) && self.sync_delete.is_none_or(|delete| delete <= now)
+    }
+
+    /// Returns whether the key signs and no successor has been planned for it.
+    fn is_current(&self, now: u32) -> bool {
+        self.activate <= now && self.inactive.is_none()
+    }
+}
+
+/// A managed key and its timeline.
+#[derive(Clone)]
+pub struct ManagedKey {
+    pub key: SigningKey,
+    pub state: KeyState,
+}
+
+/// The contents of a key state file.
+#[derive(Default, Serialize, Deserialize)]
+struct StateFile {
+    #[serde(default, rename = "key")]
+    keys: Vec<KeyState>,
+}
+
+/// The managed keys of one zone.
+pub struct KeyStore {
+    dir: PathBuf,
+    origin: String,
+    keys: Vec<ManagedKey>,
+}
+
+impl KeyStore {
+    /// Returns the path of the state file of zone `origin` in `dir`.
+    pub fn state_file(dir: &Path, origin: &str) -> PathBuf {
+        dir.join(format!("{}keys.toml", normalize(origin)))
+    }
+
+    /// Opens the keys of zone `origin` kept in `dir`. Without a state file the zone has no
+    /// keys yet.
+    pub fn open(dir: &Path, origin: &str) -> Result<Self, String> {
+        let path = KeyStore::state_file(dir, origin);
+        let state: StateFile = match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => StateFile::default(),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        let keys = state
+            .keys
+            .into_iter()
+            .map(|state| {
+                let key = SigningKey::load(&dir.join(&state.file), state.role.flags())?;
+                Ok(ManagedKey { key, state })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(KeyStore { dir: dir.to_path_buf(), origin: normalize(origin), keys })
+    }
+
+    pub fn keys(&self) -> &[ManagedKey] {
+        &self.keys
+    }
+
+    /// Returns the keys whose DNSKEY records are in the zone at `now`: those that sign,
+    /// and those that are only published.
+    pub fn zone_keys(&self, now: u32) -> (Vec<SigningKey>, Vec<SigningKey>) {
+        let (signing, published): (Vec<&ManagedKey>, Vec<&ManagedKey>) = self
+            .keys
+            .iter()
+            .filter(|managed| managed.state.is_published(now))
+            .partition(|managed| managed.state.is_active(now));
+        let keys = |managed: Vec<&ManagedKey>| managed.into_iter().map(|managed| managed.key.clone()).collect();
+        (keys(signing), keys(published))
+    }
+
+    /// Returns the DS records the parent should publish at `now`, in presentation format.
+    pub fn ds_records(&self, now: u32) -> Vec<String> {
+        self.keys
+            .iter()
+            .filter(|managed| managed.state.needs_ds(now))
+            .map(|managed| managed.key.ds_record(&self.origin))
+            .collect()
+    }
+
+    /// Moves the zone's key timelines forward to `now`: generates the first keys, starts
+    /// the rollovers that are due and forgets keys that are gone from both the zone and
+    /// the parent. Returns whether anything changed, in which case the state file has been
+    /// written.
+    pub fn advance(&mut self, policy: &KeyPolicy, ttls: ZoneTtls, now: u32) -> Result<bool, String> {
+        let before: Vec<KeyState> = self.keys.iter().map(|managed| managed.state.clone()).collect();
+
+        for managed in self.keys.iter().filter(|managed| managed.state.is_retired(now)) {
+            let _ = fs::remove_file(self.dir.join(&managed.state.file));
+        }
+        self.keys.retain(|managed| !managed.state.is_retired(now));
+
+        for role in [KeyRole::Ksk, KeyRole::Zsk] {
+            if !self.keys.iter().any(|managed| managed.state.role == role && managed.state.is_live(now)) {
+                let new = self.generate(policy, role, now)?;
+                if role == KeyRole::Ksk {
+                    self.keys[new].state.sync_publish = Some(now);
+                }
+                continue;
+            }
+            let lifetime = if role == KeyRole::Ksk { policy.ksk_lifetime } else { policy.zsk_lifetime };
+            let Some(current) = self.keys.iter().position(|managed| managed.state.role == role && managed.state.is_current(now)) else {
+                continue;
+            };
+            if lifetime == 0 || now < self.keys[current].state.activate.saturating_add(lifetime) {
+                continue;
+            }
+            match role {
+                KeyRole::Ksk => self.roll_ksk(policy, ttls, current, now)?,
+                _ => self.roll_zsk(policy, ttls, current, now)?,
+            }
+        }
+
+        let after: Vec<KeyState> = self.keys.iter().map(|managed| managed.state.clone()).collect();
+        if after == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Replaces a zone signing key by pre-publication or double signatures.
+    fn roll_zsk(&mut self, policy: &KeyPolicy, ttls: ZoneTtls, current: usize, now: u32) -> Result<(), String> {
+        let successor = self.generate(policy, KeyRole::Zsk, now)?;
+        match policy.zsk_rollover {
+            ZskRollover::PrePublish => {
+                // The new key signs once every cached DNSKEY RRset contains it, and the
+                // old one stays published until signatures made with it have expired
+                // from caches.
+                let activate = now.saturating_add(ttls.dnskey).saturating_add(policy.propagation_delay);
+                self.keys[successor].state.activate = activate;
+                let old = &mut self.keys[current].state;
+                old.inactive = Some(activate);
+                old.delete = Some(activate.saturating_add(ttls.max).saturating_add(policy.propagation_delay));
+            }
+            ZskRollover::DoubleSignature => {
+                let retire = now.saturating_add(ttls.dnskey.max(ttls.max)).saturating_add(policy.propagation_delay);
+                let old = &mut self.keys[current].state;
+                old.inactive = Some(retire);
+                old.delete = Some(retire);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces a key signing key by double DS: the new DS record is handed to the parent
+    /// first, the keys are swapped in the zone once every cache has seen it, and the old
+    /// DS record can go once the old DNSKEY RRset has expired from caches.
+    fn roll_ksk(&mut self, policy: &KeyPolicy, ttls: ZoneTtls, current: usize, now: u32) -> Result<(), String> {
+        let swap = now.saturating_add(policy.parent_ds_ttl).saturating_add(policy.parent_propagation_delay);
+        let successor = self.generate(policy, KeyRole::Ksk, now)?;
+        let new = &mut self.keys[successor].state;
+        new.publish = swap;
+        new.activate = swap;
+        new.sync_publish = Some(now);
+        let old = &mut self.keys[current].state;
+        old.inactive = Some(swap);
+        old.delete = Some(swap);
+        old.sync_delete = Some(swap.saturating_add(ttls.dnskey).saturating_add(policy.propagation_delay));
+        Ok(())
+    }
+
+    /// Generates a key that is published and signs from `now`, writes its private key file
+    /// and returns its index, so that its timeline can be adjusted.
+    fn generate(&mut self, policy: &KeyPolicy, role: KeyRole, now: u32) -> Result<usize, String> {
+        let key = loop {
+            let key = SigningKey::generate(policy.algorithm, role.flags());
+            if !self.keys.iter().any(|managed| managed.key.key_tag() == key.key_tag()) {
+                break key;
+            }
+        };
+        let file = key.file_name(&self.origin);
+        key.save(&self.dir.join(&file))?;
+        let state = KeyState {
+            file,
+            role,
+            created: now,
+            publish: now,
+            activate: now,
+            inactive: None,
+            delete: None,
+            sync_publish: None,
+            sync_delete: None,
+        };
+        self.keys.push(ManagedKey { key, state });
+        Ok(self.keys.len() - 1)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = KeyStore::state_file(&self.dir, &self.origin);
+        let state = StateFile { keys: self.keys.iter().map(|managed| managed.state.clone()).collect() };
+        let content = toml::to_string(&state).map_err(|e| format!("{}: {}", path.display(), e))?;
+        // Written to a temporary file first, so that a crash leaves the previous state.
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, content).and_then(|()| fs::rename(&temp, &path)).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
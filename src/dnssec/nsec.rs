@@ -0,0 +1,87 @@
+//! NSEC records, which chain the names of a zone in canonical order to prove that names
+//! and types do not exist (RFC 4034, section 4).
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::canonical::{compare_names, name_to_wire};
+use super::{delegation_points, is_authoritative, NSEC, RRSIG};
+use crate::db::record::RecordClass;
+use crate::db::validation::normalize;
+use crate::db::{RecordData, ResourceRecord, Zone};
+
+/// Encodes a set of types as an NSEC type bitmap (RFC 4034, section 4.1.2).
+pub fn type_bitmap(types: &BTreeSet<u16>) -> Vec<u8> {
+    let mut windows: BTreeMap<u8, Vec<u8>> = BTreeMap::new();
+    for rtype in types {
+        let bits = windows.entry((rtype >> 8) as u8).or_default();
+        let offset = (rtype & 0xff) as usize;
+        if bits.len() <= offset / 8 {
+            bits.resize(offset / 8 + 1, 0);
+        }
+        bits[offset / 8] |= 0x80 >> (offset % 8);
+    }
+    let mut bitmap = Vec::new();
+    for (window, bits) in windows {
+        bitmap.push(window);
+        bitmap.push(bits.len() as u8);
+        bitmap.extend(bits);
+    }
+    bitmap
+}
+
+/// Decodes an NSEC type bitmap. Returns `None` if it is malformed.
+pub fn parse_type_bitmap(bitmap: &[u8]) -> Option<BTreeSet<u16>> {
+    let mut types = BTreeSet::new();
+    let mut rest = bitmap;
+    while !rest.is_empty() {
+        let (&window, &length) = (rest.first()?, rest.get(1)?);
+        let bits = rest.get(2..2 + length as usize)?;
+        for (i, byte) in bits.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.insert(((window as u16) << 8) | (i * 8 + bit) as u16);
+                }
+            }
+        }
+        rest = &rest[2 + length as usize..];
+    }
+    Some(types)
+}
+
+/// Builds the NSEC chain of a zone that does not contain signatures yet.
+///
+/// Every name with authoritative data gets an NSEC record pointing to the next such
+/// name, the last one pointing back to the apex. The type bitmaps list the types at the
+/// name plus RRSIG and NSEC, since every NSEC record is signed.
+pub fn nsec_records(zone: &Zone, ttl: u32) -> Vec<ResourceRecord> {
+    let cuts = delegation_points(zone);
+    let mut names: BTreeMap<String, BTreeSet<u16>> = BTreeMap::new();
+    for record in zone.records.iter().filter(|record| is_authoritative(record, &cuts) || is_delegation(record, &cuts)) {
+        names.entry(normalize(&record.name)).or_default().insert(record.data.get_type().code());
+    }
+    let mut names: Vec<(String, BTreeSet<u16>)> = names.into_iter().collect();
+    names.sort_by(|(a, _), (b, _)| compare_names(a, b));
+
+    let mut records = Vec::with_capacity(names.len());
+    for (i, (name, types)) in names.iter().enumerate() {
+        let next = &names[(i + 1) % names.len()].0;
+        let mut types = types.clone();
+        types.insert(RRSIG);
+        types.insert(NSEC);
+        let mut data = name_to_wire(next);
+        data.extend(type_bitmap(&types));
+        records.push(ResourceRecord {
+            name: name.clone(),
+            ttl,
+            class: RecordClass::IN,
+            data: RecordData::Unknown { rtype: NSEC, data },
+        });
+    }
+    records
+}
+
+/// Returns whether a record is the NS record of a delegation point, which appears in the
+/// NSEC chain although it is not signed.
+fn is_delegation(record: &ResourceRecord, cuts: &[String]) -> bool {
+    matches!(record.data, RecordData::NS(_)) && cuts.contains(&normalize(&record.name))
+}
@@ -0,0 +1,153 @@
+//! NSEC3 records, which chain the hashes of a zone's names so that denial of existence
+//! does not reveal the names themselves (RFC 5155).
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use data_encoding::BASE32HEX_NOPAD;
+use serde::{Deserialize, Deserializer};
+use sha1::{Digest, Sha1};
+
+use super::canonical::name_to_wire;
+use super::nsec::type_bitmap;
+use super::{delegation_points, is_authoritative, DS, NSEC3, RRSIG};
+use crate::db::record::RecordClass;
+use crate::db::validation::normalize;
+use crate::db::{RecordData, ResourceRecord, Zone};
+
+/// The hash algorithm number of SHA-1, the only one defined.
+const SHA1: u8 = 1;
+
+/// The Opt-Out flag of NSEC3 records.
+const OPT_OUT: u8 = 1;
+
+/// How a zone's names are hashed and chained, given as the `nsec3` table of a zone in
+/// `config.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Nsec3Params {
+    /// Additional hash iterations. RFC 9276 recommends 0, the default.
+    #[serde(default)]
+    pub iterations: u16,
+    /// The salt, hex encoded in the configuration. Empty by default, as RFC 9276
+    /// recommends.
+    #[serde(default, deserialize_with = "decode_salt")]
+    pub salt: Vec<u8>,
+    /// Whether delegations without DS records are left out of the chain.
+    #[serde(default)]
+    pub opt_out: bool,
+}
+
+fn decode_salt<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let salt = String::deserialize(deserializer)?;
+    if salt == "-" {
+        return Ok(Vec::new());
+    }
+    data_encoding::HEXLOWER_PERMISSIVE.decode(salt.as_bytes()).map_err(serde::de::Error::custom)
+}
+
+impl Nsec3Params {
+    /// Parses NSEC3PARAM RDATA, or the start of NSEC3 RDATA. Returns `None` if it is
+    /// malformed or uses an unknown hash algorithm.
+    pub fn parse(data: &[u8]) -> Option<Nsec3Params> {
+        let salt_length = *data.get(4)? as usize;
+        if *data.first()? != SHA1 {
+            return None;
+        }
+        Some(Nsec3Params {
+            iterations: u16::from_be_bytes([*data.get(2)?, *data.get(3)?]),
+            salt: data.get(5..5 + salt_length)?.to_vec(),
+            opt_out: data[1] & OPT_OUT != 0,
+        })
+    }
+
+    /// Encodes the parameters as NSEC3PARAM RDATA, which has no flags set.
+    pub fn to_rdata(&self) -> Vec<u8> {
+        self.header(0)
+    }
+
+    fn header(&self, flags: u8) -> Vec<u8> {
+        let mut data = vec![SHA1, flags];
+        data.extend_from_slice(&self.iterations.to_be_bytes());
+        data.push(self.salt.len() as u8);
+        data.extend_from_slice(&self.salt);
+        data
+    }
+
+    /// Hashes a name (RFC 5155, section 5).
+    pub fn hash(&self, name: &str) -> [u8; 20] {
+        let mut digest: [u8; 20] = Sha1::digest([name_to_wire(name), self.salt.clone()].concat()).into();
+        for _ in 0..self.iterations {
+            digest = Sha1::digest([&digest[..], &self.salt].concat()).into();
+        }
+        digest
+    }
+
+    /// Returns the lowercase base32hex label the hash of a name is written as.
+    pub fn hashed_label(&self, name: &str) -> String {
+        BASE32HEX_NOPAD.encode(&self.hash(name)).to_ascii_lowercase()
+    }
+}
+
+/// Returns the owner name of the NSEC3 record for a hash in the zone `origin`.
+fn hashed_name(hash: &[u8], origin: &str) -> String {
+    let label = BASE32HEX_NOPAD.encode(hash).to_ascii_lowercase();
+    if origin == "." { format!("{}.", label) } else { format!("{}.{}", label, origin) }
+}
+
+/// Builds the NSEC3 chain of a zone that does not contain signatures yet.
+///
+/// Every name with authoritative data, every delegation point and every empty
+/// non-terminal above them gets an NSEC3 record at its hashed name. With opt-out,
+/// delegations without DS records are left out.
+pub fn nsec3_records(zone: &Zone, ttl: u32, params: &Nsec3Params) -> Vec<ResourceRecord> {
+    let origin = normalize(&zone.origin);
+    let cuts = delegation_points(zone);
+    let mut names: BTreeMap<String, BTreeSet<u16>> = BTreeMap::new();
+    for record in &zone.records {
+        let name = normalize(&record.name);
+        if is_authoritative(record, &cuts) || (cuts.contains(&name) && matches!(record.data, RecordData::NS(_))) {
+            names.entry(name).or_default().insert(record.data.get_type().code());
+        }
+    }
+    if params.opt_out {
+        names.retain(|name, types| !cuts.contains(name) || types.contains(&DS));
+    }
+    for name in names.keys().cloned().collect::<Vec<_>>() {
+        let mut ancestor = name.as_str();
+        while ancestor != origin
+            && let Some((_, parent)) = ancestor.split_once('.')
+        {
+            let parent = if parent.is_empty() { "." } else { parent };
+            names.entry(parent.to_string()).or_default();
+            ancestor = parent;
+        }
+    }
+
+    let mut hashed: Vec<([u8; 20], BTreeSet<u16>)> = names
+        .into_iter()
+        .map(|(name, mut types)| {
+            // Everything but the NS records of an unsigned delegation is signed.
+            if !types.is_empty() && (!cuts.contains(&name) || types.contains(&DS)) {
+                types.insert(RRSIG);
+            }
+            (params.hash(&name), types)
+        })
+        .collect();
+    hashed.sort();
+
+    let flags = if params.opt_out { OPT_OUT } else { 0 };
+    let mut records = Vec::with_capacity(hashed.len());
+    for (i, (hash, types)) in hashed.iter().enumerate() {
+        let next = &hashed[(i + 1) % hashed.len()].0;
+        let mut data = params.header(flags);
+        data.push(next.len() as u8);
+        data.extend_from_slice(next);
+        data.extend(type_bitmap(types));
+        records.push(ResourceRecord {
+            name: hashed_name(hash, &origin),
+            ttl,
+            class: RecordClass::IN,
+            data: RecordData::Unknown { rtype: NSEC3, data },
+        });
+    }
+    records
+}
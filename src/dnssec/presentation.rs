@@ -0,0 +1,261 @@
+//! The zone file presentation format of DNSSEC records (RFC 4034, sections 2.2, 3.2, 4.2
+//! and 5.3, and RFC 5155, sections 3.3 and 4.3), which are stored as wire RDATA.
+
+use std::str::FromStr;
+
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER, HEXUPPER_PERMISSIVE};
+
+use super::nsec::{parse_type_bitmap, type_bitmap};
+use super::{DNSKEY, DS, NSEC, NSEC3, NSEC3PARAM, RRSIG};
+use crate::db::RecordType;
+use crate::zone_parser::ZoneParser;
+
+/// The DNSSEC types with a presentation format, by type code.
+const TYPES: [(u16, &str); 6] =
+    [(DS, "DS"), (RRSIG, "RRSIG"), (NSEC, "NSEC"), (DNSKEY, "DNSKEY"), (NSEC3, "NSEC3"), (NSEC3PARAM, "NSEC3PARAM")];
+
+/// Algorithm mnemonics accepted in place of numbers (RFC 8624).
+const ALGORITHMS: [(u8, &str); 8] = [
+    (5, "RSASHA1"),
+    (7, "RSASHA1-NSEC3-SHA1"),
+    (8, "RSASHA256"),
+    (10, "RSASHA512"),
+    (13, "ECDSAP256SHA256"),
+    (14, "ECDSAP384SHA384"),
+    (15, "ED25519"),
+    (16, "ED448"),
+];
+
+/// Returns the mnemonic of a DNSSEC type code.
+pub fn type_mnemonic(code: u16) -> Option<&'static str> {
+    TYPES.iter().find(|(rtype, _)| *rtype == code).map(|(_, name)| *name)
+}
+
+/// Returns the type code of a DNSSEC type mnemonic, in any case.
+pub fn type_code(mnemonic: &str) -> Option<u16> {
+    TYPES.iter().find(|(_, name)| name.eq_ignore_ascii_case(mnemonic)).map(|(rtype, _)| *rtype)
+}
+
+/// Parses the RDATA fields of a DNSSEC record. Relative names are completed with
+/// `origin`.
+pub fn parse_rdata(rtype: u16, fields: &[&str], origin: &str) -> Result<Vec<u8>, String> {
+    let field = |index: usize, name: &str| fields.get(index).copied().ok_or_else(|| format!("Missing {}", name));
+    let number = |index: usize, name: &str| -> Result<u32, String> {
+        let value = field(index, name)?;
+        value.parse::<u32>().map_err(|e| format!("Invalid {}: {}: {}", name, value, e))
+    };
+    let byte = |index: usize, name: &str| -> Result<u8, String> {
+        let value = number(index, name)?;
+        u8::try_from(value).map_err(|_| format!("Invalid {}: {}", name, value))
+    };
+    let short = |index: usize, name: &str| -> Result<u16, String> {
+        let value = number(index, name)?;
+        u16::try_from(value).map_err(|_| format!("Invalid {}: {}", name, value))
+    };
+    let algorithm = |index: usize| -> Result<u8, String> {
+        let value = field(index, "algorithm")?;
+        match ALGORITHMS.iter().find(|(_, name)| name.eq_ignore_ascii_case(value)) {
+            Some((number, _)) => Ok(*number),
+            None => byte(index, "algorithm"),
+        }
+    };
+    let rest = |index: usize| fields.get(index..).unwrap_or_default().concat();
+    let name = |index: usize, what: &str| Ok::<_, String>(name_to_wire(&ZoneParser::absolute_name(field(index, what)?, origin)));
+
+    let mut data = Vec::new();
+    match rtype {
+        DS => {
+            data.extend_from_slice(&short(0, "key tag")?.to_be_bytes());
+            data.push(algorithm(1)?);
+            data.push(byte(2, "digest type")?);
+            data.extend(decode_hex(&rest(3))?);
+        }
+        DNSKEY => {
+            data.extend_from_slice(&short(0, "flags")?.to_be_bytes());
+            data.push(byte(1, "protocol")?);
+            data.push(algorithm(2)?);
+            data.extend(BASE64.decode(rest(3).as_bytes()).map_err(|e| format!("Invalid public key: {}", e))?);
+        }
+        RRSIG => {
+            let covered = field(0, "type covered")?;
+            data.extend_from_slice(&RecordType::from_str(covered)?.code().to_be_bytes());
+            data.push(algorithm(1)?);
+            data.push(byte(2, "labels")?);
+            data.extend_from_slice(&number(3, "original TTL")?.to_be_bytes());
+            data.extend_from_slice(&parse_time(field(4, "expiration")?)?.to_be_bytes());
+            data.extend_from_slice(&parse_time(field(5, "inception")?)?.to_be_bytes());
+            data.extend_from_slice(&short(6, "key tag")?.to_be_bytes());
+            data.extend(name(7, "signer name")?);
+            data.extend(BASE64.decode(rest(8).as_bytes()).map_err(|e| format!("Invalid signature: {}", e))?);
+        }
+        NSEC => {
+            data.extend(name(0, "next domain name")?);
+            data.extend(parse_types(fields.get(1..).unwrap_or_default())?);
+        }
+        NSEC3 | NSEC3PARAM => {
+            data.push(byte(0, "hash algorithm")?);
+            data.push(byte(1, "flags")?);
+            data.extend_from_slice(&short(2, "iterations")?.to_be_bytes());
+            let salt = match field(3, "salt")? {
+                "-" => Vec::new(),
+                salt => decode_hex(salt)?,
+            };
+            data.push(u8::try_from(salt.len()).map_err(|_| "Salt is too long".to_string())?);
+            data.extend(salt);
+            if rtype == NSEC3 {
+                let next = field(4, "next hashed owner name")?.to_ascii_uppercase();
+                let next = BASE32HEX_NOPAD.decode(next.as_bytes()).map_err(|e| format!("Invalid next hashed owner name: {}", e))?;
+                data.push(u8::try_from(next.len()).map_err(|_| "Next hashed owner name is too long".to_string())?);
+                data.extend(next);
+                data.extend(parse_types(fields.get(5..).unwrap_or_default())?);
+            } else if fields.len() > 4 {
+                return Err(format!("Unexpected field: {}", fields[4]));
+            }
+        }
+        _ => return Err(format!("No presentation format for TYPE{}", rtype)),
+    }
+    Ok(data)
+}
+
+/// Formats the RDATA of a DNSSEC record. Returns `None` for other types and for RDATA
+/// that is malformed, which is then written in the generic format.
+pub fn format_rdata(rtype: u16, data: &[u8]) -> Option<String> {
+    let u16_at = |pos: usize| Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?));
+    let u32_at = |pos: usize| Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?));
+    let text = match rtype {
+        DS => format!("{} {} {} {}", u16_at(0)?, data.get(2)?, data.get(3)?, HEXUPPER.encode(data.get(4..)?)),
+        DNSKEY => format!("{} {} {} {}", u16_at(0)?, data.get(2)?, data.get(3)?, BASE64.encode(data.get(4..)?)),
+        RRSIG => {
+            let (signer, end) = wire_to_name(data, 18)?;
+            format!(
+                "{} {} {} {} {} {} {} {} {}",
+                RecordType::from(u16_at(0)?),
+                data.get(2)?,
+                data.get(3)?,
+                u32_at(4)?,
+                format_time(u32_at(8)?),
+                format_time(u32_at(12)?),
+                u16_at(16)?,
+                signer,
+                BASE64.encode(&data[end..])
+            )
+        }
+        NSEC => {
+            let (next, end) = wire_to_name(data, 0)?;
+            [next, format_types(&data[end..])?].join(" ").trim_end().to_string()
+        }
+        NSEC3 | NSEC3PARAM => {
+            let salt_end = 5 + *data.get(4)? as usize;
+            let salt = match data.get(5..salt_end)? {
+                [] => "-".to_string(),
+                salt => HEXUPPER.encode(salt),
+            };
+            let params = format!("{} {} {} {}", data.first()?, data.get(1)?, u16_at(2)?, salt);
+            if rtype == NSEC3PARAM {
+                if data.len() != salt_end {
+                    return None;
+                }
+                params
+            } else {
+                let next_end = salt_end + 1 + *data.get(salt_end)? as usize;
+                let next = BASE32HEX_NOPAD.encode(data.get(salt_end + 1..next_end)?).to_ascii_lowercase();
+                format!("{} {} {}", params, next, format_types(&data[next_end..])?).trim_end().to_string()
+            }
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Encodes a name in uncompressed wire form, keeping its case.
+fn name_to_wire(name: &str) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label.as_bytes());
+    }
+    wire.push(0);
+    wire
+}
+
+/// Decodes an uncompressed wire name starting at `pos`. Returns the name and the
+/// position after it.
+pub(crate) fn wire_to_name(data: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    loop {
+        let length = *data.get(pos)? as usize;
+        pos += 1;
+        if length == 0 {
+            break;
+        }
+        name.push_str(std::str::from_utf8(data.get(pos..pos + length)?).ok()?);
+        name.push('.');
+        pos += length;
+    }
+    if name.is_empty() {
+        name.push('.');
+    }
+    Some((name, pos))
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    HEXUPPER_PERMISSIVE.decode(hex.to_ascii_uppercase().as_bytes()).map_err(|e| format!("Invalid hex {}: {}", hex, e))
+}
+
+fn parse_types(mnemonics: &[&str]) -> Result<Vec<u8>, String> {
+    let types = mnemonics.iter().map(|mnemonic| RecordType::from_str(mnemonic).map(|rtype| rtype.code())).collect::<Result<_, _>>()?;
+    Ok(type_bitmap(&types))
+}
+
+fn format_types(bitmap: &[u8]) -> Option<String> {
+    let types = parse_type_bitmap(bitmap)?;
+    Some(types.into_iter().map(|code| RecordType::from(code).to_string()).collect::<Vec<_>>().join(" "))
+}
+
+/// Parses an RRSIG time, either `YYYYMMDDHHmmSS` in UTC or seconds since the epoch.
+fn parse_time(value: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid time: {}", value);
+    if value.len() != 14 {
+        return value.parse::<u32>().map_err(|_| invalid());
+    }
+    let part = |range: std::ops::Range<usize>| value.get(range).and_then(|digits| digits.parse::<i64>().ok()).ok_or_else(invalid);
+    let (year, month, day) = (part(0..4)?, part(4..6)?, part(6..8)?);
+    let (hour, minute, second) = (part(8..10)?, part(10..12)?, part(12..14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    // Times are serial numbers modulo 2^32 (RFC 4034, section 3.1.5).
+    Ok(seconds.rem_euclid(1 << 32) as u32)
+}
+
+/// Formats an RRSIG time as `YYYYMMDDHHmmSS` in UTC.
+fn format_time(time: u32) -> String {
+    let (days, seconds) = (time as i64 / 86400, time as i64 % 86400);
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Returns the number of days from 1970-01-01 to a date in the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the date `days` days after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
@@ -0,0 +1,287 @@
+//! Signing zones with RRSIG records (RFC 4034, section 3, and RFC 4035, section 2).
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::canonical::{label_count, name_to_wire, rdata_to_wire, rrset_to_wire};
+use super::key::{self, SigningKey};
+use super::nsec::nsec_records;
+use super::nsec3::{nsec3_records, Nsec3Params};
+use super::{delegation_points, is_authoritative, DNSKEY, NSEC, NSEC3, NSEC3PARAM, RRSIG};
+use crate::db::record::RecordClass;
+use crate::db::validation::normalize;
+use crate::db::{RecordData, ResourceRecord, Zone};
+
+/// How far signature inception is backdated, to tolerate validators with slow clocks.
+const INCEPTION_OFFSET: u32 = 3600;
+
+/// The fields of an RRSIG record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rrsig {
+    pub type_covered: u16,
+    pub algorithm: u8,
+    pub labels: u8,
+    pub original_ttl: u32,
+    pub expiration: u32,
+    pub inception: u32,
+    pub key_tag: u16,
+    pub signer: String,
+    pub signature: Vec<u8>,
+}
+
+impl Rrsig {
+    /// Parses RRSIG RDATA. Returns `None` if it is malformed.
+    pub fn parse(data: &[u8]) -> Option<Rrsig> {
+        let u16_at = |pos: usize| Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?));
+        let u32_at = |pos: usize| Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?));
+        let mut signer = String::new();
+        let mut pos = 18;
+        loop {
+            let length = *data.get(pos)? as usize;
+            pos += 1;
+            if length == 0 {
+                break;
+            }
+            signer.push_str(std::str::from_utf8(data.get(pos..pos + length)?).ok()?);
+            signer.push('.');
+            pos += length;
+        }
+        if signer.is_empty() {
+            signer.push('.');
+        }
+        Some(Rrsig {
+            type_covered: u16_at(0)?,
+            algorithm: *data.get(2)?,
+            labels: *data.get(3)?,
+            original_ttl: u32_at(4)?,
+            expiration: u32_at(8)?,
+            inception: u32_at(12)?,
+            key_tag: u16_at(16)?,
+            signer,
+            signature: data[pos..].to_vec(),
+        })
+    }
+
+    /// Encodes the RDATA without the signature, the part the signature itself covers.
+    fn header(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.type_covered.to_be_bytes());
+        data.push(self.algorithm);
+        data.push(self.labels);
+        data.extend_from_slice(&self.original_ttl.to_be_bytes());
+        data.extend_from_slice(&self.expiration.to_be_bytes());
+        data.extend_from_slice(&self.inception.to_be_bytes());
+        data.extend_from_slice(&self.key_tag.to_be_bytes());
+        data.extend(name_to_wire(&self.signer));
+        data
+    }
+
+    pub fn to_rdata(&self) -> Vec<u8> {
+        let mut data = self.header();
+        data.extend_from_slice(&self.signature);
+        data
+    }
+
+    /// Checks the signature over an RRset against the RDATA of a DNSKEY record. Validity
+    /// times are not checked.
+    pub fn verify(&self, rrset: &[&ResourceRecord], dnskey_rdata: &[u8]) -> bool {
+        if rrset.is_empty() || dnskey_rdata.len() < 4 || key::key_tag(dnskey_rdata) != self.key_tag {
+            return false;
+        }
+        let mut data = self.header();
+        data.extend(rrset_to_wire(rrset, self.original_ttl));
+        key::verify(self.algorithm, &dnskey_rdata[4..], &data, &self.signature)
+    }
+}
+
+/// Returns the parsed RRSIG if `record` is one.
+pub fn as_rrsig(record: &ResourceRecord) -> Option<Rrsig> {
+    match &record.data {
+        RecordData::Unknown { rtype: RRSIG, data } => Rrsig::parse(data),
+        _ => None,
+    }
+}
+
+/// Signs zones with a set of keys at a point in time.
+///
+/// Key signing keys sign the DNSKEY RRset and all other keys sign the rest of the zone.
+/// When the keys are all of one kind, every key signs everything, as a combined signing
+/// key does.
+pub struct Signer<'a> {
+    keys: &'a [SigningKey],
+    /// Keys whose DNSKEY records are in the zone but that do not sign, e.g. during a
+    /// rollover.
+    published: &'a [SigningKey],
+    now: u32,
+    validity: u32,
+    nsec3: Option<Nsec3Params>,
+}
+
+impl<'a> Signer<'a> {
+    /// Creates a signer whose signatures are valid for `validity` seconds from `now`.
+    pub fn new(keys: &'a [SigningKey], now: u32, validity: u32) -> Self {
+        Signer { keys, published: &[], now, validity, nsec3: None }
+    }
+
+    /// Adds DNSKEY records for keys that do not sign.
+    pub fn with_published_keys(mut self, keys: &'a [SigningKey]) -> Self {
+        self.published = keys;
+        self
+    }
+
+    /// Makes the signer chain zones with NSEC3 instead of NSEC records.
+    pub fn with_nsec3(mut self, params: Nsec3Params) -> Self {
+        self.nsec3 = Some(params);
+        self
+    }
+
+    /// Returns the time before which signatures are renewed: when less than a quarter of
+    /// the validity period remains.
+    fn refresh_time(&self) -> u32 {
+        self.now.saturating_add(self.validity / 4)
+    }
+
+    /// Removes the records the signer generates: RRSIG, NSEC, NSEC3 and NSEC3PARAM records
+    /// and the DNSKEY records of its keys.
+    pub fn unsigned_zone(&self, zone: &Zone) -> Zone {
+        let dnskeys: Vec<Vec<u8>> = self.keys.iter().chain(self.published).map(SigningKey::dnskey_rdata).collect();
+        let records = zone
+            .records
+            .iter()
+            .filter(|record| match &record.data {
+                RecordData::Unknown { rtype: RRSIG | NSEC | NSEC3 | NSEC3PARAM, .. } => false,
+                RecordData::Unknown { rtype: DNSKEY, data } => !dnskeys.contains(data),
+                _ => true,
+            })
+            .cloned()
+            .collect();
+        Zone { origin: zone.origin.clone(), ttl: zone.ttl, records }
+    }
+
+    /// Returns whether some signature in the zone is due for renewal, or the zone is not
+    /// signed at all.
+    pub fn needs_refresh(&self, zone: &Zone) -> bool {
+        let mut expirations = zone.records.iter().filter_map(as_rrsig).map(|rrsig| rrsig.expiration).peekable();
+        expirations.peek().is_none() || expirations.any(|expiration| expiration <= self.refresh_time())
+    }
+
+    /// Signs a zone: adds a DNSKEY record per key, the NSEC or NSEC3 chain and RRSIG
+    /// records for every authoritative RRset, replacing the ones generated before.
+    ///
+    /// Signatures from `previous`, the version of the zone being replaced, are kept for
+    /// RRsets that did not change as long as they are not due for renewal.
+    pub fn sign_zone(&self, zone: &Zone, previous: Option<&Zone>) -> Zone {
+        let mut signed = self.unsigned_zone(zone);
+        for key in self.keys.iter().chain(self.published) {
+            signed.records.push(ResourceRecord {
+                name: zone.origin.clone(),
+                ttl: zone.ttl,
+                class: RecordClass::IN,
+                data: RecordData::Unknown { rtype: DNSKEY, data: key.dnskey_rdata() },
+            });
+        }
+        // Negative answers are cached for the SOA minimum or the SOA TTL, whichever is
+        // lower (RFC 9077), so NSEC and NSEC3 records get that TTL.
+        let nsec_ttl = match signed.soa() {
+            Some(ResourceRecord { ttl, data: RecordData::SOA { minimum, .. }, .. }) => (*ttl).min(*minimum),
+            _ => zone.ttl,
+        };
+        match &self.nsec3 {
+            Some(params) => {
+                signed.records.push(ResourceRecord {
+                    name: zone.origin.clone(),
+                    ttl: nsec_ttl,
+                    class: RecordClass::IN,
+                    data: RecordData::Unknown { rtype: NSEC3PARAM, data: params.to_rdata() },
+                });
+                signed.records.extend(nsec3_records(&signed, nsec_ttl, params));
+            }
+            None => signed.records.extend(nsec_records(&signed, nsec_ttl)),
+        }
+
+        let (ksks, zsks): (Vec<&SigningKey>, Vec<&SigningKey>) = self.keys.iter().partition(|key| key.is_ksk());
+        let dnskey_signers = if ksks.is_empty() { &zsks } else { &ksks };
+        let zone_signers = if zsks.is_empty() { &ksks } else { &zsks };
+
+        let previous = previous.map(|previous| RRsetIndex::new(&previous.records));
+        let rrsets = RRsetIndex::new(&signed.records);
+        let cuts = delegation_points(&signed);
+        let mut signatures = Vec::new();
+        for ((name, rtype), records) in &rrsets.rrsets {
+            if !is_authoritative(records[0], &cuts) {
+                continue;
+            }
+            let ttl = records.iter().map(|record| record.ttl).min().unwrap_or(zone.ttl);
+            let signers = if *rtype == DNSKEY { dnskey_signers } else { zone_signers };
+            for key in signers {
+                let reused = previous.as_ref().and_then(|previous| {
+                    previous.current_signature(name, *rtype, records, key, self.refresh_time())
+                });
+                let rrsig = reused.unwrap_or_else(|| self.sign_rrset(records, ttl, key, &zone.origin));
+                signatures.push(ResourceRecord {
+                    name: records[0].name.clone(),
+                    ttl,
+                    class: RecordClass::IN,
+                    data: RecordData::Unknown { rtype: RRSIG, data: rrsig.to_rdata() },
+                });
+            }
+        }
+        signed.records.extend(signatures);
+        signed
+    }
+
+    fn sign_rrset(&self, records: &[&ResourceRecord], ttl: u32, key: &SigningKey, origin: &str) -> Rrsig {
+        let mut rrsig = Rrsig {
+            type_covered: records[0].data.get_type().code(),
+            algorithm: key.algorithm.number(),
+            labels: label_count(&records[0].name),
+            original_ttl: ttl,
+            expiration: self.now.saturating_add(self.validity),
+            inception: self.now.saturating_sub(INCEPTION_OFFSET),
+            key_tag: key.key_tag(),
+            signer: normalize(origin),
+            signature: Vec::new(),
+        };
+        let mut data = rrsig.header();
+        data.extend(rrset_to_wire(records, ttl));
+        rrsig.signature = key.sign(&data);
+        rrsig
+    }
+}
+
+/// The records of a zone grouped into RRsets, with the signatures covering each.
+struct RRsetIndex<'z> {
+    rrsets: BTreeMap<(String, u16), Vec<&'z ResourceRecord>>,
+    signatures: HashMap<(String, u16), Vec<Rrsig>>,
+}
+
+impl<'z> RRsetIndex<'z> {
+    fn new(records: &'z [ResourceRecord]) -> Self {
+        let mut index = RRsetIndex { rrsets: BTreeMap::new(), signatures: HashMap::new() };
+        for record in records {
+            let name = normalize(&record.name);
+            match as_rrsig(record) {
+                Some(rrsig) => index.signatures.entry((name, rrsig.type_covered)).or_default().push(rrsig),
+                None => index.rrsets.entry((name, record.data.get_type().code())).or_default().push(record),
+            }
+        }
+        index
+    }
+
+    /// Returns this zone's signature by `key` over the RRset at `name` if the RRset is
+    /// still `records` and the signature does not expire before `refresh_time`.
+    fn current_signature(&self, name: &str, rtype: u16, records: &[&ResourceRecord], key: &SigningKey, refresh_time: u32) -> Option<Rrsig> {
+        let rrset_key = (name.to_string(), rtype);
+        let wire = |records: &[&ResourceRecord]| {
+            let mut rdatas: Vec<(u32, Vec<u8>)> = records.iter().map(|record| (record.ttl, rdata_to_wire(&record.data))).collect();
+            rdatas.sort();
+            rdatas.dedup();
+            rdatas
+        };
+        if wire(self.rrsets.get(&rrset_key)?) != wire(records) {
+            return None;
+        }
+        self.signatures.get(&rrset_key)?.iter().find(|rrsig| {
+            rrsig.key_tag == key.key_tag() && rrsig.algorithm == key.algorithm.number() && rrsig.expiration > refresh_time
+        }).cloned()
+    }
+}
@@ -1,4 +1,11 @@
+pub mod acl;
+pub mod config;
 pub mod db;
+pub mod dnssec;
+pub mod resolver;
+pub mod rrl;
 pub mod server;
+pub mod tsig;
 pub mod app;
 pub mod zone_parser;
+pub mod zone_writer;
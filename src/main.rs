@@ -1,64 +1,315 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::fs;
-use serde::Deserialize;
-use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use clap::{Parser, Subcommand};
 
+use dont_need_stability::db::{Severity, Zone};
 use dont_need_stability::db::in_memory::InMemoryDatabase;
-use dont_need_stability::app::App;
-use dont_need_stability::server::ServerConfig;
+use dont_need_stability::app::{App, ZoneUpdate};
+use dont_need_stability::config::{Config, KeyRole};
+use dont_need_stability::dnssec::presentation::format_rdata;
+use dont_need_stability::dnssec::{Algorithm, KeyStore, SigningKey, DNSKEY};
+use dont_need_stability::tsig;
 use dont_need_stability::zone_parser::ZoneParser;
 
+/// How often zone files are checked for changes.
+const ZONE_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     /// Path to the configuration file
     #[arg(short, long, value_name = "FILE")]
     config: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Deserialize)]
-struct Config {
-    server: ServerConfig,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate a DNSSEC key for a zone and print its DNSKEY record
+    Keygen {
+        /// The zone the key signs
+        zone: String,
+        #[arg(long, default_value = "ecdsap256sha256", value_parser = ["ecdsap256sha256", "ed25519"])]
+        algorithm: String,
+        #[arg(long, default_value = "csk", value_parser = ["csk", "ksk", "zsk"])]
+        role: String,
+        /// Directory to write the private key file to
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Print the DS records the parent of a signed zone should publish
+    Ds {
+        zone: String,
+    },
 }
 
 fn main() -> std::io::Result<()> {
     let cli = Cli::parse();
-
     let config_path = cli.config.unwrap_or_else(|| "config.toml".to_string());
-    let config_content = fs::read_to_string(&config_path)
-        .expect(&format!("Failed to read config file: {}", config_path));
-    let config: Config = toml::from_str(&config_content)
-        .expect("Failed to parse config.toml");
+    match cli.command {
+        Some(Command::Keygen { zone, algorithm, role, dir }) => keygen(&zone, &algorithm, &role, &dir),
+        Some(Command::Ds { zone }) => print_ds(&load_config(&config_path), &zone),
+        None => serve(load_config(&config_path)),
+    }
+}
+
+fn load_config(config_path: &str) -> Config {
+    let config_content = fs::read_to_string(config_path)
+        .unwrap_or_else(|_| panic!("Failed to read config file: {}", config_path));
+    toml::from_str(&config_content)
+        .expect("Failed to parse config.toml")
+}
+
+/// Writes a new key to `dir` in BIND's private key format, ready to be listed in the
+/// zone's `signing_keys`.
+fn keygen(zone: &str, algorithm: &str, role: &str, dir: &Path) -> std::io::Result<()> {
+    let algorithm = if algorithm == "ed25519" { Algorithm::Ed25519 } else { Algorithm::EcdsaP256Sha256 };
+    let role = match role {
+        "ksk" => KeyRole::Ksk,
+        "zsk" => KeyRole::Zsk,
+        _ => KeyRole::Csk,
+    };
+    let key = SigningKey::generate(algorithm, role.flags());
+    let path = dir.join(key.file_name(zone));
+    key.save(&path).map_err(std::io::Error::other)?;
+    println!("Wrote {}", path.display());
+    let dnskey = format_rdata(DNSKEY, &key.dnskey_rdata()).unwrap_or_default();
+    println!("{} IN DNSKEY {}", zone, dnskey);
+    if key.is_ksk() {
+        println!("{}", key.ds_record(zone));
+    }
+    Ok(())
+}
+
+/// Prints the DS records of a zone's key signing keys. For zones whose keys the server
+/// manages, these are the records the parent should publish right now.
+fn print_ds(config: &Config, zone: &str) -> std::io::Result<()> {
+    let Some(zone_config) = config.zone(zone) else {
+        return Err(std::io::Error::other(format!("No zone {} in the configuration", zone)));
+    };
+    let records = if zone_config.key_policy.is_some() {
+        let store = KeyStore::open(&config.server.zones_directory, zone).map_err(std::io::Error::other)?;
+        store.ds_records(tsig::now() as u32)
+    } else {
+        let mut records = Vec::new();
+        for key in zone_config.signing_keys.iter().filter(|key| key.role != KeyRole::Zsk) {
+            let key = SigningKey::load(&key.file, key.role.flags()).map_err(std::io::Error::other)?;
+            records.push(key.ds_record(zone));
+        }
+        records
+    };
+    if records.is_empty() {
+        eprintln!("Zone {} has no key signing keys yet", zone);
+    }
+    for record in records {
+        println!("{}", record);
+    }
+    Ok(())
+}
 
+fn serve(config: Config) -> std::io::Result<()> {
     let socket: SocketAddr = format!("127.0.0.1:{}", config.server.port)
         .parse()
         .expect("Invalid address");
 
-    let mut db = InMemoryDatabase::new();
+    let zones_path = config.server.zones_directory.clone();
+    let secondaries: Vec<String> = config
+        .zones
+        .iter()
+        .filter(|zone| zone.is_secondary())
+        .map(|zone| zone.origin.clone())
+        .collect();
+    let zones = load_zones(&zones_path, &secondaries)?;
 
-    let zones_path = &config.server.zones_directory;
-    if zones_path.is_dir() {
-        for entry in fs::read_dir(zones_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file() && path.extension().map_or(false, |ext| ext == "zone") {
-                println!("Loading zone file: {:?}", path);
-                let zone_content = fs::read_to_string(&path)?;
-                let file_name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("default.zone");
-                let default_origin = format!("{}.", file_name.replace(".zone", ""));
-                match ZoneParser::parse_zone_file(&zone_content, default_origin, 3600) {
-                    Ok(zone) => {
-                        db.add_zone(zone).expect("Failed to add zone to database");
-                    },
-                    Err(e) => eprintln!("Error parsing zone file {:?}: {}", path, e),
-                }
-            }
+    // Views are loaded the same way from their own directories, but cannot hold
+    // secondary or signed zones.
+    let mut views = Vec::new();
+    for view in &config.views {
+        println!("Loading view {}", view.name);
+        let zones = load_zones(&view.zones_directory, &[])?;
+        for (origin, _) in &zones.files {
+            config
+                .check_view_zone(origin)
+                .map_err(|e| std::io::Error::other(format!("View {}: {}", view.name, e)))?;
+        }
+        views.push((view.clone(), zones));
+    }
+
+    let mut app: App = App::with_config(Box::new(zones.db), socket, config);
+    for (origin, path) in zones.files {
+        app.set_zone_file(&origin, path);
+    }
+    let zone_sender = app.zone_sender();
+    thread::spawn(move || watch_zones(&zones_path, &secondaries, zones.watched, zone_sender));
+
+    for (view, zones) in views {
+        app.set_view_database(&view.name, Box::new(zones.db)).map_err(std::io::Error::other)?;
+        for (origin, path) in zones.files {
+            app.set_view_zone_file(&view.name, &origin, path).map_err(std::io::Error::other)?;
+        }
+        if let Some(zone_sender) = app.view_zone_sender(&view.name) {
+            thread::spawn(move || watch_zones(&view.zones_directory, &[], zones.watched, zone_sender));
         }
     }
 
-    let mut app: App = App::new(Box::new(db), socket);
     println!("Starting server on {}", socket);
     println!("Press Ctrl+C to stop");
     app.run();
     Ok(())
 }
+
+/// The zones loaded from a directory.
+struct LoadedZones {
+    db: InMemoryDatabase,
+    /// The file each zone was loaded from, by origin.
+    files: Vec<(String, PathBuf)>,
+    /// The zone files, for `watch_zones`.
+    watched: HashMap<PathBuf, WatchedFile>,
+}
+
+/// A zone file as `watch_zones` last loaded it.
+struct WatchedFile {
+    /// The modification times of the file and of the files it includes.
+    modified: Vec<(PathBuf, Option<SystemTime>)>,
+    /// The zone the file holds, if it was served.
+    origin: Option<String>,
+}
+
+impl WatchedFile {
+    fn new(path: &Path, includes: Vec<PathBuf>, origin: Option<String>) -> Self {
+        let modified = std::iter::once(path.to_path_buf())
+            .chain(includes)
+            .map(|path| {
+                let time = modified_time(&path);
+                (path, time)
+            })
+            .collect();
+        WatchedFile { modified, origin }
+    }
+
+    /// Returns whether the file or one of its includes changed since it was loaded.
+    fn changed(&self) -> bool {
+        self.modified.iter().any(|(path, time)| modified_time(path) != *time)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Loads the zone files in a directory.
+fn load_zones(zones_path: &Path, secondaries: &[String]) -> std::io::Result<LoadedZones> {
+    let mut db = InMemoryDatabase::new();
+    let mut watched: HashMap<PathBuf, WatchedFile> = HashMap::new();
+    let mut loaded: Vec<(String, PathBuf)> = Vec::new();
+    for path in zone_files(zones_path)? {
+        println!("Loading zone file: {:?}", path);
+        let (zone, includes) = load_zone(&path, zones_path, secondaries);
+        watched.insert(path.clone(), WatchedFile::new(&path, includes, zone.as_ref().map(|zone| zone.origin.clone())));
+        if let Some(zone) = zone {
+            loaded.push((zone.origin.clone(), path.clone()));
+            db.add_zone(zone).expect("Failed to add zone to database");
+        }
+    }
+    Ok(LoadedZones { db, files: loaded, watched })
+}
+
+/// Lists the zone files in the zones directory.
+fn zone_files(zones_path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if zones_path.is_dir() {
+        for entry in fs::read_dir(zones_path)? {
+            let path = entry?.path();
+            let is_zone_file = path
+                .extension()
+                .is_some_and(|ext| ext == "zone" || ext == "json" || ext == "yaml" || ext == "yml");
+            if path.is_file() && is_zone_file {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Parses and validates a zone file, printing any problems. Returns `None` if the zone
+/// must not be served, or is a secondary zone transferred from its primaries instead,
+/// along with the files the zone file includes.
+fn load_zone(path: &Path, zones_path: &Path, secondaries: &[String]) -> (Option<Zone>, Vec<PathBuf>) {
+    let file_name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("default.zone");
+    let default_origin = format!("{}.", file_name.replace(".zone", ""));
+    let (result, includes) = ZoneParser::load_zone_file_with_includes(path, default_origin, 3600, zones_path);
+    let zone = match result {
+        Ok(zone) if secondaries.contains(&zone.origin) => {
+            eprintln!("Ignoring zone file {:?}: {} is a secondary zone", path, zone.origin);
+            None
+        },
+        Ok(zone) => {
+            let issues = zone.validate();
+            for issue in &issues {
+                eprintln!("{:?}: {:?}: {}: {}", path, issue.severity(), issue.name, issue.message);
+            }
+            if issues.iter().any(|issue| issue.severity() == Severity::Error) {
+                eprintln!("Skipping zone file {:?}: failed validation", path);
+                None
+            } else {
+                Some(zone)
+            }
+        },
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("{}", e);
+            }
+            eprintln!("Skipping zone file {:?}: {} error(s)", path, errors.len());
+            None
+        },
+    };
+    (zone, includes)
+}
+
+/// Reloads zone files whose modification time, or that of a file they include, changed
+/// and hands them to the app. Zones whose file is deleted are removed.
+fn watch_zones(
+    zones_path: &Path,
+    secondaries: &[String],
+    mut watched: HashMap<PathBuf, WatchedFile>,
+    zone_sender: Sender<ZoneUpdate>,
+) {
+    loop {
+        thread::sleep(ZONE_RELOAD_INTERVAL);
+        let Ok(files) = zone_files(zones_path) else {
+            continue;
+        };
+        let deleted: Vec<PathBuf> = watched.keys().filter(|path| !files.contains(path)).cloned().collect();
+        for path in deleted {
+            if let Some(origin) = watched.remove(&path).and_then(|file| file.origin) {
+                println!("Zone file {:?} was removed", path);
+                if zone_sender.send(ZoneUpdate::Remove(origin)).is_err() {
+                    return;
+                }
+            }
+        }
+        for path in files {
+            let previous = watched.get(&path);
+            if previous.is_some_and(|file| !file.changed()) {
+                continue;
+            }
+            println!("Reloading zone file: {:?}", path);
+            // A zone that fails to reload keeps being served, so its deletion still counts.
+            let previous_origin = previous.and_then(|file| file.origin.clone());
+            let (zone, includes) = load_zone(&path, zones_path, secondaries);
+            let origin = zone.as_ref().map(|zone| zone.origin.clone()).or(previous_origin);
+            watched.insert(path.clone(), WatchedFile::new(&path, includes, origin));
+            if let Some(zone) = zone
+                && zone_sender.send(ZoneUpdate::Replace(zone)).is_err()
+            {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,572 @@
+//! A caching recursive resolver: names outside the local zones are resolved iteratively,
+//! starting from the root servers (RFC 1034, section 5.3.3), or sent to upstream
+//! resolvers for forwarded domains, and what the servers say is cached for its TTL.
+
+pub mod cache;
+pub mod forward;
+pub mod policy;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::serialize::binary::BinEncodable;
+
+use self::cache::{Cache, CacheStats, Cached, Key};
+use self::forward::{ForwardZone, Forwarder};
+use self::policy::{Policies, PolicyConfig, PolicyOverride};
+use crate::acl::Cidr;
+use crate::db::validation::{is_in_zone, normalize};
+use crate::dnssec;
+use crate::server::Server;
+
+/// How many referrals a lookup follows before giving up.
+const MAX_REFERRALS: usize = 30;
+
+/// How many CNAME records are followed before giving up.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// How deeply resolving the addresses of name servers may nest.
+const MAX_DEPTH: usize = 4;
+
+/// The UDP payload size advertised to servers (RFC 9715).
+const EDNS_PAYLOAD: u16 = 1232;
+
+/// The IPv4 addresses of the root servers, a to m.
+const ROOT_SERVERS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+/// The `[resolver]` table of `config.toml`. Without it the server only answers from its
+/// own zones.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ResolverConfig {
+    /// Client prefixes allowed to ask for recursion. Recursion is refused when the list is
+    /// empty.
+    pub allow_recursion: Vec<Cidr>,
+    /// Addresses of the root servers. Defaults to the IANA root servers.
+    pub root_hints: Vec<IpAddr>,
+    /// The port servers are queried on. Ports other than 53 are meant for testing.
+    pub port: u16,
+    /// How long to wait for a server to answer, in milliseconds.
+    pub timeout_ms: u64,
+    /// How many RRsets and negative answers the cache holds.
+    pub cache_size: usize,
+    /// Domains whose names are sent to upstream resolvers, given as
+    /// `[[resolver.forward]]` tables.
+    pub forward: Vec<ForwardZone>,
+    /// How long expired answers are kept, in seconds, to be served when the servers
+    /// cannot be reached (RFC 8767). 0 never serves stale answers.
+    pub max_stale_ttl: u32,
+    /// Refresh popular answers before they expire.
+    pub prefetch: bool,
+    /// Response policies, given as `[[resolver.policy]]` tables, checked in order.
+    pub policy: Vec<PolicyConfig>,
+    /// Per-client replacements for the actions of policies, given as
+    /// `[[resolver.policy_override]]` tables.
+    pub policy_override: Vec<PolicyOverride>,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            allow_recursion: Vec::new(),
+            root_hints: ROOT_SERVERS.iter().map(|addr| IpAddr::V4(*addr)).collect(),
+            port: 53,
+            timeout_ms: 2000,
+            cache_size: 10_000,
+            forward: Vec::new(),
+            max_stale_ttl: 24 * 3600,
+            prefetch: true,
+            policy: Vec::new(),
+            policy_override: Vec::new(),
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// Returns whether `addr` may ask for recursion.
+    pub fn allows(&self, addr: IpAddr) -> bool {
+        self.allow_recursion.iter().any(|cidr| cidr.contains(addr))
+    }
+}
+
+/// The outcome of resolving a question.
+#[derive(Debug)]
+pub struct Resolution {
+    pub code: ResponseCode,
+    /// The records answering the question, after the CNAME records leading to them.
+    pub answers: Vec<Record>,
+    /// The SOA record of a negative answer, and with DNSSEC the records proving it.
+    pub authority: Vec<Record>,
+}
+
+/// What is known about a single name and type, before following CNAME records.
+enum Lookup {
+    Records(Vec<Record>),
+    /// The name is an alias for the target name. Holds the CNAME record and, with
+    /// DNSSEC, its signatures.
+    Cname(Vec<Record>, String),
+    Negative(ResponseCode, Vec<Record>),
+}
+
+impl Lookup {
+    /// Turns cached data into a lookup result, telling aliases from answers.
+    fn from_cached(cached: Cached, rtype: RecordType) -> Option<Lookup> {
+        match cached {
+            Cached::Records(records) if rtype != RecordType::CNAME => {
+                match records.iter().find(|record| record.record_type() == RecordType::CNAME).and_then(cname_target) {
+                    Some(target) => Some(Lookup::Cname(records, target)),
+                    None => Some(Lookup::Records(records)),
+                }
+            }
+            Cached::Records(records) => Some(Lookup::Records(records)),
+            Cached::Negative(code, authority) => Some(Lookup::Negative(code, authority)),
+        }
+    }
+}
+
+/// Resolves names iteratively, or through upstream resolvers for forwarded domains. It
+/// is shared by the threads resolving client queries.
+pub struct Resolver {
+    config: ResolverConfig,
+    cache: Mutex<Cache>,
+    forwarder: Forwarder,
+    policies: Policies,
+}
+
+impl Resolver {
+    pub fn new(config: ResolverConfig) -> Self {
+        let cache = Mutex::new(Cache::new(config.cache_size, config.max_stale_ttl));
+        let forwarder = Forwarder::new(&config.forward, Duration::from_millis(config.timeout_ms));
+        let policies = Policies::load(&config.policy, &config.policy_override);
+        Resolver { config, cache, forwarder, policies }
+    }
+
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    pub fn forwarder(&self) -> &Forwarder {
+        &self.forwarder
+    }
+
+    pub fn policies(&self) -> &Policies {
+        &self.policies
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache().stats()
+    }
+
+    /// Resolves a question, following CNAME records. With `dnssec_ok`, the answer
+    /// includes the RRSIG records the servers sent. When the servers cannot be reached,
+    /// expired answers are served if the cache still has them, and otherwise SERVFAIL.
+    pub fn resolve(&self, name: &str, rtype: RecordType, dnssec_ok: bool) -> Resolution {
+        let name = normalize(name);
+        match self.resolve_chain(&name, rtype, dnssec_ok, 0) {
+            Ok(resolution) => resolution,
+            Err(e) => match self.resolve_stale(&name, rtype, dnssec_ok) {
+                Some(resolution) => {
+                    eprintln!("Failed to resolve {} {}, serving a stale answer: {}", name, rtype, e);
+                    resolution
+                }
+                None => {
+                    eprintln!("Failed to resolve {} {}: {}", name, rtype, e);
+                    Resolution { code: ResponseCode::ServFail, answers: Vec::new(), authority: Vec::new() }
+                }
+            },
+        }
+    }
+
+    /// Refreshes the popular cache entries that are about to expire. Meant to be called
+    /// after a response was sent, so that clients do not wait for it.
+    pub fn prefetch(&self) {
+        if !self.config.prefetch {
+            return;
+        }
+        let keys = self.cache().take_prefetches();
+        for key in keys {
+            if let Err(e) = self.fetch(&key.name, key.rtype, key.dnssec_ok, 0) {
+                eprintln!("Failed to prefetch {} {}: {}", key.name, key.rtype, e);
+            }
+        }
+    }
+
+    fn cache(&self) -> MutexGuard<'_, Cache> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn resolve_chain(&self, name: &str, rtype: RecordType, dnssec_ok: bool, depth: usize) -> Result<Resolution, String> {
+        let mut answers = Vec::new();
+        let mut name = name.to_string();
+        for _ in 0..MAX_CNAME_CHAIN {
+            match self.lookup(&name, rtype, dnssec_ok, depth)? {
+                Lookup::Records(records) => {
+                    answers.extend(records);
+                    return Ok(Resolution { code: ResponseCode::NoError, answers, authority: Vec::new() });
+                }
+                Lookup::Cname(cname, target) => {
+                    answers.extend(cname);
+                    name = target;
+                }
+                Lookup::Negative(code, authority) => {
+                    return Ok(Resolution { code, answers, authority });
+                }
+            }
+        }
+        Err(format!("CNAME chain longer than {}", MAX_CNAME_CHAIN))
+    }
+
+    /// Answers a question from the cache alone, using expired entries where needed.
+    fn resolve_stale(&self, name: &str, rtype: RecordType, dnssec_ok: bool) -> Option<Resolution> {
+        let mut answers = Vec::new();
+        let mut name = name.to_string();
+        let now = Instant::now();
+        let mut cache = self.cache();
+        for _ in 0..MAX_CNAME_CHAIN {
+            let key = Key::new(&name, rtype, dnssec_ok);
+            let cached = cache.get(&key, false, now).or_else(|| cache.get_stale(&key, now))?;
+            match Lookup::from_cached(cached, rtype)? {
+                Lookup::Records(records) => {
+                    answers.extend(records);
+                    return Some(Resolution { code: ResponseCode::NoError, answers, authority: Vec::new() });
+                }
+                Lookup::Cname(cname, target) => {
+                    answers.extend(cname);
+                    name = target;
+                }
+                Lookup::Negative(code, authority) => return Some(Resolution { code, answers, authority }),
+            }
+        }
+        None
+    }
+
+    /// Answers a single name and type from the cache, or else asks the servers.
+    fn lookup(&self, name: &str, rtype: RecordType, dnssec_ok: bool, depth: usize) -> Result<Lookup, String> {
+        let cached = self.cache().get(&Key::new(name, rtype, dnssec_ok), false, Instant::now());
+        if let Some(found) = cached.and_then(|cached| Lookup::from_cached(cached, rtype)) {
+            return Ok(found);
+        }
+        self.fetch(name, rtype, dnssec_ok, depth)
+    }
+
+    /// Asks the upstreams of the name's forwarded domain or the servers of its zone, and
+    /// caches the answer.
+    fn fetch(&self, name: &str, rtype: RecordType, dnssec_ok: bool, depth: usize) -> Result<Lookup, String> {
+        if let Some(upstreams) = self.forwarder.upstreams(name) {
+            // Upstreams resolve CNAME chains into other domains, and are trusted with
+            // all of them.
+            let response = self.forwarder.forward(upstreams, name, rtype, dnssec_ok)?;
+            return Ok(self.digest(".", name, rtype, dnssec_ok, &response));
+        }
+        let (zone, response) = self.query_authorities(name, rtype, dnssec_ok, depth)?;
+        Ok(self.digest(&zone, name, rtype, dnssec_ok, &response))
+    }
+
+    /// Follows referrals from the closest servers known for `name` down to the servers
+    /// that answer for it. Returns the zone those servers were asked as, and their
+    /// response.
+    fn query_authorities(&self, name: &str, rtype: RecordType, dnssec_ok: bool, depth: usize) -> Result<(String, Message), String> {
+        let (mut zone, mut servers) = self.closest_servers(name);
+        let msg = query_message(name, rtype, false, dnssec_ok)?;
+        for _ in 0..MAX_REFERRALS {
+            let response = self.query_servers(&servers, &msg)?;
+            let Some(child) = referral(&response, &zone, name)? else {
+                return Ok((zone, response));
+            };
+            let ns: Vec<Record> = response
+                .name_servers()
+                .iter()
+                .filter(|record| record.record_type() == RecordType::NS && owner(record) == child)
+                .cloned()
+                .collect();
+            let names = ns_names(&ns);
+            // Glue is only believed for the name servers of the child, and when the
+            // referring servers are authoritative for the names.
+            let glue: Vec<Record> = response
+                .additionals()
+                .iter()
+                .filter(|record| matches!(record.record_type(), RecordType::A | RecordType::AAAA))
+                .filter(|record| names.contains(&owner(record)) && is_in_zone(&owner(record), &zone))
+                .cloned()
+                .collect();
+            {
+                let mut cache = self.cache();
+                cache.insert(&ns, true, false, Instant::now());
+                cache.insert(&glue, true, false, Instant::now());
+            }
+            servers = self.server_addresses(&names, depth)?;
+            zone = child;
+        }
+        Err(format!("more than {} referrals", MAX_REFERRALS))
+    }
+
+    /// Returns the deepest zone at or above `name` whose servers' addresses are cached,
+    /// and those addresses. Without one, the root servers.
+    fn closest_servers(&self, name: &str) -> (String, Vec<SocketAddr>) {
+        let mut cache = self.cache();
+        let now = Instant::now();
+        let mut zone = name;
+        loop {
+            if let Some(Cached::Records(ns)) = cache.get(&Key::new(zone, RecordType::NS, false), true, now) {
+                let addresses = self.cached_addresses(&mut cache, &ns_names(&ns), now);
+                if !addresses.is_empty() {
+                    return (zone.to_string(), addresses);
+                }
+            }
+            match zone.split_once('.') {
+                Some((_, parent)) if !parent.is_empty() => zone = parent,
+                _ => break,
+            }
+        }
+        let roots = self.config.root_hints.iter().map(|addr| SocketAddr::new(*addr, self.config.port)).collect();
+        (".".to_string(), roots)
+    }
+
+    fn cached_addresses(&self, cache: &mut Cache, names: &[String], now: Instant) -> Vec<SocketAddr> {
+        let mut addresses = Vec::new();
+        for name in names {
+            for rtype in [RecordType::A, RecordType::AAAA] {
+                if let Some(Cached::Records(records)) = cache.get(&Key::new(name, rtype, false), true, now) {
+                    addresses.extend(records.iter().filter_map(address).map(|addr| SocketAddr::new(addr, self.config.port)));
+                }
+            }
+        }
+        addresses
+    }
+
+    /// Returns the addresses of name servers, from the cache or else by resolving their
+    /// names.
+    fn server_addresses(&self, names: &[String], depth: usize) -> Result<Vec<SocketAddr>, String> {
+        let addresses = self.cached_addresses(&mut self.cache(), names, Instant::now());
+        if !addresses.is_empty() {
+            return Ok(addresses);
+        }
+        if depth >= MAX_DEPTH {
+            return Err("name server addresses nested too deeply".to_string());
+        }
+        for name in names {
+            if let Ok(resolution) = self.resolve_chain(name, RecordType::A, false, depth + 1) {
+                let addresses: Vec<SocketAddr> =
+                    resolution.answers.iter().filter_map(address).map(|addr| SocketAddr::new(addr, self.config.port)).collect();
+                if !addresses.is_empty() {
+                    return Ok(addresses);
+                }
+            }
+        }
+        Err(format!("no address for name servers {}", names.join(", ")))
+    }
+
+    /// Asks the servers in turn until one answers with NOERROR or NXDOMAIN.
+    fn query_servers(&self, servers: &[SocketAddr], msg: &Message) -> Result<Message, String> {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut errors = Vec::new();
+        for server in servers {
+            match exchange_message(*server, msg, timeout) {
+                Ok(response) if matches!(response.response_code(), ResponseCode::NoError | ResponseCode::NXDomain) => {
+                    return Ok(response);
+                }
+                Ok(response) => errors.push(format!("{} answered {}", server, response.response_code())),
+                Err(e) => errors.push(format!("{}: {}", server, e)),
+            }
+        }
+        Err(errors.join(", "))
+    }
+
+    /// Caches what the servers of `zone` said about a name and type, and turns it into a
+    /// lookup result. Records outside `zone` are ignored, since its servers have no
+    /// authority over them.
+    fn digest(&self, zone: &str, name: &str, rtype: RecordType, dnssec_ok: bool, response: &Message) -> Lookup {
+        let now = Instant::now();
+        let answers: Vec<Record> = response.answers().iter().filter(|record| is_in_zone(&owner(record), zone)).cloned().collect();
+        let soa = response
+            .name_servers()
+            .iter()
+            .find(|record| record.record_type() == RecordType::SOA && is_in_zone(&owner(record), zone) && is_in_zone(name, &owner(record)))
+            .cloned();
+        let mut cache = self.cache();
+        cache.insert(&answers, false, dnssec_ok, now);
+
+        let at_name = |wanted: RecordType| -> Vec<Record> {
+            answers
+                .iter()
+                .filter(|record| owner(record) == name && (rrset_type(record) == wanted || wanted == RecordType::ANY))
+                .filter(|record| dnssec_ok || !is_rrsig(record))
+                .cloned()
+                .collect()
+        };
+        let matching = at_name(rtype);
+        if !matching.is_empty() {
+            return Lookup::Records(matching);
+        }
+        let cname = at_name(RecordType::CNAME);
+        if let Some(target) = cname.iter().find_map(cname_target) {
+            return Lookup::Cname(cname, target);
+        }
+        let code = match response.response_code() {
+            ResponseCode::NXDomain => ResponseCode::NXDomain,
+            _ => ResponseCode::NoError,
+        };
+        let mut authority: Vec<Record> = soa.into_iter().collect();
+        if dnssec_ok && !authority.is_empty() {
+            authority.extend(
+                response
+                    .name_servers()
+                    .iter()
+                    .filter(|record| is_in_zone(&owner(record), zone) && is_denial(record))
+                    .cloned(),
+            );
+        }
+        cache.insert_negative(Key::new(name, rtype, dnssec_ok), code, authority.clone(), now);
+        Lookup::Negative(code, authority)
+    }
+}
+
+/// Returns the child zone a response delegates to, if it is a referral. A referral to a
+/// zone that is not below `zone` and at or above `name` is an error: the server is
+/// misconfigured or trying to poison the cache.
+fn referral(response: &Message, zone: &str, name: &str) -> Result<Option<String>, String> {
+    if response.response_code() != ResponseCode::NoError || !response.answers().is_empty() || response.authoritative() {
+        return Ok(None);
+    }
+    let Some(ns) = response.name_servers().iter().find(|record| record.record_type() == RecordType::NS) else {
+        return Ok(None);
+    };
+    let child = owner(ns);
+    if child == zone || !is_in_zone(&child, zone) || !is_in_zone(name, &child) {
+        return Err(format!("referral to {} for {} from the servers of {}", child, name, zone));
+    }
+    Ok(Some(child))
+}
+
+/// Sends a query to a server over UDP, retrying over TCP if the response is truncated.
+/// Responses from other addresses or for other queries are ignored.
+pub fn exchange(server: SocketAddr, name: &str, rtype: RecordType, timeout: Duration) -> Result<Message, String> {
+    exchange_message(server, &query_message(name, rtype, false, false)?, timeout)
+}
+
+/// Creates a query with EDNS and a random id.
+fn query_message(name: &str, rtype: RecordType, recursion_desired: bool, dnssec_ok: bool) -> Result<Message, String> {
+    let mut msg = Message::new();
+    msg.set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(recursion_desired)
+        .add_query(Query::query(Name::from_utf8(name).map_err(|e| e.to_string())?, rtype));
+    let mut edns = Edns::new();
+    edns.set_max_payload(EDNS_PAYLOAD).set_dnssec_ok(dnssec_ok);
+    msg.set_edns(edns);
+    Ok(msg)
+}
+
+fn exchange_message(server: SocketAddr, msg: &Message, timeout: Duration) -> Result<Message, String> {
+    let bytes = msg.to_bytes().map_err(|e| e.to_string())?;
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+    socket.send_to(&bytes, server).map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0; 4096];
+    let response = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("timed out".to_string());
+        }
+        socket.set_read_timeout(Some(remaining)).map_err(|e| e.to_string())?;
+        let (len, src) = socket.recv_from(&mut buf).map_err(|e| e.to_string())?;
+        let Ok(response) = Message::from_vec(&buf[..len]) else {
+            continue;
+        };
+        if src == server && response.id() == msg.id() && response.queries() == msg.queries() {
+            break response;
+        }
+    };
+    if !response.truncated() {
+        return Ok(response);
+    }
+    exchange_tcp(server, msg, timeout)
+}
+
+/// Sends a query to a server over TCP.
+fn exchange_tcp(server: SocketAddr, msg: &Message, timeout: Duration) -> Result<Message, String> {
+    let bytes = msg.to_bytes().map_err(|e| e.to_string())?;
+    let stream = TcpStream::connect_timeout(&server, timeout).map_err(|e| e.to_string())?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+    Server::write_tcp_message(&stream, &bytes).map_err(|e| e.to_string())?;
+    let response = Message::from_vec(&Server::read_tcp_message(&stream).map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    if response.id() != msg.id() || response.queries() != msg.queries() {
+        return Err("TCP response does not match the query".to_string());
+    }
+    Ok(response)
+}
+
+/// Returns the normalized owner name of a record.
+fn owner(record: &Record) -> String {
+    normalize(&record.name().to_string())
+}
+
+fn ns_names(ns: &[Record]) -> Vec<String> {
+    ns.iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::NS(target)) => Some(normalize(&target.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns whether a record is an RRSIG record. The crate reads them with their own type,
+/// but gives records built from opaque RDATA an unknown type with the same code.
+fn is_rrsig(record: &Record) -> bool {
+    u16::from(record.record_type()) == dnssec::RRSIG
+}
+
+/// Returns the type of the RRset a record belongs to: the type covered by an RRSIG
+/// record, otherwise its own.
+fn rrset_type(record: &Record) -> RecordType {
+    match record.data() {
+        Some(RData::Unknown { rdata, .. }) if is_rrsig(record) && rdata.anything().len() >= 2 => {
+            RecordType::from(u16::from_be_bytes([rdata.anything()[0], rdata.anything()[1]]))
+        }
+        _ => record.record_type(),
+    }
+}
+
+/// Returns whether a record proves the denial of a name or type: NSEC and NSEC3 records
+/// and their signatures.
+fn is_denial(record: &Record) -> bool {
+    matches!(u16::from(rrset_type(record)), dnssec::NSEC | dnssec::NSEC3)
+}
+
+fn cname_target(record: &Record) -> Option<String> {
+    match record.data() {
+        Some(RData::CNAME(target)) => Some(normalize(&target.to_string())),
+        _ => None,
+    }
+}
+
+fn address(record: &Record) -> Option<IpAddr> {
+    match record.data() {
+        Some(RData::A(addr)) => Some(IpAddr::V4(*addr)),
+        Some(RData::AAAA(addr)) => Some(IpAddr::V6(*addr)),
+        _ => None,
+    }
+}
@@ -0,0 +1,321 @@
+//! The resolver's cache of RRsets and negative answers, each kept for its TTL. Expired
+//! entries are kept a while longer, to be served when the servers cannot be reached
+//! (RFC 8767), and the least recently used entries make room for new ones.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use trust_dns_proto::op::ResponseCode;
+use trust_dns_proto::rr::{DNSClass, RData, Record, RecordType};
+
+use super::{is_rrsig, rrset_type};
+use crate::db::validation::normalize;
+
+/// The longest time anything is cached, whatever its TTL.
+const MAX_TTL: u32 = 7 * 24 * 3600;
+
+/// The longest time a negative answer is cached (RFC 2308, section 5).
+const MAX_NEGATIVE_TTL: u32 = 3 * 3600;
+
+/// The TTL of stale answers, and how long they are served before the servers are tried
+/// again (RFC 8767, section 4).
+pub const STALE_TTL: u32 = 30;
+
+/// How often an entry must have been used to be refreshed before it expires.
+const PREFETCH_HITS: u64 = 2;
+
+/// Entries are refreshed when less than this fraction of their TTL remains.
+const PREFETCH_FRACTION: u32 = 10;
+
+/// What a cache entry answers: a name, type and class, with or without DNSSEC records.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub name: String,
+    pub rtype: RecordType,
+    pub class: DNSClass,
+    /// Whether the entry holds the RRSIG records, and the NSEC or NSEC3 records of
+    /// negative answers, for clients that set the DNSSEC OK bit.
+    pub dnssec_ok: bool,
+}
+
+impl Key {
+    /// Creates a key for a name and type in class IN.
+    pub fn new(name: &str, rtype: RecordType, dnssec_ok: bool) -> Self {
+        Key { name: normalize(name), rtype, class: DNSClass::IN, dnssec_ok }
+    }
+
+    fn with_type(&self, rtype: RecordType) -> Self {
+        Key { rtype, ..self.clone() }
+    }
+}
+
+/// What the cache knows about a name and type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cached {
+    /// The RRset, with TTLs counting down from when it was cached. When the name is an
+    /// alias, its CNAME record instead.
+    Records(Vec<Record>),
+    /// The name does not exist (NXDOMAIN) or has no records of the type (NOERROR), as
+    /// shown by the zone's SOA record and, with DNSSEC, the proof of denial.
+    Negative(ResponseCode, Vec<Record>),
+}
+
+/// Counters of the cache's use. Lookups for glue are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Expired answers served because the servers could not be reached.
+    pub stale_hits: u64,
+    /// Entries refreshed before they expired.
+    pub prefetches: u64,
+    /// Entries removed to make room for new ones.
+    pub evictions: u64,
+    pub entries: usize,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries, {} hits, {} misses, {} stale hits, {} prefetches, {} evictions",
+            self.entries, self.hits, self.misses, self.stale_hits, self.prefetches, self.evictions
+        )
+    }
+}
+
+struct Entry {
+    value: Cached,
+    /// The TTL the entry was cached with.
+    ttl: u32,
+    expires: Instant,
+    /// Whether the data came from glue or a referral rather than an authoritative
+    /// answer. It is only used to find servers, never to answer clients (RFC 2181,
+    /// section 5.4.1).
+    glue: bool,
+    hits: u64,
+    /// Position in the order of use; the lowest is evicted first.
+    used: u64,
+    /// Set while the entry is waiting to be refreshed.
+    prefetching: bool,
+    /// When a stale entry was served, the time until which it keeps being served
+    /// without asking the servers.
+    recheck: Option<Instant>,
+}
+
+impl Entry {
+    /// Returns when the entry may be served until, and whether it is stale by then.
+    fn usable_until(&self, now: Instant) -> Option<(Instant, bool)> {
+        if self.expires > now {
+            return Some((self.expires, false));
+        }
+        self.recheck.filter(|recheck| *recheck > now).map(|recheck| (recheck, true))
+    }
+}
+
+/// Cached data by key, bounded in size.
+pub struct Cache {
+    entries: HashMap<Key, Entry>,
+    /// Keys by their position in the order of use.
+    lru: BTreeMap<u64, Key>,
+    clock: u64,
+    capacity: usize,
+    /// How long expired entries are kept to be served stale.
+    max_stale: Duration,
+    stats: CacheStats,
+    /// Popular entries about to expire, waiting to be refreshed.
+    prefetch: Vec<Key>,
+}
+
+impl Cache {
+    /// Creates a cache holding at most `capacity` RRsets and negative answers, keeping
+    /// them for `max_stale` seconds after they expire. 0 disables serving stale answers.
+    pub fn new(capacity: usize, max_stale: u32) -> Self {
+        Cache {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            capacity,
+            max_stale: secs(max_stale),
+            stats: CacheStats::default(),
+            prefetch: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats { entries: self.entries.len(), ..self.stats }
+    }
+
+    /// Returns what the cache knows about a key at `now`: its RRset or negative answer,
+    /// or else the CNAME record of the name. TTLs are reduced by the time spent in the
+    /// cache. Glue is only returned when `glue` is set.
+    ///
+    /// Popular entries close to expiry are queued to be refreshed, see `take_prefetches`.
+    pub fn get(&mut self, key: &Key, glue: bool, now: Instant) -> Option<Cached> {
+        let found = self.find(key, now, |entry| entry.usable_until(now).is_some() && (glue || !entry.glue));
+        if !glue {
+            match found {
+                Some(_) => self.stats.hits += 1,
+                None => self.stats.misses += 1,
+            }
+        }
+        let found = found?;
+        self.touch(&found);
+        let entry = self.entries.get_mut(&found)?;
+        let (until, stale) = entry.usable_until(now)?;
+        let remaining = until.duration_since(now);
+        if !glue {
+            entry.hits += 1;
+            if !stale && !entry.prefetching && entry.hits >= PREFETCH_HITS && remaining < secs(entry.ttl) / PREFETCH_FRACTION {
+                entry.prefetching = true;
+                self.prefetch.push(found);
+            }
+        }
+        // Fractions of a second left round up, so that fresh entries keep their TTL.
+        Some(with_ttl(&entry.value, remaining.as_millis().div_ceil(1000) as u32))
+    }
+
+    /// Returns an expired answer for a key, for when the servers cannot be reached. It is
+    /// then served for `STALE_TTL` seconds before the servers are asked again.
+    pub fn get_stale(&mut self, key: &Key, now: Instant) -> Option<Cached> {
+        let max_stale = self.max_stale;
+        let found = self.find(key, now, |entry| !entry.glue && entry.expires + max_stale > now)?;
+        self.touch(&found);
+        let entry = self.entries.get_mut(&found)?;
+        entry.recheck = Some(now + secs(STALE_TTL));
+        self.stats.stale_hits += 1;
+        Some(with_ttl(&entry.value, STALE_TTL))
+    }
+
+    /// Returns the keys of popular entries that should be refreshed before they expire.
+    pub fn take_prefetches(&mut self) -> Vec<Key> {
+        self.stats.prefetches += self.prefetch.len() as u64;
+        std::mem::take(&mut self.prefetch)
+    }
+
+    /// Caches records, grouped into RRsets with the RRSIG records covering them. RRSIG
+    /// records are only cached for `dnssec_ok` keys. Glue does not replace authoritative
+    /// data.
+    pub fn insert(&mut self, records: &[Record], glue: bool, dnssec_ok: bool, now: Instant) {
+        let mut rrsets: HashMap<Key, Vec<Record>> = HashMap::new();
+        for record in records {
+            if !dnssec_ok && is_rrsig(record) {
+                continue;
+            }
+            let key = Key { name: normalize(&record.name().to_string()), rtype: rrset_type(record), class: record.dns_class(), dnssec_ok };
+            rrsets.entry(key).or_default().push(record.clone());
+        }
+        for (key, rrset) in rrsets {
+            if glue && self.entries.get(&key).is_some_and(|entry| !entry.glue && entry.expires > now) {
+                continue;
+            }
+            let ttl = rrset.iter().map(Record::ttl).min().unwrap_or_default().min(MAX_TTL);
+            self.store(key, Cached::Records(rrset), ttl, glue, now);
+        }
+    }
+
+    /// Caches a negative answer, for as long as the SOA record among `authority` allows
+    /// (RFC 2308, section 5). Without a SOA record nothing is cached.
+    pub fn insert_negative(&mut self, key: Key, code: ResponseCode, authority: Vec<Record>, now: Instant) {
+        let Some(ttl) = authority.iter().find(|record| record.record_type() == RecordType::SOA).map(negative_ttl) else {
+            return;
+        };
+        let authority = authority.into_iter().filter(|record| key.dnssec_ok || record.record_type() == RecordType::SOA).collect();
+        self.store(key, Cached::Negative(code, authority), ttl.min(MAX_NEGATIVE_TTL), false, now);
+    }
+
+    /// Returns the key of the entry answering `key`: its own, or else the CNAME record
+    /// of the name. Entries past serving stale are dropped on the way.
+    fn find(&mut self, key: &Key, now: Instant, usable: impl Fn(&Entry) -> bool) -> Option<Key> {
+        let mut candidates = vec![key.clone()];
+        if key.rtype != RecordType::CNAME {
+            candidates.push(key.with_type(RecordType::CNAME));
+        }
+        for candidate in candidates {
+            let Some(entry) = self.entries.get(&candidate) else {
+                continue;
+            };
+            if entry.expires + self.max_stale <= now && entry.usable_until(now).is_none() {
+                self.remove(&candidate);
+            } else if usable(entry) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    /// Stores an entry, evicting the least recently used one first when the cache is
+    /// full.
+    fn store(&mut self, key: Key, value: Cached, ttl: u32, glue: bool, now: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        if self.entries.len() >= self.capacity
+            && let Some((_, oldest)) = self.lru.pop_first()
+        {
+            self.entries.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+        self.clock += 1;
+        self.lru.insert(self.clock, key.clone());
+        let entry = Entry { value, ttl, expires: now + secs(ttl), glue, hits: 0, used: self.clock, prefetching: false, recheck: None };
+        self.entries.insert(key, entry);
+    }
+
+    /// Marks an entry as the most recently used.
+    fn touch(&mut self, key: &Key) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+        self.lru.remove(&entry.used);
+        self.clock += 1;
+        entry.used = self.clock;
+        self.lru.insert(self.clock, key.clone());
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.used);
+        }
+    }
+}
+
+/// Returns how long a negative answer may be cached: the lower of the SOA record's TTL
+/// and its minimum field (RFC 2308, section 5).
+pub fn negative_ttl(soa: &Record) -> u32 {
+    match soa.data() {
+        Some(RData::SOA(data)) => soa.ttl().min(data.minimum()),
+        _ => soa.ttl(),
+    }
+}
+
+fn with_ttl(value: &Cached, ttl: u32) -> Cached {
+    let set_ttl = |records: &Vec<Record>| -> Vec<Record> {
+        records
+            .iter()
+            .map(|record| {
+                let mut record = record.clone();
+                record.set_ttl(ttl);
+                record
+            })
+            .collect()
+    };
+    match value {
+        Cached::Records(records) => Cached::Records(set_ttl(records)),
+        Cached::Negative(code, authority) => Cached::Negative(*code, set_ttl(authority)),
+    }
+}
+
+fn secs(ttl: u32) -> Duration {
+    Duration::from_secs(u64::from(ttl))
+}
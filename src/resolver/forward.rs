@@ -0,0 +1,139 @@
+//! Forwarding: names in configured domains are sent to upstream resolvers instead of
+//! being resolved from the root servers. Upstreams that stop answering are skipped for a
+//! while.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use trust_dns_proto::op::{Message, ResponseCode};
+use trust_dns_proto::rr::RecordType;
+
+use super::{exchange_message, exchange_tcp, query_message};
+use crate::db::validation::{is_in_zone, normalize};
+
+/// How many consecutive failures take an upstream out of rotation.
+const MAX_FAILURES: u32 = 3;
+
+/// How long an upstream stays out of rotation before it is tried again.
+const DOWN_TIME: Duration = Duration::from_secs(30);
+
+/// A domain whose names are forwarded, given as a `[[resolver.forward]]` table.
+#[derive(Deserialize, Clone)]
+pub struct ForwardZone {
+    /// The domain, e.g. `corp.internal.`. `.` forwards every name.
+    pub zone: String,
+    /// The resolvers to forward to, in order of preference. An empty list resolves the
+    /// domain from the root servers, even if a parent domain is forwarded.
+    #[serde(default)]
+    pub upstreams: Vec<Upstream>,
+}
+
+/// A resolver names are forwarded to.
+#[derive(Deserialize, Clone)]
+pub struct Upstream {
+    pub address: SocketAddr,
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// How long to wait for an answer, in milliseconds. Defaults to the resolver's
+    /// `timeout_ms`.
+    pub timeout_ms: Option<u64>,
+}
+
+/// How queries are sent to an upstream.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// UDP, retried over TCP when the response is truncated.
+    #[default]
+    Udp,
+    Tcp,
+}
+
+#[derive(Default)]
+struct Health {
+    failures: u32,
+    down_until: Option<Instant>,
+}
+
+/// Sends queries for forwarded domains to their upstreams, keeping track of which
+/// upstreams answer.
+pub struct Forwarder {
+    zones: Vec<ForwardZone>,
+    timeout: Duration,
+    health: Mutex<HashMap<SocketAddr, Health>>,
+}
+
+impl Forwarder {
+    /// Creates a forwarder for the given domains. `timeout` applies to upstreams without
+    /// a timeout of their own.
+    pub fn new(zones: &[ForwardZone], timeout: Duration) -> Self {
+        let zones = zones.iter().map(|zone| ForwardZone { zone: normalize(&zone.zone), upstreams: zone.upstreams.clone() }).collect();
+        Forwarder { zones, timeout, health: Mutex::new(HashMap::new()) }
+    }
+
+    /// Returns the upstreams for the closest forwarded domain holding `name`, or `None`
+    /// if the name is resolved from the root servers.
+    pub fn upstreams(&self, name: &str) -> Option<&[Upstream]> {
+        let zone = self.zones.iter().filter(|zone| is_in_zone(name, &zone.zone)).max_by_key(|zone| zone.zone.len())?;
+        if zone.upstreams.is_empty() {
+            None
+        } else {
+            Some(&zone.upstreams)
+        }
+    }
+
+    /// Returns whether an upstream is in rotation.
+    pub fn is_up(&self, address: SocketAddr) -> bool {
+        let health = self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        health.get(&address).and_then(|health| health.down_until).is_none_or(|until| until <= Instant::now())
+    }
+
+    /// Asks the upstreams in turn, those in rotation first, until one answers with
+    /// NOERROR or NXDOMAIN. With `dnssec_ok`, the DNSSEC OK bit is set on the query.
+    pub fn forward(&self, upstreams: &[Upstream], name: &str, rtype: RecordType, dnssec_ok: bool) -> Result<Message, String> {
+        let msg = query_message(name, rtype, true, dnssec_ok)?;
+        let (up, down): (Vec<&Upstream>, Vec<&Upstream>) = upstreams.iter().partition(|upstream| self.is_up(upstream.address));
+        let mut errors = Vec::new();
+        for upstream in up.into_iter().chain(down) {
+            let timeout = upstream.timeout_ms.map(Duration::from_millis).unwrap_or(self.timeout);
+            let result = match upstream.protocol {
+                Protocol::Udp => exchange_message(upstream.address, &msg, timeout),
+                Protocol::Tcp => exchange_tcp(upstream.address, &msg, timeout),
+            };
+            match result {
+                Ok(response) if matches!(response.response_code(), ResponseCode::NoError | ResponseCode::NXDomain) => {
+                    self.record(upstream.address, true);
+                    return Ok(response);
+                }
+                Ok(response) => errors.push(format!("{} answered {}", upstream.address, response.response_code())),
+                Err(e) => errors.push(format!("{}: {}", upstream.address, e)),
+            }
+            self.record(upstream.address, false);
+        }
+        Err(errors.join(", "))
+    }
+
+    /// Records whether an upstream answered, taking it out of rotation after too many
+    /// failures and putting it back once it answers again.
+    fn record(&self, address: SocketAddr, answered: bool) {
+        let mut health = self.health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let health = health.entry(address).or_default();
+        if answered {
+            if health.down_until.take().is_some() {
+                println!("Upstream {} is answering again", address);
+            }
+            health.failures = 0;
+            return;
+        }
+        health.failures += 1;
+        if health.failures >= MAX_FAILURES {
+            if health.down_until.is_none() {
+                eprintln!("Upstream {} failed {} times in a row, skipping it for {}s", address, health.failures, DOWN_TIME.as_secs());
+            }
+            health.down_until = Some(Instant::now() + DOWN_TIME);
+        }
+    }
+}
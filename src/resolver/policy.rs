@@ -0,0 +1,316 @@
+//! Response policies: answers the resolver rewrites or withholds, e.g. to block malware
+//! and ad domains. Policies come from Response Policy Zones (RPZ) and from blocklists in
+//! hosts-file or domain-list format.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use trust_dns_proto::rr::{RData, Record};
+
+use crate::acl::Cidr;
+use crate::db::validation::normalize;
+use crate::db::{RecordData, ResourceRecord, Zone};
+use crate::zone_parser::ZoneParser;
+
+/// What is done with a query that triggered a policy.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Answer that the name does not exist.
+    Nxdomain,
+    /// Answer that the name has no records of the type asked for.
+    Nodata,
+    /// Send no answer at all.
+    Drop,
+    /// Answer normally, ignoring the policies that follow.
+    Passthru,
+    /// Answer with these records instead, their owner names replaced by the name asked
+    /// for. Only RPZ zones give local data.
+    #[serde(skip)]
+    LocalData(Vec<ResourceRecord>),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Nxdomain => write!(f, "NXDOMAIN"),
+            Action::Nodata => write!(f, "NODATA"),
+            Action::Drop => write!(f, "drop"),
+            Action::Passthru => write!(f, "passthru"),
+            Action::LocalData(_) => write!(f, "local data"),
+        }
+    }
+}
+
+/// A policy, given as a `[[resolver.policy]]` table. Exactly one of `rpz`, `hosts` and
+/// `domains` names its source.
+#[derive(Deserialize, Clone)]
+pub struct PolicyConfig {
+    /// Names the policy in logs and in `policy_override` tables. For RPZ zones, also the
+    /// zone's origin unless the file sets `$ORIGIN`.
+    pub name: String,
+    /// A Response Policy Zone file.
+    pub rpz: Option<PathBuf>,
+    /// A hosts file: each line holds an address, which is ignored, and names that are
+    /// blocked.
+    pub hosts: Option<PathBuf>,
+    /// A file with one domain per line; the domain and every name below it are blocked.
+    pub domains: Option<PathBuf>,
+    /// The action for blocked names. Defaults to NXDOMAIN for blocklists; for RPZ zones
+    /// it replaces the zone's own actions.
+    pub action: Option<Action>,
+}
+
+/// Replaces the action of policies for some clients, given as a
+/// `[[resolver.policy_override]]` table.
+#[derive(Deserialize, Clone)]
+pub struct PolicyOverride {
+    pub clients: Vec<Cidr>,
+    /// The policies overridden, by name. All of them when empty.
+    #[serde(default)]
+    pub policies: Vec<String>,
+    pub action: Action,
+}
+
+/// A policy that applies to a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub policy: String,
+    /// The name or address prefix that triggered the policy.
+    pub trigger: String,
+    pub action: Action,
+}
+
+/// The triggers of one policy.
+struct Policy {
+    name: String,
+    /// Actions for names, by normalized name.
+    names: HashMap<String, Action>,
+    /// Actions for the names below a domain, by normalized domain.
+    below: HashMap<String, Action>,
+    /// Actions for answers holding an address within a prefix.
+    addresses: Vec<(Cidr, Action)>,
+    action: Option<Action>,
+}
+
+impl Policy {
+    /// Returns the action for a name: an exact trigger, or else the one for the closest
+    /// domain above it.
+    fn match_name(&self, name: &str) -> Option<(String, &Action)> {
+        if let Some(action) = self.names.get(name) {
+            return Some((name.to_string(), action));
+        }
+        let mut domain = name;
+        while let Some((_, parent)) = domain.split_once('.') {
+            if parent.is_empty() {
+                break;
+            }
+            if let Some(action) = self.below.get(parent) {
+                return Some((format!("*.{}", parent), action));
+            }
+            domain = parent;
+        }
+        None
+    }
+
+    /// Returns the action for the longest prefix holding one of the addresses.
+    fn match_addresses(&self, addresses: &[IpAddr]) -> Option<(String, &Action)> {
+        self.addresses
+            .iter()
+            .filter(|(cidr, _)| addresses.iter().any(|addr| cidr.contains(*addr)))
+            .max_by_key(|(cidr, _)| cidr.prefix_len())
+            .map(|(cidr, action)| (cidr.to_string(), action))
+    }
+}
+
+/// The configured policies, in the order they are checked, and the per-client overrides.
+#[derive(Default)]
+pub struct Policies {
+    policies: Vec<Policy>,
+    overrides: Vec<PolicyOverride>,
+}
+
+impl Policies {
+    /// Loads the policies' files. Policies that cannot be loaded are left out.
+    pub fn load(configs: &[PolicyConfig], overrides: &[PolicyOverride]) -> Self {
+        let mut policies = Vec::new();
+        for config in configs {
+            match Policies::load_policy(config) {
+                Ok(policy) => {
+                    println!(
+                        "Loaded policy {} with triggers for {} names, {} domains and {} address prefixes",
+                        policy.name,
+                        policy.names.len(),
+                        policy.below.len(),
+                        policy.addresses.len()
+                    );
+                    policies.push(policy);
+                }
+                Err(e) => eprintln!("Failed to load policy {}: {}", config.name, e),
+            }
+        }
+        Policies { policies, overrides: overrides.to_vec() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+
+    /// Returns the policy that applies to a name asked for by `client`: the first policy
+    /// with a trigger for the name.
+    pub fn check_name(&self, name: &str, client: IpAddr) -> Option<Hit> {
+        let name = normalize(name);
+        self.policies.iter().find_map(|policy| {
+            let (trigger, action) = policy.match_name(&name)?;
+            Some(self.hit(policy, trigger, action, client))
+        })
+    }
+
+    /// Returns the policy that applies to answer records sent to `client`: the first
+    /// policy with a trigger for one of their addresses.
+    pub fn check_answers(&self, answers: &[Record], client: IpAddr) -> Option<Hit> {
+        let addresses: Vec<IpAddr> = answers
+            .iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::A(addr)) => Some(IpAddr::V4(*addr)),
+                Some(RData::AAAA(addr)) => Some(IpAddr::V6(*addr)),
+                _ => None,
+            })
+            .collect();
+        if addresses.is_empty() {
+            return None;
+        }
+        self.policies.iter().find_map(|policy| {
+            let (trigger, action) = policy.match_addresses(&addresses)?;
+            Some(self.hit(policy, trigger, action, client))
+        })
+    }
+
+    /// Builds a hit, applying the policy's configured action and then the first override
+    /// for the client.
+    fn hit(&self, policy: &Policy, trigger: String, action: &Action, client: IpAddr) -> Hit {
+        let overridden = self.overrides.iter().find(|entry| {
+            entry.clients.iter().any(|cidr| cidr.contains(client)) && (entry.policies.is_empty() || entry.policies.contains(&policy.name))
+        });
+        let action = match overridden {
+            Some(entry) => entry.action.clone(),
+            None => policy.action.clone().unwrap_or_else(|| action.clone()),
+        };
+        Hit { policy: policy.name.clone(), trigger, action }
+    }
+
+    fn load_policy(config: &PolicyConfig) -> Result<Policy, String> {
+        let mut policy = Policy {
+            name: config.name.clone(),
+            names: HashMap::new(),
+            below: HashMap::new(),
+            addresses: Vec::new(),
+            action: None,
+        };
+        let read = |path: &PathBuf| fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e));
+        match (&config.rpz, &config.hosts, &config.domains) {
+            (Some(path), None, None) => {
+                let zone = ZoneParser::parse_zone_file(&read(path)?, normalize(&config.name), 3600)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                Policies::load_rpz(&mut policy, &zone);
+                policy.action = config.action.clone();
+            }
+            (None, Some(path), None) => {
+                let action = config.action.clone().unwrap_or(Action::Nxdomain);
+                for line in read(path)?.lines() {
+                    let line = line.split('#').next().unwrap_or_default();
+                    // The address is not used: every name listed is blocked.
+                    for name in line.split_whitespace().skip(1).filter(|name| name.contains('.')) {
+                        policy.names.insert(normalize(name), action.clone());
+                    }
+                }
+            }
+            (None, None, Some(path)) => {
+                let action = config.action.clone().unwrap_or(Action::Nxdomain);
+                for line in read(path)?.lines() {
+                    let Some(domain) = line.split('#').next().unwrap_or_default().split_whitespace().next() else {
+                        continue;
+                    };
+                    policy.names.insert(normalize(domain), action.clone());
+                    policy.below.insert(normalize(domain), action.clone());
+                }
+            }
+            _ => return Err("exactly one of rpz, hosts and domains must be set".to_string()),
+        }
+        Ok(policy)
+    }
+
+    /// Reads the triggers of a Response Policy Zone. Names in the zone are triggers for
+    /// the same name relative to the zone's origin; the records say what to do.
+    fn load_rpz(policy: &mut Policy, zone: &Zone) {
+        let suffix = format!(".{}", normalize(&zone.origin));
+        let mut triggers: HashMap<String, Vec<ResourceRecord>> = HashMap::new();
+        for record in &zone.records {
+            let owner = normalize(&record.name);
+            let Some(relative) = owner.strip_suffix(&suffix) else {
+                continue;
+            };
+            triggers.entry(relative.to_string()).or_default().push(record.clone());
+        }
+        for (trigger, records) in triggers {
+            let Some(action) = rpz_action(&records) else {
+                eprintln!("Policy {}: ignoring unsupported action for {}", policy.name, trigger);
+                continue;
+            };
+            if let Some(prefix) = trigger.strip_suffix(".rpz-ip") {
+                match rpz_prefix(prefix) {
+                    Some(cidr) => policy.addresses.push((cidr, action)),
+                    None => eprintln!("Policy {}: invalid address trigger {}", policy.name, trigger),
+                }
+            } else if trigger.contains(".rpz-") {
+                eprintln!("Policy {}: ignoring unsupported trigger {}", policy.name, trigger);
+            } else if let Some(domain) = trigger.strip_prefix("*.") {
+                policy.below.insert(format!("{}.", domain), action);
+            } else {
+                policy.names.insert(format!("{}.", trigger), action);
+            }
+        }
+    }
+}
+
+/// Returns the action RPZ records stand for: CNAME records to `.`, `*.`, `rpz-drop.` and
+/// `rpz-passthru.` are NXDOMAIN, NODATA, drop and passthru, and other records are local
+/// data. `None` for actions that are not supported, such as `rpz-tcp-only.`.
+fn rpz_action(records: &[ResourceRecord]) -> Option<Action> {
+    let special = records.iter().find_map(|record| match &record.data {
+        RecordData::CNAME(target) => match normalize(target).as_str() {
+            "." => Some(Some(Action::Nxdomain)),
+            "*." => Some(Some(Action::Nodata)),
+            "rpz-drop." => Some(Some(Action::Drop)),
+            "rpz-passthru." => Some(Some(Action::Passthru)),
+            target if target.starts_with("rpz-") => Some(None),
+            _ => None,
+        },
+        _ => None,
+    });
+    special.unwrap_or_else(|| Some(Action::LocalData(records.to_vec())))
+}
+
+/// Parses the address prefix of an `rpz-ip` trigger: the prefix length followed by the
+/// address labels in reverse, e.g. `24.0.2.0.192` for `192.0.2.0/24`. IPv6 addresses
+/// use hexadecimal groups, with `zz` standing for the longest run of zeros.
+fn rpz_prefix(prefix: &str) -> Option<Cidr> {
+    let mut labels: Vec<&str> = prefix.split('.').collect();
+    let length = labels.remove(0);
+    labels.reverse();
+    let address = if labels.len() == 4 && !labels.contains(&"zz") {
+        labels.join(".")
+    } else {
+        labels.iter().map(|label| if *label == "zz" { "" } else { label }).collect::<Vec<_>>().join(":")
+    };
+    let address = match address.as_str() {
+        _ if address.starts_with(':') => format!(":{}", address),
+        _ if address.ends_with(':') => format!("{}:", address),
+        _ => address,
+    };
+    format!("{}/{}", address, length).parse().ok()
+}
@@ -0,0 +1,224 @@
+//! Response Rate Limiting: identical UDP responses to the same client prefix are limited
+//! to a number per second, so that the server cannot be used to flood a spoofed victim
+//! with large answers. Some of the responses held back are sent truncated instead, so
+//! that real clients retry over TCP.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Instant;
+
+use serde::Deserialize;
+use trust_dns_proto::op::{Message, ResponseCode};
+use trust_dns_proto::rr::RecordType;
+
+use crate::acl::Cidr;
+use crate::db::validation::normalize;
+
+/// The `[rate_limit]` table of `config.toml`. Without it UDP responses are not limited.
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// How many responses of the same class for the same name a client prefix gets per
+    /// second. Up to a second's worth can be sent at once.
+    pub responses_per_second: u32,
+    /// Every `slip`th response held back is sent truncated instead of being dropped. 0
+    /// drops them all and 1 truncates them all.
+    pub slip: u32,
+    /// The length of the IPv4 prefixes clients are grouped by.
+    pub ipv4_prefix_len: u8,
+    /// The length of the IPv6 prefixes clients are grouped by.
+    pub ipv6_prefix_len: u8,
+    /// Client prefixes whose responses are never limited.
+    pub exempt: Vec<Cidr>,
+    /// How many buckets are kept track of. When a response needs another one, the bucket
+    /// refilled longest ago is forgotten.
+    pub max_buckets: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            responses_per_second: 10,
+            slip: 2,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+            exempt: Vec::new(),
+            max_buckets: 100_000,
+        }
+    }
+}
+
+/// What kind of response is sent. Each class has buckets of its own, so that a flood of
+/// one kind does not hold back the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResponseClass {
+    /// Records answering the question.
+    Answer,
+    /// A delegation to a child zone.
+    Referral,
+    /// The name has no records of the type asked for.
+    Nodata,
+    /// The name does not exist.
+    Nxdomain,
+    /// Any other response code.
+    Error,
+}
+
+impl ResponseClass {
+    /// Classifies a response and returns the name it is counted against: the name asked
+    /// for by answers, the delegated name by referrals, and the zone by negative answers,
+    /// so that random names in a zone share one bucket. Errors share one bucket per
+    /// client prefix.
+    pub fn of(response: &Message) -> (ResponseClass, String) {
+        let qname = response.queries().first().map(|query| normalize(&query.name().to_string())).unwrap_or_default();
+        let owner = |rtype: RecordType| {
+            response.name_servers().iter().find(|record| record.record_type() == rtype).map(|record| normalize(&record.name().to_string()))
+        };
+        match response.response_code() {
+            ResponseCode::NoError if !response.answers().is_empty() => (ResponseClass::Answer, qname),
+            ResponseCode::NoError => match (owner(RecordType::SOA), owner(RecordType::NS)) {
+                (None, Some(delegation)) => (ResponseClass::Referral, delegation),
+                (zone, _) => (ResponseClass::Nodata, zone.unwrap_or(qname)),
+            },
+            ResponseCode::NXDomain => (ResponseClass::Nxdomain, owner(RecordType::SOA).unwrap_or(qname)),
+            _ => (ResponseClass::Error, String::new()),
+        }
+    }
+}
+
+impl fmt::Display for ResponseClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseClass::Answer => write!(f, "answer"),
+            ResponseClass::Referral => write!(f, "referral"),
+            ResponseClass::Nodata => write!(f, "NODATA"),
+            ResponseClass::Nxdomain => write!(f, "NXDOMAIN"),
+            ResponseClass::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// What to do with a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Send,
+    /// Send it truncated, without records.
+    Slip,
+    Drop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BucketKey {
+    prefix: Cidr,
+    class: ResponseClass,
+    name: String,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    /// The bucket's place in `RateLimiter::by_age`.
+    age: (Instant, u64),
+    /// Responses held back since the bucket ran empty.
+    dropped: u64,
+    slipped: u64,
+}
+
+/// Token buckets per client prefix, response class and name.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<BucketKey, Bucket>,
+    /// The buckets ordered by when they were last refilled, with a sequence number to
+    /// tell apart those refilled at the same instant.
+    by_age: BTreeMap<(Instant, u64), BucketKey>,
+    sequence: u64,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter { config, buckets: HashMap::new(), by_age: BTreeMap::new(), sequence: 0 }
+    }
+
+    /// Decides whether a response to `client` is sent at `now`. Rate limiting is logged
+    /// when it starts for a bucket, and again with what was held back when it ends.
+    pub fn check(&mut self, client: IpAddr, response: &Message, now: Instant) -> Decision {
+        let (class, name) = ResponseClass::of(response);
+        self.check_class(client, class, name, now)
+    }
+
+    /// Decides whether a response of `class` for `name` is sent, for replies that are
+    /// not a parsed `Message`.
+    pub fn check_class(&mut self, client: IpAddr, class: ResponseClass, name: String, now: Instant) -> Decision {
+        if self.config.responses_per_second == 0 || self.config.exempt.iter().any(|cidr| cidr.contains(client)) {
+            return Decision::Send;
+        }
+        let prefix_len = match client {
+            IpAddr::V6(v6) if v6.to_ipv4_mapped().is_none() => self.config.ipv6_prefix_len,
+            _ => self.config.ipv4_prefix_len,
+        };
+        let key = BucketKey { prefix: Cidr::containing(client, prefix_len), class, name };
+
+        let rate = f64::from(self.config.responses_per_second);
+        if !self.buckets.contains_key(&key) && self.buckets.len() >= self.config.max_buckets.max(1) {
+            self.evict_oldest();
+        }
+        self.sequence += 1;
+        let age = (now, self.sequence);
+        let bucket = self.buckets.entry(key.clone()).or_insert(Bucket { tokens: rate, refilled: now, age, dropped: 0, slipped: 0 });
+        self.by_age.remove(&bucket.age);
+        self.by_age.insert(age, key.clone());
+        bucket.tokens = (bucket.tokens + now.saturating_duration_since(bucket.refilled).as_secs_f64() * rate).min(rate);
+        bucket.refilled = now;
+        bucket.age = age;
+        // Limiting ends once the responses slowed down enough for the bucket to fill up.
+        if bucket.tokens >= rate && bucket.dropped + bucket.slipped > 0 {
+            println!(
+                "Stopped rate limiting {} responses for {} to {}: {} dropped, {} truncated",
+                key.class,
+                display_name(&key.name),
+                key.prefix,
+                bucket.dropped,
+                bucket.slipped
+            );
+            bucket.dropped = 0;
+            bucket.slipped = 0;
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Decision::Send;
+        }
+        if bucket.dropped + bucket.slipped == 0 {
+            println!("Rate limiting {} responses for {} to {}", key.class, display_name(&key.name), key.prefix);
+        }
+        let slip = self.config.slip;
+        if slip > 0 && (bucket.dropped + bucket.slipped).is_multiple_of(u64::from(slip)) {
+            bucket.slipped += 1;
+            Decision::Slip
+        } else {
+            bucket.dropped += 1;
+            Decision::Drop
+        }
+    }
+
+    /// Returns how many buckets are kept track of.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Forgets the bucket refilled longest ago.
+    fn evict_oldest(&mut self) {
+        if let Some((_, key)) = self.by_age.pop_first() {
+            self.buckets.remove(&key);
+        }
+    }
+}
+
+fn display_name(name: &str) -> &str {
+    if name.is_empty() { "any name" } else { name }
+}
@@ -1,23 +1,73 @@
-use std::net::UdpSocket;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, UdpSocket};
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
 type F = dyn FnMut(&[u8], SocketAddr, &UdpSocket) + Send + 'static;
+type T = dyn Fn(&[u8], SocketAddr, &TcpConnection) + Send + Sync + 'static;
 use serde::Deserialize;
 
-#[derive(Deserialize)]
+use crate::acl::Access;
+
+/// How long an idle TCP connection is kept open.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many TCP connections are served at once. Further ones are closed right away.
+pub const MAX_TCP_CONNECTIONS: usize = 100;
+
+/// How long writing a reply may stall before the connection is closed.
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many replies may wait to be written to a connection. A client that sends more
+/// requests without reading the replies is disconnected.
+pub const MAX_PENDING_TCP_REPLIES: usize = 16;
+
+/// A TCP connection being served. Replies are queued for the connection's own writer
+/// thread, so that a client that does not read them cannot hold up the caller.
+#[derive(Clone)]
+pub struct TcpConnection {
+    stream: Arc<TcpStream>,
+    replies: SyncSender<Vec<Vec<u8>>>,
+}
+
+impl TcpConnection {
+    /// Queues the messages answering one request, to be written in order with a length
+    /// prefix each. Closes the connection if too many replies are already waiting.
+    pub fn send(&self, messages: Vec<Vec<u8>>) -> Result<(), String> {
+        match self.replies.try_send(messages) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                let _ = self.stream.shutdown(Shutdown::Both);
+                Err(format!("{} replies are waiting to be read, closing the connection", MAX_PENDING_TCP_REPLIES))
+            }
+            Err(TrySendError::Disconnected(_)) => Err("the connection is closed".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
 pub struct ServerConfig {
     pub port: u16,
     pub zones_directory: std::path::PathBuf,
+    /// ACLs for requests received over UDP.
+    #[serde(default)]
+    pub udp: Access,
+    /// ACLs for requests received over TCP.
+    #[serde(default)]
+    pub tcp: Access,
 }
 
 pub struct Server {
     socket: UdpSocket,
+    listener: TcpListener,
     thread_handle: Option<JoinHandle<()>>,
+    tcp_thread_handle: Option<JoinHandle<()>>,
     callback: Option<Box<F>>,
+    tcp_callback: Option<Arc<T>>,
     running: Arc<AtomicBool>,
 }
 
@@ -41,8 +91,11 @@ impl Server {
     pub fn new(socket_addr: SocketAddr) -> Self {
         Server {
             socket: UdpSocket::bind(socket_addr).unwrap(),
+            listener: TcpListener::bind(socket_addr).unwrap(),
             thread_handle: None,
+            tcp_thread_handle: None,
             callback: None,
+            tcp_callback: None,
             running: Arc::new(AtomicBool::new(false)),
         }
     }
@@ -80,6 +133,37 @@ impl Server {
         self.callback = Some(f);
     }
 
+    /// Registers a callback function to be executed for each DNS message received over TCP.
+    ///
+    /// Messages are framed with the two byte length prefix of RFC 1035, section 4.2.2; the
+    /// callback receives a single message without the prefix, the peer address and the
+    /// connection, on which replies are queued with `TcpConnection::send`. Each
+    /// connection is served by its own thread, so the callback may run concurrently. At
+    /// most `MAX_TCP_CONNECTIONS` connections are open at once.
+    /// Without a TCP callback, `run()` does not accept TCP connections.
+    pub fn register_tcp_callback(&mut self, f: Box<T>) {
+        self.tcp_callback = Some(Arc::from(f));
+    }
+
+    /// Writes one length-prefixed DNS message to a TCP connection.
+    pub fn write_tcp_message(mut stream: &TcpStream, message: &[u8]) -> std::io::Result<()> {
+        let length = u16::try_from(message.len())
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "DNS message too long for TCP"))?;
+        let mut framed = Vec::with_capacity(message.len() + 2);
+        framed.extend_from_slice(&length.to_be_bytes());
+        framed.extend_from_slice(message);
+        stream.write_all(&framed)
+    }
+
+    /// Reads one length-prefixed DNS message from a TCP connection.
+    pub fn read_tcp_message(mut stream: &TcpStream) -> std::io::Result<Vec<u8>> {
+        let mut length = [0u8; 2];
+        stream.read_exact(&mut length)?;
+        let mut message = vec![0u8; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut message)?;
+        Ok(message)
+    }
+
     /// Starts the server, listening for incoming UDP packets and executing the registered callback.
     ///
     /// This method spawns a new thread to handle packet reception. It will panic if no callback
@@ -151,6 +235,74 @@ impl Server {
         });
 
         self.thread_handle = Some(thread_handle);
+
+        if let Some(tcp_callback) = self.tcp_callback.take() {
+            self.listener.set_nonblocking(true).unwrap();
+            let listener = self.listener.try_clone().unwrap();
+            let running = self.running.clone();
+            self.tcp_thread_handle = Some(thread::spawn(move || {
+                Server::accept_tcp(listener, running, tcp_callback);
+            }));
+        }
+    }
+
+    fn accept_tcp(listener: TcpListener, running: Arc<AtomicBool>, callback: Arc<T>) {
+        let connections = Arc::new(AtomicUsize::new(0));
+        while running.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, src)) => {
+                    if connections.fetch_add(1, Ordering::SeqCst) >= MAX_TCP_CONNECTIONS {
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                        eprintln!("Closing TCP connection from {}: {} connections are open", src, MAX_TCP_CONNECTIONS);
+                        continue;
+                    }
+                    let running = running.clone();
+                    let callback = callback.clone();
+                    let connections = connections.clone();
+                    thread::spawn(move || {
+                        Server::serve_tcp_connection(stream, src, running, callback);
+                        connections.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => {
+                    eprintln!("Error accepting TCP connection: {}", e);
+                }
+            }
+        }
+    }
+
+    fn serve_tcp_connection(stream: TcpStream, src: SocketAddr, running: Arc<AtomicBool>, callback: Arc<T>) {
+        if stream.set_nonblocking(false).is_err()
+            || stream.set_read_timeout(Some(TCP_IDLE_TIMEOUT)).is_err()
+            || stream.set_write_timeout(Some(TCP_WRITE_TIMEOUT)).is_err()
+        {
+            return;
+        }
+        let stream = Arc::new(stream);
+        let (replies, pending) = mpsc::sync_channel::<Vec<Vec<u8>>>(MAX_PENDING_TCP_REPLIES);
+        let writer_stream = stream.clone();
+        let writer = thread::spawn(move || {
+            for message in pending.iter().flatten() {
+                if let Err(e) = Server::write_tcp_message(&writer_stream, &message) {
+                    eprintln!("Failed to send TCP response to {}: {}", src, e);
+                    let _ = writer_stream.shutdown(Shutdown::Both);
+                    break;
+                }
+            }
+        });
+        let connection = TcpConnection { stream: stream.clone(), replies };
+        while running.load(Ordering::SeqCst) {
+            match Server::read_tcp_message(&stream) {
+                Ok(message) => callback(&message, src, &connection),
+                Err(_) => break,
+            }
+        }
+        // The writer finishes once the replies still being prepared have been written.
+        drop(connection);
+        let _ = writer.join();
     }
 
     /// Stops the server, joining the background thread.
@@ -177,6 +329,9 @@ impl Server {
         if let Some(handle) = self.thread_handle.take() {
             handle.join().unwrap();
         }
+        if let Some(handle) = self.tcp_thread_handle.take() {
+            handle.join().unwrap();
+        }
     }
 
     pub fn socket(&self) -> &UdpSocket {
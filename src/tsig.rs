@@ -0,0 +1,456 @@
+//! Transaction signatures (TSIG, RFC 8945).
+//!
+//! TSIG works on the wire form of messages: the MAC covers the exact bytes that were
+//! sent, so signing appends a TSIG record to an encoded message and verification strips
+//! it from the received bytes again.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer};
+use sha2::{Sha256, Sha512};
+
+/// The TSIG record type.
+pub const TSIG_TYPE: u16 = 250;
+
+const CLASS_ANY: u16 = 255;
+
+/// Allowed difference in seconds between the signing time and the receiver's clock.
+pub const FUDGE: u16 = 300;
+
+/// Size of the DNS message header.
+const HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TsigAlgorithm {
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+    #[serde(rename = "hmac-sha512")]
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    /// Returns the algorithm's name as it appears in TSIG records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TsigAlgorithm::HmacSha256 => "hmac-sha256.",
+            TsigAlgorithm::HmacSha512 => "hmac-sha512.",
+        }
+    }
+
+    fn mac(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => hmac_sha256(secret, data).finalize().into_bytes().to_vec(),
+            TsigAlgorithm::HmacSha512 => hmac_sha512(secret, data).finalize().into_bytes().to_vec(),
+        }
+    }
+
+    /// Checks a MAC in constant time.
+    fn verify_mac(&self, secret: &[u8], data: &[u8], mac: &[u8]) -> bool {
+        match self {
+            TsigAlgorithm::HmacSha256 => hmac_sha256(secret, data).verify_slice(mac).is_ok(),
+            TsigAlgorithm::HmacSha512 => hmac_sha512(secret, data).verify_slice(mac).is_ok(),
+        }
+    }
+}
+
+fn hmac_sha256(secret: &[u8], data: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac
+}
+
+fn hmac_sha512(secret: &[u8], data: &[u8]) -> Hmac<Sha512> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac
+}
+
+/// A secret shared with another server or a client, given as a `[[key]]` table in
+/// `config.toml`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TsigKey {
+    /// The key's name, e.g. `transfer-key.`.
+    pub name: String,
+    pub algorithm: TsigAlgorithm,
+    /// The secret, base64 encoded in the configuration.
+    #[serde(deserialize_with = "decode_secret")]
+    pub secret: Vec<u8>,
+}
+
+fn decode_secret<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let secret = String::deserialize(deserializer)?;
+    data_encoding::BASE64.decode(secret.as_bytes()).map_err(serde::de::Error::custom)
+}
+
+/// Why a signed message was rejected. Apart from `Malformed`, each variant is a TSIG
+/// error code sent back in the TSIG record of the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsigError {
+    /// The TSIG record could not be parsed, or is missing where one is required.
+    Malformed,
+    BadSig,
+    BadKey,
+    BadTime,
+}
+
+impl TsigError {
+    /// Returns the extended RCODE carried in the TSIG record's error field.
+    pub fn code(&self) -> u16 {
+        match self {
+            TsigError::Malformed => 0,
+            TsigError::BadSig => 16,
+            TsigError::BadKey => 17,
+            TsigError::BadTime => 18,
+        }
+    }
+
+    fn from_code(code: u16) -> TsigError {
+        match code {
+            17 => TsigError::BadKey,
+            18 => TsigError::BadTime,
+            _ => TsigError::BadSig,
+        }
+    }
+}
+
+impl fmt::Display for TsigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TsigError::Malformed => write!(f, "malformed TSIG record"),
+            TsigError::BadSig => write!(f, "TSIG signature does not match"),
+            TsigError::BadKey => write!(f, "unknown TSIG key"),
+            TsigError::BadTime => write!(f, "TSIG signing time outside the allowed window"),
+        }
+    }
+}
+
+/// A TSIG record found at the end of a message.
+#[derive(Debug, Clone)]
+struct TsigRecord {
+    /// Offset of the record in the message.
+    start: usize,
+    key_name: Vec<u8>,
+    algorithm: Vec<u8>,
+    time: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+    other: Vec<u8>,
+}
+
+/// The key and MAC of a request whose signature was verified.
+#[derive(Debug, Clone)]
+pub struct Verified {
+    pub key: TsigKey,
+    pub mac: Vec<u8>,
+}
+
+/// A signed request that failed verification.
+#[derive(Debug)]
+pub struct TsigFailure {
+    pub error: TsigError,
+    record: Option<Box<TsigRecord>>,
+    key: Option<TsigKey>,
+}
+
+impl TsigFailure {
+    /// Adds the TSIG record telling the client why its request was rejected to an
+    /// encoded response (RFC 8945, section 5.3.2). Only BADTIME responses are signed.
+    pub fn sign_response(&self, response: &[u8], now: u64) -> Vec<u8> {
+        let Some(record) = &self.record else {
+            return response.to_vec();
+        };
+        match (&self.key, self.error) {
+            (Some(key), TsigError::BadTime) => {
+                let other = now.to_be_bytes()[2..].to_vec();
+                let mut signer = TsigSigner::new(key.clone(), Some(record.mac.clone()));
+                signer.sign_with(response, record.time, self.error.code(), &other)
+            }
+            _ => append_tsig(response, &record.key_name, &record.algorithm, record.time, &[], self.error.code(), &[]),
+        }
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch, as used in TSIG records.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+/// Verifies the TSIG record of a request against the known keys.
+///
+/// Returns `None` for unsigned requests, and the key and MAC to sign the responses with
+/// for correctly signed ones.
+pub fn verify_request(bytes: &[u8], keys: &[TsigKey], now: u64) -> Result<Option<Verified>, TsigFailure> {
+    let record = match find_tsig(bytes) {
+        Ok(Some(record)) => record,
+        Ok(None) => return Ok(None),
+        Err(error) => return Err(TsigFailure { error, record: None, key: None }),
+    };
+    let key = keys
+        .iter()
+        .find(|key| name_to_wire(&key.name) == record.key_name && name_to_wire(key.algorithm.name()) == record.algorithm);
+    let Some(key) = key else {
+        return Err(TsigFailure { error: TsigError::BadKey, record: Some(Box::new(record)), key: None });
+    };
+
+    let message = unsigned_message(bytes, &record);
+    let data = digest_input(None, &message, &record, false);
+    if !key.algorithm.verify_mac(&key.secret, &data, &record.mac) {
+        return Err(TsigFailure { error: TsigError::BadSig, record: Some(Box::new(record)), key: None });
+    }
+    if now.abs_diff(record.time) > u64::from(record.fudge) {
+        return Err(TsigFailure { error: TsigError::BadTime, record: Some(Box::new(record)), key: Some(key.clone()) });
+    }
+    Ok(Some(Verified { key: key.clone(), mac: record.mac }))
+}
+
+/// Signs a request, or the sequence of responses to a signed request.
+///
+/// The first message covers all TSIG variables; every following message covers the
+/// previous MAC and only the signing time (RFC 8945, section 5.3.1).
+pub struct TsigSigner {
+    key: TsigKey,
+    prior_mac: Option<Vec<u8>>,
+    timers_only: bool,
+}
+
+impl TsigSigner {
+    /// Creates a signer for a request, or for the responses to the request with
+    /// `request_mac`.
+    pub fn new(key: TsigKey, request_mac: Option<Vec<u8>>) -> Self {
+        TsigSigner { key, prior_mac: request_mac, timers_only: false }
+    }
+
+    /// Appends a TSIG record to an encoded message.
+    pub fn sign(&mut self, message: &[u8], now: u64) -> Vec<u8> {
+        self.sign_with(message, now, 0, &[])
+    }
+
+    /// Returns the MAC of the last signed message.
+    pub fn mac(&self) -> Option<&[u8]> {
+        self.prior_mac.as_deref()
+    }
+
+    fn sign_with(&mut self, message: &[u8], time: u64, error: u16, other: &[u8]) -> Vec<u8> {
+        let key_name = name_to_wire(&self.key.name);
+        let algorithm = name_to_wire(self.key.algorithm.name());
+        let record = TsigRecord {
+            start: message.len(),
+            key_name,
+            algorithm,
+            time,
+            fudge: FUDGE,
+            mac: Vec::new(),
+            original_id: 0,
+            error,
+            other: other.to_vec(),
+        };
+        let data = digest_input(self.prior_mac.as_deref(), message, &record, self.timers_only);
+        let mac = self.key.algorithm.mac(&self.key.secret, &data);
+        let signed = append_tsig(message, &record.key_name, &record.algorithm, time, &mac, error, other);
+        self.prior_mac = Some(mac);
+        self.timers_only = true;
+        signed
+    }
+}
+
+/// Verifies the responses to a signed request, in the order they arrive.
+pub struct TsigVerifier {
+    key: TsigKey,
+    prior_mac: Vec<u8>,
+    timers_only: bool,
+}
+
+impl TsigVerifier {
+    /// Creates a verifier for the responses to the request that was signed with
+    /// `request_mac`.
+    pub fn new(key: TsigKey, request_mac: Vec<u8>) -> Self {
+        TsigVerifier { key, prior_mac: request_mac, timers_only: false }
+    }
+
+    /// Checks the TSIG record of the next response, which must be present.
+    pub fn verify(&mut self, bytes: &[u8], now: u64) -> Result<(), TsigError> {
+        let record = find_tsig(bytes)?.ok_or(TsigError::Malformed)?;
+        if record.key_name != name_to_wire(&self.key.name) || record.algorithm != name_to_wire(self.key.algorithm.name()) {
+            return Err(TsigError::BadKey);
+        }
+        if record.error != 0 {
+            return Err(TsigError::from_code(record.error));
+        }
+        let message = unsigned_message(bytes, &record);
+        let data = digest_input(Some(&self.prior_mac), &message, &record, self.timers_only);
+        if !self.key.algorithm.verify_mac(&self.key.secret, &data, &record.mac) {
+            return Err(TsigError::BadSig);
+        }
+        if now.abs_diff(record.time) > u64::from(record.fudge) {
+            return Err(TsigError::BadTime);
+        }
+        self.prior_mac = record.mac;
+        self.timers_only = true;
+        Ok(())
+    }
+}
+
+/// Builds the data covered by a MAC (RFC 8945, section 4.3.3): the prior MAC, if any,
+/// the message without its TSIG record, and the TSIG variables.
+fn digest_input(prior_mac: Option<&[u8]>, message: &[u8], record: &TsigRecord, timers_only: bool) -> Vec<u8> {
+    let mut data = Vec::with_capacity(message.len() + 128);
+    if let Some(mac) = prior_mac {
+        data.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+        data.extend_from_slice(mac);
+    }
+    data.extend_from_slice(message);
+    if !timers_only {
+        data.extend_from_slice(&record.key_name);
+        data.extend_from_slice(&CLASS_ANY.to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(&record.algorithm);
+    }
+    data.extend_from_slice(&record.time.to_be_bytes()[2..]);
+    data.extend_from_slice(&record.fudge.to_be_bytes());
+    if !timers_only {
+        data.extend_from_slice(&record.error.to_be_bytes());
+        data.extend_from_slice(&(record.other.len() as u16).to_be_bytes());
+        data.extend_from_slice(&record.other);
+    }
+    data
+}
+
+/// Appends a TSIG record to an encoded message and counts it in the header.
+fn append_tsig(message: &[u8], key_name: &[u8], algorithm: &[u8], time: u64, mac: &[u8], error: u16, other: &[u8]) -> Vec<u8> {
+    let mut rdata = algorithm.to_vec();
+    rdata.extend_from_slice(&time.to_be_bytes()[2..]);
+    rdata.extend_from_slice(&FUDGE.to_be_bytes());
+    rdata.extend_from_slice(&(mac.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(mac);
+    rdata.extend_from_slice(&message[..2]);
+    rdata.extend_from_slice(&error.to_be_bytes());
+    rdata.extend_from_slice(&(other.len() as u16).to_be_bytes());
+    rdata.extend_from_slice(other);
+
+    let mut signed = message.to_vec();
+    let additional = u16::from_be_bytes([signed[10], signed[11]]) + 1;
+    signed[10..12].copy_from_slice(&additional.to_be_bytes());
+    signed.extend_from_slice(key_name);
+    signed.extend_from_slice(&TSIG_TYPE.to_be_bytes());
+    signed.extend_from_slice(&CLASS_ANY.to_be_bytes());
+    signed.extend_from_slice(&0u32.to_be_bytes());
+    signed.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    signed.extend_from_slice(&rdata);
+    signed
+}
+
+/// Restores the message as it was before signing: without the TSIG record, with the
+/// additional count decremented and with the original ID.
+fn unsigned_message(bytes: &[u8], record: &TsigRecord) -> Vec<u8> {
+    let mut message = bytes[..record.start].to_vec();
+    message[..2].copy_from_slice(&record.original_id.to_be_bytes());
+    let additional = u16::from_be_bytes([message[10], message[11]]) - 1;
+    message[10..12].copy_from_slice(&additional.to_be_bytes());
+    message
+}
+
+/// Finds the TSIG record, which must be the last record of the additional section.
+fn find_tsig(bytes: &[u8]) -> Result<Option<TsigRecord>, TsigError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(TsigError::Malformed);
+    }
+    let count = |offset: usize| usize::from(u16::from_be_bytes([bytes[offset], bytes[offset + 1]]));
+    let (questions, records, additional) = (count(4), count(6) + count(8) + count(10), count(10));
+    if additional == 0 {
+        return Ok(None);
+    }
+
+    let mut pos = HEADER_SIZE;
+    for _ in 0..questions {
+        pos = read_name(bytes, pos)?.1 + 4;
+    }
+    for _ in 0..records - 1 {
+        pos = read_name(bytes, pos)?.1;
+        let rdlength = usize::from(read_u16(bytes, pos + 8)?);
+        pos += 10 + rdlength;
+    }
+
+    let start = pos;
+    let (key_name, mut pos) = read_name(bytes, pos)?;
+    if read_u16(bytes, pos)? != TSIG_TYPE {
+        return Ok(None);
+    }
+    let rdlength = usize::from(read_u16(bytes, pos + 8)?);
+    pos += 10;
+    let end = pos + rdlength;
+    if end != bytes.len() {
+        return Err(TsigError::Malformed);
+    }
+
+    let (algorithm, mut pos) = read_name(bytes, pos)?;
+    let time = (u64::from(read_u16(bytes, pos)?) << 32) | u64::from(read_u32(bytes, pos + 2)?);
+    let fudge = read_u16(bytes, pos + 6)?;
+    let mac_size = usize::from(read_u16(bytes, pos + 8)?);
+    pos += 10;
+    let mac = bytes.get(pos..pos + mac_size).ok_or(TsigError::Malformed)?.to_vec();
+    pos += mac_size;
+    let original_id = read_u16(bytes, pos)?;
+    let error = read_u16(bytes, pos + 2)?;
+    let other_len = usize::from(read_u16(bytes, pos + 4)?);
+    pos += 6;
+    let other = bytes.get(pos..pos + other_len).ok_or(TsigError::Malformed)?.to_vec();
+    if pos + other_len != end {
+        return Err(TsigError::Malformed);
+    }
+
+    Ok(Some(TsigRecord { start, key_name, algorithm, time, fudge, mac, original_id, error, other }))
+}
+
+/// Reads a possibly compressed name, returning it in lowercase uncompressed wire form
+/// together with the position after it.
+fn read_name(bytes: &[u8], mut pos: usize) -> Result<(Vec<u8>, usize), TsigError> {
+    let mut name = Vec::new();
+    let mut end = None;
+    for _ in 0..128 {
+        let len = *bytes.get(pos).ok_or(TsigError::Malformed)?;
+        match len {
+            0 => {
+                name.push(0);
+                return Ok((name, end.unwrap_or(pos + 1)));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let target = usize::from(read_u16(bytes, pos)? & 0x3fff);
+                end.get_or_insert(pos + 2);
+                pos = target;
+            }
+            len if len < 64 => {
+                let label = bytes.get(pos + 1..pos + 1 + usize::from(len)).ok_or(TsigError::Malformed)?;
+                name.push(len);
+                name.extend(label.iter().map(u8::to_ascii_lowercase));
+                pos += 1 + usize::from(len);
+            }
+            _ => return Err(TsigError::Malformed),
+        }
+    }
+    Err(TsigError::Malformed)
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> Result<u16, TsigError> {
+    let field = bytes.get(pos..pos + 2).ok_or(TsigError::Malformed)?;
+    Ok(u16::from_be_bytes([field[0], field[1]]))
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Result<u32, TsigError> {
+    let field = bytes.get(pos..pos + 4).ok_or(TsigError::Malformed)?;
+    Ok(u32::from_be_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+/// Encodes a name in lowercase uncompressed wire form.
+fn name_to_wire(name: &str) -> Vec<u8> {
+    let mut wire = Vec::new();
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        wire.push(label.len() as u8);
+        wire.extend(label.bytes().map(|byte| byte.to_ascii_lowercase()));
+    }
+    wire.push(0);
+    wire
+}
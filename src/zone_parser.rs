@@ -1,127 +1,790 @@
+pub mod error;
+
+use std::cell::RefCell;
+use std::fs;
 use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use super::db::record::{RecordClass, RecordData, RecordType, ResourceRecord, Zone};
+use super::dnssec::presentation;
+
+pub use self::error::{ZoneParseError, ZoneParseErrorKind};
+
+/// Maximum nesting of `$INCLUDE` directives before parsing is aborted.
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Maximum number of records a single `$GENERATE` directive may produce.
+pub const MAX_GENERATE_RECORDS: u32 = 65536;
+
+/// Maximum width of a `$GENERATE` modifier, the longest a name can be.
+pub const MAX_GENERATE_WIDTH: usize = 255;
 
 pub struct ZoneParser;
 
+/// An error within one line, pointing at the index of the offending token.
+struct LineError {
+    index: usize,
+    kind: ZoneParseErrorKind,
+    message: String,
+}
+
+impl LineError {
+    fn new(index: usize, kind: ZoneParseErrorKind, message: String) -> Self {
+        LineError { index, kind, message }
+    }
+}
+
+/// Where the lines being parsed come from and how errors are handled.
+struct Source<'a> {
+    file: Option<&'a Path>,
+    zones_directory: Option<&'a Path>,
+    collect_errors: bool,
+    /// Collects the paths of `$INCLUDE`d files, if set.
+    included: Option<&'a RefCell<Vec<PathBuf>>>,
+}
+
+impl Source<'_> {
+    fn track(&self, path: &Path) {
+        if let Some(included) = self.included {
+            included.borrow_mut().push(path.to_path_buf());
+        }
+    }
+}
+
 impl ZoneParser {
-    pub fn parse_zone_file(content: &str, default_origin: String, default_ttl: u32) -> Result<Zone, String> {
+    /// Parses a zone file that does not use `$INCLUDE`, stopping at the first error.
+    pub fn parse_zone_file(content: &str, default_origin: String, default_ttl: u32) -> Result<Zone, ZoneParseError> {
+        let source = Source { file: None, zones_directory: None, collect_errors: false, included: None };
+        ZoneParser::parse(content, default_origin, default_ttl, &source).map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Parses a zone file, resolving `$INCLUDE` paths relative to `zones_directory` and
+    /// stopping at the first error.
+    pub fn parse_zone_file_in_dir(content: &str, default_origin: String, default_ttl: u32, zones_directory: &Path) -> Result<Zone, ZoneParseError> {
+        let source = Source { file: None, zones_directory: Some(zones_directory), collect_errors: false, included: None };
+        ZoneParser::parse(content, default_origin, default_ttl, &source).map_err(|mut errors| errors.remove(0))
+    }
+
+    /// Reads and parses the zone file at `path`, collecting every error in the file and its
+    /// includes instead of stopping at the first one.
+    ///
+    /// Files ending in `.json`, `.yaml` or `.yml` are read as structured zones, which carry
+    /// their own origin and TTL; anything else is read as a master file.
+    pub fn load_zone_file(path: &Path, default_origin: String, default_ttl: u32, zones_directory: &Path) -> Result<Zone, Vec<ZoneParseError>> {
+        ZoneParser::load(path, default_origin, default_ttl, zones_directory, None)
+    }
+
+    /// Like `load_zone_file`, also returning the files the zone `$INCLUDE`s, whether or
+    /// not loading succeeded, so they can be watched for changes.
+    pub fn load_zone_file_with_includes(
+        path: &Path,
+        default_origin: String,
+        default_ttl: u32,
+        zones_directory: &Path,
+    ) -> (Result<Zone, Vec<ZoneParseError>>, Vec<PathBuf>) {
+        let included = RefCell::new(Vec::new());
+        let result = ZoneParser::load(path, default_origin, default_ttl, zones_directory, Some(&included));
+        (result, included.into_inner())
+    }
+
+    fn load(
+        path: &Path,
+        default_origin: String,
+        default_ttl: u32,
+        zones_directory: &Path,
+        included: Option<&RefCell<Vec<PathBuf>>>,
+    ) -> Result<Zone, Vec<ZoneParseError>> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            vec![ZoneParseError {
+                file: Some(path.to_path_buf()),
+                line: 0,
+                column: 0,
+                token: String::new(),
+                kind: ZoneParseErrorKind::Io,
+                message: format!("Failed to read zone file: {}", e),
+            }]
+        })?;
+        let structured = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Some(ZoneParser::parse_json_zone(&content)),
+            Some("yaml") | Some("yml") => Some(ZoneParser::parse_yaml_zone(&content)),
+            _ => None,
+        };
+        if let Some(result) = structured {
+            return result.map_err(|mut e| {
+                e.file = Some(path.to_path_buf());
+                vec![e]
+            });
+        }
+        let source = Source { file: Some(path), zones_directory: Some(zones_directory), collect_errors: true, included };
+        ZoneParser::parse(&content, default_origin, default_ttl, &source)
+    }
+
+    /// Parses a zone given as JSON. Owner names may be relative to the zone's origin.
+    pub fn parse_json_zone(content: &str) -> Result<Zone, ZoneParseError> {
+        let zone: Zone = serde_json::from_str(content)
+            .map_err(|e| ZoneParser::structure_error(e.line(), e.column(), e.to_string()))?;
+        Ok(ZoneParser::absolute_owners(zone))
+    }
+
+    /// Parses a zone given as YAML. Owner names may be relative to the zone's origin.
+    pub fn parse_yaml_zone(content: &str) -> Result<Zone, ZoneParseError> {
+        let zone: Zone = serde_yaml::from_str(content).map_err(|e| {
+            let (line, column) = e.location().map_or((0, 0), |location| (location.line(), location.column()));
+            ZoneParser::structure_error(line, column, e.to_string())
+        })?;
+        Ok(ZoneParser::absolute_owners(zone))
+    }
+
+    fn structure_error(line: usize, column: usize, message: String) -> ZoneParseError {
+        ZoneParseError {
+            file: None,
+            line,
+            column,
+            token: String::new(),
+            kind: ZoneParseErrorKind::InvalidStructure,
+            message,
+        }
+    }
+
+    fn absolute_owners(mut zone: Zone) -> Zone {
+        for record in &mut zone.records {
+            record.name = ZoneParser::absolute_name(&record.name, &zone.origin);
+        }
+        zone
+    }
+
+    fn parse(content: &str, default_origin: String, default_ttl: u32, source: &Source) -> Result<Zone, Vec<ZoneParseError>> {
         let mut origin = default_origin;
         let mut ttl = default_ttl;
         let mut records: Vec<ResourceRecord> = Vec::new();
+        let mut errors: Vec<ZoneParseError> = Vec::new();
 
-        for line in content.lines() {
-            let line = line.trim();
-            if line.is_empty() || line.starts_with(';') {
+        ZoneParser::parse_lines(content, &mut origin, &mut ttl, &mut records, &mut errors, source, 0);
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Zone {
+            origin,
+            ttl,
+            records,
+        })
+    }
+
+    fn parse_lines(
+        content: &str,
+        origin: &mut String,
+        ttl: &mut u32,
+        records: &mut Vec<ResourceRecord>,
+        errors: &mut Vec<ZoneParseError>,
+        source: &Source,
+        depth: usize,
+    ) {
+        let mut previous_name: Option<String> = None;
+
+        let lines: Vec<&str> = content.lines().collect();
+        let mut next_line = 0;
+        while next_line < lines.len() {
+            let line_index = next_line;
+            let raw_line = lines[line_index];
+            let (mut parts, mut open) = ZoneParser::tokenize(raw_line);
+            // An entry continues over the following lines while parentheses are open.
+            next_line += 1;
+            while open > 0 && next_line < lines.len() {
+                let (more, change) = ZoneParser::tokenize(lines[next_line]);
+                parts.extend(more);
+                open += change;
+                next_line += 1;
+            }
+            if parts.is_empty() && open == 0 {
                 continue;
             }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
+            let result = if open != 0 {
+                let message = if open > 0 { "Unclosed parenthesis" } else { "Unmatched closing parenthesis" };
+                Err(LineError::new(parts.len(), ZoneParseErrorKind::UnbalancedParentheses, message.to_string()))
+            } else if parts[0].starts_with('$') {
+                ZoneParser::parse_directive(&parts, origin, ttl, records, errors, source, depth)
+            } else {
+                // A line starting with whitespace omits the owner and reuses the previous one.
+                let owner_omitted = raw_line.starts_with(char::is_whitespace);
+                if owner_omitted && previous_name.is_none() {
+                    Err(LineError::new(0, ZoneParseErrorKind::MissingOwner, "Record without owner name and no previous owner".to_string()))
+                } else {
+                    let inherited_name = if owner_omitted { previous_name.as_deref() } else { None };
+                    ZoneParser::parse_record(&parts, origin, *ttl, inherited_name).map(|record| {
+                        previous_name = Some(record.name.clone());
+                        records.push(record);
+                    })
+                }
+            };
 
-            if parts[0].starts_with('$') {
-                // Handle directives
-                match parts[0] {
-                    "$ORIGIN" => {
-                        if parts.len() < 2 {
-                            return Err(format!("Invalid $ORIGIN directive: {}", line));
-                        }
-                        origin = parts[1].to_string();
-                    }
-                    "$TTL" => {
-                        if parts.len() < 2 {
-                            return Err(format!("Invalid $TTL directive: {}", line));
-                        }
-                        ttl = parts[1].parse::<u32>().map_err(|e| format!("Invalid $TTL value: {}: {}", parts[1], e))?;
+            if let Err(e) = result {
+                let token = parts.get(e.index).copied().unwrap_or("");
+                // Tokens borrow from the lines of the entry, so their offset gives the line
+                // and column.
+                let token_line = (line_index..next_line)
+                    .find(|&i| {
+                        let start = lines[i].as_ptr() as usize;
+                        (start..=start + lines[i].len()).contains(&(token.as_ptr() as usize))
+                    })
+                    .filter(|_| !token.is_empty())
+                    .unwrap_or(next_line - 1);
+                let line = lines[token_line];
+                let column = if token.is_empty() {
+                    line.trim_end().chars().count() + 1
+                } else {
+                    let offset = token.as_ptr() as usize - line.as_ptr() as usize;
+                    line[..offset].chars().count() + 1
+                };
+                errors.push(ZoneParseError {
+                    file: source.file.map(Path::to_path_buf),
+                    line: token_line + 1,
+                    column,
+                    token: token.to_string(),
+                    kind: e.kind,
+                    message: e.message,
+                });
+            }
+            if !source.collect_errors && !errors.is_empty() {
+                return;
+            }
+        }
+    }
+
+    fn parse_directive(
+        parts: &[&str],
+        origin: &mut String,
+        ttl: &mut u32,
+        records: &mut Vec<ResourceRecord>,
+        errors: &mut Vec<ZoneParseError>,
+        source: &Source,
+        depth: usize,
+    ) -> Result<(), LineError> {
+        let invalid = |message: &str| Err(LineError::new(0, ZoneParseErrorKind::InvalidDirective, message.to_string()));
+        // Directive names are case-insensitive, like type and class mnemonics.
+        match parts[0].to_ascii_uppercase().as_str() {
+            "$ORIGIN" => {
+                if parts.len() < 2 {
+                    return invalid("Invalid $ORIGIN directive");
+                }
+                *origin = parts[1].to_string();
+            }
+            "$TTL" => {
+                if parts.len() < 2 {
+                    return invalid("Invalid $TTL directive");
+                }
+                *ttl = ZoneParser::parse_duration(parts[1])
+                    .map_err(|e| LineError::new(1, ZoneParseErrorKind::InvalidTtl, format!("Invalid $TTL value: {}", e)))?;
+            }
+            "$INCLUDE" => {
+                if parts.len() < 2 || parts.len() > 3 {
+                    return invalid("Invalid $INCLUDE directive");
+                }
+                let Some(zones_directory) = source.zones_directory else {
+                    return invalid("$INCLUDE requires a zones directory");
+                };
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(LineError::new(
+                        1,
+                        ZoneParseErrorKind::IncludeDepthExceeded,
+                        format!("$INCLUDE nested deeper than {}", MAX_INCLUDE_DEPTH),
+                    ));
+                }
+                // Absolute paths, `..` and symbolic links must not lead out of the zones
+                // directory.
+                let read_error = |path: &Path, e: std::io::Error| {
+                    LineError::new(1, ZoneParseErrorKind::Io, format!("Failed to read included file {:?}: {}", path, e))
+                };
+                let requested = zones_directory.join(parts[1]);
+                let path = match fs::canonicalize(&requested) {
+                    Ok(path) => path,
+                    Err(e) => {
+                        // A missing file is tracked too, so the zone loads once it exists.
+                        source.track(&requested);
+                        return Err(read_error(&requested, e));
                     }
-                    _ => eprintln!("Unknown directive: {}", parts[0]),
+                };
+                let directory = fs::canonicalize(zones_directory).map_err(|e| read_error(zones_directory, e))?;
+                if !path.starts_with(&directory) {
+                    return Err(LineError::new(
+                        1,
+                        ZoneParseErrorKind::InvalidDirective,
+                        format!("$INCLUDE file {} is outside the zones directory", parts[1]),
+                    ));
+                }
+                source.track(&path);
+                let included = fs::read_to_string(&path).map_err(|e| read_error(&path, e))?;
+                // Origin and TTL changes inside the included file do not leak back out.
+                let mut included_origin = match parts.get(2) {
+                    Some(name) => ZoneParser::absolute_name(name, origin),
+                    None => origin.clone(),
+                };
+                let mut included_ttl = *ttl;
+                let included_source = Source { file: Some(&path), ..*source };
+                ZoneParser::parse_lines(&included, &mut included_origin, &mut included_ttl, records, errors, &included_source, depth + 1);
+            }
+            "$GENERATE" => {
+                if parts.len() < 4 {
+                    return invalid("Invalid $GENERATE directive");
+                }
+                let (start, stop, step) = ZoneParser::parse_generate_range(parts[1])
+                    .map_err(|e| LineError::new(1, ZoneParseErrorKind::InvalidDirective, e))?;
+                let mut value = start;
+                while value <= stop {
+                    let generated: Vec<String> = parts[2..]
+                        .iter()
+                        .enumerate()
+                        .map(|(k, part)| {
+                            ZoneParser::substitute_generate(part, value)
+                                .map_err(|e| LineError::new(k + 2, ZoneParseErrorKind::InvalidDirective, e))
+                        })
+                        .collect::<Result<_, LineError>>()?;
+                    let generated: Vec<&str> = generated.iter().map(String::as_str).collect();
+                    // Generated tokens line up with the template tokens after the range.
+                    let record = ZoneParser::parse_record(&generated, origin, *ttl, None).map_err(|mut e| {
+                        e.index += 2;
+                        e
+                    })?;
+                    records.push(record);
+                    value = match value.checked_add(step) {
+                        Some(next) => next,
+                        None => break,
+                    };
                 }
+            }
+            _ => return invalid(&format!("Unknown directive {}", parts[0])),
+        }
+        Ok(())
+    }
+
+    /// Parses a single resource record line.
+    ///
+    /// When `inherited_name` is given the line carries no owner field and the record belongs
+    /// to that name. TTL and class may appear in either order.
+    fn parse_record(parts: &[&str], origin: &str, ttl: u32, inherited_name: Option<&str>) -> Result<ResourceRecord, LineError> {
+        let mut current_ttl = ttl;
+        let mut current_class = RecordClass::IN;
+        let mut i = 0;
+
+        let current_name = match inherited_name {
+            Some(name) => name.to_string(),
+            None => {
+                i += 1;
+                parts[0].to_string()
+            }
+        };
+
+        let mut ttl_seen = false;
+        let mut class_seen = false;
+        while i < parts.len() {
+            if !ttl_seen && parts[i].starts_with(|c: char| c.is_ascii_digit()) {
+                current_ttl = ZoneParser::parse_duration(parts[i])
+                    .map_err(|e| LineError::new(i, ZoneParseErrorKind::InvalidTtl, format!("Invalid TTL: {}", e)))?;
+                ttl_seen = true;
+            } else if !class_seen && RecordClass::from_str(parts[i]).is_ok() {
+                current_class = RecordClass::from_str(parts[i]).unwrap();
+                class_seen = true;
             } else {
-                // Handle resource records
-                let mut current_name = "@".to_string();
-                let mut current_ttl = ttl;
-                let mut current_class = RecordClass::IN;
-                let mut i = 0;
+                break;
+            }
+            i += 1;
+        }
+
+        let record_type = match parts.get(i).map(|part| RecordType::from_str(part)) {
+            Some(Ok(r_type)) => {
+                i += 1;
+                r_type
+            }
+            _ => return Err(LineError::new(i, ZoneParseErrorKind::InvalidRecordType, "Missing or invalid record type".to_string())),
+        };
 
-                if !parts[i].starts_with('@') && !parts[i].ends_with('.') {
-                    current_name = parts[i].to_string();
-                    i += 1;
+        let data_error = |index: usize, message: String| LineError::new(index, ZoneParseErrorKind::InvalidRecordData, message);
+        let record_data_str = parts[i..].join(" ");
+        let record_data = match record_type {
+            // RFC 3597, section 5: the generic form is accepted for known types too.
+            ref known if record_data_str.starts_with("\\#") && !matches!(known, RecordType::Unknown(_)) => {
+                let data = ZoneParser::parse_generic_rdata(&parts[i..]).map_err(|e| data_error(i, e))?;
+                ZoneParser::typed_rdata(known, &data).map_err(|e| data_error(i, format!("Invalid {} record data: {}", known, e)))?
+            },
+            RecordType::A => RecordData::A(Ipv4Addr::from_str(&record_data_str).map_err(|e| data_error(i, format!("Invalid A record data: {}", e)))?),
+            RecordType::AAAA => RecordData::AAAA(Ipv6Addr::from_str(&record_data_str).map_err(|e| data_error(i, format!("Invalid AAAA record data: {}", e)))?),
+            RecordType::CNAME => RecordData::CNAME(record_data_str),
+            RecordType::NS => RecordData::NS(record_data_str),
+            RecordType::PTR => RecordData::PTR(record_data_str),
+            RecordType::MX => {
+                if parts.len() < i + 2 {
+                    return Err(data_error(i, "Invalid MX record data".to_string()));
+                }
+                let preference = parts[i].parse::<u16>().map_err(|e| data_error(i, format!("Invalid MX preference: {}", e)))?;
+                let exchange = parts[i + 1..].join(" ");
+                RecordData::MX { preference, exchange }
+            },
+            RecordType::SOA => {
+                if parts.len() < i + 7 {
+                    return Err(data_error(i, "Invalid SOA record data".to_string()));
+                }
+                let timer = |offset: usize, field: &str| {
+                    ZoneParser::parse_duration(parts[i + offset])
+                        .map_err(|e| LineError::new(i + offset, ZoneParseErrorKind::InvalidTtl, format!("Invalid SOA {}: {}", field, e)))
+                };
+                RecordData::SOA {
+                    mname: parts[i].to_string(),
+                    rname: parts[i + 1].to_string(),
+                    serial: parts[i + 2].parse::<u32>().map_err(|e| data_error(i + 2, format!("Invalid SOA serial: {}", e)))?,
+                    refresh: timer(3, "refresh")?,
+                    retry: timer(4, "retry")?,
+                    expire: timer(5, "expire")?,
+                    minimum: timer(6, "minimum")?,
                 }
+            },
+            RecordType::SRV => {
+                if parts.len() < i + 4 {
+                    return Err(data_error(i, "Invalid SRV record data".to_string()));
+                }
+                let field = |offset: usize, name: &str| {
+                    parts[i + offset].parse::<u16>().map_err(|e| data_error(i + offset, format!("Invalid SRV {}: {}", name, e)))
+                };
+                RecordData::SRV {
+                    priority: field(0, "priority")?,
+                    weight: field(1, "weight")?,
+                    port: field(2, "port")?,
+                    target: parts[i + 3..].join(" "),
+                }
+            },
+            RecordType::TXT => {
+                if parts.len() <= i {
+                    return Err(data_error(i, "TXT record needs at least one string".to_string()));
+                }
+                let strings = parts[i..]
+                    .iter()
+                    .enumerate()
+                    .map(|(k, part)| ZoneParser::parse_character_string(part).map_err(|e| data_error(i + k, e)))
+                    .collect::<Result<Vec<String>, LineError>>()?;
+                RecordData::TXT(strings)
+            },
+            RecordType::Unknown(rtype) if presentation::type_mnemonic(rtype).is_some() && !record_data_str.starts_with("\\#") => RecordData::Unknown {
+                rtype,
+                data: presentation::parse_rdata(rtype, &parts[i..], origin).map_err(|e| data_error(i, format!("Invalid {} record data: {}", record_type, e)))?,
+            },
+            RecordType::Unknown(rtype) => RecordData::Unknown {
+                rtype,
+                data: ZoneParser::parse_generic_rdata(&parts[i..]).map_err(|e| data_error(i, e))?,
+            },
+        };
+
+        let fqdn = ZoneParser::absolute_name(&current_name, origin);
 
-                if i < parts.len() && parts[i].parse::<u32>().is_ok() {
-                    current_ttl = parts[i].parse::<u32>().unwrap();
-                    i += 1;
+        Ok(ResourceRecord {
+            name: fqdn,
+            ttl: current_ttl,
+            class: current_class,
+            data: record_data,
+        })
+    }
+
+    /// Splits a line into whitespace separated tokens, stopping at a `;` comment. Also
+    /// returns how many more parentheses the line opens than it closes.
+    ///
+    /// Double quoted strings are kept together as one token including their quotes, and
+    /// may contain whitespace, `;`, parentheses and backslash escapes.
+    fn tokenize(line: &str) -> (Vec<&str>, i32) {
+        let mut tokens = Vec::new();
+        let mut open = 0;
+        let mut chars = line.char_indices().peekable();
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                open += match c {
+                    '(' => 1,
+                    ')' => -1,
+                    _ => 0,
+                };
+                chars.next();
+                continue;
+            }
+            if c == ';' {
+                break;
+            }
+            let mut end = line.len();
+            let mut in_quotes = false;
+            while let Some(&(index, c)) = chars.peek() {
+                if !in_quotes && (c.is_whitespace() || c == ';' || c == '(' || c == ')') {
+                    end = index;
+                    break;
                 }
+                chars.next();
+                match c {
+                    '"' => in_quotes = !in_quotes,
+                    '\\' => {
+                        chars.next();
+                    }
+                    _ => {}
+                }
+            }
+            tokens.push(&line[start..end]);
+        }
+        (tokens, open)
+    }
 
-                if i < parts.len() && RecordClass::from_str(parts[i]).is_ok() {
-                    current_class = RecordClass::from_str(parts[i]).unwrap();
-                    i += 1;
+    /// Parses a TXT character-string, removing surrounding quotes and resolving `\X` and
+    /// `\DDD` escapes, the latter standing for one byte each. The result must be UTF-8 and
+    /// at most 255 bytes long.
+    fn parse_character_string(token: &str) -> Result<String, String> {
+        let inner = match token.strip_prefix('"') {
+            Some(rest) => rest.strip_suffix('"').ok_or_else(|| format!("Unterminated quoted string: {}", token))?,
+            None => token,
+        };
+        let mut result = Vec::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+            match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    let digits: String = std::iter::once(d).chain(chars.by_ref().take(2)).collect();
+                    let byte = digits
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|_| digits.len() == 3 && digits.chars().all(|c| c.is_ascii_digit()))
+                        .ok_or_else(|| format!("Invalid escape \\{} in {}", digits, token))?;
+                    result.push(byte);
                 }
+                Some(escaped) => result.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes()),
+                None => return Err(format!("Dangling backslash in {}", token)),
+            }
+        }
+        if result.len() > 255 {
+            return Err(format!("String of {} bytes is longer than 255: {}", result.len(), token));
+        }
+        String::from_utf8(result).map_err(|_| format!("String is not valid UTF-8: {}", token))
+    }
 
-                let record_type_str = if i < parts.len() && RecordType::from_str(parts[i]).is_ok() {
-                    let r_type = parts[i];
-                    i += 1;
-                    r_type
-                } else {
-                    return Err(format!("Missing or invalid record type: {}", line));
-                };
+    /// Parses a BIND style duration such as `300`, `300s`, `1h` or `1d12h` into seconds.
+    ///
+    /// Units are `s`, `m`, `h`, `d` and `w` in either case; a plain number means seconds.
+    pub fn parse_duration(value: &str) -> Result<u32, String> {
+        if value.is_empty() {
+            return Err("empty duration".to_string());
+        }
+        if let Ok(seconds) = value.parse::<u32>() {
+            return Ok(seconds);
+        }
 
-                let record_data_str = parts[i..].join(" ");
-                let record_data = match record_type_str {
-                    "A" => RecordData::A(Ipv4Addr::from_str(&record_data_str).map_err(|e| format!("Invalid A record data: {}: {}", record_data_str, e))?),
-                    "AAAA" => RecordData::AAAA(Ipv6Addr::from_str(&record_data_str).map_err(|e| format!("Invalid AAAA record data: {}: {}", record_data_str, e))?),
-                    "CNAME" => RecordData::CNAME(record_data_str),
-                    "NS" => RecordData::NS(record_data_str),
-                    "MX" => {
-                        let mx_parts: Vec<&str> = record_data_str.split_whitespace().collect();
-                        if mx_parts.len() < 2 {
-                            return Err(format!("Invalid MX record data: {}", record_data_str));
-                        }
-                        let preference = mx_parts[0].parse::<u16>().map_err(|e| format!("Invalid MX preference: {}: {}", mx_parts[0], e))?;
-                        let exchange = mx_parts[1..].join(" ");
-                        RecordData::MX { preference, exchange }
-                    },
-                    "SOA" => {
-                        let soa_parts: Vec<&str> = record_data_str.split_whitespace().collect();
-                        if soa_parts.len() < 7 {
-                            return Err(format!("Invalid SOA record data: {}", record_data_str));
-                        }
-                        RecordData::SOA {
-                            mname: soa_parts[0].to_string(),
-                            rname: soa_parts[1].to_string(),
-                            serial: soa_parts[2].parse::<u32>().map_err(|e| format!("Invalid SOA serial: {}: {}", soa_parts[2], e))?,
-                            refresh: soa_parts[3].parse::<u32>().map_err(|e| format!("Invalid SOA refresh: {}: {}", soa_parts[3], e))?,
-                            retry: soa_parts[4].parse::<u32>().map_err(|e| format!("Invalid SOA retry: {}: {}", soa_parts[4], e))?,
-                            expire: soa_parts[5].parse::<u32>().map_err(|e| format!("Invalid SOA expire: {}: {}", soa_parts[5], e))?,
-                            minimum: soa_parts[6].parse::<u32>().map_err(|e| format!("Invalid SOA minimum: {}: {}", soa_parts[6], e))?,
-                        }
-                    },
-                    "TXT" => RecordData::TXT(record_data_str),
-                    _ => return Err(format!("Unsupported record type: {}", record_type_str)),
-                };
+        let mut total: u32 = 0;
+        let mut number = String::new();
+        for c in value.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            let multiplier = match c.to_ascii_lowercase() {
+                's' => 1,
+                'm' => 60,
+                'h' => 3600,
+                'd' => 86400,
+                'w' => 604800,
+                _ => return Err(format!("{}: unknown unit '{}'", value, c)),
+            };
+            if number.is_empty() {
+                return Err(format!("{}: unit '{}' without a number", value, c));
+            }
+            let amount = number.parse::<u32>().map_err(|e| format!("{}: {}", value, e))?;
+            total = amount
+                .checked_mul(multiplier)
+                .and_then(|seconds| total.checked_add(seconds))
+                .ok_or_else(|| format!("{}: duration too large", value))?;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return Err(format!("{}: trailing number without a unit", value));
+        }
+        Ok(total)
+    }
 
-                let fqdn = if current_name == "@" {
-                    origin.clone()
-                } else if current_name.ends_with('.') {
-                    current_name
-                } else {
-                    format!("{}.{}", current_name, origin)
-                };
+    /// Turns a possibly relative domain name into an absolute one below `origin`.
+    pub(crate) fn absolute_name(name: &str, origin: &str) -> String {
+        if name == "@" {
+            origin.to_string()
+        } else if name.ends_with('.') {
+            name.to_string()
+        } else {
+            format!("{}.{}", name, origin)
+        }
+    }
 
-                records.push(ResourceRecord {
-                    name: fqdn,
-                    ttl: current_ttl,
-                    class: current_class,
-                    data: record_data,
-                });
+    /// Parses a `$GENERATE` range of the form `start-stop[/step]`.
+    fn parse_generate_range(range: &str) -> Result<(u32, u32, u32), String> {
+        let (bounds, step) = match range.split_once('/') {
+            Some((bounds, step)) => (bounds, step.parse::<u32>().map_err(|e| format!("Invalid $GENERATE step: {}: {}", step, e))?),
+            None => (range, 1),
+        };
+        let (start, stop) = bounds
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid $GENERATE range: {}", range))?;
+        let start = start.parse::<u32>().map_err(|e| format!("Invalid $GENERATE start: {}: {}", start, e))?;
+        let stop = stop.parse::<u32>().map_err(|e| format!("Invalid $GENERATE stop: {}: {}", stop, e))?;
+        if step == 0 || start > stop {
+            return Err(format!("Invalid $GENERATE range: {}", range));
+        }
+        if (stop - start) / step >= MAX_GENERATE_RECORDS {
+            return Err(format!("$GENERATE range produces more than {} records: {}", MAX_GENERATE_RECORDS, range));
+        }
+        Ok((start, stop, step))
+    }
+
+    /// Replaces `$` and `${offset[,width[,base]]}` in a `$GENERATE` template with `value`.
+    ///
+    /// `\$` produces a literal dollar sign. Supported bases are `d`, `o`, `x`, `X` and
+    /// the nibble formats `n` and `N` used for `ip6.arpa` names.
+    fn substitute_generate(template: &str, value: u32) -> Result<String, String> {
+        let mut result = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'$') => {
+                    chars.next();
+                    result.push('$');
+                }
+                '$' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let mut modifier = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => modifier.push(c),
+                            None => return Err(format!("Unterminated $GENERATE modifier: {}", template)),
+                        }
+                    }
+                    let fields: Vec<&str> = modifier.split(',').collect();
+                    if fields.len() > 3 {
+                        return Err(format!("Invalid $GENERATE modifier: {}", template));
+                    }
+                    let offset = fields[0].parse::<i64>().map_err(|e| format!("Invalid $GENERATE offset: {}: {}", fields[0], e))?;
+                    let width = match fields.get(1) {
+                        Some(width) => width.parse::<usize>().map_err(|e| format!("Invalid $GENERATE width: {}: {}", width, e))?,
+                        None => 0,
+                    };
+                    if width > MAX_GENERATE_WIDTH {
+                        return Err(format!("$GENERATE width is larger than {}: {}", MAX_GENERATE_WIDTH, template));
+                    }
+                    let value = i64::from(value)
+                        .checked_add(offset)
+                        .and_then(|value| u64::try_from(value).ok())
+                        .ok_or_else(|| format!("$GENERATE offset takes value out of range: {}", template))?;
+                    let formatted = match fields.get(2).copied().unwrap_or("d") {
+                        "d" => format!("{:0width$}", value, width = width),
+                        "o" => format!("{:0width$o}", value, width = width),
+                        "x" => format!("{:0width$x}", value, width = width),
+                        "X" => format!("{:0width$X}", value, width = width),
+                        "n" => ZoneParser::nibbles(&format!("{:0width$x}", value, width = width.max(1))),
+                        "N" => ZoneParser::nibbles(&format!("{:0width$X}", value, width = width.max(1))),
+                        base => return Err(format!("Invalid $GENERATE base: {}", base)),
+                    };
+                    result.push_str(&formatted);
+                }
+                '$' => result.push_str(&value.to_string()),
+                c => result.push(c),
             }
         }
+        Ok(result)
+    }
 
-        Ok(Zone {
-            origin,
-            ttl,
-            records,
-        })
+    /// Reverses hex digits into dot separated nibbles, e.g. `1a` becomes `a.1`.
+    fn nibbles(hex: &str) -> String {
+        hex.chars().rev().map(String::from).collect::<Vec<_>>().join(".")
+    }
+
+    /// Parses RDATA in the RFC 3597 generic form `\# <length> <hex>...`.
+    ///
+    /// The hex digits may be split into several whitespace separated words.
+    fn parse_generic_rdata(parts: &[&str]) -> Result<Vec<u8>, String> {
+        if parts.len() < 2 || parts[0] != "\\#" {
+            return Err(format!("Invalid generic RDATA: {}", parts.join(" ")));
+        }
+        let length = parts[1].parse::<usize>().map_err(|e| format!("Invalid generic RDATA length: {}: {}", parts[1], e))?;
+        let hex: String = parts[2..].concat();
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("Invalid hex in generic RDATA: {}", hex));
+        }
+        if !hex.len().is_multiple_of(2) {
+            return Err(format!("Odd number of hex digits in generic RDATA: {}", hex));
+        }
+        let data: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|j| u8::from_str_radix(&hex[j..j + 2], 16).unwrap())
+            .collect();
+        if data.len() != length {
+            return Err(format!("Generic RDATA length mismatch: declared {}, got {}", length, data.len()));
+        }
+        Ok(data)
+    }
+
+    /// Decodes the wire form of RDATA given in the generic form for a type the crate
+    /// models. Names must not be compressed.
+    fn typed_rdata(record_type: &RecordType, data: &[u8]) -> Result<RecordData, String> {
+        let truncated = || "truncated RDATA".to_string();
+        let name_at = |pos: usize| presentation::wire_to_name(data, pos).ok_or_else(|| "invalid name".to_string());
+        let u16_at = |pos: usize| data.get(pos..pos + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]])).ok_or_else(truncated);
+        let u32_at = |pos: usize| {
+            data.get(pos..pos + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok_or_else(truncated)
+        };
+        let (record_data, end) = match record_type {
+            RecordType::A => (RecordData::A(<[u8; 4]>::try_from(data).map_err(|_| "expected 4 bytes".to_string())?.into()), data.len()),
+            RecordType::AAAA => (RecordData::AAAA(<[u8; 16]>::try_from(data).map_err(|_| "expected 16 bytes".to_string())?.into()), data.len()),
+            RecordType::CNAME | RecordType::NS | RecordType::PTR => {
+                let (name, end) = name_at(0)?;
+                let record_data = match record_type {
+                    RecordType::CNAME => RecordData::CNAME(name),
+                    RecordType::NS => RecordData::NS(name),
+                    _ => RecordData::PTR(name),
+                };
+                (record_data, end)
+            }
+            RecordType::MX => {
+                let (exchange, end) = name_at(2)?;
+                (RecordData::MX { preference: u16_at(0)?, exchange }, end)
+            }
+            RecordType::SOA => {
+                let (mname, pos) = name_at(0)?;
+                let (rname, pos) = name_at(pos)?;
+                let soa = RecordData::SOA {
+                    mname,
+                    rname,
+                    serial: u32_at(pos)?,
+                    refresh: u32_at(pos + 4)?,
+                    retry: u32_at(pos + 8)?,
+                    expire: u32_at(pos + 12)?,
+                    minimum: u32_at(pos + 16)?,
+                };
+                (soa, pos + 20)
+            }
+            RecordType::SRV => {
+                let (target, end) = name_at(6)?;
+                (RecordData::SRV { priority: u16_at(0)?, weight: u16_at(2)?, port: u16_at(4)?, target }, end)
+            }
+            RecordType::TXT => {
+                let mut strings = Vec::new();
+                let mut pos = 0;
+                while pos < data.len() {
+                    let length = data[pos] as usize;
+                    let text = data.get(pos + 1..pos + 1 + length).ok_or_else(truncated)?;
+                    strings.push(String::from_utf8(text.to_vec()).map_err(|_| "character-string is not UTF-8".to_string())?);
+                    pos += 1 + length;
+                }
+                if strings.is_empty() {
+                    return Err("TXT record needs at least one string".to_string());
+                }
+                (RecordData::TXT(strings), pos)
+            }
+            RecordType::Unknown(rtype) => (RecordData::Unknown { rtype: *rtype, data: data.to_vec() }, data.len()),
+        };
+        if end != data.len() {
+            return Err(format!("{} bytes after the end of the RDATA", data.len() - end));
+        }
+        Ok(record_data)
     }
 }
 
@@ -139,7 +802,13 @@ impl FromStr for RecordType {
             "SOA" => Ok(RecordType::SOA),
             "SRV" => Ok(RecordType::SRV),
             "TXT" => Ok(RecordType::TXT),
-            _ => Err(format!("Unknown RecordType: {}", s)),
+            upper => match upper.strip_prefix("TYPE").map(str::parse::<u16>) {
+                Some(Ok(code)) => Ok(RecordType::from(code)),
+                _ => match presentation::type_code(upper) {
+                    Some(code) => Ok(RecordType::Unknown(code)),
+                    None => Err(format!("Unknown RecordType: {}", s)),
+                },
+            },
         }
     }
 }
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// The category of a problem found while parsing a zone file.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ZoneParseErrorKind {
+    /// The zone file or an included file could not be read.
+    Io,
+    /// A `$ORIGIN`, `$TTL`, `$INCLUDE` or `$GENERATE` directive is malformed, or an
+    /// `$INCLUDE` names a file outside the zones directory.
+    InvalidDirective,
+    /// `$INCLUDE` directives are nested deeper than `MAX_INCLUDE_DEPTH`.
+    IncludeDepthExceeded,
+    /// A record line starts with whitespace but no previous owner exists.
+    MissingOwner,
+    /// A `(` is not closed before the end of the file, or a `)` was not opened.
+    UnbalancedParentheses,
+    /// A TTL or duration field could not be parsed.
+    InvalidTtl,
+    /// The record type is missing or not recognised.
+    InvalidRecordType,
+    /// The RDATA does not match the record type.
+    InvalidRecordData,
+    /// A JSON or YAML zone does not have the expected structure.
+    InvalidStructure,
+}
+
+/// An error found while parsing a zone file, with the position of the offending token.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ZoneParseError {
+    /// The file the error was found in, if the zone was read from disk.
+    pub file: Option<PathBuf>,
+    /// 1-based line number, or 0 if the error is not tied to a line.
+    pub line: usize,
+    /// 1-based column of the offending token, or 0 if the error is not tied to a token.
+    pub column: usize,
+    /// The offending token, or an empty string if there is none.
+    pub token: String,
+    pub kind: ZoneParseErrorKind,
+    pub message: String,
+}
+
+impl fmt::Display for ZoneParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}", file.display())?,
+            None => write!(f, "<input>")?,
+        }
+        if self.line > 0 {
+            write!(f, ":{}:{}", self.line, self.column)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if !self.token.is_empty() {
+            write!(f, " (at `{}`)", self.token)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ZoneParseError {}
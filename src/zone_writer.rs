@@ -0,0 +1,153 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::db::record::{RecordData, ResourceRecord, Zone};
+use super::dnssec::presentation;
+
+pub struct ZoneWriter;
+
+impl ZoneWriter {
+    /// Renders a zone as an RFC 1035 master file that `ZoneParser` reads back into an equal
+    /// `Zone`.
+    ///
+    /// The SOA record comes first, followed by the remaining records in canonical name order
+    /// and then by type. Owner names below the origin are written relative to it, and every
+    /// record carries an explicit TTL and class.
+    pub fn write_zone(zone: &Zone) -> String {
+        let mut records: Vec<&ResourceRecord> = zone.records.iter().collect();
+        records.sort_by(|a, b| ZoneWriter::compare_records(a, b));
+
+        let rows: Vec<[String; 5]> = records
+            .iter()
+            .map(|record| {
+                [
+                    ZoneWriter::relative_name(&record.name, &zone.origin),
+                    record.ttl.to_string(),
+                    record.class.to_string(),
+                    record.data.get_type().to_string(),
+                    ZoneWriter::format_record_data(&record.data),
+                ]
+            })
+            .collect();
+
+        let mut widths = [0; 4];
+        for row in &rows {
+            for (width, field) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(field.len());
+            }
+        }
+
+        let mut output = String::new();
+        writeln!(output, "$ORIGIN {}", zone.origin).unwrap();
+        writeln!(output, "$TTL {}", zone.ttl).unwrap();
+        for row in &rows {
+            let line = format!(
+                "{:<w0$} {:>w1$} {:<w2$} {:<w3$} {}",
+                row[0], row[1], row[2], row[3], row[4],
+                w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3],
+            );
+            writeln!(output, "{}", line.trim_end()).unwrap();
+        }
+        output
+    }
+
+    /// Writes a zone to `path` in the format its extension names, like
+    /// `ZoneParser::load_zone_file` reads it: JSON for `.json`, YAML for `.yaml` and
+    /// `.yml`, a master file otherwise.
+    ///
+    /// The file is written next to `path` first and then renamed over it, so readers never
+    /// see a partially written zone.
+    pub fn write_zone_file(zone: &Zone, path: &Path) -> io::Result<()> {
+        let content = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::to_string_pretty(zone).map_err(io::Error::other)?,
+            Some("yaml") | Some("yml") => serde_yaml::to_string(zone).map_err(io::Error::other)?,
+            _ => ZoneWriter::write_zone(zone),
+        };
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, content)?;
+        fs::rename(&temp, path)
+    }
+
+    /// Renders the RDATA of a record in master file syntax.
+    pub fn format_record_data(data: &RecordData) -> String {
+        match data {
+            RecordData::A(addr) => addr.to_string(),
+            RecordData::AAAA(addr) => addr.to_string(),
+            RecordData::CNAME(name) | RecordData::NS(name) | RecordData::PTR(name) => name.clone(),
+            RecordData::MX { preference, exchange } => format!("{} {}", preference, exchange),
+            RecordData::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => format!("{} {} {} {} {} {} {}", mname, rname, serial, refresh, retry, expire, minimum),
+            RecordData::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => format!("{} {} {} {}", priority, weight, port, target),
+            RecordData::TXT(strings) => strings.iter().map(|txt| ZoneWriter::quote(txt)).collect::<Vec<_>>().join(" "),
+            RecordData::Unknown { rtype, data } => presentation::format_rdata(*rtype, data).unwrap_or_else(|| {
+                let hex: String = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!("\\# {} {}", data.len(), hex).trim_end().to_string()
+            }),
+        }
+    }
+
+    /// Writes `name` relative to `origin` when it is the origin itself or below it.
+    fn relative_name(name: &str, origin: &str) -> String {
+        if name == origin {
+            return "@".to_string();
+        }
+        match name.strip_suffix(origin) {
+            Some(prefix) if prefix.len() > 1 && prefix.ends_with('.') && origin.ends_with('.') => {
+                prefix[..prefix.len() - 1].to_string()
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// Quotes a TXT string, escaping quotes, backslashes and control characters.
+    fn quote(txt: &str) -> String {
+        let mut quoted = String::from("\"");
+        for c in txt.chars() {
+            match c {
+                '"' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c if c.is_ascii_control() => write!(quoted, "\\{:03}", c as u8).unwrap(),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    /// Orders the SOA record first, then by canonical owner name, type and RDATA.
+    fn compare_records(a: &ResourceRecord, b: &ResourceRecord) -> Ordering {
+        let is_soa = |record: &ResourceRecord| matches!(record.data, RecordData::SOA { .. });
+        is_soa(b)
+            .cmp(&is_soa(a))
+            .then_with(|| ZoneWriter::compare_names(&a.name, &b.name))
+            .then_with(|| a.data.get_type().code().cmp(&b.data.get_type().code()))
+            .then_with(|| ZoneWriter::format_record_data(&a.data).cmp(&ZoneWriter::format_record_data(&b.data)))
+    }
+
+    /// Compares names in DNSSEC canonical order (RFC 4034, section 6.1): label by label from
+    /// the root, case-insensitively.
+    fn compare_names(a: &str, b: &str) -> Ordering {
+        let labels = |name: &str| -> Vec<String> {
+            name.trim_end_matches('.').rsplit('.').map(|label| label.to_ascii_lowercase()).collect()
+        };
+        labels(a).cmp(&labels(b))
+    }
+}
@@ -0,0 +1,955 @@
+use dont_need_stability::acl::AclConfig;
+use dont_need_stability::app::{notify, App, ZoneUpdate};
+use dont_need_stability::config::{Config, KeyRole, SigningKeyConfig, UpdateGrant, ViewConfig, ZoneConfig};
+use dont_need_stability::db::InMemoryDatabase;
+use dont_need_stability::dnssec::{Algorithm, KeyPolicy, KeyStore, Nsec3Params, Signer, SigningKey};
+use dont_need_stability::server::{Server, TcpConnection};
+use dont_need_stability::tsig::{self, TsigAlgorithm, TsigError, TsigKey, TsigSigner, TsigVerifier};
+use dont_need_stability::zone_parser::ZoneParser;
+use dont_need_stability::zone_writer::ZoneWriter;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::rdata::SOA;
+use trust_dns_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+@ IN SOA ns1.example.com. admin.example.com. 7 7200 3600 1209600 3600
+@ IN NS ns1.example.com.
+ns1 IN A 192.0.2.1
+www IN A 192.0.2.3
+";
+
+fn start_app(port: u16, zone: &str, config: Config) -> SocketAddr {
+    start_app_with_reload(port, zone, config).0
+}
+
+fn start_app_with_reload(port: u16, zone: &str, config: Config) -> (SocketAddr, Sender<ZoneUpdate>) {
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(ZoneParser::parse_zone_file(zone, "example.com.".to_string(), 3600).unwrap()).unwrap();
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), port);
+    let mut app = App::with_config(Box::new(db), addr, config);
+    let zone_sender = app.zone_sender();
+    thread::spawn(move || app.run());
+    thread::sleep(Duration::from_millis(100));
+    (addr, zone_sender)
+}
+
+fn transfer_config(allow: &str) -> Config {
+    Config {
+        zones: vec![ZoneConfig {
+            origin: "example.com.".to_string(),
+            allow_transfer: vec![allow.parse().unwrap()],
+            ..ZoneConfig::default()
+        }],
+        ..Config::default()
+    }
+}
+
+fn query(name: &str, record_type: RecordType) -> Message {
+    let mut msg = Message::new();
+    msg.set_id(4321)
+        .set_message_type(MessageType::Query)
+        .add_query(Query::query(Name::from_utf8(name).unwrap(), record_type));
+    msg
+}
+
+/// Sends `msg` over TCP and reads responses until `done` returns true for one of them.
+fn tcp_exchange(addr: SocketAddr, msg: &Message, done: impl Fn(&Message) -> bool) -> Vec<Message> {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    Server::write_tcp_message(&stream, &msg.to_bytes().unwrap()).unwrap();
+    let mut responses = Vec::new();
+    loop {
+        let response = Message::from_bytes(&Server::read_tcp_message(&stream).unwrap()).unwrap();
+        let finished = done(&response);
+        responses.push(response);
+        if finished {
+            return responses;
+        }
+    }
+}
+
+fn udp_exchange(addr: SocketAddr, msg: &Message) -> Message {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket.send_to(&msg.to_bytes().unwrap(), addr).unwrap();
+    let mut buf = [0u8; 4096];
+    let (len, _) = socket.recv_from(&mut buf).unwrap();
+    Message::from_bytes(&buf[..len]).unwrap()
+}
+
+#[test]
+fn test_axfr_streams_zone_between_soa_records() {
+    let addr = start_app(5401, ZONE, transfer_config("127.0.0.0/8"));
+
+    let soa_count = std::cell::Cell::new(0);
+    let responses = tcp_exchange(addr, &query("example.com.", RecordType::AXFR), |response| {
+        soa_count.set(soa_count.get() + response.answers().iter().filter(|r| r.record_type() == RecordType::SOA).count());
+        response.response_code() != ResponseCode::NoError || soa_count.get() >= 2
+    });
+
+    let answers: Vec<_> = responses.iter().flat_map(|r| r.answers().iter()).collect();
+    assert_eq!(responses[0].response_code(), ResponseCode::NoError);
+    assert_eq!(answers.len(), 5);
+    assert_eq!(answers.first().unwrap().record_type(), RecordType::SOA);
+    assert_eq!(answers.last().unwrap().record_type(), RecordType::SOA);
+}
+
+#[test]
+fn test_axfr_spans_multiple_messages() {
+    let mut zone = ZONE.to_string();
+    for i in 0..2000 {
+        zone.push_str(&format!("host{} IN A 192.0.2.{}\n", i, i % 250));
+    }
+    let addr = start_app(5402, &zone, transfer_config("127.0.0.1"));
+
+    let soa_count = std::cell::Cell::new(0);
+    let responses = tcp_exchange(addr, &query("example.com.", RecordType::AXFR), |response| {
+        soa_count.set(soa_count.get() + response.answers().iter().filter(|r| r.record_type() == RecordType::SOA).count());
+        soa_count.get() >= 2
+    });
+
+    assert!(responses.len() > 1);
+    assert_eq!(responses.iter().map(|r| r.answers().len()).sum::<usize>(), 2005);
+    assert!(responses.iter().all(|r| r.id() == 4321));
+}
+
+#[test]
+fn test_axfr_refused_outside_acl_and_over_udp() {
+    let addr = start_app(5403, ZONE, transfer_config("192.0.2.0/24"));
+
+    let responses = tcp_exchange(addr, &query("example.com.", RecordType::AXFR), |_| true);
+    assert_eq!(responses[0].response_code(), ResponseCode::Refused);
+    assert!(responses[0].answers().is_empty());
+
+    let response = udp_exchange(addr, &query("example.com.", RecordType::AXFR));
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+}
+
+#[test]
+fn test_axfr_for_unknown_zone_is_notauth() {
+    let addr = start_app(5404, ZONE, transfer_config("127.0.0.1"));
+
+    let responses = tcp_exchange(addr, &query("other.net.", RecordType::AXFR), |_| true);
+    assert_eq!(responses[0].response_code(), ResponseCode::NotAuth);
+}
+
+fn ixfr_query(serial: u32) -> Message {
+    let mut msg = query("example.com.", RecordType::IXFR);
+    let soa = SOA::new(
+        Name::from_utf8("ns1.example.com.").unwrap(),
+        Name::from_utf8("admin.example.com.").unwrap(),
+        serial,
+        7200,
+        3600,
+        1209600,
+        3600,
+    );
+    msg.add_name_server(Record::from_rdata(Name::from_utf8("example.com.").unwrap(), 3600, RData::SOA(soa)));
+    msg
+}
+
+fn reload(zone_sender: &Sender<ZoneUpdate>, content: &str) {
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+    zone_sender.send(ZoneUpdate::Replace(zone)).unwrap();
+    thread::sleep(Duration::from_millis(100));
+}
+
+fn serials(records: &[Record]) -> Vec<u32> {
+    records
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::SOA(soa)) => Some(soa.serial()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_ixfr_returns_condensed_diff() {
+    let (addr, zone_sender) = start_app_with_reload(5405, ZONE, transfer_config("127.0.0.1"));
+    reload(&zone_sender, &ZONE.replace(" 7 ", " 8 ").replace("www IN A 192.0.2.3", "www IN A 192.0.2.4"));
+    reload(&zone_sender, &ZONE.replace(" 7 ", " 9 ").replace("www IN A 192.0.2.3", "www IN A 192.0.2.5"));
+
+    let responses = tcp_exchange(addr, &ixfr_query(7), |_| true);
+    let answers = responses[0].answers();
+
+    assert_eq!(serials(answers), vec![9, 7, 9, 9]);
+    assert_eq!(answers.len(), 6);
+    assert_eq!(answers[2].data(), Some(&RData::A("192.0.2.3".parse().unwrap())));
+    assert_eq!(answers[4].data(), Some(&RData::A("192.0.2.5".parse().unwrap())));
+
+    // Small diffs are also served over UDP.
+    let response = udp_exchange(addr, &ixfr_query(8));
+    assert_eq!(serials(response.answers()), vec![9, 8, 9, 9]);
+}
+
+#[test]
+fn test_ixfr_when_up_to_date_returns_single_soa() {
+    let addr = start_app(5406, ZONE, transfer_config("127.0.0.1"));
+
+    let response = udp_exchange(addr, &ixfr_query(7));
+    assert_eq!(serials(response.answers()), vec![7]);
+    assert_eq!(response.answers().len(), 1);
+}
+
+#[test]
+fn test_ixfr_without_history_falls_back_to_axfr() {
+    let addr = start_app(5407, ZONE, transfer_config("127.0.0.1"));
+
+    let responses = tcp_exchange(addr, &ixfr_query(3), |_| true);
+    assert_eq!(serials(responses[0].answers()), vec![7, 7]);
+    assert_eq!(responses[0].answers().len(), 5);
+
+    // Over UDP the client is told to retry over TCP with the current SOA alone.
+    let response = udp_exchange(addr, &ixfr_query(3));
+    assert_eq!(response.answers().len(), 1);
+}
+
+/// Starts an app without zones that is secondary for example.com. to `primary`.
+fn start_secondary(port: u16, primary: SocketAddr) -> SocketAddr {
+    let mut config = transfer_config("127.0.0.1");
+    config.zones[0].primaries = vec![primary];
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), port);
+    let mut app = App::with_config(Box::new(InMemoryDatabase::new()), addr, config);
+    thread::spawn(move || app.run());
+    addr
+}
+
+fn axfr_records(addr: SocketAddr) -> Vec<Record> {
+    let responses = tcp_exchange(addr, &query("example.com.", RecordType::AXFR), |response| {
+        response.response_code() != ResponseCode::NoError || serials(response.answers()).len() > 1
+    });
+    responses.iter().flat_map(|response| response.answers().iter().cloned()).collect()
+}
+
+#[test]
+fn test_secondary_transfers_zone_and_follows_primary() {
+    let zone = ZONE.replace("7200 3600 1209600", "1 1 1209600");
+    let (primary, zone_sender) = start_app_with_reload(5408, &zone, transfer_config("127.0.0.1"));
+    let secondary = start_secondary(5409, primary);
+    thread::sleep(Duration::from_millis(500));
+
+    let records = axfr_records(secondary);
+    assert_eq!(serials(&records), vec![7, 7]);
+    assert_eq!(records.len(), 5);
+
+    reload(&zone_sender, &zone.replace(" 7 ", " 8 ").replace("www IN A 192.0.2.3", "www IN A 192.0.2.4"));
+    thread::sleep(Duration::from_millis(2500));
+
+    let records = axfr_records(secondary);
+    assert_eq!(serials(&records), vec![8, 8]);
+    assert!(records.iter().any(|record| record.data() == Some(&RData::A("192.0.2.4".parse().unwrap()))));
+    assert!(!records.iter().any(|record| record.data() == Some(&RData::A("192.0.2.3".parse().unwrap()))));
+}
+
+#[test]
+fn test_secondary_zone_expires_without_primary() {
+    let soa = SOA::new(
+        Name::from_utf8("ns1.example.com.").unwrap(),
+        Name::from_utf8("admin.example.com.").unwrap(),
+        1,
+        1,
+        1,
+        2,
+        3600,
+    );
+    let soa = Record::from_rdata(Name::from_utf8("example.com.").unwrap(), 3600, RData::SOA(soa));
+
+    // A primary that answers every request with the whole zone, until it is stopped.
+    let primary_addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5410);
+    let mut primary = Server::new(primary_addr);
+    let tcp_soa = soa.clone();
+    primary.register_tcp_callback(Box::new(move |data: &[u8], _src: SocketAddr, connection: &TcpConnection| {
+        let request = Message::from_bytes(data).unwrap();
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .add_queries(request.queries().iter().cloned())
+            .add_answers(vec![tcp_soa.clone(), tcp_soa.clone()]);
+        connection.send(vec![response.to_bytes().unwrap()]).unwrap();
+    }));
+    primary.register_callback(Box::new(|_data: &[u8], _src: SocketAddr, _socket: &UdpSocket| {}));
+    primary.run();
+
+    let secondary = start_secondary(5411, primary_addr);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(serials(&axfr_records(secondary)), vec![1, 1]);
+
+    primary.stop();
+    thread::sleep(Duration::from_millis(4000));
+    let responses = tcp_exchange(secondary, &query("example.com.", RecordType::AXFR), |_| true);
+    assert_eq!(responses[0].response_code(), ResponseCode::NotAuth);
+}
+
+fn soa_serial(addr: SocketAddr) -> Vec<u32> {
+    serials(udp_exchange(addr, &query("example.com.", RecordType::SOA)).answers())
+}
+
+#[test]
+fn test_notify_triggers_secondary_refresh() {
+    let mut config = transfer_config("127.0.0.1");
+    config.zones[0].also_notify = vec!["127.0.0.1:5413".parse().unwrap()];
+    let (primary, zone_sender) = start_app_with_reload(5412, ZONE, config);
+    let secondary = start_secondary(5413, primary);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(soa_serial(secondary), vec![7]);
+
+    // The SOA refresh timer is two hours, so only the NOTIFY can bring the change over.
+    reload(&zone_sender, &ZONE.replace(" 7 ", " 8 "));
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(soa_serial(secondary), vec![8]);
+}
+
+#[test]
+fn test_notify_is_only_accepted_from_primaries() {
+    let mut notify = query("example.com.", RecordType::SOA);
+    notify.set_op_code(OpCode::Notify);
+
+    let addr = start_app(5414, ZONE, transfer_config("127.0.0.1"));
+    let response = udp_exchange(addr, &notify);
+    assert_eq!(response.op_code(), OpCode::Notify);
+    assert_eq!(response.response_code(), ResponseCode::NotAuth);
+
+    let secondary = start_secondary(5415, "192.0.2.53:53".parse().unwrap());
+    thread::sleep(Duration::from_millis(100));
+    let response = udp_exchange(secondary, &notify);
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+}
+
+#[test]
+fn test_notify_targets_name_servers_except_primary() {
+    let zone = format!("{}@ IN NS ns2.example.com.\nns2 IN A 192.0.2.2\n", ZONE);
+    let zone = ZoneParser::parse_zone_file(&zone, "example.com.".to_string(), 3600).unwrap();
+    let also_notify: SocketAddr = "198.51.100.1:5300".parse().unwrap();
+
+    let targets = notify::notify_targets(&zone, &[also_notify]);
+    assert_eq!(targets, vec!["192.0.2.2:53".parse().unwrap(), also_notify]);
+
+    // Names match whatever their case, and addresses are notified once.
+    let zone = format!("{}@ IN NS NS2.Example.COM.\n@ IN NS ns3.example.com.\nns2 IN A 192.0.2.2\nns3 IN A 192.0.2.2\n", ZONE);
+    let zone = ZoneParser::parse_zone_file(&zone.replace("SOA ns1", "SOA NS1"), "example.com.".to_string(), 3600).unwrap();
+    let targets = notify::notify_targets(&zone, &[also_notify, "192.0.2.2:53".parse().unwrap()]);
+    assert_eq!(targets, vec!["192.0.2.2:53".parse().unwrap(), also_notify]);
+}
+
+#[test]
+fn test_unsupported_opcodes_are_notimp() {
+    let addr = start_app(5416, ZONE, Config::default());
+
+    let mut status = query("example.com.", RecordType::SOA);
+    status.set_op_code(OpCode::Status);
+    let response = udp_exchange(addr, &status);
+    assert_eq!(response.op_code(), OpCode::Status);
+    assert_eq!(response.response_code(), ResponseCode::NotImp);
+
+    // Opcode 3 is unassigned, so the request cannot even be parsed into a `Message`.
+    let mut request = query("example.com.", RecordType::SOA).to_bytes().unwrap();
+    request[2] |= 3 << 3;
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket.send_to(&request, addr).unwrap();
+    let mut buf = [0u8; 512];
+    let (len, _) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(len, 12);
+    assert_eq!(&buf[..2], &request[..2]);
+    assert_eq!((buf[2] >> 3) & 0x0f, 3);
+    assert_eq!(buf[3] & 0x0f, ResponseCode::NotImp.low());
+}
+
+#[test]
+fn test_requests_without_single_question_are_formerr() {
+    let addr = start_app(5417, ZONE, Config::default());
+
+    let mut empty = Message::new();
+    empty.set_id(4321).set_message_type(MessageType::Query);
+    assert_eq!(udp_exchange(addr, &empty).response_code(), ResponseCode::FormErr);
+
+    let mut two = query("example.com.", RecordType::SOA);
+    two.add_query(Query::query(Name::from_utf8("example.com.").unwrap(), RecordType::NS));
+    assert_eq!(udp_exchange(addr, &two).response_code(), ResponseCode::FormErr);
+}
+
+#[test]
+fn test_responses_are_dropped() {
+    let addr = start_app(5418, ZONE, Config::default());
+
+    let mut response = query("example.com.", RecordType::SOA);
+    response.set_message_type(MessageType::Response);
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+    socket.send_to(&response.to_bytes().unwrap(), addr).unwrap();
+    let mut buf = [0u8; 512];
+    assert!(socket.recv_from(&mut buf).is_err());
+
+    // The server keeps answering queries.
+    assert_eq!(soa_serial(addr), vec![7]);
+}
+
+fn update_message(prerequisites: Vec<Record>, updates: Vec<Record>) -> Message {
+    let mut msg = query("example.com.", RecordType::SOA);
+    msg.set_op_code(OpCode::Update).add_answers(prerequisites).add_name_servers(updates);
+    msg
+}
+
+/// A record without data, as used in prerequisites and deletions.
+fn empty_record(name: &str, record_type: RecordType, class: DNSClass) -> Record {
+    let mut record = Record::with(Name::from_utf8(name).unwrap(), record_type, 0);
+    record.set_dns_class(class);
+    record
+}
+
+#[test]
+fn test_update_changes_zone_and_zone_file() {
+    let dir = std::env::temp_dir().join(format!("dns-update-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("example.com.zone");
+    std::fs::write(&path, ZONE).unwrap();
+
+    let mut config = transfer_config("127.0.0.1");
+    config.zones[0].allow_update = vec!["127.0.0.0/8".parse().unwrap()];
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(ZoneParser::parse_zone_file(ZONE, "example.com.".to_string(), 3600).unwrap()).unwrap();
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5419);
+    let mut app = App::with_config(Box::new(db), addr, config);
+    app.set_zone_file("example.com.", path.clone());
+    thread::spawn(move || app.run());
+    thread::sleep(Duration::from_millis(100));
+
+    // Add a record if the name is not in use yet.
+    let api = Record::from_rdata(Name::from_utf8("api.example.com.").unwrap(), 300, RData::A("192.0.2.9".parse().unwrap()));
+    let not_in_use = empty_record("api.example.com.", RecordType::ANY, DNSClass::NONE);
+    let response = udp_exchange(addr, &update_message(vec![not_in_use.clone()], vec![api]));
+    assert_eq!(response.op_code(), OpCode::Update);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+
+    let records = axfr_records(addr);
+    assert_eq!(serials(&records), vec![8, 8]);
+    assert!(records.iter().any(|record| record.data() == Some(&RData::A("192.0.2.9".parse().unwrap()))));
+
+    let saved = ZoneParser::parse_zone_file(&std::fs::read_to_string(&path).unwrap(), "example.com.".to_string(), 3600);
+    assert_eq!(saved.unwrap().serial(), Some(8));
+
+    // The same update now fails its prerequisite.
+    let response = udp_exchange(addr, &update_message(vec![not_in_use], vec![]));
+    assert_eq!(response.response_code(), ResponseCode::YXDomain);
+
+    // Delete the www A RRset.
+    let delete = empty_record("www.example.com.", RecordType::A, DNSClass::ANY);
+    let response = udp_exchange(addr, &update_message(vec![], vec![delete]));
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    let records = axfr_records(addr);
+    assert_eq!(serials(&records), vec![9, 9]);
+    assert!(!records.iter().any(|record| record.name().to_string() == "www.example.com."));
+
+    // Deleting the glue of the zone's name server would leave an invalid zone.
+    let delete = empty_record("ns1.example.com.", RecordType::A, DNSClass::ANY);
+    let response = udp_exchange(addr, &update_message(vec![], vec![delete]));
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert_eq!(soa_serial(addr), vec![9]);
+
+    // Only zones of class IN are updated.
+    let mut chaos = update_message(vec![], vec![]);
+    chaos.queries_mut()[0].set_query_class(DNSClass::CH);
+    assert_eq!(udp_exchange(addr, &chaos).response_code(), ResponseCode::NotAuth);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_update_is_refused_without_allow_update() {
+    let addr = start_app(5420, ZONE, transfer_config("127.0.0.1"));
+
+    let delete = empty_record("www.example.com.", RecordType::A, DNSClass::ANY);
+    let response = udp_exchange(addr, &update_message(vec![], vec![delete]));
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert_eq!(soa_serial(addr), vec![7]);
+}
+
+fn tsig_key(secret: &[u8]) -> TsigKey {
+    TsigKey {
+        name: "transfer-key.".to_string(),
+        algorithm: TsigAlgorithm::HmacSha256,
+        secret: secret.to_vec(),
+    }
+}
+
+/// Sends a request signed with `key` over TCP and returns the raw responses, read until
+/// `count` have arrived.
+fn signed_tcp_exchange(addr: SocketAddr, msg: &Message, key: &TsigKey, count: usize) -> (Vec<Vec<u8>>, Vec<u8>) {
+    let mut signer = TsigSigner::new(key.clone(), None);
+    let request = signer.sign(&msg.to_bytes().unwrap(), tsig::now());
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    Server::write_tcp_message(&stream, &request).unwrap();
+    let responses = (0..count).map(|_| Server::read_tcp_message(&stream).unwrap()).collect();
+    (responses, signer.mac().unwrap().to_vec())
+}
+
+fn tsig_config() -> Config {
+    let mut config = transfer_config("127.0.0.1");
+    config.keys = vec![tsig_key(b"secret")];
+    config.zones[0].transfer_keys = vec!["transfer-key.".to_string()];
+    config
+}
+
+#[test]
+fn test_axfr_with_tsig_key() {
+    let zone: String = std::iter::once(ZONE.to_string())
+        .chain((0..800).map(|i| format!("host{} IN A 192.0.2.{}\n", i, i % 250)))
+        .collect();
+    let addr = start_app(5421, &zone, tsig_config());
+
+    // Unsigned requests are refused, even from `allow_transfer`.
+    let responses = tcp_exchange(addr, &query("example.com.", RecordType::AXFR), |_| true);
+    assert_eq!(responses[0].response_code(), ResponseCode::Refused);
+
+    // Signed requests are served, and every response is signed.
+    let key = tsig_key(b"secret");
+    let (responses, request_mac) = signed_tcp_exchange(addr, &query("example.com.", RecordType::AXFR), &key, 2);
+    let mut verifier = TsigVerifier::new(key, request_mac);
+    for response in &responses {
+        verifier.verify(response, tsig::now()).unwrap();
+    }
+    let last = Message::from_bytes(responses.last().unwrap()).unwrap();
+    assert_eq!(serials(last.answers()), vec![7]);
+}
+
+#[test]
+fn test_request_with_wrong_tsig_secret_is_notauth() {
+    let addr = start_app(5422, ZONE, tsig_config());
+
+    let (responses, _) = signed_tcp_exchange(addr, &query("example.com.", RecordType::AXFR), &tsig_key(b"wrong"), 1);
+    let response = Message::from_bytes(&responses[0]).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NotAuth);
+    assert!(response.answers().is_empty());
+
+    let mut verifier = TsigVerifier::new(tsig_key(b"wrong"), Vec::new());
+    assert_eq!(verifier.verify(&responses[0], tsig::now()), Err(TsigError::BadSig));
+}
+
+#[test]
+fn test_update_policy_limits_names_per_key() {
+    let mut config = tsig_config();
+    config.zones[0].update_policy = vec![UpdateGrant {
+        key: "transfer-key.".to_string(),
+        names: vec!["*.dhcp.example.com.".to_string()],
+    }];
+    config.zones[0].allow_update = vec!["127.0.0.1".parse().unwrap()];
+    let addr = start_app(5423, ZONE, config);
+    let key = tsig_key(b"secret");
+
+    // Unsigned updates are refused, even from `allow_update`.
+    let record = Record::from_rdata(Name::from_utf8("pc.dhcp.example.com.").unwrap(), 300, RData::A("192.0.2.9".parse().unwrap()));
+    assert_eq!(udp_exchange(addr, &update_message(vec![], vec![record])).response_code(), ResponseCode::Refused);
+
+    let update = |name: &str| {
+        let record = Record::from_rdata(Name::from_utf8(name).unwrap(), 300, RData::A("192.0.2.9".parse().unwrap()));
+        let (responses, _) = signed_tcp_exchange(addr, &update_message(vec![], vec![record]), &key, 1);
+        Message::from_bytes(&responses[0]).unwrap().response_code()
+    };
+    assert_eq!(update("laptop.dhcp.example.com."), ResponseCode::NoError);
+    assert_eq!(update("www.example.com."), ResponseCode::Refused);
+    assert_eq!(soa_serial(addr), vec![8]);
+}
+
+#[test]
+fn test_secondary_transfers_with_tsig() {
+    let (primary, zone_sender) = start_app_with_reload(5424, ZONE, tsig_config());
+
+    let mut config = tsig_config();
+    config.zones[0].primaries = vec![primary];
+    config.zones[0].primary_key = Some("transfer-key.".to_string());
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5425);
+    let mut app = App::with_config(Box::new(InMemoryDatabase::new()), addr, config);
+    thread::spawn(move || app.run());
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(soa_serial(addr), vec![7]);
+
+    // An unsigned NOTIFY is refused even though it comes from the primary's address.
+    let mut notify = query("example.com.", RecordType::SOA);
+    notify.set_op_code(OpCode::Notify);
+    assert_eq!(udp_exchange(addr, &notify).response_code(), ResponseCode::Refused);
+
+    // Updates on the primary are transferred after a signed NOTIFY.
+    reload(&zone_sender, &ZONE.replace(" 7 ", " 8 "));
+    let (responses, _) = signed_tcp_exchange(addr, &notify, &tsig_key(b"secret"), 1);
+    assert_eq!(Message::from_bytes(&responses[0]).unwrap().response_code(), ResponseCode::NoError);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(soa_serial(addr), vec![8]);
+}
+
+/// Starts an app serving `zone` signed with an Ed25519 key, written to a key file under
+/// `dir`, with the zone's settings adjusted by `configure`.
+fn start_signed_app(port: u16, zone: &str, dir: &std::path::Path, configure: impl FnOnce(&mut ZoneConfig)) -> SocketAddr {
+    std::fs::create_dir_all(dir).unwrap();
+    let path = dir.join("Kexample.com.+015+csk.private");
+    let key = SigningKey::new(Algorithm::Ed25519, [3; 32], 257).unwrap();
+    std::fs::write(&path, key.to_private_file()).unwrap();
+
+    let mut config = transfer_config("127.0.0.1");
+    config.zones[0].signing_keys = vec![SigningKeyConfig { file: path, role: KeyRole::Csk }];
+    configure(&mut config.zones[0]);
+    start_app(port, zone, config)
+}
+
+fn dnssec_query(name: &str, record_type: RecordType) -> Message {
+    let mut msg = query(name, record_type);
+    let mut edns = Edns::new();
+    edns.set_dnssec_ok(true);
+    msg.set_edns(edns);
+    msg
+}
+
+fn is_rrsig(record: &Record) -> bool {
+    u16::from(record.record_type()) == 46
+}
+
+#[test]
+fn test_signed_zone_answers_with_signatures_when_asked() {
+    let dir = std::env::temp_dir().join(format!("dns-signing-test-{}", std::process::id()));
+    let addr = start_signed_app(5426, ZONE, &dir, |_| {});
+
+    let response = udp_exchange(addr, &dnssec_query("www.example.com.", RecordType::A));
+    assert!(response.extensions().as_ref().is_some_and(Edns::dnssec_ok));
+    assert_eq!(response.answers().len(), 2);
+    assert!(response.answers().iter().any(is_rrsig));
+
+    let response = udp_exchange(addr, &query("www.example.com.", RecordType::A));
+    assert_eq!(response.answers().len(), 1);
+    assert!(!response.answers().iter().any(is_rrsig));
+
+    // Negative answers carry the signed SOA and the NSEC records are part of the zone.
+    let response = udp_exchange(addr, &dnssec_query("missing.example.com.", RecordType::A));
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+    assert!(response.name_servers().iter().any(is_rrsig));
+    let response = udp_exchange(addr, &dnssec_query("www.example.com.", RecordType::MX));
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+    assert_eq!(response.name_servers()[0].record_type(), RecordType::SOA);
+    assert!(axfr_records(addr).iter().any(|record| u16::from(record.record_type()) == 47));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_signatures_are_renewed_with_new_serial() {
+    let dir = std::env::temp_dir().join(format!("dns-resigning-test-{}", std::process::id()));
+    let addr = start_signed_app(5427, ZONE, &dir, |zone| zone.signature_validity = Some(4));
+    assert_eq!(soa_serial(addr), vec![7]);
+
+    // Signatures valid for four seconds are renewed once less than one second remains.
+    thread::sleep(Duration::from_millis(4500));
+    assert!(soa_serial(addr)[0] > 7);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_wildcard_and_negative_answers_carry_nsec3_proofs() {
+    let dir = std::env::temp_dir().join(format!("dns-nsec3-test-{}", std::process::id()));
+    let zone = format!("{}*.wild IN TXT \"wildcard\"\n", ZONE);
+    let addr = start_signed_app(5428, &zone, &dir, |zone| zone.nsec3 = Some(Nsec3Params::default()));
+    let is_nsec3 = |record: &Record| u16::from(record.record_type()) == 50;
+
+    // The wildcard answer takes the queried name, and the authority section proves that
+    // the name itself does not exist.
+    let response = udp_exchange(addr, &dnssec_query("a.wild.example.com.", RecordType::TXT));
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().iter().all(|record| record.name().to_string() == "a.wild.example.com."));
+    assert!(response.answers().iter().any(is_rrsig));
+    assert_eq!(response.name_servers().iter().filter(|record| is_nsec3(record)).count(), 1);
+    let response = udp_exchange(addr, &query("a.wild.example.com.", RecordType::TXT));
+    assert_eq!(response.answers().len(), 1);
+    assert!(response.name_servers().is_empty());
+
+    // Closest encloser, next closer name and wildcard, where one record may prove more
+    // than one of them.
+    let response = udp_exchange(addr, &dnssec_query("missing.example.com.", RecordType::A));
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    let proofs = response.name_servers().iter().filter(|record| is_nsec3(record)).count();
+    assert!((1..=3).contains(&proofs));
+    assert_eq!(response.name_servers().iter().filter(|record| is_rrsig(record)).count(), proofs + 1);
+    let response = udp_exchange(addr, &dnssec_query("www.example.com.", RecordType::MX));
+    assert_eq!(response.name_servers().iter().filter(|record| is_nsec3(record)).count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_presigned_zone_is_served_as_is() {
+    let keys = [SigningKey::new(Algorithm::Ed25519, [5; 32], 257).unwrap()];
+    let zone = ZoneParser::parse_zone_file(ZONE, "example.com.".to_string(), 3600).unwrap();
+    let signed = Signer::new(&keys, tsig::now() as u32, 3600).sign_zone(&zone, None);
+    let addr = start_app(5429, &ZoneWriter::write_zone(&signed), transfer_config("127.0.0.1"));
+
+    let response = udp_exchange(addr, &dnssec_query("www.example.com.", RecordType::A));
+    assert_eq!(response.answers().iter().filter(|record| is_rrsig(record)).count(), 1);
+    let response = udp_exchange(addr, &query("www.example.com.", RecordType::A));
+    assert!(!response.answers().iter().any(is_rrsig));
+
+    let response = udp_exchange(addr, &dnssec_query("missing.example.com.", RecordType::A));
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert!(response.name_servers().iter().any(|record| u16::from(record.record_type()) == 47));
+    let response = udp_exchange(addr, &query("missing.example.com.", RecordType::A));
+    assert_eq!(response.name_servers().len(), 1);
+
+    // Without keys the zone is not signed again: the transfer holds the original records.
+    let records = axfr_records(addr);
+    assert_eq!(records.len(), signed.records.len() + 1);
+    assert_eq!(serials(&records), vec![7, 7]);
+}
+
+fn dnskeys(addr: SocketAddr) -> Vec<Vec<u8>> {
+    let response = udp_exchange(addr, &dnssec_query("example.com.", RecordType::DNSKEY));
+    let mut keys: Vec<Vec<u8>> = response
+        .answers()
+        .iter()
+        .filter(|record| !is_rrsig(record))
+        .map(|record| record.to_bytes().unwrap())
+        .collect();
+    keys.sort();
+    keys
+}
+
+#[test]
+fn test_managed_keys_are_generated_and_kept_across_restarts() {
+    let dir = std::env::temp_dir().join(format!("dns-managed-keys-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut config = transfer_config("127.0.0.1");
+    config.server.zones_directory = dir.clone();
+    config.zones[0].key_policy = Some(KeyPolicy::default());
+    let addr = start_app(5430, ZONE, config);
+
+    let keys = dnskeys(addr);
+    assert_eq!(keys.len(), 2);
+    let response = udp_exchange(addr, &dnssec_query("www.example.com.", RecordType::A));
+    assert_eq!(response.answers().iter().filter(|record| is_rrsig(record)).count(), 1);
+    assert!(KeyStore::state_file(&dir, "example.com.").exists());
+
+    let mut config = transfer_config("127.0.0.1");
+    config.server.zones_directory = dir.clone();
+    config.zones[0].key_policy = Some(KeyPolicy::default());
+    let addr = start_app(5431, ZONE, config);
+    assert_eq!(dnskeys(addr), keys);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_managed_zone_is_re_signed_during_zsk_rollover() {
+    let dir = std::env::temp_dir().join(format!("dns-zsk-rollover-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let zone = format!("$TTL 1\n{}", ZONE.replace(" 3600\n", " 1\n"));
+    let mut config = transfer_config("127.0.0.1");
+    config.server.zones_directory = dir.clone();
+    let policy = KeyPolicy { algorithm: Algorithm::Ed25519, zsk_lifetime: 2, propagation_delay: 0, ..KeyPolicy::default() };
+    config.zones[0].key_policy = Some(policy);
+    let addr = start_app(5432, &zone, config);
+    let keys = dnskeys(addr);
+    let serial = serials(&axfr_records(addr))[0];
+
+    // The successor is published next to the old key, then replaces it.
+    let mut seen = vec![keys.clone()];
+    for _ in 0..50 {
+        thread::sleep(Duration::from_millis(200));
+        let current = dnskeys(addr);
+        if seen.last() != Some(&current) {
+            seen.push(current);
+        }
+        if seen.len() >= 3 {
+            break;
+        }
+    }
+    assert_eq!(seen[1].len(), 3);
+    assert_eq!(seen[2].len(), 2);
+    assert!(seen[2].iter().any(|key| !keys.contains(key)));
+    assert!(serials(&axfr_records(addr))[0] > serial);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn addresses_from(client: &str, addr: SocketAddr, name: &str) -> Vec<RData> {
+    let socket = UdpSocket::bind(format!("{}:0", client)).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket.send_to(&query(name, RecordType::A).to_bytes().unwrap(), addr).unwrap();
+    let mut buf = [0u8; 4096];
+    let (len, _) = socket.recv_from(&mut buf).unwrap();
+    let response = Message::from_bytes(&buf[..len]).unwrap();
+    response.answers().iter().filter_map(|record| record.data().cloned()).collect()
+}
+
+#[test]
+fn test_views_serve_their_own_zones_by_client_and_key() {
+    let mut config = tsig_config();
+    config.views = vec![ViewConfig {
+        name: "internal".to_string(),
+        match_clients: vec!["127.0.0.9/32".parse().unwrap()],
+        match_keys: vec!["transfer-key.".to_string()],
+        ..ViewConfig::default()
+    }];
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(ZoneParser::parse_zone_file(ZONE, "example.com.".to_string(), 3600).unwrap()).unwrap();
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5455);
+    let mut app = App::with_config(Box::new(db), addr, config);
+    let mut internal = InMemoryDatabase::new();
+    let internal_zone = ZONE.replace("www IN A 192.0.2.3", "www IN A 10.0.0.3");
+    internal.add_zone(ZoneParser::parse_zone_file(&internal_zone, "example.com.".to_string(), 3600).unwrap()).unwrap();
+    app.set_view_database("internal", Box::new(internal)).unwrap();
+    assert!(app.set_view_database("missing", Box::new(InMemoryDatabase::new())).is_err());
+    let view_sender = app.view_zone_sender("internal").unwrap();
+    thread::spawn(move || app.run());
+    thread::sleep(Duration::from_millis(100));
+
+    let public = RData::A("192.0.2.3".parse().unwrap());
+    let private = RData::A("10.0.0.3".parse().unwrap());
+    assert_eq!(addresses_from("127.0.0.1", addr, "www.example.com."), vec![public.clone()]);
+    assert_eq!(addresses_from("127.0.0.9", addr, "www.example.com."), vec![private.clone()]);
+
+    // Requests signed with a key of the view get it wherever they come from.
+    let (responses, _) = signed_tcp_exchange(addr, &query("example.com.", RecordType::AXFR), &tsig_key(b"secret"), 1);
+    let response = Message::from_bytes(&responses[0]).unwrap();
+    assert!(response.answers().iter().any(|record| record.data() == Some(&private)));
+
+    // Reloads sent to the view only change the view.
+    reload(&view_sender, &internal_zone.replace("10.0.0.3", "10.0.0.4").replace(" 7 ", " 8 "));
+    assert_eq!(addresses_from("127.0.0.9", addr, "www.example.com."), vec![RData::A("10.0.0.4".parse().unwrap())]);
+    assert_eq!(addresses_from("127.0.0.1", addr, "www.example.com."), vec![public]);
+}
+
+#[test]
+fn test_views_cannot_hold_secondary_or_signed_zones() {
+    let mut config = transfer_config("127.0.0.1");
+    config.zones.push(ZoneConfig {
+        origin: "example.net.".to_string(),
+        primaries: vec!["192.0.2.53:53".parse().unwrap()],
+        ..ZoneConfig::default()
+    });
+    config.zones.push(ZoneConfig {
+        origin: "example.org.".to_string(),
+        signing_keys: vec![SigningKeyConfig { file: "Kexample.org.private".into(), role: KeyRole::Csk }],
+        ..ZoneConfig::default()
+    });
+
+    assert_eq!(config.check_view_zone("example.com."), Ok(()));
+    assert_eq!(config.check_view_zone("other.com."), Ok(()));
+    assert!(config.check_view_zone("EXAMPLE.net.").unwrap_err().contains("Secondary zone"));
+    assert!(config.check_view_zone("example.org.").unwrap_err().contains("Signed zone"));
+}
+
+#[test]
+fn test_acls_restrict_operations_globally_per_listener_and_per_zone() {
+    let mut config = transfer_config("127.0.0.0/8");
+    config.acls = vec![
+        AclConfig { name: "internal".to_string(), allow: vec!["127.0.0.0/8".parse().unwrap()], deny: vec!["127.0.0.9".parse().unwrap()] },
+        AclConfig { name: "secondaries".to_string(), allow: vec!["127.0.0.1".parse().unwrap()], deny: vec![] },
+    ];
+    config.access.query = Some(vec!["internal".to_string()]);
+    config.server.tcp.query = Some(vec!["none".to_string()]);
+    config.server.tcp.transfer = Some(vec!["secondaries".to_string()]);
+    config.zones[0].allow_update = vec!["127.0.0.1".parse().unwrap()];
+    config.zones[0].access.update = Some(vec!["none".to_string()]);
+    let addr = start_app(5456, ZONE, config);
+
+    // Queries pass the global ACL from 127.0.0.1, but not from the denied 127.0.0.9.
+    assert_eq!(addresses_from("127.0.0.1", addr, "www.example.com."), vec![RData::A("192.0.2.3".parse().unwrap())]);
+    let socket = UdpSocket::bind("127.0.0.9:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket.send_to(&query("www.example.com.", RecordType::A).to_bytes().unwrap(), addr).unwrap();
+    let mut buf = [0u8; 4096];
+    let (len, _) = socket.recv_from(&mut buf).unwrap();
+    assert_eq!(Message::from_bytes(&buf[..len]).unwrap().response_code(), ResponseCode::Refused);
+
+    // The TCP listener refuses queries but allows transfers to secondaries.
+    let responses = tcp_exchange(addr, &query("www.example.com.", RecordType::A), |_| true);
+    assert_eq!(responses[0].response_code(), ResponseCode::Refused);
+    assert_eq!(serials(&axfr_records(addr)), vec![7, 7]);
+
+    // The zone's ACL refuses updates that `allow_update` would accept.
+    let delete = empty_record("www.example.com.", RecordType::A, DNSClass::ANY);
+    let response = udp_exchange(addr, &update_message(vec![], vec![delete]));
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert_eq!(soa_serial(addr), vec![7]);
+}
+
+#[test]
+fn test_txt_records_keep_their_character_strings() {
+    let zone = format!("{}dkim IN TXT \"{}\" \"{}\"\n", ZONE, "a".repeat(200), "b".repeat(200));
+    let addr = start_app(5459, &zone, transfer_config("127.0.0.1"));
+    let strings = |record: &Record| match record.data() {
+        Some(RData::TXT(txt)) => txt.txt_data().iter().map(|string| string.len()).collect::<Vec<_>>(),
+        _ => Vec::new(),
+    };
+
+    let response = udp_exchange(addr, &query("dkim.example.com.", RecordType::TXT));
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(strings(&response.answers()[0]), vec![200, 200]);
+
+    let responses = tcp_exchange(addr, &query("example.com.", RecordType::AXFR), |_| true);
+    let transferred = responses.iter().flat_map(Message::answers).find(|record| record.record_type() == RecordType::TXT).unwrap();
+    assert_eq!(strings(transferred), vec![200, 200]);
+
+    // A secondary serves the strings as transferred.
+    let secondary = start_secondary(5460, addr);
+    thread::sleep(Duration::from_millis(500));
+    let response = udp_exchange(secondary, &query("dkim.example.com.", RecordType::TXT));
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(strings(&response.answers()[0]), vec![200, 200]);
+}
+
+#[test]
+fn test_missing_signing_key_fails_startup() {
+    let mut config = transfer_config("127.0.0.1");
+    config.zones[0].signing_keys = vec![SigningKeyConfig { file: "/nonexistent/Kexample.com.private".into(), role: KeyRole::Csk }];
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5463);
+    let started = std::panic::catch_unwind(|| App::with_config(Box::new(InMemoryDatabase::new()), addr, config));
+    assert!(started.is_err());
+}
+
+#[test]
+fn test_unknown_records_survive_the_wire() {
+    let zone = format!("{}opaque IN TYPE65534 \\# 4 0a0b0c0d\ngeneric IN A \\# 4 c0000205\n", ZONE);
+    let parsed = ZoneParser::parse_zone_file(&zone, "example.com.".to_string(), 3600).unwrap();
+    let addr = start_app(5464, &zone, transfer_config("127.0.0.1"));
+
+    // The record is emitted with its RDATA as is, and reads back the same.
+    let response = udp_exchange(addr, &query("opaque.example.com.", RecordType::Unknown(65534)));
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 1);
+    let opaque = parsed.records.iter().find(|record| record.name == "opaque.example.com.").unwrap();
+    assert_eq!(App::from_wire_record(&response.answers()[0]).as_ref(), Some(opaque));
+
+    let responses = tcp_exchange(addr, &query("example.com.", RecordType::AXFR), |_| true);
+    let transferred = responses.iter().flat_map(Message::answers).find(|record| record.record_type() == RecordType::Unknown(65534)).unwrap();
+    assert_eq!(App::from_wire_record(transferred).as_ref(), Some(opaque));
+
+    // Generic RDATA of a known type is served in the type's own form.
+    let response = udp_exchange(addr, &query("generic.example.com.", RecordType::A));
+    assert_eq!(response.answers()[0].data(), Some(&RData::A(Ipv4Addr::new(192, 0, 2, 5))));
+}
+
+#[test]
+fn test_tcp_client_that_stops_reading_does_not_hold_up_the_server() {
+    let records: String = (0..3000).map(|i| format!("host{} IN TXT \"{}\"\n", i, "x".repeat(200))).collect();
+    let addr = start_app(5465, &format!("{}{}", ZONE, records), transfer_config("127.0.0.1"));
+
+    // Zone transfers are requested over and over, and none of them is read.
+    let stream = TcpStream::connect(addr).unwrap();
+    for _ in 0..20 {
+        if Server::write_tcp_message(&stream, &query("example.com.", RecordType::AXFR).to_bytes().unwrap()).is_err() {
+            break;
+        }
+    }
+    thread::sleep(Duration::from_millis(500));
+
+    let response = udp_exchange(addr, &query("www.example.com.", RecordType::A));
+    assert_eq!(response.answers().len(), 1);
+    drop(stream);
+}
@@ -1,4 +1,7 @@
-use dont_need_stability::db::{Database, InMemoryDatabase, RecordData, RecordType, ResourceRecord, Zone};
+use dont_need_stability::db::{
+    Database, InMemoryDatabase, Prerequisite, RecordData, RecordType, ResourceRecord, UpdateError, UpdateOperation, Zone,
+    ZoneDiff,
+};
 use dont_need_stability::db::record::RecordClass;
 use std::net::Ipv4Addr;
 
@@ -141,3 +144,285 @@ fn test_insert_duplicate_soa_record() {
     let result2 = db.add_zone(zone.clone());
     assert!(result2.is_err());
 }
+
+fn soa_zone(serial: u32, hosts: &[(&str, [u8; 4])]) -> Zone {
+    let mut records = vec![ResourceRecord {
+        name: "example.com.".to_string(),
+        ttl: 3600,
+        class: RecordClass::IN,
+        data: RecordData::SOA {
+            mname: "ns1.example.com.".to_string(),
+            rname: "admin.example.com.".to_string(),
+            serial,
+            refresh: 3600,
+            retry: 1800,
+            expire: 604800,
+            minimum: 600,
+        },
+    }];
+    for (name, addr) in hosts {
+        records.push(ResourceRecord {
+            name: format!("{}.example.com.", name),
+            ttl: 3600,
+            class: RecordClass::IN,
+            data: RecordData::A(Ipv4Addr::from(*addr)),
+        });
+    }
+    Zone {
+        origin: "example.com.".to_string(),
+        ttl: 3600,
+        records,
+    }
+}
+
+#[test]
+fn test_replace_zone_journals_changes() {
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(soa_zone(1, &[("a", [192, 0, 2, 1]), ("b", [192, 0, 2, 2])])).unwrap();
+    db.replace_zone(soa_zone(2, &[("a", [192, 0, 2, 1]), ("c", [192, 0, 2, 3])])).unwrap();
+    db.replace_zone(soa_zone(3, &[("a", [192, 0, 2, 9]), ("c", [192, 0, 2, 3])])).unwrap();
+
+    let diffs = db.zone_changes_since("example.com.", 1).unwrap();
+    assert_eq!(diffs.len(), 2);
+    assert_eq!((diffs[0].old_serial(), diffs[0].new_serial()), (1, 2));
+    assert_eq!(diffs[0].deleted[0].name, "b.example.com.");
+    assert_eq!(diffs[0].added[0].name, "c.example.com.");
+
+    let condensed = ZoneDiff::condense(&diffs);
+    assert_eq!((condensed.old_serial(), condensed.new_serial()), (1, 3));
+    assert_eq!(condensed.deleted.len(), 2);
+    assert_eq!(condensed.added.len(), 2);
+
+    assert_eq!(db.zone_changes_since("example.com.", 2).unwrap().len(), 1);
+    assert!(db.zone_changes_since("example.com.", 3).is_none());
+}
+
+#[test]
+fn test_condense_drops_transient_records() {
+    let v1 = soa_zone(1, &[]);
+    let v2 = soa_zone(2, &[("tmp", [192, 0, 2, 7])]);
+    let v3 = soa_zone(3, &[]);
+    let first = ZoneDiff::between(&v1, &v2).unwrap();
+    let second = ZoneDiff::between(&v2, &v3).unwrap();
+
+    let condensed = ZoneDiff::condense(&[&first, &second]);
+    assert!(condensed.deleted.is_empty());
+    assert!(condensed.added.is_empty());
+}
+
+#[test]
+fn test_replace_zone_requires_serial_increase() {
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(soa_zone(5, &[])).unwrap();
+
+    assert!(db.replace_zone(soa_zone(5, &[])).is_ok());
+    assert!(db.replace_zone(soa_zone(5, &[("a", [192, 0, 2, 1])])).is_err());
+    assert!(db.replace_zone(soa_zone(4, &[("a", [192, 0, 2, 1])])).is_err());
+    // In RFC 1982 serial arithmetic, u32::MAX comes before 5.
+    assert!(db.replace_zone(soa_zone(u32::MAX, &[("a", [192, 0, 2, 1])])).is_err());
+    assert!(db.zone_changes_since("example.com.", 5).is_none());
+}
+
+fn a_record(name: &str, addr: [u8; 4]) -> ResourceRecord {
+    ResourceRecord {
+        name: name.to_string(),
+        ttl: 300,
+        class: RecordClass::IN,
+        data: RecordData::A(Ipv4Addr::from(addr)),
+    }
+}
+
+#[test]
+fn test_update_checks_prerequisites() {
+    let zone = soa_zone(1, &[("www", [192, 0, 2, 1])]);
+    let add = [UpdateOperation::Add(a_record("api.example.com.", [192, 0, 2, 2]))];
+
+    let cases = [
+        (Prerequisite::NameInUse("api.example.com.".to_string()), UpdateError::NameNotInUse("api.example.com.".to_string())),
+        (Prerequisite::NameNotInUse("www.example.com.".to_string()), UpdateError::NameInUse("www.example.com.".to_string())),
+        (
+            Prerequisite::RRsetExists { name: "www.example.com.".to_string(), rtype: RecordType::AAAA },
+            UpdateError::RRsetMissing("www.example.com.".to_string()),
+        ),
+        (
+            Prerequisite::RRsetDoesNotExist { name: "WWW.example.com.".to_string(), rtype: RecordType::A },
+            UpdateError::RRsetExists("WWW.example.com.".to_string()),
+        ),
+        (
+            Prerequisite::RRsetEquals {
+                name: "www.example.com.".to_string(),
+                rtype: RecordType::A,
+                data: vec![RecordData::A(Ipv4Addr::new(192, 0, 2, 9))],
+            },
+            UpdateError::RRsetMissing("www.example.com.".to_string()),
+        ),
+        (Prerequisite::NameInUse("www.example.org.".to_string()), UpdateError::NotZone("www.example.org.".to_string())),
+    ];
+    for (prerequisite, error) in cases {
+        assert_eq!(zone.update(&[prerequisite], &add), Err(error));
+    }
+
+    let holds = Prerequisite::RRsetEquals {
+        name: "www.example.com.".to_string(),
+        rtype: RecordType::A,
+        data: vec![RecordData::A(Ipv4Addr::new(192, 0, 2, 1))],
+    };
+    assert!(zone.update(&[holds], &add).unwrap().is_some());
+
+    let zone = soa_zone(1, &[("www", [192, 0, 2, 1]), ("www", [192, 0, 2, 2])]);
+    let a = RecordData::A(Ipv4Addr::new(192, 0, 2, 1));
+    let b = RecordData::A(Ipv4Addr::new(192, 0, 2, 2));
+    let repeated = Prerequisite::RRsetEquals {
+        name: "www.example.com.".to_string(),
+        rtype: RecordType::A,
+        data: vec![a.clone(), b, a.clone()],
+    };
+    assert!(zone.update(&[repeated], &add).unwrap().is_some());
+    let missing = Prerequisite::RRsetEquals {
+        name: "www.example.com.".to_string(),
+        rtype: RecordType::A,
+        data: vec![a.clone(), a],
+    };
+    assert_eq!(zone.update(&[missing], &add), Err(UpdateError::RRsetMissing("www.example.com.".to_string())));
+}
+
+#[test]
+fn test_update_applies_operations_and_increments_serial() {
+    let mut zone = soa_zone(1, &[("www", [192, 0, 2, 1]), ("www", [192, 0, 2, 2]), ("old", [192, 0, 2, 3])]);
+    zone.records.push(ResourceRecord {
+        name: "example.com.".to_string(),
+        ttl: 3600,
+        class: RecordClass::IN,
+        data: RecordData::NS("ns1.example.com.".to_string()),
+    });
+
+    let updated = zone
+        .update(
+            &[],
+            &[
+                UpdateOperation::Add(a_record("api.example.com.", [192, 0, 2, 4])),
+                UpdateOperation::DeleteRecord {
+                    name: "www.example.com.".to_string(),
+                    data: RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                },
+                UpdateOperation::DeleteName("old.example.com.".to_string()),
+                // The SOA record and the last apex NS record cannot be deleted.
+                UpdateOperation::DeleteName("example.com.".to_string()),
+                UpdateOperation::DeleteRecord {
+                    name: "example.com.".to_string(),
+                    data: RecordData::NS("ns1.example.com.".to_string()),
+                },
+            ],
+        )
+        .unwrap()
+        .unwrap();
+
+    let mut expected = soa_zone(2, &[("www", [192, 0, 2, 2])]);
+    expected.records.push(zone.records[4].clone());
+    expected.records.push(a_record("api.example.com.", [192, 0, 2, 4]));
+    assert_eq!(updated, expected);
+
+    // A CNAME cannot be added next to other data, so nothing changes.
+    let cname = ResourceRecord {
+        name: "www.example.com.".to_string(),
+        ttl: 300,
+        class: RecordClass::IN,
+        data: RecordData::CNAME("api.example.com.".to_string()),
+    };
+    assert_eq!(updated.update(&[], &[UpdateOperation::Add(cname)]), Ok(None));
+}
+
+#[test]
+fn test_update_must_leave_a_valid_zone() {
+    let mut zone = soa_zone(1, &[("ns1", [192, 0, 2, 1]), ("www", [192, 0, 2, 2])]);
+    zone.records.push(ResourceRecord {
+        name: "example.com.".to_string(),
+        ttl: 3600,
+        class: RecordClass::IN,
+        data: RecordData::NS("ns1.example.com.".to_string()),
+    });
+    assert!(zone.update(&[], &[UpdateOperation::DeleteName("www.example.com.".to_string())]).unwrap().is_some());
+
+    let result = zone.update(&[], &[UpdateOperation::DeleteName("ns1.example.com.".to_string())]);
+    assert!(matches!(result, Err(UpdateError::Invalid(_))), "{:?}", result);
+
+    // Problems the zone already had do not stand in the way.
+    zone.records.push(ResourceRecord {
+        name: "example.com.".to_string(),
+        ttl: 3600,
+        class: RecordClass::IN,
+        data: RecordData::NS("ns2.example.com.".to_string()),
+    });
+    assert!(zone.update(&[], &[UpdateOperation::DeleteName("www.example.com.".to_string())]).unwrap().is_some());
+}
+
+#[test]
+fn test_update_zone_is_atomic_and_journaled() {
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(soa_zone(1, &[("www", [192, 0, 2, 1])])).unwrap();
+
+    let add = [UpdateOperation::Add(a_record("api.example.com.", [192, 0, 2, 2]))];
+    let failing = [Prerequisite::NameInUse("missing.example.com.".to_string())];
+    assert_eq!(
+        db.update_zone("example.com.", &failing, &add),
+        Err(UpdateError::NameNotInUse("missing.example.com.".to_string()))
+    );
+    assert_eq!(db.get_zone("example.com.").unwrap().serial(), Some(1));
+
+    assert_eq!(db.update_zone("example.com.", &[], &add), Ok(true));
+    assert_eq!(db.get_zone("example.com.").unwrap().serial(), Some(2));
+    let diffs = db.zone_changes_since("example.com.", 1).unwrap();
+    assert_eq!(diffs[0].added, vec![a_record("api.example.com.", [192, 0, 2, 2])]);
+
+    assert_eq!(
+        db.update_zone("example.org.", &[], &add),
+        Err(UpdateError::NotAuth("example.org.".to_string()))
+    );
+}
+
+#[test]
+fn test_zone_index_follows_the_zone() {
+    let mut db = InMemoryDatabase::new();
+    let mut zone = soa_zone(1, &[("www.eu", [192, 0, 2, 1]), ("WWW", [192, 0, 2, 2])]);
+    zone.records.push(ResourceRecord {
+        name: "sub.example.com.".to_string(),
+        ttl: 3600,
+        class: RecordClass::IN,
+        data: RecordData::NS("ns.sub.example.com.".to_string()),
+    });
+    db.add_zone(zone).unwrap();
+
+    let zone = db.get_zone("example.com.").unwrap();
+    let index = db.zone_index("example.com.").unwrap();
+    assert_eq!(index.rrset(zone, "www.example.com.", &RecordType::A), vec![&zone.records[2]]);
+    assert!(index.rrset(zone, "www.example.com.", &RecordType::AAAA).is_empty());
+    // Empty non-terminals exist, and names below them are enclosed by them.
+    assert!(index.name_exists("eu.example.com."));
+    assert!(!index.name_exists("mail.example.com."));
+    assert_eq!(index.closest_encloser("a.b.eu.example.com."), "eu.example.com.");
+    assert_eq!(index.closest_encloser("mail.example.com."), "example.com.");
+    assert_eq!(index.delegation("a.sub.example.com.", false), Some("sub.example.com."));
+    assert_eq!(index.delegation("sub.example.com.", true), Some("sub.example.com."));
+    assert_eq!(index.delegation("sub.example.com.", false), None);
+
+    assert_eq!(db.update_zone("example.com.", &[], &[UpdateOperation::Add(a_record("mail.example.com.", [192, 0, 2, 3]))]), Ok(true));
+    assert!(db.zone_index("example.com.").unwrap().name_exists("mail.example.com."));
+    db.remove_zone("example.com.");
+    assert!(db.zone_index("example.com.").is_none());
+}
+
+#[test]
+fn test_zones_are_found_whatever_the_case_of_their_origin() {
+    let mut db = InMemoryDatabase::new();
+    let mut zone = soa_zone(1, &[("www", [192, 0, 2, 1])]);
+    zone.origin = "Example.COM".to_string();
+    db.add_zone(zone).unwrap();
+
+    assert!(db.add_zone(soa_zone(1, &[])).is_err());
+    assert_eq!(db.find_zone("WWW.example.com.").map(|zone| zone.origin.as_str()), Some("Example.COM"));
+    assert!(db.zone_index("example.com.").unwrap().name_exists("www.example.com."));
+    assert_eq!(db.update_zone("example.com", &[], &[UpdateOperation::Add(a_record("api.example.com.", [192, 0, 2, 2]))]), Ok(true));
+    assert_eq!(db.zone_changes_since("EXAMPLE.com.", 1).unwrap().len(), 1);
+    assert!(db.remove_zone("example.com.").is_some());
+    assert!(db.find_zone("www.example.com.").is_none());
+}
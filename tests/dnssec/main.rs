@@ -0,0 +1,587 @@
+use std::collections::BTreeSet;
+
+use dont_need_stability::db::{Database, InMemoryDatabase, RecordData, ResourceRecord, Zone};
+use data_encoding::{BASE32HEX_NOPAD, BASE64, HEXUPPER};
+use dont_need_stability::db::record::RecordClass;
+use dont_need_stability::dnssec::canonical::compare_names;
+use dont_need_stability::dnssec::denial::{denial_records, Denial};
+use dont_need_stability::dnssec::key::{ds_rdata, verify, DIGEST_SHA1, DIGEST_SHA256, SECURE_ENTRY_POINT, ZONE_KEY};
+use dont_need_stability::dnssec::keystore::{KeyStore, ZoneTtls, ZskRollover};
+use dont_need_stability::dnssec::nsec::{parse_type_bitmap, type_bitmap};
+use dont_need_stability::dnssec::sign::{as_rrsig, Rrsig};
+use dont_need_stability::dnssec::{Algorithm, KeyPolicy, Nsec3Params, Signer, SigningKey, DNSKEY, DS, NSEC, NSEC3, NSEC3PARAM, RRSIG};
+use dont_need_stability::zone_parser::ZoneParser;
+use dont_need_stability::zone_writer::ZoneWriter;
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+@ IN SOA ns1.example.com. admin.example.com. 7 7200 3600 1209600 300
+@ IN NS ns1.example.com.
+@ IN MX 10 mail.example.com.
+ns1 IN A 192.0.2.1
+www IN A 192.0.2.3
+www IN A 192.0.2.4
+Mail IN A 192.0.2.5
+sub IN NS ns.sub.example.com.
+ns.sub IN A 192.0.2.53
+";
+
+const NOW: u32 = 1_700_000_000;
+const VALIDITY: u32 = 14 * 24 * 3600;
+
+fn parse(content: &str) -> Zone {
+    ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap()
+}
+
+fn csk(algorithm: Algorithm) -> SigningKey {
+    SigningKey::new(algorithm, [7; 32], ZONE_KEY | SECURE_ENTRY_POINT).unwrap()
+}
+
+fn of_type(zone: &Zone, rtype: u16) -> Vec<&ResourceRecord> {
+    zone.records.iter().filter(|record| record.data.get_type().code() == rtype).collect()
+}
+
+/// Checks every RRSIG in the zone against the zone's DNSKEY records and returns the
+/// covered (name, type) pairs.
+fn verify_signatures(zone: &Zone) -> BTreeSet<(String, u16)> {
+    let dnskeys: Vec<Vec<u8>> = of_type(zone, DNSKEY)
+        .iter()
+        .map(|record| match &record.data {
+            RecordData::Unknown { data, .. } => data.clone(),
+            _ => unreachable!(),
+        })
+        .collect();
+    let mut covered = BTreeSet::new();
+    for record in of_type(zone, RRSIG) {
+        let rrsig = as_rrsig(record).unwrap();
+        let rrset: Vec<&ResourceRecord> = zone
+            .records
+            .iter()
+            .filter(|r| r.name.eq_ignore_ascii_case(&record.name) && r.data.get_type().code() == rrsig.type_covered)
+            .collect();
+        assert!(
+            dnskeys.iter().any(|dnskey| rrsig.verify(&rrset, dnskey)),
+            "bad signature over {} type {}",
+            record.name,
+            rrsig.type_covered
+        );
+        assert_eq!((rrsig.inception, rrsig.expiration), (NOW - 3600, NOW + VALIDITY));
+        covered.insert((record.name.to_ascii_lowercase(), rrsig.type_covered));
+    }
+    covered
+}
+
+#[test]
+fn test_key_file_round_trip_and_key_tag() {
+    for algorithm in [Algorithm::EcdsaP256Sha256, Algorithm::Ed25519] {
+        let key = csk(algorithm);
+        let parsed = SigningKey::parse(&key.to_private_file(), key.flags).unwrap();
+        assert_eq!(parsed.public_key(), key.public_key());
+        assert_eq!(parsed.key_tag(), key.key_tag());
+        assert_eq!(key.dnskey_rdata()[..4], [1, 1, 3, algorithm.number()]);
+    }
+    assert!(SigningKey::parse("Private-key-format: v1.3\nAlgorithm: 8 (RSASHA256)\n", 256).is_err());
+
+    // Key files are created readable by the owner only, and never overwritten.
+    let dir = key_dir("save");
+    let key = csk(Algorithm::Ed25519);
+    let path = dir.join(key.file_name("example.com."));
+    key.save(&path).unwrap();
+    assert_eq!(SigningKey::load(&path, key.flags).unwrap().key_tag(), key.key_tag());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+    assert!(csk(Algorithm::Ed25519).save(&path).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn test_ed25519_vectors() {
+    // RFC 8032, section 7.1, tests 1 and 2.
+    let private: [u8; 32] = unhex("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").try_into().unwrap();
+    let key = SigningKey::new(Algorithm::Ed25519, private, ZONE_KEY).unwrap();
+    assert_eq!(key.public_key(), unhex("d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"));
+    let signature = key.sign(b"");
+    assert_eq!(
+        signature,
+        unhex(
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+             5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        )
+    );
+    assert!(verify(15, key.public_key(), b"", &signature));
+
+    let private: [u8; 32] = unhex("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb").try_into().unwrap();
+    let key = SigningKey::new(Algorithm::Ed25519, private, ZONE_KEY).unwrap();
+    assert_eq!(key.public_key(), unhex("3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c"));
+    let signature = key.sign(&[0x72]);
+    assert_eq!(
+        signature,
+        unhex(
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+             085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+        )
+    );
+    assert!(verify(15, key.public_key(), &[0x72], &signature));
+    assert!(!verify(15, key.public_key(), &[0x73], &signature));
+}
+
+#[test]
+fn test_p256_vectors() {
+    // RFC 6979, appendix A.2.5, with SHA-256 and the message "sample".
+    let private: [u8; 32] = unhex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721").try_into().unwrap();
+    let key = SigningKey::new(Algorithm::EcdsaP256Sha256, private, ZONE_KEY).unwrap();
+    assert_eq!(
+        key.public_key(),
+        unhex(
+            "60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6\
+             7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299"
+        )
+    );
+    let signature = key.sign(b"sample");
+    assert_eq!(
+        signature,
+        unhex(
+            "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716\
+             f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8"
+        )
+    );
+    assert!(verify(13, key.public_key(), b"sample", &signature));
+    assert!(!verify(13, key.public_key(), b"samples", &signature));
+    assert!(!verify(13, &key.public_key()[1..], b"sample", &signature));
+    assert!(SigningKey::new(Algorithm::EcdsaP256Sha256, [0; 32], ZONE_KEY).is_err());
+}
+
+#[test]
+fn test_signed_zone_verifies() {
+    for algorithm in [Algorithm::EcdsaP256Sha256, Algorithm::Ed25519] {
+        let keys = [csk(algorithm)];
+        let signed = Signer::new(&keys, NOW, VALIDITY).sign_zone(&parse(ZONE), None);
+        assert_eq!(of_type(&signed, DNSKEY).len(), 1);
+
+        let covered = verify_signatures(&signed);
+        let expected: BTreeSet<(String, u16)> = [
+            ("example.com.", 6),
+            ("example.com.", 2),
+            ("example.com.", 15),
+            ("example.com.", DNSKEY),
+            ("example.com.", NSEC),
+            ("ns1.example.com.", 1),
+            ("ns1.example.com.", NSEC),
+            ("www.example.com.", 1),
+            ("www.example.com.", NSEC),
+            ("mail.example.com.", 1),
+            ("mail.example.com.", NSEC),
+            // The delegation is in the NSEC chain, but its NS records and glue are not signed.
+            ("sub.example.com.", NSEC),
+        ]
+        .into_iter()
+        .map(|(name, rtype)| (name.to_string(), rtype))
+        .collect();
+        assert_eq!(covered, expected);
+    }
+}
+
+#[test]
+fn test_ksk_signs_only_dnskey_rrset() {
+    let keys = [
+        SigningKey::new(Algorithm::Ed25519, [1; 32], ZONE_KEY | SECURE_ENTRY_POINT).unwrap(),
+        SigningKey::new(Algorithm::Ed25519, [2; 32], ZONE_KEY).unwrap(),
+    ];
+    let signed = Signer::new(&keys, NOW, VALIDITY).sign_zone(&parse(ZONE), None);
+    verify_signatures(&signed);
+    for record in of_type(&signed, RRSIG) {
+        let rrsig = as_rrsig(record).unwrap();
+        let expected = if rrsig.type_covered == DNSKEY { &keys[0] } else { &keys[1] };
+        assert_eq!(rrsig.key_tag, expected.key_tag());
+    }
+}
+
+#[test]
+fn test_nsec_chain_is_in_canonical_order() {
+    let keys = [csk(Algorithm::Ed25519)];
+    let signed = Signer::new(&keys, NOW, VALIDITY).sign_zone(&parse(ZONE), None);
+    let chain: Vec<(String, String, BTreeSet<u16>)> = of_type(&signed, NSEC)
+        .iter()
+        .map(|record| match &record.data {
+            RecordData::Unknown { data, .. } => {
+                let (next, bitmap) = read_name(data);
+                (record.name.clone(), next, parse_type_bitmap(bitmap).unwrap())
+            }
+            _ => unreachable!(),
+        })
+        .collect();
+
+    let mut names: Vec<&str> = chain.iter().map(|(name, _, _)| name.as_str()).collect();
+    names.sort_by(|a, b| compare_names(a, b));
+    assert_eq!(names, ["example.com.", "mail.example.com.", "ns1.example.com.", "sub.example.com.", "www.example.com."]);
+    for (i, name) in names.iter().enumerate() {
+        let (_, next, _) = chain.iter().find(|(owner, _, _)| owner == name).unwrap();
+        assert_eq!(next, names[(i + 1) % names.len()]);
+    }
+
+    let types = |name: &str| chain.iter().find(|(owner, _, _)| owner == name).unwrap().2.clone();
+    assert_eq!(types("example.com."), BTreeSet::from([2, 6, 15, RRSIG, NSEC, DNSKEY]));
+    assert_eq!(types("sub.example.com."), BTreeSet::from([2, RRSIG, NSEC]));
+    // Every NSEC record has the TTL of negative answers, the SOA minimum.
+    assert!(of_type(&signed, NSEC).iter().all(|record| record.ttl == 300));
+}
+
+#[test]
+fn test_type_bitmap_windows() {
+    let types = BTreeSet::from([1, 15, 46, 47, 257]);
+    let bitmap = type_bitmap(&types);
+    assert_eq!(bitmap, [0, 6, 0x40, 0x01, 0, 0, 0, 0x03, 1, 1, 0x40]);
+    assert_eq!(parse_type_bitmap(&bitmap).unwrap(), types);
+}
+
+#[test]
+fn test_resigning_keeps_current_signatures() {
+    let keys = [csk(Algorithm::EcdsaP256Sha256)];
+    let signed = Signer::new(&keys, NOW, VALIDITY).sign_zone(&parse(ZONE), None);
+
+    // A day later nothing is due for renewal, and only the changed RRset, its NSEC
+    // neighbours and the SOA get new signatures.
+    let later = Signer::new(&keys, NOW + 86400, VALIDITY);
+    assert!(!later.needs_refresh(&signed));
+    let changed = parse(&ZONE.replace(" 7 7200", " 8 7200").replace("192.0.2.4", "192.0.2.9"));
+    let resigned = later.sign_zone(&changed, Some(&signed));
+    let new_signatures: BTreeSet<(String, u16)> = of_type(&resigned, RRSIG)
+        .iter()
+        .map(|record| (record, as_rrsig(record).unwrap()))
+        .filter(|(_, rrsig)| rrsig.inception == NOW + 86400 - 3600)
+        .map(|(record, rrsig): (&&ResourceRecord, Rrsig)| (record.name.clone(), rrsig.type_covered))
+        .collect();
+    assert_eq!(
+        new_signatures,
+        BTreeSet::from([("example.com.".to_string(), 6), ("www.example.com.".to_string(), 1)])
+    );
+
+    // Close to expiry everything is renewed.
+    let late = Signer::new(&keys, NOW + VALIDITY - 3600, VALIDITY);
+    assert!(late.needs_refresh(&resigned));
+    let renewed = late.sign_zone(&resigned, Some(&resigned));
+    assert!(of_type(&renewed, RRSIG).iter().all(|record| as_rrsig(record).unwrap().expiration == NOW + 2 * VALIDITY - 3600));
+
+    // The signer's own records are removed again for writing the zone back.
+    assert_eq!(later.unsigned_zone(&resigned), changed);
+}
+
+/// Reads an uncompressed wire name from the start of `data`.
+fn read_name(data: &[u8]) -> (String, &[u8]) {
+    let mut name = String::new();
+    let mut pos = 0;
+    while data[pos] != 0 {
+        let length = data[pos] as usize;
+        name.push_str(std::str::from_utf8(&data[pos + 1..pos + 1 + length]).unwrap());
+        name.push('.');
+        pos += 1 + length;
+    }
+    (name, &data[pos + 1..])
+}
+
+#[test]
+fn test_nsec3_hash_vectors() {
+    // RFC 5155, appendix A.
+    let params = Nsec3Params { iterations: 12, salt: vec![0xaa, 0xbb, 0xcc, 0xdd], opt_out: false };
+    assert_eq!(params.hashed_label("example."), "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom");
+    assert_eq!(params.hashed_label("a.example."), "35mthgpgcu1qg68fab165klnsnk3dpvl");
+    assert_eq!(params.hashed_label("*.w.example."), "r53bq7cc2uvmubfu5ocmm6pers9tk9en");
+    assert_eq!(Nsec3Params::parse(&params.to_rdata()), Some(params));
+}
+
+const DENIAL_ZONE: &str = "\
+$ORIGIN example.com.
+@ IN SOA ns1.example.com. admin.example.com. 7 7200 3600 1209600 300
+@ IN NS ns1.example.com.
+ns1 IN A 192.0.2.1
+x.ent IN A 192.0.2.2
+*.wild IN TXT \"wildcard\"
+sub IN NS ns.sub.example.com.
+ns.sub IN A 192.0.2.53
+secure IN NS ns.sub.example.com.
+";
+
+/// Parses the denial test zone and gives the `secure` delegation a DS record.
+fn denial_zone() -> Zone {
+    let mut zone = parse(DENIAL_ZONE);
+    zone.records.push(ResourceRecord {
+        name: "secure.example.com.".to_string(),
+        ttl: 3600,
+        class: RecordClass::IN,
+        data: RecordData::Unknown { rtype: DS, data: vec![0x30, 0x39, 15, 2, 0xab] },
+    });
+    zone
+}
+
+/// An NSEC3 record as its owner hash, next hash and types, in lowercase base32hex.
+fn nsec3_fields(record: &ResourceRecord) -> (String, String, BTreeSet<u16>, bool) {
+    let RecordData::Unknown { data, .. } = &record.data else { unreachable!() };
+    let salt_end = 5 + data[4] as usize;
+    let next = &data[salt_end + 1..salt_end + 1 + data[salt_end] as usize];
+    let owner = record.name.split('.').next().unwrap().to_string();
+    let types = parse_type_bitmap(&data[salt_end + 1 + next.len()..]).unwrap();
+    (owner, BASE32HEX_NOPAD.encode(next).to_ascii_lowercase(), types, data[1] & 1 != 0)
+}
+
+/// Returns whether an NSEC3 record matches or covers the hash `label`.
+fn nsec3_covers(record: &ResourceRecord, label: &str) -> bool {
+    let (owner, next, _, _) = nsec3_fields(record);
+    if owner < next { owner.as_str() <= label && label < next.as_str() } else { owner.as_str() <= label || label < next.as_str() }
+}
+
+#[test]
+fn test_nsec3_chain() {
+    let keys = [csk(Algorithm::Ed25519)];
+    for opt_out in [false, true] {
+        let params = Nsec3Params { iterations: 0, salt: vec![0x12, 0x34], opt_out };
+        let signed = Signer::new(&keys, NOW, VALIDITY).with_nsec3(params.clone()).sign_zone(&denial_zone(), None);
+        assert!(of_type(&signed, NSEC).is_empty());
+        assert_eq!(of_type(&signed, NSEC3PARAM).len(), 1);
+        verify_signatures(&signed);
+
+        let chain: Vec<_> = of_type(&signed, NSEC3).into_iter().map(nsec3_fields).collect();
+        let owners: BTreeSet<&str> = chain.iter().map(|(owner, _, _, _)| owner.as_str()).collect();
+        let nexts: BTreeSet<&str> = chain.iter().map(|(_, next, _, _)| next.as_str()).collect();
+        assert_eq!(owners, nexts);
+        assert!(chain.iter().all(|(_, _, _, flag)| *flag == opt_out));
+
+        let types = |name: &str| chain.iter().find(|(owner, _, _, _)| *owner == params.hashed_label(name)).map(|fields| fields.2.clone());
+        assert_eq!(types("example.com."), Some(BTreeSet::from([2, 6, RRSIG, DNSKEY, NSEC3PARAM])));
+        assert_eq!(types("ent.example.com."), Some(BTreeSet::new()));
+        assert_eq!(types("wild.example.com."), Some(BTreeSet::new()));
+        assert_eq!(types("*.wild.example.com."), Some(BTreeSet::from([16, RRSIG])));
+        assert_eq!(types("secure.example.com."), Some(BTreeSet::from([2, DS, RRSIG])));
+        assert_eq!(types("ns.sub.example.com."), None);
+        // Opt-out leaves the unsigned delegation out of the chain.
+        assert_eq!(types("sub.example.com."), (!opt_out).then(|| BTreeSet::from([2])));
+    }
+}
+
+#[test]
+fn test_nsec_denial_records() {
+    let keys = [csk(Algorithm::Ed25519)];
+    let signed = Signer::new(&keys, NOW, VALIDITY).sign_zone(&denial_zone(), None);
+    let owners = |denial: Denial| -> Vec<String> {
+        denial_records(&signed, &denial).iter().map(|record| record.name.clone()).collect()
+    };
+
+    // The name falls between x.ent and ns1, and the wildcard *.example.com. between the
+    // apex and x.ent.
+    assert_eq!(owners(Denial::NxDomain("nope.example.com.")), ["x.ent.example.com.", "example.com."]);
+    assert_eq!(owners(Denial::NoData("ns1.example.com.")), ["ns1.example.com."]);
+    // The empty non-terminal is covered by the NSEC record before it.
+    assert_eq!(owners(Denial::NoData("ent.example.com.")), ["example.com."]);
+    assert_eq!(
+        owners(Denial::Wildcard { name: "a.wild.example.com.", wildcard: "*.wild.example.com." }),
+        ["*.wild.example.com."]
+    );
+    assert_eq!(
+        owners(Denial::WildcardNoData { name: "z.wild.example.com.", wildcard: "*.wild.example.com." }),
+        ["*.wild.example.com."]
+    );
+}
+
+#[test]
+fn test_nsec3_denial_records() {
+    let keys = [csk(Algorithm::Ed25519)];
+    let params = Nsec3Params { iterations: 1, salt: vec![0xab], opt_out: true };
+    let signed = Signer::new(&keys, NOW, VALIDITY).with_nsec3(params.clone()).sign_zone(&denial_zone(), None);
+    let hash = |name: &str| params.hashed_label(name);
+    let matches = |record: &ResourceRecord, name: &str| record.name.starts_with(&format!("{}.", hash(name)));
+
+    // Closest encloser, next closer name and wildcard at the closest encloser.
+    let records = denial_records(&signed, &Denial::NxDomain("a.b.example.com."));
+    assert!(records.iter().any(|record| matches(record, "example.com.")));
+    assert!(records.iter().any(|record| nsec3_covers(record, &hash("b.example.com."))));
+    assert!(records.iter().any(|record| nsec3_covers(record, &hash("*.example.com."))));
+    assert!(records.len() <= 3);
+
+    let records = denial_records(&signed, &Denial::NoData("ent.example.com."));
+    assert!(records.len() == 1 && matches(records[0], "ent.example.com."));
+
+    let records = denial_records(&signed, &Denial::Wildcard { name: "a.b.wild.example.com.", wildcard: "*.wild.example.com." });
+    assert!(records.len() == 1 && nsec3_covers(records[0], &hash("b.wild.example.com.")));
+
+    // The opted-out delegation has no record of its own: the proof shows the closest
+    // encloser and an opt-out record covering the delegation.
+    let records = denial_records(&signed, &Denial::NoData("sub.example.com."));
+    assert!(records.iter().any(|record| matches(record, "example.com.")));
+    assert!(records.iter().any(|record| nsec3_covers(record, &hash("sub.example.com.")) && nsec3_fields(record).3));
+}
+
+/// Returns whether an NSEC record matches or covers `name`.
+fn nsec_covers(record: &ResourceRecord, name: &str) -> bool {
+    let RecordData::Unknown { data, .. } = &record.data else { unreachable!() };
+    let (next, _) = read_name(data);
+    let after_owner = compare_names(&record.name, name).is_le();
+    match compare_names(&record.name, &next).is_lt() {
+        true => after_owner && compare_names(name, &next).is_lt(),
+        // The last record of the chain wraps around to the apex.
+        false => after_owner || compare_names(name, &next).is_lt(),
+    }
+}
+
+#[test]
+fn test_denial_records_from_the_zone_index() {
+    let hosts: String = (0..300).map(|i| format!("host{} IN A 192.0.2.{}\n", i, i % 250)).collect();
+    let zone = parse(&format!("{}{}", ZONE, hosts));
+    let keys = [csk(Algorithm::Ed25519)];
+    let params = Nsec3Params { iterations: 0, salt: Vec::new(), opt_out: false };
+    for signer in [Signer::new(&keys, NOW, VALIDITY), Signer::new(&keys, NOW, VALIDITY).with_nsec3(params.clone())] {
+        let mut db = InMemoryDatabase::new();
+        db.add_zone(signer.sign_zone(&zone, None)).unwrap();
+        let signed = db.get_zone("example.com.").unwrap();
+        let index = db.zone_index("example.com.").unwrap();
+        let nsec3 = !of_type(signed, NSEC3).is_empty();
+
+        // Names before the first owner, after the last one and between the others.
+        for name in ["0.example.com.", "zzz.example.com.", "host150a.example.com.", "a.host7.example.com."] {
+            let records = index.denial_records(signed, &Denial::NxDomain(name));
+            assert_eq!(records, denial_records(signed, &Denial::NxDomain(name)));
+            match nsec3 {
+                true => assert!(records.iter().any(|record| nsec3_covers(record, &params.hashed_label(name)))),
+                false => assert!(nsec_covers(records[0], name), "{} is not covered by {}", name, records[0].name),
+            }
+        }
+        let records = index.denial_records(signed, &Denial::NoData("host42.example.com."));
+        assert_eq!(records.len(), 1);
+        assert!(records[0].name.starts_with(&if nsec3 { params.hashed_label("host42.example.com.") } else { "host42.".to_string() }));
+    }
+}
+
+#[test]
+fn test_signed_zone_survives_zone_file_round_trip() {
+    let keys = [csk(Algorithm::EcdsaP256Sha256)];
+    for signer in [Signer::new(&keys, NOW, VALIDITY), Signer::new(&keys, NOW, VALIDITY).with_nsec3(Nsec3Params::default())] {
+        let signed = signer.sign_zone(&parse(ZONE), None);
+        let reparsed = parse(&ZoneWriter::write_zone(&signed));
+        assert_eq!(reparsed.records.len(), signed.records.len());
+        verify_signatures(&reparsed);
+        assert!(reparsed.validate().is_empty());
+    }
+}
+
+#[test]
+fn test_ds_digests() {
+    // The example of RFC 4034, section 5.4, and RFC 4509, section 2.3.
+    let public_key = "AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvxegXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw==";
+    let dnskey = [&[1, 0, 3, 5][..], &BASE64.decode(public_key.as_bytes()).unwrap()].concat();
+    let sha1 = ds_rdata("dskey.example.com.", &dnskey, DIGEST_SHA1).unwrap();
+    assert_eq!(sha1[..4], [0xec, 0x45, 5, 1]);
+    assert_eq!(HEXUPPER.encode(&sha1[4..]), "2BB183AF5F22588179A53B0A98631FAD1A292118");
+    let sha256 = ds_rdata("DSKEY.example.com", &dnskey, DIGEST_SHA256).unwrap();
+    assert_eq!(HEXUPPER.encode(&sha256[4..]), "D4B7D520E7BB5F0F67674A0CCEB1E3E0614B93C4F9E99B8383F6A1E4469DA50A");
+    assert!(ds_rdata("dskey.example.com.", &dnskey, 4).is_none());
+}
+
+const TTLS: ZoneTtls = ZoneTtls { dnskey: 300, max: 600 };
+
+fn key_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("dns-keys-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rollover_policy(zsk_rollover: ZskRollover) -> KeyPolicy {
+    KeyPolicy {
+        algorithm: Algorithm::Ed25519,
+        zsk_lifetime: 1000,
+        ksk_lifetime: 0,
+        zsk_rollover,
+        propagation_delay: 10,
+        parent_ds_ttl: 100,
+        parent_propagation_delay: 10,
+    }
+}
+
+fn tags(keys: &[SigningKey]) -> BTreeSet<u16> {
+    keys.iter().map(SigningKey::key_tag).collect()
+}
+
+#[test]
+fn test_zsk_pre_publish_rollover() {
+    let dir = key_dir("pre-publish");
+    let policy = rollover_policy(ZskRollover::PrePublish);
+    let mut store = KeyStore::open(&dir, "example.com.").unwrap();
+    assert!(store.advance(&policy, TTLS, NOW).unwrap());
+    let (signing, published) = store.zone_keys(NOW);
+    assert_eq!(signing.len(), 2);
+    assert!(published.is_empty());
+    assert_eq!(store.ds_records(NOW).len(), 1);
+    let old_zsk = signing.iter().find(|key| !key.is_ksk()).unwrap().key_tag();
+    assert!(!store.advance(&policy, TTLS, NOW + 999).unwrap());
+
+    // The new key is published first and signs once the DNSKEY RRset has expired from
+    // caches; the old one stays until its signatures have expired.
+    assert!(store.advance(&policy, TTLS, NOW + 1000).unwrap());
+    let (signing, published) = store.zone_keys(NOW + 1000);
+    assert!(tags(&signing).contains(&old_zsk));
+    assert_eq!(published.len(), 1);
+    let new_zsk = published[0].key_tag();
+    let (signing, published) = store.zone_keys(NOW + 1310);
+    assert!(tags(&signing).contains(&new_zsk) && !tags(&signing).contains(&old_zsk));
+    assert_eq!(tags(&published), BTreeSet::from([old_zsk]));
+    assert_eq!(store.zone_keys(NOW + 1919).1.len(), 1);
+    assert!(store.zone_keys(NOW + 1920).1.is_empty());
+
+    // A restarted server picks up the rollover where it was.
+    let reopened = KeyStore::open(&dir, "example.com.").unwrap();
+    let states = |store: &KeyStore| store.keys().iter().map(|managed| managed.state.clone()).collect::<Vec<_>>();
+    assert_eq!(states(&reopened), states(&store));
+    assert_eq!(tags(&reopened.zone_keys(NOW + 1310).0), tags(&store.zone_keys(NOW + 1310).0));
+
+    assert!(store.advance(&policy, TTLS, NOW + 1920).unwrap());
+    assert_eq!(store.keys().len(), 2);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+}
+
+#[test]
+fn test_zsk_double_signature_rollover() {
+    let dir = key_dir("double-signature");
+    let policy = rollover_policy(ZskRollover::DoubleSignature);
+    let mut store = KeyStore::open(&dir, "example.com.").unwrap();
+    store.advance(&policy, TTLS, NOW).unwrap();
+    store.advance(&policy, TTLS, NOW + 1000).unwrap();
+    let (signing, published) = store.zone_keys(NOW + 1000);
+    assert_eq!(signing.iter().filter(|key| !key.is_ksk()).count(), 2);
+    assert!(published.is_empty());
+    assert_eq!(store.zone_keys(NOW + 1609).0.len(), 3);
+    assert_eq!(store.zone_keys(NOW + 1610).0.len(), 2);
+    assert!(store.zone_keys(NOW + 1610).1.is_empty());
+}
+
+#[test]
+fn test_ksk_double_ds_rollover() {
+    let dir = key_dir("double-ds");
+    let policy = KeyPolicy { zsk_lifetime: 0, ksk_lifetime: 5000, ..rollover_policy(ZskRollover::PrePublish) };
+    let mut store = KeyStore::open(&dir, "example.com.").unwrap();
+    store.advance(&policy, TTLS, NOW).unwrap();
+    let before = tags(&store.zone_keys(NOW).0);
+
+    // The new DS record goes to the parent first and the zone keeps its keys until the
+    // parent's DS RRset has expired from caches.
+    assert!(store.advance(&policy, TTLS, NOW + 5000).unwrap());
+    assert_eq!(store.ds_records(NOW + 5000).len(), 2);
+    assert_eq!(tags(&store.zone_keys(NOW + 5109).0), before);
+    assert!(store.zone_keys(NOW + 5109).1.is_empty());
+
+    let (signing, published) = store.zone_keys(NOW + 5110);
+    assert_eq!(signing.len(), 2);
+    assert!(published.is_empty());
+    let new_ksk = signing.iter().find(|key| key.is_ksk()).unwrap();
+    assert!(!before.contains(&new_ksk.key_tag()));
+
+    // The old DS record is kept until the old DNSKEY RRset has expired from caches.
+    assert_eq!(store.ds_records(NOW + 5419).len(), 2);
+    assert_eq!(store.ds_records(NOW + 5420), vec![new_ksk.ds_record("example.com.")]);
+    assert!(store.advance(&policy, TTLS, NOW + 5420).unwrap());
+    assert_eq!(store.keys().len(), 2);
+}
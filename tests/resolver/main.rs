@@ -0,0 +1,581 @@
+use dont_need_stability::acl::AclConfig;
+use dont_need_stability::app::{App, ZoneUpdate};
+use dont_need_stability::config::Config;
+use dont_need_stability::db::InMemoryDatabase;
+use dont_need_stability::resolver::cache::{Cache, Cached, Key, STALE_TTL};
+use dont_need_stability::resolver::forward::{ForwardZone, Protocol, Upstream};
+use dont_need_stability::resolver::policy::{Action, PolicyConfig, PolicyOverride};
+use dont_need_stability::resolver::{Resolver, ResolverConfig};
+use dont_need_stability::zone_parser::ZoneParser;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::rdata::NULL;
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::serialize::binary::BinEncodable;
+
+/// The port every server of the test hierarchy listens on, each on its own loopback
+/// address.
+const PORT: u16 = 5440;
+
+const ROOT: &str = "\
+$ORIGIN .
+@ IN SOA a.root-servers.test. admin.root-servers.test. 1 7200 3600 1209600 300
+@ IN NS a.root-servers.test.
+test. IN NS ns1.test.
+ns1.test. IN A 127.0.0.3
+";
+
+const TLD: &str = "\
+$ORIGIN test.
+@ IN SOA ns1.test. admin.test. 1 7200 3600 1209600 300
+@ IN NS ns1.test.
+ns1 IN A 127.0.0.3
+example IN NS ns1.example.test.
+ns1.example IN A 127.0.0.4
+; No glue: the address of ns.example.test. must be resolved first.
+nog IN NS ns.example.test.
+evil IN NS ns.evil.test.
+ns.evil IN A 127.0.0.5
+";
+
+const EXAMPLE: &str = "\
+$ORIGIN example.test.
+@ IN SOA ns1.example.test. admin.example.test. 1 7200 3600 1209600 300
+@ IN NS ns1.example.test.
+ns1 IN A 127.0.0.4
+ns IN A 127.0.0.4
+www IN A 192.0.2.1
+alias IN CNAME www.example.test.
+ext IN CNAME www.nog.test.
+";
+
+const NOG: &str = "\
+$ORIGIN nog.test.
+@ IN SOA ns.example.test. admin.nog.test. 1 7200 3600 1209600 300
+@ IN NS ns.example.test.
+www IN A 192.0.2.2
+";
+
+/// A zone that is not delegated from the root, and can only be reached by forwarding.
+const CORP: &str = "\
+$ORIGIN corp.internal.
+@ IN SOA ns1.example.test. admin.corp.internal. 1 7200 3600 1209600 300
+@ IN NS ns1.example.test.
+host IN A 10.1.0.1
+www IN CNAME www.example.test.
+";
+
+fn parse(content: &str, origin: &str) -> dont_need_stability::db::Zone {
+    ZoneParser::parse_zone_file(content, origin.to_string(), 3600).unwrap()
+}
+
+fn start_server(addr: SocketAddr, zones: &[(&str, &str)], config: Config) -> Sender<ZoneUpdate> {
+    let mut db = InMemoryDatabase::new();
+    for (content, origin) in zones {
+        db.add_zone(parse(content, origin)).unwrap();
+    }
+    let mut app = App::with_config(Box::new(db), addr, config);
+    let zone_sender = app.zone_sender();
+    thread::spawn(move || app.run());
+    zone_sender
+}
+
+/// Starts the root, TLD and authoritative servers once, plus a server that adds records
+/// it has no authority over to its answers. Returns the sender for the zones of the
+/// authoritative server.
+fn hierarchy() -> &'static Sender<ZoneUpdate> {
+    static HIERARCHY: OnceLock<Sender<ZoneUpdate>> = OnceLock::new();
+    HIERARCHY.get_or_init(|| {
+        let at = |last: u8| SocketAddr::new(Ipv4Addr::new(127, 0, 0, last).into(), PORT);
+        start_server(at(2), &[(ROOT, ".")], Config::default());
+        start_server(at(3), &[(TLD, "test.")], Config::default());
+        let sender = start_server(at(4), &[(EXAMPLE, "example.test."), (NOG, "nog.test."), (CORP, "corp.internal.")], Config::default());
+        start_poisoner(at(5));
+        thread::sleep(Duration::from_millis(100));
+        sender
+    })
+}
+
+/// Answers every question with an address, followed by a forged record for
+/// `www.example.test.`.
+fn start_poisoner(addr: SocketAddr) {
+    let socket = UdpSocket::bind(addr).unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok((len, src)) = socket.recv_from(&mut buf) {
+            let Ok(query) = Message::from_vec(&buf[..len]) else {
+                continue;
+            };
+            let mut response = query.clone();
+            response.set_message_type(MessageType::Response).set_authoritative(true);
+            let name = query.queries()[0].name().clone();
+            response.add_answer(Record::from_rdata(name, 300, RData::A(Ipv4Addr::new(192, 0, 2, 66))));
+            let forged = Name::from_utf8("www.example.test.").unwrap();
+            response.add_answer(Record::from_rdata(forged, 300, RData::A(Ipv4Addr::new(198, 51, 100, 66))));
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src);
+        }
+    });
+}
+
+fn resolver_config(allow: &str) -> Config {
+    Config {
+        resolver: Some(ResolverConfig {
+            allow_recursion: vec![allow.parse().unwrap()],
+            root_hints: vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))],
+            port: PORT,
+            timeout_ms: 500,
+            ..ResolverConfig::default()
+        }),
+        ..Config::default()
+    }
+}
+
+/// Starts a resolver with a local zone of its own.
+fn start_resolver(port: u16, config: Config) -> SocketAddr {
+    hierarchy();
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), port);
+    let local = "$ORIGIN local.\n@ IN SOA ns.local. admin.local. 1 7200 3600 1209600 300\nhost IN A 10.0.0.1\n";
+    start_server(addr, &[(local, "local.")], config);
+    thread::sleep(Duration::from_millis(100));
+    addr
+}
+
+fn forward_config(zone: &str, upstreams: Vec<Upstream>) -> Config {
+    let mut config = resolver_config("127.0.0.0/8");
+    let resolver = config.resolver.as_mut().unwrap();
+    resolver.forward.push(ForwardZone { zone: zone.to_string(), upstreams });
+    config
+}
+
+fn upstream(address: &str, protocol: Protocol, timeout_ms: Option<u64>) -> Upstream {
+    Upstream { address: address.parse().unwrap(), protocol, timeout_ms }
+}
+
+fn resolve(addr: SocketAddr, name: &str, record_type: RecordType) -> Message {
+    send_query("127.0.0.1:0", addr, name, record_type, Duration::from_secs(5)).unwrap()
+}
+
+/// Sends a recursive query from `client`, returning `None` if no response arrives in time.
+fn send_query(client: &str, addr: SocketAddr, name: &str, record_type: RecordType, timeout: Duration) -> Option<Message> {
+    let mut msg = Message::new();
+    msg.set_id(7)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::from_utf8(name).unwrap(), record_type));
+    let socket = UdpSocket::bind(client).unwrap();
+    socket.set_read_timeout(Some(timeout)).unwrap();
+    socket.send_to(&msg.to_bytes().unwrap(), addr).unwrap();
+    let mut buf = [0; 4096];
+    let (len, _) = socket.recv_from(&mut buf).ok()?;
+    Some(Message::from_vec(&buf[..len]).unwrap())
+}
+
+fn addresses(response: &Message) -> Vec<Ipv4Addr> {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            Some(RData::A(addr)) => Some(*addr),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_resolves_iteratively_from_the_root() {
+    let addr = start_resolver(5441, resolver_config("127.0.0.0/8"));
+
+    let response = resolve(addr, "www.example.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.recursion_available() && !response.authoritative());
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+
+    // Local zones are still answered authoritatively.
+    let response = resolve(addr, "host.local.", RecordType::A);
+    assert!(response.authoritative());
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(10, 0, 0, 1)]);
+}
+
+#[test]
+fn test_follows_cnames_and_delegations_without_glue() {
+    let addr = start_resolver(5442, resolver_config("127.0.0.0/8"));
+
+    let response = resolve(addr, "alias.example.test.", RecordType::A);
+    assert_eq!(response.answers().len(), 2);
+    assert_eq!(response.answers()[0].record_type(), RecordType::CNAME);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+
+    // The CNAME leads to a zone whose name server has no glue.
+    let response = resolve(addr, "ext.example.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 2)]);
+}
+
+#[test]
+fn test_answers_and_negative_answers_are_cached() {
+    let addr = start_resolver(5443, resolver_config("127.0.0.0/8"));
+
+    let response = resolve(addr, "later.example.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    let soa = &response.name_servers()[0];
+    assert_eq!(soa.record_type(), RecordType::SOA);
+    assert!(soa.ttl() <= 300);
+    let response = resolve(addr, "www.example.test.", RecordType::MX);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+    let ttl = resolve(addr, "www.example.test.", RecordType::A).answers()[0].ttl();
+
+    // The name appears in the zone, but the resolver keeps the negative answer until it
+    // expires, and counts TTLs down.
+    let zone = EXAMPLE.replace(" 1 7200", " 2 7200") + "later IN A 192.0.2.9\n";
+    hierarchy().send(ZoneUpdate::Replace(parse(&zone, "example.test."))).unwrap();
+    thread::sleep(Duration::from_millis(1100));
+    let response = resolve(addr, "later.example.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert!(resolve(addr, "www.example.test.", RecordType::A).answers()[0].ttl() < ttl);
+}
+
+#[test]
+fn test_ignores_records_outside_the_servers_authority() {
+    let addr = start_resolver(5444, resolver_config("127.0.0.0/8"));
+
+    let response = resolve(addr, "www.evil.test.", RecordType::A);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 66)]);
+    let response = resolve(addr, "www.example.test.", RecordType::A);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+}
+
+#[test]
+fn test_refuses_recursion_to_other_clients() {
+    let addr = start_resolver(5445, resolver_config("192.0.2.0/24"));
+
+    let response = resolve(addr, "www.example.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert!(response.answers().is_empty());
+}
+
+#[test]
+fn test_recursion_acl_applies_on_top_of_allow_recursion() {
+    let mut config = resolver_config("127.0.0.0/8");
+    config.acls = vec![AclConfig { name: "trusted".to_string(), allow: vec!["127.0.0.1".parse().unwrap()], deny: vec![] }];
+    config.access.recursion = Some(vec!["trusted".to_string()]);
+    let addr = start_resolver(5457, config);
+
+    let response = send_query("127.0.0.1:0", addr, "www.example.test.", RecordType::A, Duration::from_secs(5)).unwrap();
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    let response = send_query("127.0.0.9:0", addr, "www.example.test.", RecordType::A, Duration::from_secs(5)).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+
+    // The local zones are still answered: the recursion ACL only applies to the resolver.
+    let response = send_query("127.0.0.9:0", addr, "host.local.", RecordType::A, Duration::from_secs(5)).unwrap();
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(10, 0, 0, 1)]);
+}
+
+#[test]
+fn test_forwards_names_in_configured_domains() {
+    // The first upstream never answers.
+    let _silent = UdpSocket::bind("127.0.0.1:5447").unwrap();
+    let upstreams = vec![upstream("127.0.0.1:5447", Protocol::Udp, Some(200)), upstream("127.0.0.4:5440", Protocol::Udp, None)];
+    let addr = start_resolver(5446, forward_config("corp.internal.", upstreams));
+
+    let response = resolve(addr, "HOST.corp.internal.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.recursion_available() && !response.authoritative());
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(10, 1, 0, 1)]);
+
+    // A CNAME out of the forwarded domain is followed from the root servers.
+    let response = resolve(addr, "www.corp.internal.", RecordType::A);
+    assert_eq!(response.answers()[0].record_type(), RecordType::CNAME);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+
+    // Other names are still resolved from the root servers, which do not know the
+    // forwarded domain.
+    assert_eq!(resolve(addr, "www.example.test.", RecordType::A).response_code(), ResponseCode::NoError);
+    let response = resolve(start_resolver(5451, resolver_config("127.0.0.0/8")), "host.corp.internal.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+}
+
+#[test]
+fn test_forwarding_skips_upstreams_that_stop_answering() {
+    let resolver = start_resolver(5448, resolver_config("127.0.0.0/8"));
+    let _silent = UdpSocket::bind("127.0.0.1:5449").unwrap();
+    let upstreams = vec![upstream("127.0.0.1:5449", Protocol::Udp, Some(300)), upstream(&resolver.to_string(), Protocol::Tcp, None)];
+    let addr = start_resolver(5450, forward_config(".", upstreams));
+
+    // The silent upstream is tried first until it has failed three times in a row.
+    for name in ["n1.example.test.", "n2.example.test.", "n3.example.test."] {
+        let start = Instant::now();
+        assert_eq!(resolve(addr, name, RecordType::A).response_code(), ResponseCode::NXDomain);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+    let start = Instant::now();
+    let response = resolve(addr, "www.example.test.", RecordType::A);
+    assert!(start.elapsed() < Duration::from_millis(300));
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+}
+
+const RPZ: &str = "\
+$ORIGIN rpz.test.
+@ IN SOA localhost. admin.rpz.test. 1 7200 3600 1209600 300
+@ IN NS localhost.
+alias.example.test IN CNAME .
+*.evil.test IN CNAME *.
+ns.example.test IN CNAME rpz-drop.
+ns1.example.test IN CNAME rpz-passthru.
+local.example.test IN A 10.9.9.9
+local.example.test IN TXT \"walled garden\"
+redirect.example.test IN CNAME www.nog.test.
+32.2.2.0.192.rpz-ip IN CNAME .
+";
+
+#[test]
+fn test_applies_response_policies() {
+    let dir = std::env::temp_dir().join(format!("dns-policy-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("rpz.test.zone"), RPZ).unwrap();
+    std::fs::write(dir.join("ads.txt"), "# Ad domains\nexample.test\n").unwrap();
+    std::fs::write(dir.join("hosts"), "127.0.0.1 localhost\n0.0.0.0 host.nog.test # tracker\n").unwrap();
+    let policy = |name: &str, kind: &str, file: &str, action: Option<Action>| {
+        let mut policy = PolicyConfig { name: name.to_string(), rpz: None, hosts: None, domains: None, action };
+        let path = Some(dir.join(file));
+        match kind {
+            "rpz" => policy.rpz = path,
+            "hosts" => policy.hosts = path,
+            _ => policy.domains = path,
+        }
+        policy
+    };
+    let mut config = resolver_config("127.0.0.0/8");
+    let resolver = config.resolver.as_mut().unwrap();
+    resolver.policy = vec![
+        policy("rpz.test", "rpz", "rpz.test.zone", None),
+        policy("ads", "domains", "ads.txt", None),
+        policy("trackers", "hosts", "hosts", Some(Action::Nodata)),
+    ];
+    resolver.policy_override =
+        vec![PolicyOverride { clients: vec!["127.0.0.9".parse().unwrap()], policies: vec!["ads".to_string()], action: Action::Passthru }];
+    let addr = start_resolver(5454, config);
+
+    // QNAME triggers.
+    assert_eq!(resolve(addr, "alias.example.test.", RecordType::A).response_code(), ResponseCode::NXDomain);
+    let response = resolve(addr, "www.evil.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+    let timeout = Duration::from_millis(500);
+    assert!(send_query("127.0.0.1:0", addr, "ns.example.test.", RecordType::A, timeout).is_none());
+    let response = resolve(addr, "local.example.test.", RecordType::A);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(10, 9, 9, 9)]);
+    assert_eq!(response.answers()[0].name().to_string(), "local.example.test.");
+    assert!(resolve(addr, "local.example.test.", RecordType::MX).answers().is_empty());
+    // The target of a rewrite is resolved without applying the policies again.
+    let response = resolve(addr, "redirect.example.test.", RecordType::A);
+    assert_eq!(response.answers()[0].record_type(), RecordType::CNAME);
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 2)]);
+
+    // Blocklists, and passthru taking precedence over the policies that follow.
+    assert_eq!(resolve(addr, "www.example.test.", RecordType::A).response_code(), ResponseCode::NXDomain);
+    assert_eq!(addresses(&resolve(addr, "ns1.example.test.", RecordType::A)), vec![Ipv4Addr::new(127, 0, 0, 4)]);
+    let response = resolve(addr, "host.nog.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+    assert_eq!(resolve(addr, "sub.host.nog.test.", RecordType::A).response_code(), ResponseCode::NXDomain);
+
+    // Answer address triggers, also behind a CNAME.
+    assert_eq!(resolve(addr, "www.nog.test.", RecordType::A).response_code(), ResponseCode::NXDomain);
+    let response = resolve(addr, "ext.example.test.", RecordType::A);
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+    // The client override lets the ad domains through, but not the other policies.
+    let response = send_query("127.0.0.9:0", addr, "www.example.test.", RecordType::A, timeout).unwrap();
+    assert_eq!(addresses(&response), vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    let response = send_query("127.0.0.9:0", addr, "alias.example.test.", RecordType::A, timeout).unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Answers every question with an address with a TTL of a second, while `answering` is
+/// set. Counts the questions.
+fn start_upstream(addr: &str) -> (Arc<AtomicBool>, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind(addr).unwrap();
+    let answering = Arc::new(AtomicBool::new(true));
+    let queries = Arc::new(AtomicUsize::new(0));
+    let (answering_cloned, queries_cloned) = (Arc::clone(&answering), Arc::clone(&queries));
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok((len, src)) = socket.recv_from(&mut buf) {
+            let Ok(query) = Message::from_vec(&buf[..len]) else {
+                continue;
+            };
+            queries_cloned.fetch_add(1, Ordering::SeqCst);
+            if !answering_cloned.load(Ordering::SeqCst) {
+                continue;
+            }
+            let mut response = query.clone();
+            response.set_message_type(MessageType::Response).set_recursion_available(true);
+            let name = query.queries()[0].name().clone();
+            response.add_answer(Record::from_rdata(name, 1, RData::A(Ipv4Addr::new(192, 0, 2, 77))));
+            let _ = socket.send_to(&response.to_bytes().unwrap(), src);
+        }
+    });
+    (answering, queries)
+}
+
+fn forwarding_resolver(upstream_addr: &str) -> Resolver {
+    let config = forward_config(".", vec![upstream(upstream_addr, Protocol::Udp, Some(200))]);
+    Resolver::new(config.resolver.unwrap())
+}
+
+#[test]
+fn test_serves_stale_answers_when_upstreams_fail() {
+    let (answering, queries) = start_upstream("127.0.0.1:5452");
+    let resolver = forwarding_resolver("127.0.0.1:5452");
+
+    let resolution = resolver.resolve("www.example.org.", RecordType::A, false);
+    assert_eq!(resolution.code, ResponseCode::NoError);
+    assert_eq!(resolution.answers[0].ttl(), 1);
+
+    answering.store(false, Ordering::SeqCst);
+    thread::sleep(Duration::from_millis(1100));
+    let resolution = resolver.resolve("www.example.org.", RecordType::A, false);
+    assert_eq!(resolution.code, ResponseCode::NoError);
+    assert_eq!(resolution.answers[0].ttl(), STALE_TTL);
+    assert_eq!(resolver.stats().stale_hits, 1);
+
+    // The stale answer is served on without asking the upstream again.
+    let asked = queries.load(Ordering::SeqCst);
+    assert_eq!(resolver.resolve("www.example.org.", RecordType::A, false).code, ResponseCode::NoError);
+    assert_eq!(queries.load(Ordering::SeqCst), asked);
+    assert_eq!(resolver.resolve("www.example.net.", RecordType::A, false).code, ResponseCode::ServFail);
+}
+
+#[test]
+fn test_prefetches_popular_answers_before_they_expire() {
+    let (_, queries) = start_upstream("127.0.0.1:5453");
+    let resolver = forwarding_resolver("127.0.0.1:5453");
+
+    resolver.resolve("www.example.org.", RecordType::A, false);
+    resolver.resolve("www.example.org.", RecordType::A, false);
+    resolver.prefetch();
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+    // Used again when less than a tenth of its TTL is left, the answer is refreshed.
+    thread::sleep(Duration::from_millis(950));
+    resolver.resolve("www.example.org.", RecordType::A, false);
+    resolver.prefetch();
+    assert_eq!(queries.load(Ordering::SeqCst), 2);
+    let stats = resolver.stats();
+    assert_eq!((stats.hits, stats.misses, stats.prefetches), (2, 1, 1));
+    assert_eq!(resolver.resolve("www.example.org.", RecordType::A, false).answers[0].ttl(), 1);
+}
+
+fn record(name: &str, ttl: u32) -> Record {
+    Record::from_rdata(Name::from_utf8(name).unwrap(), ttl, RData::A(Ipv4Addr::new(192, 0, 2, 1)))
+}
+
+fn key(name: &str) -> Key {
+    Key::new(name, RecordType::A, false)
+}
+
+fn ttls(cached: Option<Cached>) -> Vec<u32> {
+    match cached {
+        Some(Cached::Records(records)) => records.iter().map(Record::ttl).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[test]
+fn test_cache_expires_entries_and_keeps_glue_apart() {
+    let mut cache = Cache::new(10, 0);
+    let now = Instant::now();
+
+    cache.insert(&[record("a.test.", 60)], false, false, now);
+    assert_eq!(ttls(cache.get(&key("A.test"), false, now + Duration::from_secs(20))), vec![40]);
+    assert!(cache.get(&key("a.test."), false, now + Duration::from_secs(60)).is_none());
+    assert!(cache.is_empty());
+
+    // Glue is only used to find servers, and never replaces authoritative data.
+    cache.insert(&[record("ns.test.", 60)], true, false, now);
+    assert!(cache.get(&key("ns.test."), false, now).is_none());
+    assert!(cache.get(&key("ns.test."), true, now).is_some());
+    cache.insert(&[record("a.test.", 60)], false, false, now);
+    cache.insert(&[record("a.test.", 600)], true, false, now);
+    assert_eq!(ttls(cache.get(&key("a.test."), true, now)), vec![60]);
+
+    // Aliases are found whatever the type asked for.
+    let alias = Record::from_rdata(Name::from_utf8("alias.test.").unwrap(), 60, RData::CNAME(Name::from_utf8("a.test.").unwrap()));
+    cache.insert(std::slice::from_ref(&alias), false, false, now);
+    assert_eq!(cache.get(&key("alias.test."), false, now), Some(Cached::Records(vec![alias])));
+}
+
+#[test]
+fn test_cache_keys_answers_with_signatures_apart() {
+    let mut cache = Cache::new(10, 0);
+    let now = Instant::now();
+    let rrsig = Record::from_rdata(
+        Name::from_utf8("a.test.").unwrap(),
+        60,
+        RData::Unknown { code: 46, rdata: NULL::with(vec![0, 1, 13, 2, 0, 0, 0, 60]) },
+    );
+    let signed = Key::new("a.test.", RecordType::A, true);
+
+    cache.insert(&[record("a.test.", 60), rrsig.clone()], false, false, now);
+    assert_eq!(ttls(cache.get(&key("a.test."), false, now)), vec![60]);
+    assert!(cache.get(&signed, false, now).is_none());
+    cache.insert(&[record("a.test.", 60), rrsig], false, true, now);
+    assert_eq!(ttls(cache.get(&signed, false, now)).len(), 2);
+    assert_eq!(ttls(cache.get(&key("a.test."), false, now)).len(), 1);
+}
+
+#[test]
+fn test_cache_evicts_least_recently_used_entries() {
+    let mut cache = Cache::new(2, 0);
+    let now = Instant::now();
+
+    cache.insert(&[record("a.test.", 600)], false, false, now);
+    cache.insert(&[record("b.test.", 60)], false, false, now);
+    assert!(cache.get(&key("a.test."), false, now).is_some());
+    cache.insert(&[record("c.test.", 60)], false, false, now);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(&key("b.test."), false, now).is_none());
+    assert!(cache.get(&key("a.test."), false, now).is_some());
+    assert!(cache.get(&key("c.test."), false, now).is_some());
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions, stats.entries), (3, 1, 1, 2));
+}
+
+#[test]
+fn test_cache_serves_stale_entries_within_their_window() {
+    let mut cache = Cache::new(10, 3600);
+    let now = Instant::now();
+    let at = |secs: u64| now + Duration::from_secs(secs);
+
+    cache.insert(&[record("a.test.", 60)], false, false, now);
+    assert!(cache.get(&key("a.test."), false, at(120)).is_none());
+    assert_eq!(ttls(cache.get_stale(&key("a.test."), at(120))), vec![STALE_TTL]);
+    assert_eq!(ttls(cache.get(&key("a.test."), false, at(130))), vec![20]);
+    assert!(cache.get(&key("a.test."), false, at(150)).is_none());
+    assert!(cache.get_stale(&key("a.test."), at(3700)).is_none());
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_cache_queues_popular_entries_for_prefetch() {
+    let mut cache = Cache::new(10, 0);
+    let now = Instant::now();
+
+    cache.insert(&[record("a.test.", 100), record("b.test.", 100)], false, false, now);
+    cache.get(&key("a.test."), false, now);
+    cache.get(&key("b.test."), false, now + Duration::from_secs(95));
+    assert!(cache.take_prefetches().is_empty());
+    cache.get(&key("a.test."), false, now + Duration::from_secs(95));
+    cache.get(&key("a.test."), false, now + Duration::from_secs(96));
+    assert_eq!(cache.take_prefetches(), vec![key("a.test.")]);
+    assert_eq!(cache.stats().prefetches, 1);
+}
@@ -0,0 +1,213 @@
+use dont_need_stability::acl::Access;
+use dont_need_stability::app::App;
+use dont_need_stability::config::Config;
+use dont_need_stability::db::InMemoryDatabase;
+use dont_need_stability::rrl::{Decision, RateLimitConfig, RateLimiter, ResponseClass};
+use dont_need_stability::server::Server;
+use dont_need_stability::zone_parser::ZoneParser;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+use trust_dns_proto::op::{Message, MessageType, Query, ResponseCode};
+use trust_dns_proto::rr::rdata::SOA;
+use trust_dns_proto::rr::{Name, RData, Record, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+fn response(name: &str, code: ResponseCode, answer: bool) -> Message {
+    let mut msg = Message::new();
+    msg.set_message_type(MessageType::Response)
+        .set_response_code(code)
+        .add_query(Query::query(Name::from_utf8(name).unwrap(), RecordType::A));
+    if answer {
+        msg.add_answer(Record::from_rdata(Name::from_utf8(name).unwrap(), 300, RData::A(Ipv4Addr::new(192, 0, 2, 1))));
+    } else {
+        let soa = SOA::new(Name::from_utf8("ns1.example.com.").unwrap(), Name::from_utf8("admin.example.com.").unwrap(), 1, 7200, 3600, 1209600, 300);
+        msg.add_name_server(Record::from_rdata(Name::from_utf8("example.com.").unwrap(), 300, RData::SOA(soa)));
+    }
+    msg
+}
+
+fn client(last: u32) -> IpAddr {
+    IpAddr::V4(Ipv4Addr::from(u32::from(Ipv4Addr::new(192, 0, 2, 0)) + last))
+}
+
+fn limiter(responses_per_second: u32, slip: u32) -> RateLimiter {
+    RateLimiter::new(RateLimitConfig { responses_per_second, slip, ..RateLimitConfig::default() })
+}
+
+#[test]
+fn test_identical_responses_to_a_prefix_are_limited() {
+    let mut limiter = limiter(5, 2);
+    let answer = response("www.example.com.", ResponseCode::NoError, true);
+    let now = Instant::now();
+
+    // A flood from many addresses in one /24 shares a bucket.
+    let decisions: Vec<Decision> = (1..=20).map(|i| limiter.check(client(i), &answer, now)).collect();
+    assert!(decisions[..5].iter().all(|decision| *decision == Decision::Send));
+    assert_eq!(decisions.iter().filter(|decision| **decision == Decision::Slip).count(), 8);
+    assert_eq!(decisions.iter().filter(|decision| **decision == Decision::Drop).count(), 7);
+    assert_eq!(decisions[5..7], [Decision::Slip, Decision::Drop]);
+
+    // Other prefixes and other names have buckets of their own.
+    let other = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+    assert_eq!(limiter.check(other, &answer, now), Decision::Send);
+    let mail = response("mail.example.com.", ResponseCode::NoError, true);
+    assert_eq!(limiter.check(client(1), &mail, now), Decision::Send);
+
+    // Tokens come back at the configured rate.
+    assert_eq!(limiter.check(client(1), &answer, now + Duration::from_millis(200)), Decision::Send);
+    assert_ne!(limiter.check(client(1), &answer, now + Duration::from_millis(200)), Decision::Send);
+    let later = now + Duration::from_secs(2);
+    assert!((0..5).all(|_| limiter.check(client(1), &answer, later) == Decision::Send));
+}
+
+#[test]
+fn test_negative_answers_for_random_names_share_the_zone_bucket() {
+    let nxdomain = response("a1.example.com.", ResponseCode::NXDomain, false);
+    assert_eq!(ResponseClass::of(&nxdomain), (ResponseClass::Nxdomain, "example.com.".to_string()));
+    let nodata = response("www.example.com.", ResponseCode::NoError, false);
+    assert_eq!(ResponseClass::of(&nodata), (ResponseClass::Nodata, "example.com.".to_string()));
+    let refused = response("www.example.com.", ResponseCode::Refused, false);
+    assert_eq!(ResponseClass::of(&refused), (ResponseClass::Error, String::new()));
+
+    let mut limiter = limiter(3, 0);
+    let now = Instant::now();
+    let decisions: Vec<Decision> = (0..10)
+        .map(|i| limiter.check(client(1), &response(&format!("random{}.example.com.", i), ResponseCode::NXDomain, false), now))
+        .collect();
+    assert_eq!(decisions.iter().filter(|decision| **decision == Decision::Send).count(), 3);
+    // Without slip, everything held back is dropped.
+    assert!(decisions[3..].iter().all(|decision| *decision == Decision::Drop));
+}
+
+#[test]
+fn test_exempt_clients_and_bucket_limit() {
+    let mut limiter = RateLimiter::new(RateLimitConfig {
+        responses_per_second: 1,
+        slip: 1,
+        exempt: vec!["192.0.2.0/24".parse().unwrap()],
+        max_buckets: 10,
+        ..RateLimitConfig::default()
+    });
+    let answer = response("www.example.com.", ResponseCode::NoError, true);
+    let now = Instant::now();
+    assert!((0..10).all(|_| limiter.check(client(1), &answer, now) == Decision::Send));
+    assert!(limiter.is_empty());
+
+    // Every response beyond the first is truncated with a slip of 1.
+    let source = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+    assert_eq!(limiter.check(source, &answer, now), Decision::Send);
+    assert_eq!(limiter.check(source, &answer, now), Decision::Slip);
+    assert_eq!(limiter.check(source, &answer, now), Decision::Slip);
+
+    // A flood from more prefixes than there are buckets for makes room by forgetting the
+    // buckets refilled longest ago, and every prefix is still limited.
+    let spoofed = |i: u32| IpAddr::V4(Ipv4Addr::from(u32::from(Ipv4Addr::new(10, 0, 0, 0)) + (i << 8)));
+    for i in 0..1000u32 {
+        assert_eq!(limiter.check(spoofed(i), &answer, now), Decision::Send);
+        assert_eq!(limiter.check(spoofed(i), &answer, now), Decision::Slip);
+    }
+    assert_eq!(limiter.len(), 10);
+    assert_eq!(limiter.check(spoofed(999), &answer, now), Decision::Slip);
+    assert_eq!(limiter.check(source, &answer, now), Decision::Send);
+}
+
+const ZONE: &str = "\
+$ORIGIN example.com.
+@ IN SOA ns1.example.com. admin.example.com. 7 7200 3600 1209600 3600
+@ IN NS ns1.example.com.
+ns1 IN A 192.0.2.1
+www IN A 192.0.2.3
+";
+
+fn query(name: &str) -> Vec<u8> {
+    let mut msg = Message::new();
+    msg.set_id(99).set_message_type(MessageType::Query).add_query(Query::query(Name::from_utf8(name).unwrap(), RecordType::A));
+    msg.to_bytes().unwrap()
+}
+
+#[test]
+fn test_udp_flood_from_many_sources_is_limited_and_slipped() {
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(ZoneParser::parse_zone_file(ZONE, "example.com.".to_string(), 3600).unwrap()).unwrap();
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5458);
+    let config = Config { rate_limit: Some(RateLimitConfig { responses_per_second: 5, slip: 2, ..RateLimitConfig::default() }), ..Config::default() };
+    let mut app = App::with_config(Box::new(db), addr, config);
+    thread::spawn(move || app.run());
+    thread::sleep(Duration::from_millis(100));
+
+    // Forty sources in 127.0.0.0/24 ask for the same name at once.
+    let sockets: Vec<UdpSocket> = (10..50).map(|i| UdpSocket::bind(format!("127.0.0.{}:0", i)).unwrap()).collect();
+    for socket in &sockets {
+        socket.send_to(&query("www.example.com."), addr).unwrap();
+    }
+    let (mut full, mut truncated, mut dropped) = (0, 0, 0);
+    for socket in &sockets {
+        socket.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+        let mut buf = [0u8; 4096];
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => {
+                let response = Message::from_bytes(&buf[..len]).unwrap();
+                if response.truncated() {
+                    assert!(response.answers().is_empty());
+                    truncated += 1;
+                } else {
+                    assert_eq!(response.answers().len(), 1);
+                    full += 1;
+                }
+            }
+            Err(_) => dropped += 1,
+        }
+    }
+    assert!((5..=7).contains(&full), "{} full responses", full);
+    assert!(truncated >= 15, "{} truncated responses", truncated);
+    assert!(dropped >= 15, "{} dropped responses", dropped);
+
+    // Truncated clients retry over TCP, which is not limited.
+    let stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    Server::write_tcp_message(&stream, &query("www.example.com.")).unwrap();
+    let response = Message::from_bytes(&Server::read_tcp_message(&stream).unwrap()).unwrap();
+    assert_eq!(response.answers().len(), 1);
+
+    // Another name is answered while the first one is limited.
+    let socket = UdpSocket::bind("127.0.0.10:0").unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    socket.send_to(&query("ns1.example.com."), addr).unwrap();
+    let mut buf = [0u8; 4096];
+    let (len, _) = socket.recv_from(&mut buf).unwrap();
+    assert!(!Message::from_bytes(&buf[..len]).unwrap().truncated());
+}
+
+#[test]
+fn test_error_replies_are_limited_too() {
+    let mut db = InMemoryDatabase::new();
+    db.add_zone(ZoneParser::parse_zone_file(ZONE, "example.com.".to_string(), 3600).unwrap()).unwrap();
+    let addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5461);
+    let config = Config {
+        access: Access { query: Some(vec!["none".to_string()]), ..Access::default() },
+        rate_limit: Some(RateLimitConfig { responses_per_second: 2, slip: 0, ..RateLimitConfig::default() }),
+        ..Config::default()
+    };
+    let mut app = App::with_config(Box::new(db), addr, config);
+    thread::spawn(move || app.run());
+    thread::sleep(Duration::from_millis(100));
+
+    // Refused queries and unparsable packets, which are answered with FORMERR, share the
+    // error bucket of the client's prefix.
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let unparsable = [0x12, 0x34, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for _ in 0..5 {
+        socket.send_to(&query("www.example.com."), addr).unwrap();
+        socket.send_to(&unparsable, addr).unwrap();
+    }
+    socket.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    let mut codes = Vec::new();
+    let mut buf = [0u8; 4096];
+    while let Ok((len, _)) = socket.recv_from(&mut buf) {
+        codes.push(ResponseCode::from_low(buf[3] & 0x0f));
+        assert!(len >= 12);
+    }
+    assert!((2..=3).contains(&codes.len()), "{:?}", codes);
+    assert!(codes.iter().all(|code| matches!(code, ResponseCode::Refused | ResponseCode::FormErr)));
+}
@@ -1,5 +1,6 @@
-use dont_need_stability::server::{Server};
-use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use dont_need_stability::server::{Server, MAX_TCP_CONNECTIONS};
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
 use rand::{Rng, RngCore, thread_rng};
 use std::thread;
 use std::time::Duration;
@@ -38,3 +39,35 @@ fn test_udp_packet_capture() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_tcp_connections_are_capped() {
+    let server_socket_addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 5462);
+    let mut serv = Server::new(server_socket_addr);
+    serv.register_callback(Box::new(|_, _, _| {}));
+    serv.register_tcp_callback(Box::new(|data, _, connection| {
+        connection.send(vec![data.to_vec()]).unwrap();
+    }));
+    serv.run();
+
+    let open: Vec<TcpStream> = (0..MAX_TCP_CONNECTIONS).map(|_| TcpStream::connect(server_socket_addr).unwrap()).collect();
+    thread::sleep(Duration::from_millis(200));
+
+    // One connection too many is closed without being served.
+    let mut extra = TcpStream::connect(server_socket_addr).unwrap();
+    extra.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let _ = Server::write_tcp_message(&extra, b"hello");
+    assert!(matches!(extra.read(&mut [0u8; 16]), Ok(0) | Err(_)));
+
+    // The open ones are still served, and closing one makes room for another.
+    Server::write_tcp_message(&open[0], b"hello").unwrap();
+    open[0].set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(Server::read_tcp_message(&open[0]).unwrap(), b"hello");
+    drop(open);
+    thread::sleep(Duration::from_millis(200));
+    let stream = TcpStream::connect(server_socket_addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    Server::write_tcp_message(&stream, b"again").unwrap();
+    assert_eq!(Server::read_tcp_message(&stream).unwrap(), b"again");
+    serv.stop();
+}
@@ -0,0 +1,127 @@
+use dont_need_stability::config::{Config, UpdateGrant};
+use dont_need_stability::tsig::{self, TsigAlgorithm, TsigError, TsigKey, TsigSigner, TsigVerifier};
+use trust_dns_proto::op::{Message, MessageType, Query};
+use trust_dns_proto::rr::{Name, RecordType};
+use trust_dns_proto::serialize::binary::{BinDecodable, BinEncodable};
+
+const NOW: u64 = 1_700_000_000;
+
+fn key(secret: &[u8]) -> TsigKey {
+    TsigKey {
+        name: "transfer-key.".to_string(),
+        algorithm: TsigAlgorithm::HmacSha256,
+        secret: secret.to_vec(),
+    }
+}
+
+fn request() -> Vec<u8> {
+    let mut msg = Message::new();
+    msg.set_id(99)
+        .set_message_type(MessageType::Query)
+        .add_query(Query::query(Name::from_utf8("example.com.").unwrap(), RecordType::AXFR));
+    msg.to_bytes().unwrap()
+}
+
+#[test]
+fn test_signed_request_verifies() {
+    let mut signer = TsigSigner::new(key(b"secret"), None);
+    let signed = signer.sign(&request(), NOW);
+
+    // The signed request is still a valid message, with the TSIG record at the end.
+    let msg = Message::from_bytes(&signed).unwrap();
+    assert_eq!(msg.additionals().len() + msg.sig0().len(), 1);
+
+    let verified = tsig::verify_request(&signed, &[key(b"secret")], NOW + 10).unwrap().unwrap();
+    assert_eq!(verified.key.name, "transfer-key.");
+    assert_eq!(Some(verified.mac.as_slice()), signer.mac());
+
+    assert!(tsig::verify_request(&request(), &[key(b"secret")], NOW).unwrap().is_none());
+}
+
+#[test]
+fn test_bad_signatures_are_rejected() {
+    let signed = TsigSigner::new(key(b"secret"), None).sign(&request(), NOW);
+
+    let error = |keys: &[TsigKey], now: u64| tsig::verify_request(&signed, keys, now).unwrap_err().error;
+    assert_eq!(error(&[key(b"other")], NOW), TsigError::BadSig);
+    assert_eq!(error(&[], NOW), TsigError::BadKey);
+    assert_eq!(error(&[key(b"secret")], NOW + u64::from(tsig::FUDGE) + 1), TsigError::BadTime);
+
+    let mut tampered = signed.clone();
+    tampered[13] ^= 1;
+    assert_eq!(tsig::verify_request(&tampered, &[key(b"secret")], NOW).unwrap_err().error, TsigError::BadSig);
+}
+
+#[test]
+fn test_response_sequence_is_chained() {
+    let mut client = TsigSigner::new(key(b"secret"), None);
+    let signed = client.sign(&request(), NOW);
+    let verified = tsig::verify_request(&signed, &[key(b"secret")], NOW).unwrap().unwrap();
+
+    let mut server = TsigSigner::new(verified.key, Some(verified.mac));
+    let responses: Vec<Vec<u8>> = (0..3).map(|_| server.sign(&request(), NOW)).collect();
+
+    let mut verifier = TsigVerifier::new(key(b"secret"), client.mac().unwrap().to_vec());
+    for response in &responses {
+        verifier.verify(response, NOW).unwrap();
+    }
+
+    // Messages cannot be reordered or dropped.
+    let mut verifier = TsigVerifier::new(key(b"secret"), client.mac().unwrap().to_vec());
+    verifier.verify(&responses[0], NOW).unwrap();
+    assert_eq!(verifier.verify(&responses[2], NOW), Err(TsigError::BadSig));
+}
+
+#[test]
+fn test_keys_are_read_from_config() {
+    let config: Config = toml::from_str(
+        r#"
+        [server]
+        port = 53
+        zones_directory = "zones"
+
+        [[key]]
+        name = "transfer-key."
+        algorithm = "hmac-sha512"
+        secret = "c2VjcmV0"
+        "#,
+    )
+    .unwrap();
+
+    let key = config.key("TRANSFER-KEY.").unwrap();
+    assert_eq!(key.algorithm, TsigAlgorithm::HmacSha512);
+    assert_eq!(key.secret, b"secret");
+}
+
+#[test]
+fn test_update_grants_cover_only_their_names() {
+    let grant = |names: &[&str]| UpdateGrant { key: "dhcp-key.".to_string(), names: names.iter().map(|name| name.to_string()).collect() };
+
+    let host = grant(&["host.example.com"]);
+    assert!(host.covers("host.example.com."));
+    assert!(host.covers("HOST.example.com"));
+    assert!(!host.covers("www.example.com."));
+
+    let dhcp = grant(&["*.dhcp.example.com"]);
+    assert!(dhcp.covers("laptop.dhcp.example.com."));
+    assert!(dhcp.covers("dhcp.example.com."));
+    assert!(!dhcp.covers("xdhcp.example.com."));
+    assert!(!dhcp.covers("example.com."));
+
+    // A grant without names grants nothing.
+    assert!(!grant(&[]).covers("example.com."));
+
+    // A grant without names is not a valid configuration.
+    let config: Result<Config, _> = toml::from_str(
+        r#"
+        [server]
+        port = 53
+        zones_directory = "zones"
+
+        [[zone]]
+        origin = "example.com."
+        update_policy = [{ key = "admin-key." }]
+        "#,
+    );
+    assert!(config.is_err());
+}
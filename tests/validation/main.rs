@@ -0,0 +1,76 @@
+use dont_need_stability::db::{Severity, Zone, ZoneIssueKind};
+use dont_need_stability::zone_parser::ZoneParser;
+
+const VALID_ZONE: &str = "\
+@ IN SOA ns1.example.com. admin.example.com. 1 7200 3600 1209600 3600
+@ IN NS ns1.example.com.
+@ IN NS ns.other.net.
+ns1 IN A 192.0.2.1
+www IN A 192.0.2.3
+";
+
+fn parse(content: &str) -> Zone {
+    ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap()
+}
+
+fn kinds(zone: &Zone) -> Vec<ZoneIssueKind> {
+    zone.validate().iter().map(|issue| issue.kind).collect()
+}
+
+#[test]
+fn test_valid_zone_has_no_issues() {
+    assert!(parse(VALID_ZONE).validate().is_empty());
+}
+
+#[test]
+fn test_missing_soa_and_ns() {
+    let zone = parse("www IN A 192.0.2.3\n");
+    assert_eq!(kinds(&zone), vec![ZoneIssueKind::MissingSoa, ZoneIssueKind::MissingApexNs]);
+}
+
+#[test]
+fn test_soa_not_at_apex() {
+    let content = VALID_ZONE.replacen("@ IN SOA", "sub IN SOA", 1);
+    assert!(kinds(&parse(&content)).contains(&ZoneIssueKind::SoaNotAtApex));
+}
+
+#[test]
+fn test_cname_with_other_data() {
+    let content = format!("{}www IN CNAME host.other.net.\n", VALID_ZONE);
+    assert_eq!(kinds(&parse(&content)), vec![ZoneIssueKind::CnameWithOtherData]);
+}
+
+#[test]
+fn test_signed_cname_is_valid() {
+    let zone = parse(&format!(
+        "{}alias 300 IN CNAME www.example.com.\n\
+         alias 300 IN RRSIG CNAME 15 3 300 20240102030405 20231201000000 1 example.com. q83v\n\
+         alias 3600 IN NSEC www.example.com. CNAME RRSIG NSEC\n\
+         alias 3600 IN RRSIG NSEC 15 3 3600 20240102030405 20231201000000 1 example.com. q83v\n",
+        VALID_ZONE
+    ));
+    assert!(zone.validate().is_empty());
+}
+
+#[test]
+fn test_ns_without_glue() {
+    let content = format!("{}@ IN NS ns2.example.com.\n", VALID_ZONE);
+    let issues = parse(&content).validate();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].kind, ZoneIssueKind::MissingGlue);
+    assert_eq!(issues[0].severity(), Severity::Error);
+}
+
+#[test]
+fn test_record_outside_origin() {
+    let content = format!("{}host.other.net. IN A 192.0.2.9\n", VALID_ZONE);
+    assert_eq!(kinds(&parse(&content)), vec![ZoneIssueKind::OutOfZone]);
+}
+
+#[test]
+fn test_duplicates_and_ttl_mismatch_are_warnings() {
+    let content = format!("{}www IN A 192.0.2.3\nwww 60 IN A 192.0.2.4\n", VALID_ZONE);
+    let issues = parse(&content).validate();
+    assert_eq!(issues.iter().map(|i| i.kind).collect::<Vec<_>>(), vec![ZoneIssueKind::DuplicateRecord, ZoneIssueKind::TtlMismatch]);
+    assert!(issues.iter().all(|issue| issue.severity() == Severity::Warning));
+}
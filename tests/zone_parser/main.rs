@@ -0,0 +1,454 @@
+use dont_need_stability::db::{RecordData, RecordType};
+use dont_need_stability::dnssec::key::key_tag;
+use dont_need_stability::dnssec::nsec::parse_type_bitmap;
+use dont_need_stability::dnssec::sign::Rrsig;
+use dont_need_stability::dnssec::Nsec3Params;
+use dont_need_stability::zone_parser::{ZoneParseErrorKind, ZoneParser};
+use std::str::FromStr;
+
+#[test]
+fn test_parse_generic_unknown_record() {
+    let content = "host IN TYPE65534 \\# 4 0a0b 0c0d\n";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+
+    assert_eq!(zone.records.len(), 1);
+    assert_eq!(zone.records[0].name, "host.example.com.");
+    assert_eq!(
+        zone.records[0].data,
+        RecordData::Unknown { rtype: 65534, data: vec![0x0a, 0x0b, 0x0c, 0x0d] }
+    );
+    assert_eq!(zone.records[0].data.get_type(), RecordType::Unknown(65534));
+}
+
+#[test]
+fn test_parse_generic_empty_rdata() {
+    let content = "host TYPE260 \\# 0\n";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+
+    assert_eq!(zone.records[0].data, RecordData::Unknown { rtype: 260, data: vec![] });
+}
+
+#[test]
+fn test_parse_generic_rdata_length_mismatch() {
+    let content = "host TYPE65534 \\# 3 0a0b\n";
+    let result = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_parse_generic_rdata_of_known_types() {
+    // RFC 3597, section 5: the generic form is converted to the type's own form.
+    let content = "\
+a TYPE1 \\# 4 c0000201
+mx MX \\# 16 000a 046d61696c 076578616d706c65 00
+txt TXT \\# 6 02 6869 026f6b
+srv SRV \\# 7 0001 0002 0035 00
+";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+    let data: Vec<&RecordData> = zone.records.iter().map(|record| &record.data).collect();
+    assert_eq!(data, [
+        &RecordData::A("192.0.2.1".parse().unwrap()),
+        &RecordData::MX { preference: 10, exchange: "mail.example.".to_string() },
+        &RecordData::TXT(vec!["hi".to_string(), "ok".to_string()]),
+        &RecordData::SRV { priority: 1, weight: 2, port: 53, target: ".".to_string() },
+    ]);
+
+    for invalid in ["a A \\# 3 c00002", "mx MX \\# 3 000a05", "ns NS \\# 2 0000", "txt TXT \\# 2 0568"] {
+        let result = ZoneParser::parse_zone_file(invalid, "example.com.".to_string(), 3600);
+        assert_eq!(result.unwrap_err().kind, ZoneParseErrorKind::InvalidRecordData, "{}", invalid);
+    }
+}
+
+#[test]
+fn test_type_mnemonic_maps_to_known_type() {
+    assert_eq!(RecordType::from_str("TYPE1").unwrap(), RecordType::A);
+    assert_eq!(RecordType::from_str("type257").unwrap(), RecordType::Unknown(257));
+    assert_eq!(RecordType::Unknown(257).to_string(), "TYPE257");
+    assert_eq!(RecordType::MX.code(), 15);
+}
+
+fn temp_zones_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("dns_zone_parser_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_include_with_origin() {
+    let dir = temp_zones_dir("include");
+    std::fs::write(dir.join("hosts.inc"), "www IN A 192.0.2.1\n").unwrap();
+    let content = "$INCLUDE hosts.inc sub\nmail IN A 192.0.2.2\n";
+
+    let zone = ZoneParser::parse_zone_file_in_dir(content, "example.com.".to_string(), 3600, &dir).unwrap();
+
+    assert_eq!(zone.records.len(), 2);
+    assert_eq!(zone.records[0].name, "www.sub.example.com.");
+    assert_eq!(zone.records[1].name, "mail.example.com.");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_include_depth_limit() {
+    let dir = temp_zones_dir("recursive");
+    std::fs::write(dir.join("loop.inc"), "$INCLUDE loop.inc\n").unwrap();
+
+    let result = ZoneParser::parse_zone_file_in_dir("$INCLUDE loop.inc\n", "example.com.".to_string(), 3600, &dir);
+
+    assert_eq!(result.unwrap_err().kind, ZoneParseErrorKind::IncludeDepthExceeded);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_include_stays_in_the_zones_directory() {
+    let dir = temp_zones_dir("escape");
+    let zones = dir.join("zones");
+    std::fs::create_dir_all(zones.join("sub")).unwrap();
+    std::fs::write(dir.join("outside.inc"), "www IN A 192.0.2.1\n").unwrap();
+    std::fs::write(zones.join("sub/hosts.inc"), "www IN A 192.0.2.2\n").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(dir.join("outside.inc"), zones.join("link.inc")).unwrap();
+
+    let outside = dir.join("outside.inc");
+    let mut escapes = vec!["../outside.inc", "sub/../../outside.inc", outside.to_str().unwrap()];
+    if cfg!(unix) {
+        escapes.push("link.inc");
+    }
+    for path in escapes {
+        let result = ZoneParser::parse_zone_file_in_dir(&format!("$INCLUDE {}\n", path), "example.com.".to_string(), 3600, &zones);
+        assert_eq!(result.unwrap_err().kind, ZoneParseErrorKind::InvalidDirective, "{}", path);
+    }
+    let zone = ZoneParser::parse_zone_file_in_dir("$INCLUDE sub/../sub/hosts.inc\n", "example.com.".to_string(), 3600, &zones).unwrap();
+    assert_eq!(zone.records.len(), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_include_without_directory_fails() {
+    let result = ZoneParser::parse_zone_file("$INCLUDE hosts.inc\n", "example.com.".to_string(), 3600);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_directives_are_case_insensitive() {
+    let content = "$ttl 2h\n$Origin sub.example.com.\n$generate 1-2 host$ A 192.0.2.$\n";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+    let names: Vec<&str> = zone.records.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, vec!["host1.sub.example.com.", "host2.sub.example.com."]);
+    assert!(zone.records.iter().all(|r| r.ttl == 7200));
+
+    let result = ZoneParser::parse_zone_file("$TTTL 300\n", "example.com.".to_string(), 3600);
+    assert_eq!(result.unwrap_err().kind, ZoneParseErrorKind::InvalidDirective);
+}
+
+#[test]
+fn test_generate_reverse_records() {
+    let content = "$GENERATE 1-10/3 $ PTR host-${100,3}.example.com.\n";
+    let zone = ZoneParser::parse_zone_file(content, "2.0.192.in-addr.arpa.".to_string(), 3600).unwrap();
+
+    let names: Vec<&str> = zone.records.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, vec![
+        "1.2.0.192.in-addr.arpa.",
+        "4.2.0.192.in-addr.arpa.",
+        "7.2.0.192.in-addr.arpa.",
+        "10.2.0.192.in-addr.arpa.",
+    ]);
+    assert!(zone.records.iter().all(|r| r.data.get_type() == RecordType::PTR));
+}
+
+#[test]
+fn test_generate_rejects_bad_modifiers() {
+    for template in ["host${9223372036854775807}", "host${0,4000000000,d}", "host${0,256}", "host${1,3"] {
+        let content = format!("$GENERATE 1-2 {} A 192.0.2.1\n", template);
+        let result = ZoneParser::parse_zone_file(&content, "example.com.".to_string(), 3600);
+        assert_eq!(result.unwrap_err().kind, ZoneParseErrorKind::InvalidDirective, "{}", template);
+    }
+    let zone = ZoneParser::parse_zone_file("$GENERATE 1-1 host${-1,3} A 192.0.2.1\n", "example.com.".to_string(), 3600).unwrap();
+    assert_eq!(zone.records[0].name, "host000.example.com.");
+}
+
+#[test]
+fn test_generate_stops_at_the_largest_value() {
+    let content = "$GENERATE 4294967294-4294967295/2 host$ A 192.0.2.1\n";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+    let names: Vec<&str> = zone.records.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, vec!["host4294967294.example.com."]);
+}
+
+#[test]
+fn test_generate_hex_and_nibble_modifiers() {
+    let content = "$GENERATE 26-27 pool-${0,2,x} 300 IN A 192.0.2.$\n$GENERATE 26-26 ${0,2,n} TXT \\$\n";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+
+    assert_eq!(zone.records[0].name, "pool-1a.example.com.");
+    assert_eq!(zone.records[0].ttl, 300);
+    assert_eq!(zone.records[1].data, RecordData::A("192.0.2.27".parse().unwrap()));
+    assert_eq!(zone.records[2].name, "a.1.example.com.");
+    assert_eq!(zone.records[2].data, RecordData::TXT(vec!["$".to_string()]));
+}
+
+#[test]
+fn test_parse_duration_units() {
+    assert_eq!(ZoneParser::parse_duration("300").unwrap(), 300);
+    assert_eq!(ZoneParser::parse_duration("300s").unwrap(), 300);
+    assert_eq!(ZoneParser::parse_duration("1h").unwrap(), 3600);
+    assert_eq!(ZoneParser::parse_duration("1d12h").unwrap(), 129600);
+    assert_eq!(ZoneParser::parse_duration("2W").unwrap(), 1209600);
+    assert!(ZoneParser::parse_duration("1h30").is_err());
+    assert!(ZoneParser::parse_duration("h").is_err());
+    assert!(ZoneParser::parse_duration("10y").is_err());
+}
+
+#[test]
+fn test_ttl_suffixes_and_field_order() {
+    let content = "\
+$TTL 1h
+@ IN SOA ns1.example.com. admin.example.com. 1 2h 30m 2w 1d
+www IN 5m A 192.0.2.1
+mail 300s IN A 192.0.2.2
+     IN MX 10 mail.example.com.
+";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 60).unwrap();
+
+    assert_eq!(zone.ttl, 3600);
+    assert_eq!(zone.records[0].name, "example.com.");
+    assert_eq!(zone.records[0].ttl, 3600);
+    assert_eq!(zone.records[0].data, RecordData::SOA {
+        mname: "ns1.example.com.".to_string(),
+        rname: "admin.example.com.".to_string(),
+        serial: 1,
+        refresh: 7200,
+        retry: 1800,
+        expire: 1209600,
+        minimum: 86400,
+    });
+    assert_eq!(zone.records[1].ttl, 300);
+    assert_eq!(zone.records[2].ttl, 300);
+    assert_eq!(zone.records[3].name, "mail.example.com.");
+    assert_eq!(zone.records[3].data.get_type(), RecordType::MX);
+}
+
+#[test]
+fn test_error_reports_line_column_and_token() {
+    let content = "www IN A 192.0.2.1\nmail IN  A 192.0.2.300\n";
+    let error = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap_err();
+
+    assert_eq!(error.line, 2);
+    assert_eq!(error.column, 12);
+    assert_eq!(error.token, "192.0.2.300");
+    assert_eq!(error.kind, ZoneParseErrorKind::InvalidRecordData);
+    assert!(error.to_string().starts_with("<input>:2:12: "));
+}
+
+#[test]
+fn test_load_zone_file_collects_all_errors() {
+    let dir = temp_zones_dir("collect");
+    std::fs::write(dir.join("broken.inc"), "bad 1x A 192.0.2.9\n").unwrap();
+    let zone_path = dir.join("example.com.zone");
+    std::fs::write(&zone_path, "\
+www IN BOGUS 192.0.2.1
+$INCLUDE broken.inc
+ok IN A 192.0.2.2
+  IN MX ten mail.example.com.
+").unwrap();
+
+    let errors = ZoneParser::load_zone_file(&zone_path, "example.com.".to_string(), 3600, &dir).unwrap_err();
+
+    assert_eq!(errors.len(), 3);
+    assert_eq!((errors[0].line, errors[0].kind), (1, ZoneParseErrorKind::InvalidRecordType));
+    assert_eq!(errors[0].token, "BOGUS");
+    assert_eq!(errors[1].file.as_deref(), Some(dir.join("broken.inc").as_path()));
+    assert_eq!((errors[1].line, errors[1].column, errors[1].kind), (1, 5, ZoneParseErrorKind::InvalidTtl));
+    assert_eq!(errors[2].file.as_deref(), Some(zone_path.as_path()));
+    assert_eq!((errors[2].line, errors[2].token.as_str()), (4, "ten"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_load_zone_file_returns_included_files() {
+    let dir = temp_zones_dir("tracked");
+    std::fs::write(dir.join("hosts.inc"), "$INCLUDE nested.inc\n").unwrap();
+    std::fs::write(dir.join("nested.inc"), "www IN A 192.0.2.1\n").unwrap();
+    let zone_path = dir.join("example.com.zone");
+    std::fs::write(&zone_path, "$INCLUDE hosts.inc\n").unwrap();
+
+    let (result, includes) = ZoneParser::load_zone_file_with_includes(&zone_path, "example.com.".to_string(), 3600, &dir);
+    let canonical = std::fs::canonicalize(&dir).unwrap();
+    assert_eq!(result.unwrap().records.len(), 1);
+    assert_eq!(includes, vec![canonical.join("hosts.inc"), canonical.join("nested.inc")]);
+
+    // Files that are missing are returned too, so the zone can be loaded once they exist.
+    std::fs::remove_file(dir.join("nested.inc")).unwrap();
+    let (result, includes) = ZoneParser::load_zone_file_with_includes(&zone_path, "example.com.".to_string(), 3600, &dir);
+    assert!(result.is_err());
+    assert_eq!(includes, vec![canonical.join("hosts.inc"), dir.join("nested.inc")]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_load_zone_file_reports_unknown_directives() {
+    let dir = temp_zones_dir("directive");
+    let zone_path = dir.join("example.com.zone");
+    std::fs::write(&zone_path, "www IN A 192.0.2.1\n$FOO bar\nmail IN A 192.0.2.2\n").unwrap();
+
+    let errors = ZoneParser::load_zone_file(&zone_path, "example.com.".to_string(), 3600, &dir).unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].line, errors[0].column, errors[0].kind), (2, 1, ZoneParseErrorKind::InvalidDirective));
+    assert_eq!(errors[0].token, "$FOO");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_parse_json_zone() {
+    let content = r#"{
+        "origin": "example.com.",
+        "ttl": 3600,
+        "records": [
+            { "name": "@", "ttl": 3600, "type": "NS", "data": "ns1.example.com." },
+            { "name": "www", "ttl": 300, "class": "IN", "type": "A", "data": "192.0.2.1" },
+            { "name": "example.com.", "ttl": 3600, "type": "MX", "data": { "preference": 10, "exchange": "mail.example.com." } },
+            { "name": "opaque", "ttl": 60, "type": "Unknown", "data": { "rtype": 65534, "data": [1, 2] } }
+        ]
+    }"#;
+    let zone = ZoneParser::parse_json_zone(content).unwrap();
+
+    assert_eq!(zone.records[0].name, "example.com.");
+    assert_eq!(zone.records[1].name, "www.example.com.");
+    assert_eq!(zone.records[1].data, RecordData::A("192.0.2.1".parse().unwrap()));
+    assert_eq!(zone.records[2].data, RecordData::MX { preference: 10, exchange: "mail.example.com.".to_string() });
+    assert_eq!(zone.records[3].data, RecordData::Unknown { rtype: 65534, data: vec![1, 2] });
+
+    let reparsed = ZoneParser::parse_json_zone(&serde_json::to_string(&zone).unwrap()).unwrap();
+    assert_eq!(reparsed, zone);
+}
+
+#[test]
+fn test_txt_keeps_character_strings_apart() {
+    let long = "a".repeat(200);
+    let content = format!("dkim IN TXT \"{}\" \"{}\"\nutf8 IN TXT caf\\195\\169 \"\\\"x y\\\"\"\n", long, long);
+    let zone = ZoneParser::parse_zone_file(&content, "example.com.".to_string(), 3600).unwrap();
+    assert_eq!(zone.records[0].data, RecordData::TXT(vec![long.clone(), long]));
+    // `\DDD` escapes stand for bytes, not characters.
+    assert_eq!(zone.records[1].data, RecordData::TXT(vec!["café".to_string(), "\"x y\"".to_string()]));
+
+    let too_long = format!("dkim IN TXT {}\n", "a".repeat(256));
+    assert!(ZoneParser::parse_zone_file(&too_long, "example.com.".to_string(), 3600).is_err());
+    assert!(ZoneParser::parse_zone_file("bin IN TXT \\255\n", "example.com.".to_string(), 3600).is_err());
+    assert!(ZoneParser::parse_zone_file("empty IN TXT\n", "example.com.".to_string(), 3600).is_err());
+
+    let json = r#"{"origin": "example.com.", "ttl": 60, "records": [{"name": "txt", "ttl": 60, "type": "TXT", "data": ["one", "two"]}]}"#;
+    let zone = ZoneParser::parse_json_zone(json).unwrap();
+    assert_eq!(zone.records[0].data, RecordData::TXT(vec!["one".to_string(), "two".to_string()]));
+}
+
+#[test]
+fn test_parse_yaml_zone() {
+    let content = "\
+origin: example.com.
+ttl: 3600
+records:
+  - name: www
+    ttl: 300
+    type: AAAA
+    data: 2001:db8::1
+  - name: txt
+    ttl: 300
+    type: TXT
+    data: hello world
+";
+    let zone = ZoneParser::parse_yaml_zone(content).unwrap();
+
+    assert_eq!(zone.records[0].name, "www.example.com.");
+    assert_eq!(zone.records[0].data, RecordData::AAAA("2001:db8::1".parse().unwrap()));
+    assert_eq!(zone.records[1].data, RecordData::TXT(vec!["hello world".to_string()]));
+}
+
+#[test]
+fn test_structured_zone_errors_have_position() {
+    let json = "{\n  \"origin\": \"example.com.\",\n  \"ttl\": \"soon\",\n  \"records\": []\n}";
+    let error = ZoneParser::parse_json_zone(json).unwrap_err();
+    assert_eq!(error.kind, ZoneParseErrorKind::InvalidStructure);
+    assert_eq!(error.line, 3);
+
+    let dir = temp_zones_dir("yaml");
+    let path = dir.join("example.com.yaml");
+    std::fs::write(&path, "origin: example.com.\nttl: 3600\nrecords:\n  - name: www\n    ttl: 300\n    type: A\n    data: not-an-ip\n").unwrap();
+    let errors = ZoneParser::load_zone_file(&path, "example.com.".to_string(), 3600, &dir).unwrap_err();
+    assert_eq!(errors[0].file.as_deref(), Some(path.as_path()));
+    assert_eq!(errors[0].kind, ZoneParseErrorKind::InvalidStructure);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Records from the examples of RFC 4034, sections 2.3, 3.3, 4.3 and 5.4, and RFC 5155,
+/// appendix A.
+const SIGNED_RECORDS: &str = "\
+$ORIGIN example.com.
+host 86400 IN RRSIG A 5 3 86400 20030322173103 ( 20030220173103 2642 example.com.
+                                  oJB1W6WNGv+ldvQ3WDG0MQkg5IEhjRip8WTr
+                                  PYGv07h108dUKGMeDPKijVCHX3DDKdfb+v6o
+                                  B9wfuh3DTJXUAfI/M0zmO/zz8bW0Rznl8O3t
+                                  GNazPwQKkRN20XPXV6nwwfoXmJQbsLNrLfkG
+                                  J5D6fwFm8nN+6pBzeDQfsS3Ap3o= )
+alfa 86400 IN NSEC host.example.com. (
+                                A MX RRSIG NSEC TYPE1234 )
+dskey 86400 IN DNSKEY 256 3 RSASHA1 ( AQOeiiR0GOMYkDshWoSKz9Xz
+                                      fwJr1AYtsmx3TGkJaNXVbfi/
+                                      2pHm822aJ5iI9BMzNXxeYCmZ
+                                      DRD99WYwYqUSdjMmmAphXdvx
+                                      egXd/M5+X7OrzKBaMbCVdFLU
+                                      Uh6DhweJBjEVv5f2wwjM9Xzc
+                                      nOf+EPbtG9DMBmADjFDc2w/r
+                                      ljwvFw==
+                                      ) ; key id = 60485
+dskey 86400 IN DS 60485 5 1 ( 2BB183AF5F22588179A53B0A9
+                              8631FAD1A292118 )
+@ NSEC3PARAM 1 0 12 aabbccdd
+0p9mhaveqvm6t7vbl5lop2u3t2rp3tom NSEC3 1 1 12 aabbccdd (
+                        2t7b4g4vsa5smi47k61mv5bv1a22bojr NS SOA MX RRSIG DNSKEY NSEC3PARAM )
+";
+
+fn unknown_data(data: &RecordData) -> &[u8] {
+    match data {
+        RecordData::Unknown { data, .. } => data,
+        _ => panic!("not opaque RDATA: {:?}", data),
+    }
+}
+
+#[test]
+fn test_parse_dnssec_records() {
+    let zone = ZoneParser::parse_zone_file(SIGNED_RECORDS, "example.com.".to_string(), 3600).unwrap();
+    let types: Vec<String> = zone.records.iter().map(|record| record.data.get_type().to_string()).collect();
+    assert_eq!(types, ["RRSIG", "NSEC", "DNSKEY", "DS", "NSEC3PARAM", "NSEC3"]);
+
+    let rrsig = Rrsig::parse(unknown_data(&zone.records[0].data)).unwrap();
+    assert_eq!((rrsig.type_covered, rrsig.algorithm, rrsig.labels, rrsig.original_ttl), (1, 5, 3, 86400));
+    assert_eq!((rrsig.expiration, rrsig.inception, rrsig.key_tag), (1048354263, 1045762263, 2642));
+    assert_eq!(rrsig.signer, "example.com.");
+    assert_eq!(rrsig.signature.len(), 128);
+
+    let nsec = unknown_data(&zone.records[1].data);
+    assert_eq!(nsec[..18], *b"\x04host\x07example\x03com\x00");
+    assert_eq!(parse_type_bitmap(&nsec[18..]).unwrap().into_iter().collect::<Vec<_>>(), [1, 15, 46, 47, 1234]);
+
+    assert_eq!(key_tag(unknown_data(&zone.records[2].data)), 60485);
+    let ds = unknown_data(&zone.records[3].data);
+    assert_eq!(ds[..4], [0xec, 0x45, 5, 1]);
+    assert_eq!(ds.len(), 24);
+
+    let params = Nsec3Params { iterations: 12, salt: vec![0xaa, 0xbb, 0xcc, 0xdd], opt_out: false };
+    assert_eq!(Nsec3Params::parse(unknown_data(&zone.records[4].data)), Some(params.clone()));
+    assert_eq!(zone.records[5].name, format!("{}.example.com.", params.hashed_label("example.")));
+    assert!(Nsec3Params::parse(unknown_data(&zone.records[5].data)).unwrap().opt_out);
+}
+
+#[test]
+fn test_parentheses_continue_entries_over_lines() {
+    let content = "www IN A 192.0.2.1\nsrv IN SRV ( 1 2 ; priority and weight\n  99999 target. )\n";
+    let error = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap_err();
+    assert_eq!((error.line, error.column, error.token.as_str()), (3, 3, "99999"));
+
+    let error = ZoneParser::parse_zone_file("www IN A ( 192.0.2.1\n", "example.com.".to_string(), 3600).unwrap_err();
+    assert_eq!(error.kind, ZoneParseErrorKind::UnbalancedParentheses);
+    let error = ZoneParser::parse_zone_file("www IN A 192.0.2.1 )\n", "example.com.".to_string(), 3600).unwrap_err();
+    assert_eq!(error.kind, ZoneParseErrorKind::UnbalancedParentheses);
+}
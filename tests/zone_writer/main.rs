@@ -0,0 +1,94 @@
+use dont_need_stability::db::record::RecordClass;
+use dont_need_stability::db::{RecordData, ResourceRecord, Zone};
+use dont_need_stability::zone_parser::ZoneParser;
+use dont_need_stability::zone_writer::ZoneWriter;
+
+fn record(name: &str, ttl: u32, data: RecordData) -> ResourceRecord {
+    ResourceRecord {
+        name: name.to_string(),
+        ttl,
+        class: RecordClass::IN,
+        data,
+    }
+}
+
+fn sample_zone() -> Zone {
+    Zone {
+        origin: "example.com.".to_string(),
+        ttl: 3600,
+        records: vec![
+            record("www.example.com.", 300, RecordData::A("192.0.2.3".parse().unwrap())),
+            record("example.com.", 3600, RecordData::NS("ns1.example.com.".to_string())),
+            record("example.com.", 3600, RecordData::SOA {
+                mname: "ns1.example.com.".to_string(),
+                rname: "admin.example.com.".to_string(),
+                serial: 2023112201,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                minimum: 3600,
+            }),
+            record("ns1.example.com.", 3600, RecordData::AAAA("2001:db8::1".parse().unwrap())),
+            record("example.com.", 3600, RecordData::MX { preference: 10, exchange: "mail.example.com.".to_string() }),
+            record("_sip._tcp.example.com.", 3600, RecordData::SRV { priority: 10, weight: 5, port: 5060, target: "sip.example.com.".to_string() }),
+            record("txt.example.com.", 60, RecordData::TXT(vec!["say \"hi\"; then  leave\\".to_string(), "café".to_string()])),
+            record("opaque.example.com.", 60, RecordData::Unknown { rtype: 65534, data: vec![0xde, 0xad] }),
+            record("empty.example.com.", 60, RecordData::Unknown { rtype: 65535, data: vec![] }),
+            record("host.other.net.", 60, RecordData::PTR("target.example.com.".to_string())),
+        ],
+    }
+}
+
+#[test]
+fn test_write_then_parse_round_trips() {
+    let zone = sample_zone();
+    let text = ZoneWriter::write_zone(&zone);
+
+    let parsed = ZoneParser::parse_zone_file(&text, "ignored.".to_string(), 1).unwrap();
+    assert_eq!(parsed, zone);
+}
+
+#[test]
+fn test_write_is_canonical_and_relative() {
+    let text = ZoneWriter::write_zone(&sample_zone());
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert_eq!(lines[0], "$ORIGIN example.com.");
+    assert_eq!(lines[1], "$TTL 3600");
+    assert!(lines[2].starts_with("@ "));
+    assert!(lines[2].contains(" SOA "));
+    assert!(lines[3].contains(" NS "));
+    assert!(lines[4].contains(" MX "));
+    assert!(lines.iter().any(|line| line.starts_with("www ")));
+    assert!(lines.iter().any(|line| line.starts_with("host.other.net. ")));
+    assert!(text.contains(r#""say \"hi\"; then  leave\\" "café""#));
+    assert!(text.contains(r"TYPE65534 \# 2 dead"));
+
+    // Columns are aligned: every record's type starts at the same offset.
+    let type_column = lines[2].find("SOA").unwrap();
+    assert_eq!(lines[3].find("NS ").unwrap(), type_column);
+}
+
+#[test]
+fn test_dnssec_records_are_written_in_presentation_format() {
+    let content = "\
+$ORIGIN example.com.
+@ IN SOA ns1.example.com. admin.example.com. 1 7200 3600 1209600 3600
+@ IN NS ns1.example.com.
+@ IN RRSIG SOA 15 2 3600 20240102030405 20231201000000 12345 example.com. ( q83v )
+@ IN NSEC ns1.example.com. NS SOA RRSIG NSEC DNSKEY
+@ IN DNSKEY 257 3 15 l02Woi0iS8Aa25FQkUd9RMzZHJpBoRQwAQEX1SxZJA4=
+@ IN NSEC3PARAM 1 0 0 -
+ns1 IN A 192.0.2.1
+ns1 IN DS 12345 15 2 ABCDEF
+k0gq8s6m3kl2j3i5b0rqe7qkb1ae1mt5 IN NSEC3 1 1 0 - ( 9dlbdpqbcvm4a5fmr6bg7an11fpm8tjv A RRSIG )
+";
+    let zone = ZoneParser::parse_zone_file(content, "example.com.".to_string(), 3600).unwrap();
+    let text = ZoneWriter::write_zone(&zone);
+    assert!(text.contains("RRSIG      SOA 15 2 3600 20240102030405 20231201000000 12345 example.com. q83v"));
+    assert!(text.contains("NSEC       ns1.example.com. NS SOA RRSIG NSEC DNSKEY"));
+    assert!(text.contains("DS         12345 15 2 ABCDEF"));
+    assert!(text.contains("NSEC3      1 1 0 - 9dlbdpqbcvm4a5fmr6bg7an11fpm8tjv A RRSIG"));
+    assert!(text.contains("NSEC3PARAM 1 0 0 -"));
+    assert_eq!(ZoneParser::parse_zone_file(&text, "example.com.".to_string(), 3600).unwrap(), zone);
+}